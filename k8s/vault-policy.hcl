@@ -6,7 +6,40 @@ path "pki/issue/cert-keeper" {
   capabilities = ["create", "update"]
 }
 
+# Only needed with CERT_KEY_SOURCE=local: sign CSRs for locally generated keys.
+# path "pki/sign/cert-keeper" {
+#   capabilities = ["create", "update"]
+# }
+
+# Only needed with VAULT_PKI_ISSUER set: issue from a pinned issuer.
+# path "pki/issuer/+/issue/cert-keeper" {
+#   capabilities = ["create", "update"]
+# }
+
+# Only needed with CERT_REVOKE_SUPERSEDED=true: revoke replaced certificates.
+# path "pki/revoke" {
+#   capabilities = ["update"]
+# }
+
 # Allow reading PKI roles (optional, for validation).
 path "pki/roles/cert-keeper" {
   capabilities = ["read"]
 }
+
+# Only needed with ACME_ENABLED=true: sign CSRs submitted by ACME clients.
+# path "pki/sign/cert-keeper-acme" {
+#   capabilities = ["create", "update"]
+# }
+
+# Only needed with KV_SECRETS: read the listed secrets from the KV v2 mount.
+# path "secret/data/my-app/*" {
+#   capabilities = ["read"]
+# }
+
+# Only needed with CERT_KEY_ENCRYPTION=transit: wrap and unwrap data keys.
+# path "transit/datakey/plaintext/cert-keeper" {
+#   capabilities = ["update"]
+# }
+# path "transit/decrypt/cert-keeper" {
+#   capabilities = ["update"]
+# }
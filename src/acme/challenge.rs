@@ -0,0 +1,199 @@
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::process::Stdio;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use bytes::Bytes;
+use http_body_util::Full;
+use hyper::body::Incoming;
+use hyper::header::{HeaderValue, CONTENT_TYPE};
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper::{Request, Response, StatusCode};
+use hyper_util::rt::TokioIo;
+use rcgen::{CertificateParams, CustomExtension, KeyPair};
+use ring::digest;
+use rustls::pki_types::PrivateKeyDer;
+use rustls::sign::CertifiedKey;
+use tokio::net::TcpListener;
+use tokio::process::Command;
+use tokio::task::JoinHandle;
+use tokio::time::timeout;
+use tracing::{debug, info, warn};
+
+use crate::acme::jws;
+use crate::cert::hook;
+use crate::config::Config;
+use crate::error::{Error, Result};
+
+const HTTP01_PREFIX: &str = "/.well-known/acme-challenge/";
+
+/// Answers `http-01` validation requests while an order is being
+/// validated. Stops listening when dropped.
+pub struct Http01Responder {
+    /// Key authorizations by token.
+    tokens: Arc<Mutex<HashMap<String, String>>>,
+    task: JoinHandle<()>,
+}
+
+impl Http01Responder {
+    pub async fn start(addr: SocketAddr) -> Result<Self> {
+        let listener = TcpListener::bind(addr)
+            .await
+            .map_err(|e| Error::Bind(format!("ACME_ISSUER_HTTP_ADDR {addr}: {e}")))?;
+        debug!(addr = %addr, "answering ACME http-01 challenges");
+
+        let tokens = Arc::new(Mutex::new(HashMap::new()));
+        let task = tokio::spawn(serve(listener, tokens.clone()));
+        Ok(Self { tokens, task })
+    }
+
+    pub fn insert(&self, token: &str, key_authorization: &str) {
+        lock(&self.tokens).insert(token.to_string(), key_authorization.to_string());
+    }
+
+    pub fn remove(&self, token: &str) {
+        lock(&self.tokens).remove(token);
+    }
+}
+
+impl Drop for Http01Responder {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+async fn serve(listener: TcpListener, tokens: Arc<Mutex<HashMap<String, String>>>) {
+    loop {
+        let (stream, peer_addr) = match listener.accept().await {
+            Ok(conn) => conn,
+            Err(e) => {
+                warn!(error = %e, "failed to accept ACME http-01 connection");
+                continue;
+            }
+        };
+
+        let tokens = tokens.clone();
+        tokio::spawn(async move {
+            let service = service_fn(move |req| {
+                let reply = respond(&req, &tokens);
+                async move { Ok::<_, Infallible>(reply) }
+            });
+            if let Err(e) = http1::Builder::new()
+                .serve_connection(TokioIo::new(stream), service)
+                .await
+            {
+                debug!(peer = %peer_addr, error = %e, "ACME http-01 connection ended");
+            }
+        });
+    }
+}
+
+fn respond(
+    req: &Request<Incoming>,
+    tokens: &Mutex<HashMap<String, String>>,
+) -> Response<Full<Bytes>> {
+    let key_authorization = req
+        .uri()
+        .path()
+        .strip_prefix(HTTP01_PREFIX)
+        .and_then(|token| lock(tokens).get(token).cloned());
+
+    match key_authorization {
+        Some(body) => {
+            info!(path = %req.uri().path(), "answered ACME http-01 challenge");
+            let mut response = Response::new(Full::new(Bytes::from(body)));
+            response.headers_mut().insert(
+                CONTENT_TYPE,
+                HeaderValue::from_static("application/octet-stream"),
+            );
+            response
+        }
+        None => {
+            let mut response = Response::new(Full::new(Bytes::new()));
+            *response.status_mut() = StatusCode::NOT_FOUND;
+            response
+        }
+    }
+}
+
+fn lock(tokens: &Mutex<HashMap<String, String>>) -> MutexGuard<'_, HashMap<String, String>> {
+    tokens.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// The self-signed certificate that answers a `tls-alpn-01` challenge for
+/// `domain` (RFC 8737 section 3).
+pub fn tls_alpn_cert(domain: &str, key_authorization: &str) -> Result<CertifiedKey> {
+    let failed = |e: rcgen::Error| {
+        Error::AcmeOrder(format!(
+            "failed to build tls-alpn-01 certificate for {domain}: {e}"
+        ))
+    };
+    let digest = digest::digest(&digest::SHA256, key_authorization.as_bytes());
+    let mut params = CertificateParams::new(vec![domain.to_string()]).map_err(failed)?;
+    params.custom_extensions = vec![CustomExtension::new_acme_identifier(digest.as_ref())];
+    let key_pair = KeyPair::generate().map_err(failed)?;
+    let cert = params.self_signed(&key_pair).map_err(failed)?;
+
+    // `CertifiedKey::from_der` would parse the certificate with webpki,
+    // which refuses the critical acmeIdentifier extension.
+    let key = PrivateKeyDer::Pkcs8(key_pair.serialize_der().into());
+    let signing_key = rustls::crypto::ring::default_provider()
+        .key_provider
+        .load_private_key(key)
+        .map_err(|e| Error::Tls(format!("failed to load tls-alpn-01 key: {e}")))?;
+    Ok(CertifiedKey::new(vec![cert.der().clone()], signing_key))
+}
+
+/// The TXT record value for a `dns-01` challenge (RFC 8555 section 8.4).
+pub fn dns_value(key_authorization: &str) -> String {
+    jws::encode(digest::digest(&digest::SHA256, key_authorization.as_bytes()).as_ref())
+}
+
+/// Run `ACME_ISSUER_DNS_HOOK` with `sh -c` to publish (`present`) or
+/// remove (`cleanup`) the `dns-01` record for `domain`, killing it after
+/// `ACME_ISSUER_TIMEOUT`.
+pub async fn run_dns_hook(config: &Config, action: &str, domain: &str, value: &str) -> Result<()> {
+    let Some(ref cmd) = config.acme_issuer_dns_hook else {
+        return Err(Error::Config(
+            "ACME_ISSUER_CHALLENGE=dns-01 requires ACME_ISSUER_DNS_HOOK".into(),
+        ));
+    };
+    let failed = |detail: String| {
+        Error::AcmeOrder(format!(
+            "ACME_ISSUER_DNS_HOOK {action} for {domain} {detail}"
+        ))
+    };
+
+    let child = Command::new("/bin/sh")
+        .arg("-c")
+        .arg(cmd)
+        .env("CERT_KEEPER_ACME_ACTION", action)
+        .env("CERT_KEEPER_ACME_DOMAIN", domain)
+        .env(
+            "CERT_KEEPER_ACME_RECORD",
+            format!("_acme-challenge.{domain}"),
+        )
+        .env("CERT_KEEPER_ACME_VALUE", value)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| failed(format!("could not start: {e}")))?;
+
+    // Dropping the child on timeout kills it.
+    let limit = config.acme_issuer_timeout;
+    let output = timeout(limit, child.wait_with_output())
+        .await
+        .map_err(|_| failed(format!("timed out after {}s", limit.as_secs())))?
+        .map_err(|e| failed(format!("could not be waited for: {e}")))?;
+
+    let (stdout, stderr) = hook::captured(&output);
+    if !output.status.success() {
+        return Err(failed(format!("failed with {}: {stderr}", output.status)));
+    }
+    debug!(action, domain, stdout, stderr, "ACME DNS hook succeeded");
+    Ok(())
+}
@@ -0,0 +1,608 @@
+use std::fs;
+use std::io::{self, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use rcgen::{KeyPair, PKCS_ECDSA_P256_SHA256};
+use reqwest::header::{CONTENT_TYPE, LOCATION, RETRY_AFTER};
+use reqwest::{Client, Response};
+use ring::rand::SystemRandom;
+use ring::signature::{EcdsaKeyPair, KeyPair as _, ECDSA_P256_SHA256_FIXED_SIGNING};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use time::OffsetDateTime;
+use tokio::sync::OnceCell;
+use tokio::time::{sleep, Instant};
+use tracing::{debug, info, instrument, warn};
+
+use crate::acme::challenge::{self, Http01Responder};
+use crate::acme::jws::{self, Jwk};
+use crate::cert::info::{parse_pem_chain, CertInfo};
+use crate::cert::issuer::{CertBundle, CertRequest, Issuer};
+use crate::cert::keygen;
+use crate::cert::resolver::AlpnChallenges;
+use crate::cert::store::split_leaf;
+use crate::config::{AcmeChallenge, CertNames, Config};
+use crate::dns::Resolver;
+use crate::error::{Error, Result};
+
+/// How often a `badNonce` rejection is retried with a fresh nonce.
+const NONCE_RETRIES: usize = 3;
+/// Polling interval for authorizations and orders when the CA sends no
+/// `Retry-After`.
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Orders certificates from an ACME CA (RFC 8555), such as Let's Encrypt,
+/// with an ES256 account key.
+pub struct AcmeIssuer {
+    http: Client,
+    directory_url: String,
+    contact: Option<String>,
+    key: EcdsaKeyPair,
+    rng: SystemRandom,
+    jwk: Jwk,
+    thumbprint: String,
+    directory: OnceCell<Directory>,
+    /// The account URL, used as `kid` once registered.
+    account: OnceCell<String>,
+    nonce: Mutex<Option<String>>,
+    alpn_challenges: Option<AlpnChallenges>,
+}
+
+#[async_trait]
+impl Issuer for AcmeIssuer {
+    /// Register the account, so a CA that refuses it fails startup.
+    async fn prepare(&self, _config: &Config) -> Result<()> {
+        self.register().await
+    }
+
+    async fn issue(&self, req: CertRequest<'_>) -> Result<CertBundle> {
+        self.order(req.config, req.names).await
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Directory {
+    new_nonce: String,
+    new_account: String,
+    new_order: String,
+}
+
+#[derive(Deserialize)]
+struct Order {
+    status: String,
+    #[serde(default)]
+    authorizations: Vec<String>,
+    finalize: String,
+    certificate: Option<String>,
+    error: Option<Problem>,
+}
+
+#[derive(Deserialize)]
+struct Authorization {
+    status: String,
+    identifier: Identifier,
+    #[serde(default)]
+    challenges: Vec<Challenge>,
+}
+
+#[derive(Deserialize)]
+struct Identifier {
+    value: String,
+}
+
+#[derive(Deserialize)]
+struct Challenge {
+    #[serde(rename = "type")]
+    kind: String,
+    url: String,
+    token: Option<String>,
+    error: Option<Problem>,
+}
+
+/// An RFC 7807 problem document, as ACME errors are reported.
+#[derive(Default, Deserialize)]
+struct Problem {
+    #[serde(rename = "type", default)]
+    kind: String,
+    #[serde(default)]
+    detail: String,
+}
+
+impl std::fmt::Display for Problem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let kind = self.kind.trim_start_matches("urn:ietf:params:acme:error:");
+        match (kind, self.detail.as_str()) {
+            ("", "") => f.write_str("no details"),
+            (kind, "") => f.write_str(kind),
+            ("", detail) => f.write_str(detail),
+            (kind, detail) => write!(f, "{kind}: {detail}"),
+        }
+    }
+}
+
+impl AcmeIssuer {
+    /// `alpn_challenges` is where `tls-alpn-01` certificates go for the
+    /// proxy to answer with.
+    pub fn new(
+        config: &Config,
+        resolver: Arc<Resolver>,
+        alpn_challenges: Option<AlpnChallenges>,
+    ) -> Result<Self> {
+        if config.acme_issuer_challenge == AcmeChallenge::TlsAlpn01 && alpn_challenges.is_none() {
+            return Err(Error::Config(
+                "ACME_ISSUER_CHALLENGE=tls-alpn-01 is answered by the proxy; use http-01 or dns-01 without it".into(),
+            ));
+        }
+
+        let mut builder = Client::builder()
+            .dns_resolver(resolver)
+            .timeout(Duration::from_secs(30))
+            .user_agent(concat!("cert-keeper/", env!("CARGO_PKG_VERSION")));
+        if let Some(ref ca_path) = config.acme_issuer_cacert {
+            let ca_pem = fs::read(ca_path).map_err(|e| {
+                Error::Config(format!(
+                    "failed to read ACME_ISSUER_CACERT '{ca_path}': {e}"
+                ))
+            })?;
+            let cert = reqwest::Certificate::from_pem(&ca_pem)
+                .map_err(|e| Error::Config(format!("invalid ACME_ISSUER_CACERT: {e}")))?;
+            builder = builder.add_root_certificate(cert);
+        }
+        let http = builder
+            .build()
+            .map_err(|e| Error::Config(format!("failed to build ACME client: {e}")))?;
+
+        let rng = SystemRandom::new();
+        let pkcs8 = account_key(config.acme_issuer_account_key_file.as_deref())?;
+        let key = EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &pkcs8, &rng)
+            .map_err(|e| {
+                Error::Config(format!(
+                    "ACME_ISSUER_ACCOUNT_KEY_FILE must hold an ECDSA P-256 key: {e}"
+                ))
+            })?;
+
+        // Uncompressed SEC1 point: 0x04 || x || y.
+        let point = key.public_key().as_ref();
+        let jwk = Jwk {
+            kty: "EC".into(),
+            crv: Some("P-256".into()),
+            x: Some(jws::encode(&point[1..33])),
+            y: Some(jws::encode(&point[33..65])),
+            n: None,
+            e: None,
+        };
+        let thumbprint = jwk.thumbprint()?;
+
+        Ok(Self {
+            http,
+            directory_url: config.acme_issuer_directory.clone(),
+            contact: config
+                .acme_issuer_email
+                .as_ref()
+                .map(|e| format!("mailto:{e}")),
+            key,
+            rng,
+            jwk,
+            thumbprint,
+            directory: OnceCell::new(),
+            account: OnceCell::new(),
+            nonce: Mutex::new(None),
+            alpn_challenges,
+        })
+    }
+
+    /// Register the account key with the CA, agreeing to its terms of
+    /// service, or look up the account it already belongs to.
+    pub async fn register(&self) -> Result<()> {
+        self.account
+            .get_or_try_init(|| async {
+                let directory = self.directory().await?;
+                let mut payload = json!({ "termsOfServiceAgreed": true });
+                if let Some(ref contact) = self.contact {
+                    payload["contact"] = json!([contact]);
+                }
+                let response = self.post(&directory.new_account, Some(&payload)).await?;
+                let created = response.status() == reqwest::StatusCode::CREATED;
+                let account = location(&response)?;
+                info!(account = %account, created, "ACME account registered");
+                Ok::<_, Error>(account)
+            })
+            .await?;
+        Ok(())
+    }
+
+    /// Order a certificate for the common name and DNS alt names in
+    /// `names`, answering each authorization with `ACME_ISSUER_CHALLENGE`,
+    /// and give up after `ACME_ISSUER_TIMEOUT`.
+    #[instrument(name = "acme_order", skip_all, fields(common_name = %names.common_name))]
+    pub async fn order(&self, config: &Config, names: &CertNames) -> Result<CertBundle> {
+        let deadline = Instant::now() + config.acme_issuer_timeout;
+        self.register().await?;
+        let directory = self.directory().await?;
+
+        let domains = domains(names);
+        let identifiers: Vec<Value> = domains
+            .iter()
+            .map(|d| json!({ "type": "dns", "value": d }))
+            .collect();
+        let response = self
+            .post(
+                &directory.new_order,
+                Some(&json!({ "identifiers": identifiers })),
+            )
+            .await?;
+        let order_url = location(&response)?;
+        let order: Order = response.json().await?;
+        info!(order = %order_url, domains = %domains.join(","), "ACME order created");
+
+        let responder = match config.acme_issuer_challenge {
+            AcmeChallenge::Http01 => {
+                Some(Http01Responder::start(config.acme_issuer_http_addr).await?)
+            }
+            _ => None,
+        };
+        for url in &order.authorizations {
+            self.authorize(config, url, responder.as_ref(), deadline)
+                .await?;
+        }
+        drop(responder);
+
+        // ACME CAs want every name in the SANs, the common name included.
+        let mut key_names = names.clone();
+        key_names.alt_names = Some(domains.join(","));
+        let key = keygen::generate(config, &key_names)?;
+
+        let order = self.wait_for_order(&order_url, "ready", deadline).await?;
+        if order.status == "ready" {
+            let csr = json!({ "csr": jws::encode(&key.csr_der) });
+            self.post(&order.finalize, Some(&csr)).await?;
+        }
+        let order = self.wait_for_order(&order_url, "valid", deadline).await?;
+        let certificate_url = order
+            .certificate
+            .ok_or_else(|| Error::AcmeOrder("valid order has no certificate URL".into()))?;
+        let chain = self.post(&certificate_url, None).await?.text().await?;
+
+        let leaf = parse_pem_chain(&chain).and_then(|c| CertInfo::from_der(&c[0]))?;
+        let lease = (leaf.not_after - OffsetDateTime::now_utc())
+            .whole_seconds()
+            .max(0) as u64;
+        let (certificate, ca_certificate) = split_leaf(&chain);
+        info!(serial = %leaf.serial, not_after = %leaf.not_after, "ACME certificate issued");
+
+        Ok(CertBundle {
+            certificate: format!("{certificate}{ca_certificate}"),
+            private_key: key.private_key_pem,
+            ca_certificate,
+            lease_duration_secs: lease,
+            request_id: Some(order_url),
+        })
+    }
+
+    /// Prove control of one authorization's domain, unless it is already
+    /// valid.
+    async fn authorize(
+        &self,
+        config: &Config,
+        url: &str,
+        responder: Option<&Http01Responder>,
+        deadline: Instant,
+    ) -> Result<()> {
+        let authz: Authorization = self.post(url, None).await?.json().await?;
+        let domain = authz.identifier.value.as_str();
+        if authz.status == "valid" {
+            debug!(domain, "ACME authorization already valid");
+            return Ok(());
+        }
+
+        let kind = config.acme_issuer_challenge.name();
+        let challenge = authz
+            .challenges
+            .iter()
+            .find(|c| c.kind == kind)
+            .ok_or_else(|| {
+                Error::AcmeOrder(format!("the CA offers no {kind} challenge for {domain}"))
+            })?;
+        let token = challenge.token.as_deref().ok_or_else(|| {
+            Error::AcmeOrder(format!("{kind} challenge for {domain} has no token"))
+        })?;
+        let key_authorization = format!("{token}.{}", self.thumbprint);
+
+        match config.acme_issuer_challenge {
+            AcmeChallenge::Http01 => {
+                if let Some(responder) = responder {
+                    responder.insert(token, &key_authorization);
+                }
+            }
+            AcmeChallenge::TlsAlpn01 => {
+                if let Some(ref challenges) = self.alpn_challenges {
+                    challenges.insert(
+                        domain,
+                        challenge::tls_alpn_cert(domain, &key_authorization)?,
+                    );
+                }
+            }
+            AcmeChallenge::Dns01 => {
+                let value = challenge::dns_value(&key_authorization);
+                challenge::run_dns_hook(config, "present", domain, &value).await?;
+                // Give the record time to reach every authoritative server.
+                sleep(config.acme_issuer_dns_delay).await;
+            }
+        }
+
+        let result = self.validate(&challenge.url, url, domain, deadline).await;
+
+        match config.acme_issuer_challenge {
+            AcmeChallenge::Http01 => {
+                if let Some(responder) = responder {
+                    responder.remove(token);
+                }
+            }
+            AcmeChallenge::TlsAlpn01 => {
+                if let Some(ref challenges) = self.alpn_challenges {
+                    challenges.remove(domain);
+                }
+            }
+            AcmeChallenge::Dns01 => {
+                let value = challenge::dns_value(&key_authorization);
+                if let Err(e) = challenge::run_dns_hook(config, "cleanup", domain, &value).await {
+                    warn!(error = %e, code = e.code(), domain, "failed to remove ACME DNS record");
+                }
+            }
+        }
+
+        result
+    }
+
+    /// Tell the CA the challenge is ready and wait for its verdict.
+    async fn validate(
+        &self,
+        challenge_url: &str,
+        authz_url: &str,
+        domain: &str,
+        deadline: Instant,
+    ) -> Result<()> {
+        self.post(challenge_url, Some(&json!({}))).await?;
+        loop {
+            let response = self.post(authz_url, None).await?;
+            let delay = retry_after(&response);
+            let authz: Authorization = response.json().await?;
+            match authz.status.as_str() {
+                "valid" => {
+                    info!(domain, "ACME authorization valid");
+                    return Ok(());
+                }
+                "pending" => {}
+                status => {
+                    let problem = authz
+                        .challenges
+                        .into_iter()
+                        .find_map(|c| c.error)
+                        .unwrap_or_default();
+                    return Err(Error::AcmeOrder(format!(
+                        "authorization for {domain} is {status}: {problem}"
+                    )));
+                }
+            }
+            pause(delay, deadline, "authorization").await?;
+        }
+    }
+
+    /// Poll the order until it reaches `wanted` (or `valid`), failing if it
+    /// becomes invalid.
+    async fn wait_for_order(&self, url: &str, wanted: &str, deadline: Instant) -> Result<Order> {
+        loop {
+            let response = self.post(url, None).await?;
+            let delay = retry_after(&response);
+            let order: Order = response.json().await?;
+            match order.status.as_str() {
+                "invalid" => {
+                    return Err(Error::AcmeOrder(format!(
+                        "order is invalid: {}",
+                        order.error.unwrap_or_default()
+                    )))
+                }
+                status if status == wanted || status == "valid" => return Ok(order),
+                _ => {}
+            }
+            pause(delay, deadline, "order").await?;
+        }
+    }
+
+    async fn directory(&self) -> Result<&Directory> {
+        self.directory
+            .get_or_try_init(|| async {
+                let response = self
+                    .http
+                    .get(&self.directory_url)
+                    .send()
+                    .await?
+                    .error_for_status()?;
+                Ok::<_, Error>(response.json::<Directory>().await?)
+            })
+            .await
+    }
+
+    /// Send a signed POST (or POST-as-GET when `payload` is `None`),
+    /// retrying with a fresh nonce if the CA rejects the one used.
+    async fn post(&self, url: &str, payload: Option<&Value>) -> Result<Response> {
+        let mut attempt = 0;
+        loop {
+            let nonce = self.nonce().await?;
+            let body = self.sign(url, &nonce, payload)?;
+            let response = self
+                .http
+                .post(url)
+                .header(CONTENT_TYPE, "application/jose+json")
+                .body(body)
+                .send()
+                .await?;
+            self.keep_nonce(&response);
+            if response.status().is_success() {
+                return Ok(response);
+            }
+
+            let status = response.status();
+            let problem: Problem = response.json().await.unwrap_or_default();
+            if problem.kind == "urn:ietf:params:acme:error:badNonce" && attempt < NONCE_RETRIES {
+                attempt += 1;
+                debug!(url, attempt, "ACME nonce rejected, retrying");
+                continue;
+            }
+            return Err(Error::AcmeOrder(format!(
+                "{url} returned {status}: {problem}"
+            )));
+        }
+    }
+
+    /// A flattened JWS over `payload`, identified by the account URL once
+    /// there is one and by the public key before.
+    fn sign(&self, url: &str, nonce: &str, payload: Option<&Value>) -> Result<String> {
+        let mut protected = json!({ "alg": "ES256", "nonce": nonce, "url": url });
+        match self.account.get() {
+            Some(kid) => protected["kid"] = json!(kid),
+            None => protected["jwk"] = serde_json::to_value(&self.jwk)?,
+        }
+        let protected = jws::encode(protected.to_string().as_bytes());
+        let payload = payload
+            .map(|p| jws::encode(p.to_string().as_bytes()))
+            .unwrap_or_default();
+        let signature = self
+            .key
+            .sign(&self.rng, format!("{protected}.{payload}").as_bytes())
+            .map_err(|_| Error::AcmeOrder("failed to sign ACME request".into()))?;
+
+        Ok(json!({
+            "protected": protected,
+            "payload": payload,
+            "signature": jws::encode(signature.as_ref()),
+        })
+        .to_string())
+    }
+
+    /// The nonce from the last response, or a new one.
+    async fn nonce(&self) -> Result<String> {
+        if let Some(nonce) = self.nonce.lock().unwrap_or_else(|e| e.into_inner()).take() {
+            return Ok(nonce);
+        }
+        let directory = self.directory().await?;
+        let response = self
+            .http
+            .head(&directory.new_nonce)
+            .send()
+            .await?
+            .error_for_status()?;
+        replay_nonce(&response)
+            .ok_or_else(|| Error::AcmeOrder("newNonce returned no Replay-Nonce".into()))
+    }
+
+    fn keep_nonce(&self, response: &Response) {
+        if let Some(nonce) = replay_nonce(response) {
+            *self.nonce.lock().unwrap_or_else(|e| e.into_inner()) = Some(nonce);
+        }
+    }
+}
+
+/// The account key's PKCS#8 encoding: read from `path`, or generated and
+/// saved there (owner-only) if the file does not exist yet. Without a
+/// path, a new key every time.
+fn account_key(path: Option<&str>) -> Result<Vec<u8>> {
+    let Some(path) = path else {
+        return generate_account_key().map(|k| k.serialized_der().to_vec());
+    };
+
+    match fs::read_to_string(path) {
+        Ok(pem) => {
+            let key = KeyPair::from_pem(&pem).map_err(|e| {
+                Error::Config(format!(
+                    "invalid ACME_ISSUER_ACCOUNT_KEY_FILE '{path}': {e}"
+                ))
+            })?;
+            Ok(key.serialized_der().to_vec())
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            let key = generate_account_key()?;
+            let write = || {
+                let mut file = fs::OpenOptions::new()
+                    .write(true)
+                    .create_new(true)
+                    .mode(0o600)
+                    .open(path)?;
+                file.write_all(key.serialize_pem().as_bytes())?;
+                file.sync_all()
+            };
+            write().map_err(|e| {
+                Error::Config(format!(
+                    "failed to write ACME_ISSUER_ACCOUNT_KEY_FILE '{path}': {e}"
+                ))
+            })?;
+            info!(path, "created ACME account key");
+            Ok(key.serialized_der().to_vec())
+        }
+        Err(e) => Err(Error::Config(format!(
+            "failed to read ACME_ISSUER_ACCOUNT_KEY_FILE '{path}': {e}"
+        ))),
+    }
+}
+
+fn generate_account_key() -> Result<KeyPair> {
+    KeyPair::generate_for(&PKCS_ECDSA_P256_SHA256)
+        .map_err(|e| Error::KeyGen(format!("failed to generate ACME account key: {e}")))
+}
+
+/// The common name and DNS alt names, each once.
+fn domains(names: &CertNames) -> Vec<String> {
+    let alt_names = names.alt_names.as_deref().unwrap_or_default().split(',');
+    let mut domains: Vec<String> = Vec::new();
+    for name in std::iter::once(names.common_name.as_str()).chain(alt_names) {
+        let name = name.trim().to_ascii_lowercase();
+        if !name.is_empty() && !domains.contains(&name) {
+            domains.push(name);
+        }
+    }
+    domains
+}
+
+fn location(response: &Response) -> Result<String> {
+    response
+        .headers()
+        .get(LOCATION)
+        .and_then(|v| v.to_str().ok())
+        .map(String::from)
+        .ok_or_else(|| Error::AcmeOrder(format!("{} returned no Location", response.url())))
+}
+
+fn replay_nonce(response: &Response) -> Option<String> {
+    response
+        .headers()
+        .get("replay-nonce")
+        .and_then(|v| v.to_str().ok())
+        .map(String::from)
+}
+
+/// The CA's `Retry-After` in seconds, if it sent one.
+fn retry_after(response: &Response) -> Option<Duration> {
+    response
+        .headers()
+        .get(RETRY_AFTER)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.trim().parse().ok())
+        .map(Duration::from_secs)
+}
+
+/// Sleep before polling `what` again, or fail if that would pass the
+/// deadline.
+async fn pause(delay: Option<Duration>, deadline: Instant, what: &str) -> Result<()> {
+    let delay = delay.unwrap_or(POLL_INTERVAL);
+    if Instant::now() + delay > deadline {
+        return Err(Error::AcmeOrder(format!(
+            "timed out waiting for the {what}, see ACME_ISSUER_TIMEOUT"
+        )));
+    }
+    sleep(delay).await;
+    Ok(())
+}
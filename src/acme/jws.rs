@@ -0,0 +1,137 @@
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use ring::{digest, signature};
+use serde::{Deserialize, Serialize};
+
+use crate::error::{Error, Result};
+
+/// A JWS in the flattened JSON serialization used by every ACME POST.
+#[derive(Debug, Deserialize)]
+pub struct Jws {
+    pub protected: String,
+    pub payload: String,
+    pub signature: String,
+}
+
+/// The protected header of an ACME request (RFC 8555 section 6.2).
+#[derive(Debug, Deserialize)]
+pub struct ProtectedHeader {
+    pub alg: String,
+    pub nonce: String,
+    pub url: String,
+    pub jwk: Option<Jwk>,
+    pub kid: Option<String>,
+}
+
+/// An account public key. Only the members needed for EC and RSA keys are
+/// kept.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Jwk {
+    pub kty: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub crv: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub x: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub y: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub n: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub e: Option<String>,
+}
+
+impl Jws {
+    pub fn header(&self) -> Result<ProtectedHeader> {
+        Ok(serde_json::from_slice(&decode(&self.protected)?)?)
+    }
+
+    /// The decoded payload; empty for POST-as-GET requests.
+    pub fn payload(&self) -> Result<Vec<u8>> {
+        decode(&self.payload)
+    }
+
+    /// Check the signature against `jwk` using the algorithm in the header.
+    pub fn verify(&self, alg: &str, jwk: &Jwk) -> Result<()> {
+        let message = format!("{}.{}", self.protected, self.payload);
+        let sig = decode(&self.signature)?;
+
+        let result = match (alg, jwk.kty.as_str(), jwk.crv.as_deref()) {
+            ("ES256", "EC", Some("P-256")) => signature::UnparsedPublicKey::new(
+                &signature::ECDSA_P256_SHA256_FIXED,
+                jwk.ec_point()?,
+            )
+            .verify(message.as_bytes(), &sig),
+            ("ES384", "EC", Some("P-384")) => signature::UnparsedPublicKey::new(
+                &signature::ECDSA_P384_SHA384_FIXED,
+                jwk.ec_point()?,
+            )
+            .verify(message.as_bytes(), &sig),
+            ("RS256", "RSA", _) => signature::RsaPublicKeyComponents {
+                n: decode(member(&jwk.n, "n")?)?,
+                e: decode(member(&jwk.e, "e")?)?,
+            }
+            .verify(
+                &signature::RSA_PKCS1_2048_8192_SHA256,
+                message.as_bytes(),
+                &sig,
+            ),
+            _ => {
+                return Err(Error::Acme(format!(
+                    "unsupported signature algorithm {alg} for {} key",
+                    jwk.kty
+                )))
+            }
+        };
+
+        result.map_err(|_| Error::Acme("JWS signature verification failed".into()))
+    }
+}
+
+impl Jwk {
+    /// RFC 7638 thumbprint: SHA-256 over the required members in
+    /// lexicographic order, base64url-encoded.
+    pub fn thumbprint(&self) -> Result<String> {
+        let canonical = match self.kty.as_str() {
+            "EC" => format!(
+                r#"{{"crv":"{}","kty":"EC","x":"{}","y":"{}"}}"#,
+                member(&self.crv, "crv")?,
+                member(&self.x, "x")?,
+                member(&self.y, "y")?
+            ),
+            "RSA" => format!(
+                r#"{{"e":"{}","kty":"RSA","n":"{}"}}"#,
+                member(&self.e, "e")?,
+                member(&self.n, "n")?
+            ),
+            other => return Err(Error::Acme(format!("unsupported key type {other}"))),
+        };
+
+        Ok(encode(
+            digest::digest(&digest::SHA256, canonical.as_bytes()).as_ref(),
+        ))
+    }
+
+    /// Uncompressed SEC1 point (`0x04 || x || y`) as expected by ring.
+    fn ec_point(&self) -> Result<Vec<u8>> {
+        let mut point = vec![0x04];
+        point.extend(decode(member(&self.x, "x")?)?);
+        point.extend(decode(member(&self.y, "y")?)?);
+        Ok(point)
+    }
+}
+
+pub fn encode(bytes: &[u8]) -> String {
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+pub fn decode(value: &str) -> Result<Vec<u8>> {
+    URL_SAFE_NO_PAD
+        .decode(value)
+        .map_err(|e| Error::Acme(format!("invalid base64url: {e}")))
+}
+
+fn member<'a>(value: &'a Option<String>, name: &str) -> Result<&'a str> {
+    value
+        .as_deref()
+        .ok_or_else(|| Error::Acme(format!("JWK is missing member '{name}'")))
+}
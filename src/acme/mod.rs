@@ -0,0 +1,4 @@
+pub mod challenge;
+pub mod client;
+pub mod jws;
+pub mod server;
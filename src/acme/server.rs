@@ -0,0 +1,866 @@
+use std::collections::{HashMap, HashSet};
+use std::convert::Infallible;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+use bytes::Bytes;
+use http_body_util::{BodyExt, Full, Limited};
+use hyper::body::Incoming;
+use hyper::header::{HeaderValue, CACHE_CONTROL, CONTENT_TYPE, HOST, LINK, LOCATION};
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper::{Method, Request, Response, StatusCode};
+use hyper_util::rt::TokioIo;
+use ring::rand::{SecureRandom, SystemRandom};
+use rustls::ServerConfig;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use time::OffsetDateTime;
+use tokio::net::TcpListener;
+use tokio::sync::watch;
+use tokio_rustls::TlsAcceptor;
+use tracing::{debug, error, info, warn};
+use x509_parser::extensions::{GeneralName, ParsedExtension};
+use x509_parser::prelude::{FromDer, X509CertificationRequest};
+
+use crate::acme::jws::{self, Jwk, Jws, ProtectedHeader};
+use crate::cert::info::{format_time, pem_encode};
+use crate::config::Config;
+use crate::dns::Resolver;
+use crate::error::{Error, Result};
+use crate::vault::auth;
+use crate::vault::client::VaultClient;
+use crate::vault::pki;
+
+const MAX_BODY_BYTES: usize = 64 * 1024;
+/// Outstanding nonces are forgotten past this count; clients retry on
+/// `badNonce`, so this only bounds memory.
+const MAX_NONCES: usize = 10_000;
+const OBJECT_LIFETIME: Duration = Duration::from_secs(24 * 3600);
+/// How long the Vault token must still last for a finalize to sign with it.
+const TOKEN_VALIDITY: Duration = Duration::from_secs(60);
+
+/// An ACME (RFC 8555) server that issues certificates through Vault PKI.
+///
+/// Accounts, orders, and issued certificates are kept in memory only; a
+/// restart simply makes clients register again. Only `dns` identifiers and
+/// the `http-01` challenge are supported.
+pub struct AcmeServer {
+    client: Arc<VaultClient>,
+    config: Config,
+    http: reqwest::Client,
+    rng: SystemRandom,
+    /// Kept apart from `state` so responses can be built while it is locked.
+    nonces: Mutex<HashSet<String>>,
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    accounts: HashMap<String, Account>,
+    orders: HashMap<String, Order>,
+    authzs: HashMap<String, Authz>,
+    certs: HashMap<String, String>,
+}
+
+struct Account {
+    jwk: Jwk,
+    thumbprint: String,
+    contact: Vec<String>,
+}
+
+struct Order {
+    account: String,
+    identifiers: Vec<Identifier>,
+    authzs: Vec<String>,
+    status: &'static str,
+    expires: OffsetDateTime,
+    cert: Option<String>,
+}
+
+struct Authz {
+    account: String,
+    identifier: Identifier,
+    token: String,
+    status: &'static str,
+    validated: Option<OffsetDateTime>,
+    expires: OffsetDateTime,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+struct Identifier {
+    #[serde(rename = "type")]
+    kind: String,
+    value: String,
+}
+
+/// An RFC 7807 problem document returned to the client.
+struct Problem {
+    status: StatusCode,
+    kind: &'static str,
+    detail: String,
+}
+
+impl Problem {
+    fn new(status: StatusCode, kind: &'static str, detail: impl Into<String>) -> Self {
+        Self {
+            status,
+            kind,
+            detail: detail.into(),
+        }
+    }
+
+    fn malformed(detail: impl Into<String>) -> Self {
+        Self::new(StatusCode::BAD_REQUEST, "malformed", detail)
+    }
+
+    fn not_found() -> Self {
+        Self::new(StatusCode::NOT_FOUND, "malformed", "no such resource")
+    }
+}
+
+impl From<Error> for Problem {
+    fn from(e: Error) -> Self {
+        Problem::malformed(format!("[{}] {e}", e.code()))
+    }
+}
+
+type Reply = std::result::Result<Response<Full<Bytes>>, Problem>;
+
+/// Who signed an authenticated request.
+enum Signer {
+    NewKey(Jwk),
+    Account(String),
+}
+
+impl AcmeServer {
+    pub fn new(client: Arc<VaultClient>, config: Config, resolver: Arc<Resolver>) -> Result<Self> {
+        let http = reqwest::Client::builder()
+            .dns_resolver(resolver)
+            .timeout(Duration::from_secs(10))
+            .redirect(reqwest::redirect::Policy::limited(10))
+            .build()
+            .map_err(|e| Error::Config(format!("failed to build ACME validation client: {e}")))?;
+
+        Ok(Self {
+            client,
+            config,
+            http,
+            rng: SystemRandom::new(),
+            nonces: Mutex::new(HashSet::new()),
+            state: Mutex::new(State::default()),
+        })
+    }
+
+    async fn handle(&self, req: Request<Incoming>) -> Response<Full<Bytes>> {
+        let base = match req.headers().get(HOST).and_then(|h| h.to_str().ok()) {
+            Some(host) => format!("https://{host}"),
+            None => return self.problem(Problem::malformed("missing Host header")),
+        };
+        let method = req.method().clone();
+        let path = req.uri().path().to_string();
+
+        let reply = match (&method, path.as_str()) {
+            (&Method::GET, "/directory") => Ok(self.directory(&base)),
+            (&Method::HEAD, "/new-nonce") => Ok(self.empty(StatusCode::OK)),
+            (&Method::GET, "/new-nonce") => Ok(self.empty(StatusCode::NO_CONTENT)),
+            (&Method::POST, _) => self.handle_post(req, &base, &path).await,
+            _ => Err(Problem::new(
+                StatusCode::METHOD_NOT_ALLOWED,
+                "malformed",
+                format!("{method} {path} is not supported"),
+            )),
+        };
+
+        let mut response = reply.unwrap_or_else(|p| self.problem(p));
+        let headers = response.headers_mut();
+        headers.insert(CACHE_CONTROL, HeaderValue::from_static("no-store"));
+        if let Ok(link) = HeaderValue::from_str(&format!("<{base}/directory>;rel=\"index\"")) {
+            headers.append(LINK, link);
+        }
+        response
+    }
+
+    async fn handle_post(&self, req: Request<Incoming>, base: &str, path: &str) -> Reply {
+        let body = Limited::new(req.into_body(), MAX_BODY_BYTES)
+            .collect()
+            .await
+            .map_err(|e| Problem::malformed(format!("failed to read request body: {e}")))?
+            .to_bytes();
+        let jws: Jws = serde_json::from_slice(&body)
+            .map_err(|e| Problem::malformed(format!("request body is not a JWS: {e}")))?;
+        let url = format!("{base}{path}");
+        let signer = self.authenticate(&jws, &url, base, path == "/new-account")?;
+        let payload = jws.payload()?;
+
+        let segments: Vec<&str> = path.trim_start_matches('/').split('/').collect();
+        match (signer, segments.as_slice()) {
+            (Signer::NewKey(jwk), ["new-account"]) => self.new_account(base, jwk, &payload),
+            (Signer::Account(acct), ["new-order"]) => self.new_order(base, &acct, &payload),
+            (Signer::Account(acct), ["acct", id]) if *id == acct => self.account(base, &acct),
+            (Signer::Account(acct), ["authz", id]) => self.authz(base, &acct, id),
+            (Signer::Account(acct), ["chall", id]) => self.challenge(base, &acct, id).await,
+            (Signer::Account(acct), ["order", id]) => self.order(base, &acct, id),
+            (Signer::Account(acct), ["order", id, "finalize"]) => {
+                self.finalize(base, &acct, id, &payload).await
+            }
+            (Signer::Account(acct), ["cert", id]) => self.certificate(&acct, id),
+            _ => Err(Problem::not_found()),
+        }
+    }
+
+    /// Check the nonce, URL, and signature of a request and identify the
+    /// signer. Only `new-account` may be signed with an embedded JWK.
+    fn authenticate(
+        &self,
+        jws: &Jws,
+        url: &str,
+        base: &str,
+        new_account: bool,
+    ) -> std::result::Result<Signer, Problem> {
+        let header: ProtectedHeader = jws.header()?;
+
+        if !self.nonces().remove(&header.nonce) {
+            return Err(Problem::new(
+                StatusCode::BAD_REQUEST,
+                "badNonce",
+                "nonce is invalid or already used",
+            ));
+        }
+        if header.url != url {
+            return Err(Problem::new(
+                StatusCode::UNAUTHORIZED,
+                "unauthorized",
+                format!("JWS url {} does not match request url {url}", header.url),
+            ));
+        }
+
+        match (new_account, header.jwk, header.kid) {
+            (true, Some(jwk), None) => {
+                jws.verify(&header.alg, &jwk).map_err(unauthorized)?;
+                Ok(Signer::NewKey(jwk))
+            }
+            (false, None, Some(kid)) => {
+                let id = kid
+                    .strip_prefix(&format!("{base}/acct/"))
+                    .ok_or_else(|| {
+                        Problem::new(
+                            StatusCode::UNAUTHORIZED,
+                            "accountDoesNotExist",
+                            "unknown kid",
+                        )
+                    })?
+                    .to_string();
+                let jwk = self
+                    .state()
+                    .accounts
+                    .get(&id)
+                    .map(|a| a.jwk.clone())
+                    .ok_or_else(|| {
+                        Problem::new(
+                            StatusCode::UNAUTHORIZED,
+                            "accountDoesNotExist",
+                            "unknown account",
+                        )
+                    })?;
+                jws.verify(&header.alg, &jwk).map_err(unauthorized)?;
+                Ok(Signer::Account(id))
+            }
+            _ => Err(Problem::malformed(
+                "exactly one of jwk (new-account) or kid (all other requests) is required",
+            )),
+        }
+    }
+
+    fn directory(&self, base: &str) -> Response<Full<Bytes>> {
+        self.json(
+            StatusCode::OK,
+            &json!({
+                "newNonce": format!("{base}/new-nonce"),
+                "newAccount": format!("{base}/new-account"),
+                "newOrder": format!("{base}/new-order"),
+                "meta": { "externalAccountRequired": false },
+            }),
+        )
+    }
+
+    fn new_account(&self, base: &str, jwk: Jwk, payload: &[u8]) -> Reply {
+        #[derive(Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        struct NewAccount {
+            #[serde(default)]
+            contact: Vec<String>,
+            #[serde(default)]
+            only_return_existing: bool,
+        }
+
+        let req: NewAccount = parse_payload(payload)?;
+        let thumbprint = jwk.thumbprint()?;
+        let mut state = self.state();
+
+        if let Some((id, account)) = state
+            .accounts
+            .iter()
+            .find(|(_, a)| a.thumbprint == thumbprint)
+        {
+            let body = account_json(account);
+            return Ok(self.located(StatusCode::OK, format!("{base}/acct/{id}"), &body));
+        }
+        if req.only_return_existing {
+            return Err(Problem::new(
+                StatusCode::BAD_REQUEST,
+                "accountDoesNotExist",
+                "no account for this key",
+            ));
+        }
+
+        let id = self.random_id();
+        let account = Account {
+            jwk,
+            thumbprint,
+            contact: req.contact,
+        };
+        let body = account_json(&account);
+        state.accounts.insert(id.clone(), account);
+        info!(account = %id, "ACME account registered");
+
+        Ok(self.located(StatusCode::CREATED, format!("{base}/acct/{id}"), &body))
+    }
+
+    fn account(&self, base: &str, id: &str) -> Reply {
+        let state = self.state();
+        let account = state.accounts.get(id).ok_or_else(Problem::not_found)?;
+        Ok(self.located(
+            StatusCode::OK,
+            format!("{base}/acct/{id}"),
+            &account_json(account),
+        ))
+    }
+
+    fn new_order(&self, base: &str, account: &str, payload: &[u8]) -> Reply {
+        #[derive(Deserialize)]
+        struct NewOrder {
+            identifiers: Vec<Identifier>,
+        }
+
+        let req: NewOrder = parse_payload(payload)?;
+        if req.identifiers.is_empty() {
+            return Err(Problem::malformed("order has no identifiers"));
+        }
+        if let Some(id) = req.identifiers.iter().find(|i| i.kind != "dns") {
+            return Err(Problem::new(
+                StatusCode::BAD_REQUEST,
+                "unsupportedIdentifier",
+                format!("identifier type {} is not supported", id.kind),
+            ));
+        }
+
+        let now = OffsetDateTime::now_utc();
+        let expires = now + OBJECT_LIFETIME;
+        let validate = self.config.acme_validate_challenges;
+
+        let mut state = self.state();
+        state.prune(now);
+
+        let mut authz_ids = Vec::new();
+        for identifier in &req.identifiers {
+            let id = self.random_id();
+            state.authzs.insert(
+                id.clone(),
+                Authz {
+                    account: account.to_string(),
+                    identifier: identifier.clone(),
+                    token: self.random_id(),
+                    status: if validate { "pending" } else { "valid" },
+                    validated: (!validate).then_some(now),
+                    expires,
+                },
+            );
+            authz_ids.push(id);
+        }
+
+        let order_id = self.random_id();
+        let order = Order {
+            account: account.to_string(),
+            identifiers: req.identifiers,
+            authzs: authz_ids,
+            status: if validate { "pending" } else { "ready" },
+            expires,
+            cert: None,
+        };
+        let body = order_json(base, &order_id, &order);
+        state.orders.insert(order_id.clone(), order);
+        debug!(account = %account, order = %order_id, "ACME order created");
+
+        Ok(self.located(
+            StatusCode::CREATED,
+            format!("{base}/order/{order_id}"),
+            &body,
+        ))
+    }
+
+    fn order(&self, base: &str, account: &str, id: &str) -> Reply {
+        let state = self.state();
+        let order = state
+            .orders
+            .get(id)
+            .filter(|o| o.account == account)
+            .ok_or_else(Problem::not_found)?;
+        Ok(self.located(
+            StatusCode::OK,
+            format!("{base}/order/{id}"),
+            &order_json(base, id, order),
+        ))
+    }
+
+    fn authz(&self, base: &str, account: &str, id: &str) -> Reply {
+        let state = self.state();
+        let authz = state
+            .authzs
+            .get(id)
+            .filter(|a| a.account == account)
+            .ok_or_else(Problem::not_found)?;
+        Ok(self.json(StatusCode::OK, &authz_json(base, id, authz)))
+    }
+
+    /// Respond to a challenge by validating it over HTTP (`http-01`).
+    async fn challenge(&self, base: &str, account: &str, id: &str) -> Reply {
+        let (domain, token, thumbprint, pending) = {
+            let state = self.state();
+            let authz = state
+                .authzs
+                .get(id)
+                .filter(|a| a.account == account)
+                .ok_or_else(Problem::not_found)?;
+            let thumbprint = state
+                .accounts
+                .get(account)
+                .map(|a| a.thumbprint.clone())
+                .ok_or_else(Problem::not_found)?;
+            (
+                authz.identifier.value.clone(),
+                authz.token.clone(),
+                thumbprint,
+                authz.status == "pending",
+            )
+        };
+
+        if pending {
+            let key_authorization = format!("{token}.{thumbprint}");
+            let valid = match self.fetch_http01(&domain, &token).await {
+                Ok(body) if body.trim() == key_authorization => true,
+                Ok(_) => {
+                    warn!(domain = %domain, "ACME http-01 response did not match key authorization");
+                    false
+                }
+                Err(e) => {
+                    warn!(domain = %domain, error = %e, "ACME http-01 validation request failed");
+                    false
+                }
+            };
+
+            let mut state = self.state();
+            if let Some(authz) = state.authzs.get_mut(id) {
+                authz.status = if valid { "valid" } else { "invalid" };
+                authz.validated = valid.then(OffsetDateTime::now_utc);
+            }
+            state.refresh_orders();
+        }
+
+        let state = self.state();
+        let authz = state.authzs.get(id).ok_or_else(Problem::not_found)?;
+        let mut response = self.json(StatusCode::OK, &challenge_json(base, id, authz));
+        if let Ok(up) = HeaderValue::from_str(&format!("<{base}/authz/{id}>;rel=\"up\"")) {
+            response.headers_mut().append(LINK, up);
+        }
+        Ok(response)
+    }
+
+    async fn fetch_http01(&self, domain: &str, token: &str) -> Result<String> {
+        let url = format!("http://{domain}/.well-known/acme-challenge/{token}");
+        let response = self.http.get(&url).send().await?.error_for_status()?;
+        Ok(response.text().await?)
+    }
+
+    /// Check the CSR against the order, have Vault sign it, and store the
+    /// resulting chain.
+    async fn finalize(&self, base: &str, account: &str, id: &str, payload: &[u8]) -> Reply {
+        #[derive(Deserialize)]
+        struct Finalize {
+            csr: String,
+        }
+
+        let req: Finalize = parse_payload(payload)?;
+        let csr_der = jws::decode(&req.csr)?;
+        let names = csr_names(&csr_der)
+            .map_err(|e| Problem::new(StatusCode::BAD_REQUEST, "badCSR", e.to_string()))?;
+
+        let identifiers = {
+            let mut state = self.state();
+            let order = state
+                .orders
+                .get_mut(id)
+                .filter(|o| o.account == account)
+                .ok_or_else(Problem::not_found)?;
+            if order.status != "ready" {
+                return Err(Problem::new(
+                    StatusCode::FORBIDDEN,
+                    "orderNotReady",
+                    format!("order is {}", order.status),
+                ));
+            }
+
+            let wanted: HashSet<&str> =
+                order.identifiers.iter().map(|i| i.value.as_str()).collect();
+            let got: HashSet<&str> = names.iter().map(String::as_str).collect();
+            if wanted != got {
+                return Err(Problem::new(
+                    StatusCode::BAD_REQUEST,
+                    "badCSR",
+                    "CSR names do not match the order identifiers",
+                ));
+            }
+
+            order.status = "processing";
+            order
+                .identifiers
+                .iter()
+                .map(|i| i.value.clone())
+                .collect::<Vec<_>>()
+        };
+
+        let issued = self.sign(&csr_der, &identifiers).await;
+
+        let mut state = self.state();
+        match issued {
+            Ok(chain) => {
+                let cert_id = self.random_id();
+                state.certs.insert(cert_id.clone(), chain);
+                if let Some(order) = state.orders.get_mut(id) {
+                    order.status = "valid";
+                    order.cert = Some(cert_id);
+                }
+                info!(order = %id, names = ?identifiers, "ACME order finalized");
+            }
+            Err(e) => {
+                error!(order = %id, error = %e, code = e.code(), "ACME finalize failed");
+                if let Some(order) = state.orders.get_mut(id) {
+                    order.status = "invalid";
+                }
+                return Err(Problem::new(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "serverInternal",
+                    format!("[{}] {e}", e.code()),
+                ));
+            }
+        }
+
+        let order = state.orders.get(id).ok_or_else(Problem::not_found)?;
+        Ok(self.located(
+            StatusCode::OK,
+            format!("{base}/order/{id}"),
+            &order_json(base, id, order),
+        ))
+    }
+
+    async fn sign(&self, csr_der: &[u8], names: &[String]) -> Result<String> {
+        // The token is shared with the renewal loop, so it is only renewed,
+        // or replaced, once it is about to run out; a client finalizing
+        // many orders does not log in for each of them.
+        let expiring = self
+            .client
+            .token_lease()
+            .await
+            .is_none_or(|lease| lease.remaining() < TOKEN_VALIDITY);
+        if expiring {
+            auth::refresh(&self.client, &self.config, TOKEN_VALIDITY).await?;
+        }
+
+        let bundle = pki::sign_csr(
+            &self.client,
+            &self.config.vault_pki_mount,
+            &self.config.acme_pki_role,
+            &pem_encode("CERTIFICATE REQUEST", csr_der),
+            &names[0],
+            &names[1..],
+            &self.config.acme_cert_ttl,
+        )
+        .await?;
+
+        Ok(format!("{}\n", bundle.certificate.trim()))
+    }
+
+    fn certificate(&self, account: &str, id: &str) -> Reply {
+        let state = self.state();
+        let owned = state
+            .orders
+            .values()
+            .any(|o| o.account == account && o.cert.as_deref() == Some(id));
+        let chain = state
+            .certs
+            .get(id)
+            .filter(|_| owned)
+            .ok_or_else(Problem::not_found)?;
+
+        let mut response = Response::new(Full::new(Bytes::from(chain.clone())));
+        response.headers_mut().insert(
+            CONTENT_TYPE,
+            HeaderValue::from_static("application/pem-certificate-chain"),
+        );
+        self.add_nonce(&mut response);
+        Ok(response)
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn nonces(&self) -> MutexGuard<'_, HashSet<String>> {
+        self.nonces.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn random_id(&self) -> String {
+        let mut buf = [0u8; 16];
+        // SystemRandom only fails if the OS RNG is unavailable.
+        self.rng.fill(&mut buf).expect("system RNG unavailable");
+        jws::encode(&buf)
+    }
+
+    fn add_nonce(&self, response: &mut Response<Full<Bytes>>) {
+        let nonce = self.random_id();
+        if let Ok(value) = HeaderValue::from_str(&nonce) {
+            response.headers_mut().insert("replay-nonce", value);
+            let mut nonces = self.nonces();
+            if nonces.len() >= MAX_NONCES {
+                nonces.clear();
+            }
+            nonces.insert(nonce);
+        }
+    }
+
+    fn empty(&self, status: StatusCode) -> Response<Full<Bytes>> {
+        let mut response = Response::new(Full::new(Bytes::new()));
+        *response.status_mut() = status;
+        self.add_nonce(&mut response);
+        response
+    }
+
+    fn json(&self, status: StatusCode, body: &serde_json::Value) -> Response<Full<Bytes>> {
+        let mut response = Response::new(Full::new(Bytes::from(body.to_string())));
+        *response.status_mut() = status;
+        response
+            .headers_mut()
+            .insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+        self.add_nonce(&mut response);
+        response
+    }
+
+    fn located(
+        &self,
+        status: StatusCode,
+        location: String,
+        body: &serde_json::Value,
+    ) -> Response<Full<Bytes>> {
+        let mut response = self.json(status, body);
+        if let Ok(value) = HeaderValue::from_str(&location) {
+            response.headers_mut().insert(LOCATION, value);
+        }
+        response
+    }
+
+    fn problem(&self, problem: Problem) -> Response<Full<Bytes>> {
+        debug!(kind = problem.kind, detail = %problem.detail, "ACME request rejected");
+        let body = json!({
+            "type": format!("urn:ietf:params:acme:error:{}", problem.kind),
+            "detail": problem.detail,
+        });
+        let mut response = self.json(problem.status, &body);
+        response.headers_mut().insert(
+            CONTENT_TYPE,
+            HeaderValue::from_static("application/problem+json"),
+        );
+        response
+    }
+}
+
+impl State {
+    /// Recompute order status after an authorization changed.
+    fn refresh_orders(&mut self) {
+        for order in self.orders.values_mut().filter(|o| o.status == "pending") {
+            let statuses: Vec<&str> = order
+                .authzs
+                .iter()
+                .filter_map(|id| self.authzs.get(id).map(|a| a.status))
+                .collect();
+            if statuses.contains(&"invalid") {
+                order.status = "invalid";
+            } else if statuses.iter().all(|s| *s == "valid") {
+                order.status = "ready";
+            }
+        }
+    }
+
+    /// Drop expired orders along with their authorizations and certificates.
+    fn prune(&mut self, now: OffsetDateTime) {
+        let expired: Vec<String> = self
+            .orders
+            .iter()
+            .filter(|(_, o)| o.expires < now)
+            .map(|(id, _)| id.clone())
+            .collect();
+        for id in expired {
+            if let Some(order) = self.orders.remove(&id) {
+                for authz in order.authzs {
+                    self.authzs.remove(&authz);
+                }
+                if let Some(cert) = order.cert {
+                    self.certs.remove(&cert);
+                }
+            }
+        }
+    }
+}
+
+/// Serve the ACME API over TLS using the managed certificate.
+pub async fn run(
+    server: Arc<AcmeServer>,
+    listener: TcpListener,
+    config_rx: watch::Receiver<Option<Arc<ServerConfig>>>,
+    mut shutdown: watch::Receiver<bool>,
+) -> Result<()> {
+    info!(addr = %listener.local_addr()?, "ACME server listening");
+
+    loop {
+        tokio::select! {
+            result = listener.accept() => {
+                let (tcp_stream, peer_addr) = match result {
+                    Ok(conn) => conn,
+                    Err(e) => {
+                        error!(error = %e, "failed to accept ACME connection");
+                        continue;
+                    }
+                };
+
+                let Some(mut tls_config) = config_rx.borrow().clone() else {
+                    continue;
+                };
+                // The shared config may offer h2 (ALPN_PROTOCOLS), which this
+                // server does not speak.
+                if !tls_config.alpn_protocols.is_empty() {
+                    Arc::make_mut(&mut tls_config).alpn_protocols = vec![b"http/1.1".to_vec()];
+                }
+                let acceptor = TlsAcceptor::from(tls_config);
+                let server = server.clone();
+
+                tokio::spawn(async move {
+                    let tls_stream = match acceptor.accept(tcp_stream).await {
+                        Ok(s) => s,
+                        Err(e) => {
+                            debug!(peer = %peer_addr, error = %e, "ACME TLS handshake failed");
+                            return;
+                        }
+                    };
+
+                    let service = service_fn(move |req| {
+                        let server = server.clone();
+                        async move { Ok::<_, Infallible>(server.handle(req).await) }
+                    });
+
+                    if let Err(e) = http1::Builder::new()
+                        .serve_connection(TokioIo::new(tls_stream), service)
+                        .await
+                    {
+                        debug!(peer = %peer_addr, error = %e, "ACME connection ended");
+                    }
+                });
+            }
+            _ = shutdown.changed() => {
+                info!("ACME server shutting down");
+                return Ok(());
+            }
+        }
+    }
+}
+
+fn parse_payload<T: for<'de> Deserialize<'de>>(payload: &[u8]) -> std::result::Result<T, Problem> {
+    serde_json::from_slice(payload).map_err(|e| Problem::malformed(format!("invalid payload: {e}")))
+}
+
+fn unauthorized(e: Error) -> Problem {
+    Problem::new(StatusCode::UNAUTHORIZED, "unauthorized", e.to_string())
+}
+
+/// DNS names requested by a CSR: the subject CN plus any DNS SANs.
+fn csr_names(der: &[u8]) -> Result<Vec<String>> {
+    let (_, csr) = X509CertificationRequest::from_der(der)
+        .map_err(|e| Error::Acme(format!("failed to parse CSR: {e}")))?;
+
+    let mut names: Vec<String> = csr
+        .certification_request_info
+        .subject
+        .iter_common_name()
+        .filter_map(|cn| cn.as_str().ok().map(String::from))
+        .collect();
+
+    if let Some(extensions) = csr.requested_extensions() {
+        for ext in extensions {
+            if let ParsedExtension::SubjectAlternativeName(san) = ext {
+                for name in &san.general_names {
+                    if let GeneralName::DNSName(dns) = name {
+                        names.push(dns.to_string());
+                    }
+                }
+            }
+        }
+    }
+
+    names.sort();
+    names.dedup();
+    if names.is_empty() {
+        return Err(Error::Acme("CSR contains no DNS names".into()));
+    }
+    Ok(names)
+}
+
+fn account_json(account: &Account) -> serde_json::Value {
+    json!({
+        "status": "valid",
+        "contact": account.contact,
+    })
+}
+
+fn order_json(base: &str, id: &str, order: &Order) -> serde_json::Value {
+    let mut body = json!({
+        "status": order.status,
+        "expires": format_time(order.expires),
+        "identifiers": order.identifiers,
+        "authorizations": order.authzs.iter().map(|a| format!("{base}/authz/{a}")).collect::<Vec<_>>(),
+        "finalize": format!("{base}/order/{id}/finalize"),
+    });
+    if let Some(ref cert) = order.cert {
+        body["certificate"] = json!(format!("{base}/cert/{cert}"));
+    }
+    body
+}
+
+fn authz_json(base: &str, id: &str, authz: &Authz) -> serde_json::Value {
+    json!({
+        "status": authz.status,
+        "expires": format_time(authz.expires),
+        "identifier": authz.identifier,
+        "challenges": [challenge_json(base, id, authz)],
+    })
+}
+
+fn challenge_json(base: &str, id: &str, authz: &Authz) -> serde_json::Value {
+    let mut body = json!({
+        "type": "http-01",
+        "url": format!("{base}/chall/{id}"),
+        "token": authz.token,
+        "status": authz.status,
+    });
+    if let Some(validated) = authz.validated {
+        body["validated"] = json!(format_time(validated));
+    }
+    body
+}
@@ -0,0 +1,418 @@
+use std::convert::Infallible;
+use std::sync::Arc;
+
+use bytes::Bytes;
+use http_body_util::combinators::UnsyncBoxBody;
+use http_body_util::{BodyExt, Full, Limited};
+use hyper::body::Incoming;
+use hyper::header::{HeaderValue, CONTENT_TYPE};
+#[cfg(not(feature = "grpc-health"))]
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper::{Method, Request, Response, StatusCode};
+#[cfg(feature = "grpc-health")]
+use hyper::HeaderMap;
+#[cfg(feature = "grpc-health")]
+use hyper_util::rt::TokioExecutor;
+use hyper_util::rt::TokioIo;
+#[cfg(feature = "grpc-health")]
+use hyper_util::server::conn::auto;
+use serde_json::json;
+#[cfg(feature = "grpc-health")]
+use time::format_description::well_known::Rfc3339;
+#[cfg(feature = "grpc-health")]
+use time::OffsetDateTime;
+use tokio::net::TcpListener;
+use tokio::sync::{mpsc, oneshot, watch};
+use tracing::{debug, error, info};
+
+use cert_keeper::cert::manager::{CertStatus, ManagerCommand};
+use cert_keeper::error::{Error, Result};
+use cert_keeper::metrics;
+use cert_keeper::vault::client::VaultClient;
+
+use crate::logging::LogControl;
+#[cfg(feature = "grpc-health")]
+use crate::proxy::health::grpc;
+
+const MAX_BODY_BYTES: usize = 4 * 1024;
+
+/// The service name answered besides the empty one, which stands for the
+/// whole server.
+#[cfg(feature = "grpc-health")]
+const GRPC_SERVICE: &str = "cert-keeper";
+
+/// gRPC status codes sent in `grpc-status`.
+#[cfg(feature = "grpc-health")]
+const GRPC_OK: u16 = 0;
+#[cfg(feature = "grpc-health")]
+const GRPC_INVALID_ARGUMENT: u16 = 3;
+#[cfg(feature = "grpc-health")]
+const GRPC_NOT_FOUND: u16 = 5;
+#[cfg(feature = "grpc-health")]
+const GRPC_UNIMPLEMENTED: u16 = 12;
+
+type Body = UnsyncBoxBody<Bytes, Infallible>;
+
+/// Operator endpoints, served over plain HTTP on a loopback address:
+///
+/// - `GET /status`: every certificate's serial, expiry, and last renewal
+///   result, the Vault token TTL and circuit state, and the in-process
+///   counters, gauges, and histograms.
+/// - `GET /cert`: the served certificate's subject, SANs, issuer,
+///   validity, serial, and fingerprints; another one's with
+///   `?common_name=`.
+/// - `POST /renew`: re-issue every certificate now and wait for the result.
+/// - `PUT /log`: change the log filter or format, with the same
+///   `filter=`/`format=` lines as `LOG_CONTROL_FILE`.
+/// - `POST /grpc.health.v1.Health/Check`, over HTTP/2, with
+///   `ADMIN_GRPC_HEALTH`.
+pub struct AdminServer {
+    client: Arc<VaultClient>,
+    status: watch::Receiver<Vec<CertStatus>>,
+    commands: mpsc::Sender<ManagerCommand>,
+    log_control: Arc<LogControl>,
+    /// Whether the proxy is taking connections, for the gRPC health
+    /// service; `None` while it is not served.
+    #[cfg(feature = "grpc-health")]
+    grpc_health: Option<watch::Receiver<bool>>,
+}
+
+impl AdminServer {
+    pub fn new(
+        client: Arc<VaultClient>,
+        status: watch::Receiver<Vec<CertStatus>>,
+        commands: mpsc::Sender<ManagerCommand>,
+        log_control: Arc<LogControl>,
+    ) -> Self {
+        Self {
+            client,
+            status,
+            commands,
+            log_control,
+            #[cfg(feature = "grpc-health")]
+            grpc_health: None,
+        }
+    }
+
+    /// Also answer the gRPC health service, reporting `NOT_SERVING` while
+    /// `proxy_ready` is false.
+    #[cfg(feature = "grpc-health")]
+    pub fn with_grpc_health(mut self, proxy_ready: watch::Receiver<bool>) -> Self {
+        self.grpc_health = Some(proxy_ready);
+        self
+    }
+
+    async fn route(&self, req: Request<Incoming>) -> Response<Body> {
+        #[cfg(feature = "grpc-health")]
+        if let Some(proxy_ready) = &self.grpc_health {
+            if req.method() == Method::POST
+                && req.uri().path().starts_with("/grpc.health.v1.Health/")
+            {
+                return self.grpc_check(req, proxy_ready).await;
+            }
+        }
+        self.handle(req).await.map(BodyExt::boxed_unsync)
+    }
+
+    async fn handle(&self, req: Request<Incoming>) -> Response<Full<Bytes>> {
+        let method = req.method().clone();
+        let path = req.uri().path().to_string();
+
+        let reply = match (&method, path.as_str()) {
+            (&Method::GET, "/status") => Ok(self.status().await),
+            (&Method::GET, "/cert") => Ok(self.cert(req.uri().query())),
+            (&Method::POST, "/renew") => self.renew().await,
+            (&Method::PUT, "/log") => self.set_log(req).await,
+            (_, "/status" | "/cert" | "/renew" | "/log") => {
+                return error_response(
+                    StatusCode::METHOD_NOT_ALLOWED,
+                    &format!("{method} is not supported on {path}"),
+                    None,
+                );
+            }
+            _ => {
+                return error_response(
+                    StatusCode::NOT_FOUND,
+                    &format!("no such endpoint {path}"),
+                    None,
+                );
+            }
+        };
+
+        reply.unwrap_or_else(|e| {
+            let status = match e {
+                Error::Config(_) => StatusCode::BAD_REQUEST,
+                _ => StatusCode::INTERNAL_SERVER_ERROR,
+            };
+            error_response(status, &e.to_string(), Some(e.code()))
+        })
+    }
+
+    async fn status(&self) -> Response<Full<Bytes>> {
+        let vault_token = self.client.token_lease().await.map(|lease| {
+            json!({
+                "ttl_secs": lease.remaining().as_secs(),
+                "renewable": lease.renewable,
+            })
+        });
+        let metrics = metrics::snapshot();
+        let counters: Vec<_> = metrics
+            .counters
+            .into_iter()
+            .map(|(name, label, value)| json!({ "name": name, "label": label, "value": value }))
+            .collect();
+        let gauges: Vec<_> = metrics
+            .gauges
+            .into_iter()
+            .map(|(name, label, value)| json!({ "name": name, "label": label, "value": value }))
+            .collect();
+        let histograms: Vec<_> = metrics
+            .histograms
+            .into_iter()
+            .map(|(name, label, histogram)| {
+                json!({
+                    "name": name,
+                    "label": label,
+                    "bounds": histogram.bounds,
+                    "buckets": histogram.buckets,
+                    "sum": histogram.sum,
+                    "count": histogram.count,
+                })
+            })
+            .collect();
+
+        json_response(
+            StatusCode::OK,
+            &json!({
+                "certificates": *self.status.borrow(),
+                "vault_token": vault_token,
+                "vault_circuit": self.client.circuit_status(),
+                "counters": counters,
+                "gauges": gauges,
+                "histograms": histograms,
+            }),
+        )
+    }
+
+    /// The certificate served for `CERT_COMMON_NAME`, or with a
+    /// `common_name=` query the SNI or profile certificate of that name,
+    /// which may be percent-encoded, as `%2A.` for a wildcard. Never the
+    /// key.
+    fn cert(&self, query: Option<&str>) -> Response<Full<Bytes>> {
+        let common_name = query
+            .into_iter()
+            .flat_map(|query| form_urlencoded::parse(query.as_bytes()))
+            .find_map(|(key, value)| (key == "common_name").then_some(value));
+        let common_name = common_name.as_deref();
+        let statuses = self.status.borrow();
+        let status = match common_name {
+            Some(name) => statuses
+                .iter()
+                .find(|status| status.common_name.eq_ignore_ascii_case(name)),
+            None => statuses.first(),
+        };
+        let Some(status) = status else {
+            return error_response(
+                StatusCode::NOT_FOUND,
+                &format!("no certificate for {}", common_name.unwrap_or("-")),
+                None,
+            );
+        };
+        let Some(ref certificate) = status.certificate else {
+            return error_response(
+                StatusCode::SERVICE_UNAVAILABLE,
+                &format!("no certificate for {} has been issued yet", status.common_name),
+                None,
+            );
+        };
+
+        let mut body = json!(certificate);
+        body["common_name"] = json!(status.common_name);
+        body["profile"] = json!(status.profile);
+        body["bootstrap"] = json!(status.bootstrap);
+        json_response(StatusCode::OK, &body)
+    }
+
+    async fn renew(&self) -> Result<Response<Full<Bytes>>> {
+        let (reply_tx, reply_rx) = oneshot::channel();
+        if self.commands.send(ManagerCommand::Renew(reply_tx)).await.is_err() {
+            return Ok(stopped());
+        }
+        match reply_rx.await {
+            Ok(result) => result?,
+            Err(_) => return Ok(stopped()),
+        }
+
+        Ok(json_response(
+            StatusCode::OK,
+            &json!({ "certificates": *self.status.borrow() }),
+        ))
+    }
+
+    async fn set_log(&self, req: Request<Incoming>) -> Result<Response<Full<Bytes>>> {
+        let body = Limited::new(req.into_body(), MAX_BODY_BYTES)
+            .collect()
+            .await
+            .map_err(|e| Error::Config(format!("failed to read request body: {e}")))?
+            .to_bytes();
+        let settings = std::str::from_utf8(&body)
+            .map_err(|_| Error::Config("request body is not UTF-8".into()))?;
+        self.log_control.apply(settings)?;
+
+        let mut response = Response::new(Full::new(Bytes::new()));
+        *response.status_mut() = StatusCode::NO_CONTENT;
+        Ok(response)
+    }
+
+    /// `grpc.health.v1.Health/Check` for the whole server or `cert-keeper`:
+    /// `SERVING` while every served certificate has been issued and has not
+    /// expired, and the proxy, if any, is taking connections, which with
+    /// `BACKEND_HEALTH_CHECK` also means the backend is healthy. `Watch`
+    /// is not implemented.
+    #[cfg(feature = "grpc-health")]
+    async fn grpc_check(
+        &self,
+        req: Request<Incoming>,
+        proxy_ready: &watch::Receiver<bool>,
+    ) -> Response<Body> {
+        if req.uri().path() != "/grpc.health.v1.Health/Check" {
+            return grpc_error(GRPC_UNIMPLEMENTED, "only Check is implemented");
+        }
+        let body = match Limited::new(req.into_body(), MAX_BODY_BYTES).collect().await {
+            Ok(body) => body.to_bytes(),
+            Err(_) => return grpc_error(GRPC_INVALID_ARGUMENT, "failed to read the request"),
+        };
+        // A single uncompressed length-prefixed message.
+        let service = match body.get(..5) {
+            Some([0, len @ ..]) => {
+                let len = u32::from_be_bytes([len[0], len[1], len[2], len[3]]) as usize;
+                body.get(5..5 + len).and_then(grpc::decode_request)
+            }
+            _ => None,
+        };
+        let Some(service) = service else {
+            return grpc_error(GRPC_INVALID_ARGUMENT, "malformed HealthCheckRequest");
+        };
+        if !service.is_empty() && service != GRPC_SERVICE {
+            return grpc_error(GRPC_NOT_FOUND, "unknown service");
+        }
+
+        let serving = self.certificates_valid() && *proxy_ready.borrow();
+        let status = if serving {
+            grpc::GRPC_SERVING
+        } else {
+            grpc::GRPC_NOT_SERVING
+        };
+        let mut trailers = HeaderMap::new();
+        trailers.insert("grpc-status", HeaderValue::from(GRPC_OK));
+        let body = Full::new(Bytes::from(grpc::grpc_frame(&grpc::encode_response(status))))
+            .with_trailers(std::future::ready(Some(Ok(trailers))));
+        let mut response = Response::new(body.boxed_unsync());
+        response
+            .headers_mut()
+            .insert(CONTENT_TYPE, HeaderValue::from_static("application/grpc"));
+        response
+    }
+
+    /// Every served certificate has been issued, rather than being a
+    /// bootstrap one, and has not expired.
+    #[cfg(feature = "grpc-health")]
+    fn certificates_valid(&self) -> bool {
+        let now = OffsetDateTime::now_utc();
+        let certs = self.status.borrow();
+        let mut served = certs.iter().filter(|c| c.profile.is_none()).peekable();
+        served.peek().is_some()
+            && served.all(|c| {
+                !c.bootstrap
+                    && c.serial.is_some()
+                    && c.not_after
+                        .as_deref()
+                        .and_then(|t| OffsetDateTime::parse(t, &Rfc3339).ok())
+                        .is_some_and(|t| t > now)
+            })
+    }
+}
+
+/// Serve the admin API until shutdown.
+pub async fn run(
+    server: Arc<AdminServer>,
+    listener: TcpListener,
+    mut shutdown: watch::Receiver<bool>,
+) -> Result<()> {
+    info!(addr = %listener.local_addr()?, "admin API listening");
+
+    loop {
+        tokio::select! {
+            result = listener.accept() => {
+                let (tcp_stream, peer_addr) = match result {
+                    Ok(conn) => conn,
+                    Err(e) => {
+                        error!(error = %e, "failed to accept admin connection");
+                        continue;
+                    }
+                };
+
+                let server = server.clone();
+                tokio::spawn(async move {
+                    let service = service_fn(move |req| {
+                        let server = server.clone();
+                        async move { Ok::<_, Infallible>(server.route(req).await) }
+                    });
+
+                    // gRPC needs HTTP/2; clients use it without TLS from
+                    // the first byte, which the builder tells from HTTP/1.
+                    #[cfg(feature = "grpc-health")]
+                    let served = auto::Builder::new(TokioExecutor::new())
+                        .serve_connection(TokioIo::new(tcp_stream), service)
+                        .await;
+                    #[cfg(not(feature = "grpc-health"))]
+                    let served = http1::Builder::new()
+                        .serve_connection(TokioIo::new(tcp_stream), service)
+                        .await;
+                    if let Err(e) = served {
+                        debug!(peer = %peer_addr, error = %e, "admin connection ended");
+                    }
+                });
+            }
+            _ = shutdown.changed() => {
+                info!("admin API shutting down");
+                return Ok(());
+            }
+        }
+    }
+}
+
+fn json_response(status: StatusCode, body: &serde_json::Value) -> Response<Full<Bytes>> {
+    let mut response = Response::new(Full::new(Bytes::from(body.to_string())));
+    *response.status_mut() = status;
+    response
+        .headers_mut()
+        .insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    response
+}
+
+/// The renewal loop has exited, which only happens during shutdown.
+fn stopped() -> Response<Full<Bytes>> {
+    error_response(
+        StatusCode::SERVICE_UNAVAILABLE,
+        "cert-keeper is shutting down",
+        None,
+    )
+}
+
+fn error_response(status: StatusCode, message: &str, code: Option<&str>) -> Response<Full<Bytes>> {
+    json_response(status, &json!({ "error": message, "code": code }))
+}
+
+/// A trailers-only gRPC error: the status travels in the headers and there
+/// is no message.
+#[cfg(feature = "grpc-health")]
+fn grpc_error(code: u16, message: &'static str) -> Response<Body> {
+    let mut response = Response::new(Full::new(Bytes::new()).boxed_unsync());
+    let headers = response.headers_mut();
+    headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/grpc"));
+    headers.insert("grpc-status", HeaderValue::from(code));
+    headers.insert("grpc-message", HeaderValue::from_static(message));
+    response
+}
@@ -0,0 +1,231 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::sync::{Mutex, PoisonError};
+
+use ring::digest::{digest, SHA256};
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+use tracing::{error, warn};
+
+use crate::cert::info::{format_time, parse_pem_chain, CertInfo};
+use crate::cert::issuer::CertBundle;
+use crate::cert::webhook::EventError;
+use crate::config::{AuditTarget, Config};
+use crate::error::{Error, Result};
+use crate::metrics;
+
+/// `prev_hash` of the first record of a chain.
+const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// How much of the end of an existing log is read to find its last record.
+const TAIL_LEN: u64 = 1 << 20;
+
+/// Appends certificate lifecycle records to `AUDIT_LOG`, one JSON object
+/// per line.
+///
+/// Each record carries the SHA-256 of the one before it and of itself, so
+/// a record that is changed, removed, or inserted later breaks the chain.
+pub struct AuditLog {
+    sink: Mutex<Sink>,
+}
+
+struct Sink {
+    out: Output,
+    prev_hash: String,
+}
+
+enum Output {
+    Stderr,
+    File { file: File, path: String },
+}
+
+/// What happened to the certificate.
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditEvent {
+    /// The issuer returned a new certificate.
+    Issued,
+    /// Issuing a certificate failed.
+    IssueFailed,
+    /// The certificate a previous run left in `CERT_DIR` is served again.
+    Loaded,
+    /// A self-signed bootstrap certificate is served.
+    Bootstrap,
+    /// A superseded certificate was revoked (`CERT_REVOKE_SUPERSEDED`).
+    Revoked,
+    /// Revoking a superseded certificate failed.
+    RevokeFailed,
+}
+
+/// Why the certificate was issued or generated.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Trigger {
+    /// The first issuance at startup.
+    Startup,
+    /// The renewal threshold was reached.
+    Scheduled,
+    /// An earlier attempt failed.
+    Retry,
+    /// `POST /renew` or `SIGHUP`.
+    Forced,
+    /// Replacing a certificate from a previous run.
+    WarmStart,
+    /// Replacing a self-signed bootstrap certificate.
+    Bootstrap,
+    /// Serving a certificate the leader issued, with `LEADER_ELECTION`.
+    Leader,
+    /// Serving a certificate another agent wrote, with
+    /// `CERT_ISSUER=external`.
+    External,
+}
+
+/// One audit record, before it is timestamped and chained.
+#[derive(Debug, Serialize)]
+pub struct AuditRecord<'a> {
+    pub event: AuditEvent,
+    pub common_name: &'a str,
+    /// The `CERT_PROFILES` name, for a certificate that is not served.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub profile: Option<&'a str>,
+    pub trigger: Trigger,
+    /// `CERT_ISSUER`, for issuances.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cert_issuer: Option<&'static str>,
+    /// The issuer's reference for the request, see
+    /// [`CertBundle::request_id`].
+    pub request_id: Option<&'a str>,
+    pub certificate: Option<&'a CertInfo>,
+    pub error: Option<EventError>,
+}
+
+#[derive(Serialize)]
+struct Chained<'a> {
+    time: String,
+    #[serde(flatten)]
+    record: &'a AuditRecord<'a>,
+    prev_hash: &'a str,
+}
+
+#[derive(Deserialize)]
+struct LastRecord {
+    hash: String,
+}
+
+impl AuditLog {
+    /// Open the audit log, or `None` when `AUDIT_LOG` is not set. An
+    /// existing file is appended to and its chain continued.
+    pub fn new(config: &Config) -> Result<Option<Self>> {
+        let sink = match config.audit_log {
+            None => return Ok(None),
+            Some(AuditTarget::Stderr) => Sink {
+                out: Output::Stderr,
+                prev_hash: GENESIS_HASH.into(),
+            },
+            Some(AuditTarget::File(ref path)) => {
+                let open = || -> io::Result<(File, Option<String>)> {
+                    let mut file = OpenOptions::new()
+                        .read(true)
+                        .append(true)
+                        .create(true)
+                        .mode(0o600)
+                        .open(path)?;
+                    let tail = read_tail(&mut file)?;
+                    // Start on a line of its own after a record cut short.
+                    if !tail.is_empty() && !tail.ends_with('\n') {
+                        file.write_all(b"\n")?;
+                    }
+                    let last = tail
+                        .lines()
+                        .rev()
+                        .find(|line| !line.trim().is_empty())
+                        .map(String::from);
+                    Ok((file, last))
+                };
+                let (file, last) = open().map_err(|e| {
+                    Error::Config(format!("failed to open AUDIT_LOG '{path}': {e}"))
+                })?;
+                let prev_hash = match last {
+                    None => GENESIS_HASH.into(),
+                    Some(line) => match serde_json::from_str::<LastRecord>(&line) {
+                        Ok(last) => last.hash,
+                        Err(e) => {
+                            warn!(path = %path, error = %e, "the audit log does not end with a record, starting a new chain");
+                            GENESIS_HASH.into()
+                        }
+                    },
+                };
+                Sink {
+                    out: Output::File {
+                        file,
+                        path: path.clone(),
+                    },
+                    prev_hash,
+                }
+            }
+        };
+        Ok(Some(Self {
+            sink: Mutex::new(sink),
+        }))
+    }
+
+    /// Append `record`. A record that cannot be written is logged and
+    /// counted in `audit_log_failures`; the certificate is served anyway.
+    pub fn record(&self, record: &AuditRecord) {
+        let mut sink = self.sink.lock().unwrap_or_else(PoisonError::into_inner);
+        let chained = Chained {
+            time: format_time(OffsetDateTime::now_utc()),
+            record,
+            prev_hash: &sink.prev_hash,
+        };
+        let body = match serde_json::to_string(&chained) {
+            Ok(body) => body,
+            Err(e) => {
+                error!(error = %e, "failed to encode audit record");
+                metrics::inc("audit_log_failures", "encode");
+                return;
+            }
+        };
+        let hash = hex(digest(&SHA256, body.as_bytes()).as_ref());
+        // The hash covers the record as it would read without it.
+        let line = format!("{},\"hash\":\"{hash}\"}}\n", &body[..body.len() - 1]);
+
+        let written = match sink.out {
+            Output::Stderr => io::stderr().lock().write_all(line.as_bytes()),
+            Output::File { ref mut file, .. } => file
+                .write_all(line.as_bytes())
+                .and_then(|()| file.sync_data()),
+        };
+        match written {
+            Ok(()) => sink.prev_hash = hash,
+            Err(e) => {
+                let path = match sink.out {
+                    Output::Stderr => "stderr",
+                    Output::File { ref path, .. } => path,
+                };
+                error!(path = %path, error = %e, common_name = %record.common_name, "failed to write audit record");
+                metrics::inc("audit_log_failures", "write");
+            }
+        }
+    }
+}
+
+/// The leaf of `bundle`, parsed for an audit record.
+pub fn bundle_leaf(bundle: &CertBundle) -> Option<CertInfo> {
+    let chain = parse_pem_chain(&bundle.certificate).ok()?;
+    CertInfo::from_der(chain.first()?).ok()
+}
+
+/// The final [`TAIL_LEN`] bytes of `file`, where its last record is.
+fn read_tail(file: &mut File) -> io::Result<String> {
+    let len = file.seek(SeekFrom::End(0))?;
+    file.seek(SeekFrom::Start(len.saturating_sub(TAIL_LEN)))?;
+    let mut tail = Vec::new();
+    file.read_to_end(&mut tail)?;
+    Ok(String::from_utf8_lossy(&tail).into_owned())
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
@@ -0,0 +1,87 @@
+use std::sync::Arc;
+
+use rustls::pki_types::CertificateRevocationListDer;
+use rustls::server::danger::ClientCertVerifier;
+use rustls::server::WebPkiClientVerifier;
+use rustls::RootCertStore;
+
+use crate::cert::roots;
+use crate::config::{ClientAuth, Config};
+use crate::error::{Error, Result};
+use crate::vault::client::VaultClient;
+use crate::vault::pki;
+
+pub type Crls = Vec<CertificateRevocationListDer<'static>>;
+
+/// Load and build the client certificate verifier in one go, or `None`
+/// when client certificates are not requested.
+pub async fn verifier(
+    client: &VaultClient,
+    config: &Config,
+) -> Result<Option<Arc<dyn ClientCertVerifier>>> {
+    let Some(roots) = load_roots(client, config).await? else {
+        return Ok(None);
+    };
+    let crls = fetch_crls(client, config).await?;
+    build_verifier(config.client_auth, roots, crls).map(Some)
+}
+
+/// CAs trusted to sign client certificates: `CLIENT_CA_FILE` (or the PKI
+/// mount's CA chain when unset) plus any `EXTRA_CA_FILES`. `None` when
+/// client auth is off.
+///
+/// Everything is re-read on each call so a rotated CA can be picked up.
+pub async fn load_roots(
+    client: &VaultClient,
+    config: &Config,
+) -> Result<Option<Arc<RootCertStore>>> {
+    if config.client_auth == ClientAuth::None {
+        return Ok(None);
+    }
+
+    let roots = roots::load(
+        client,
+        config,
+        "CLIENT_CA_FILE",
+        config.client_ca_file.as_deref(),
+    )
+    .await?;
+    Ok(Some(Arc::new(roots)))
+}
+
+/// The PKI mount's current CRL, or nothing when `CLIENT_CRL_CHECK` is off.
+pub async fn fetch_crls(client: &VaultClient, config: &Config) -> Result<Crls> {
+    if !config.client_crl_check {
+        return Ok(Vec::new());
+    }
+
+    let pem = pki::fetch_crl(client, config).await?;
+    let crls = rustls_pemfile::crls(&mut pem.as_bytes())
+        .collect::<std::result::Result<Crls, _>>()
+        .map_err(|e| Error::CertParse(format!("failed to parse CRL PEM: {e}")))?;
+    if crls.is_empty() {
+        return Err(Error::CertParse("no CRL found in PEM".into()));
+    }
+    Ok(crls)
+}
+
+pub fn build_verifier(
+    mode: ClientAuth,
+    roots: Arc<RootCertStore>,
+    crls: Crls,
+) -> Result<Arc<dyn ClientCertVerifier>> {
+    // The Vault CRL only covers leaves issued by the mount, so chains
+    // through CLIENT_CA_FILE or EXTRA_CA_FILES roots must not be rejected
+    // for lacking one.
+    let builder = WebPkiClientVerifier::builder(roots)
+        .with_crls(crls)
+        .only_check_end_entity_revocation()
+        .allow_unknown_revocation_status();
+    let builder = match mode {
+        ClientAuth::Optional => builder.allow_unauthenticated(),
+        _ => builder,
+    };
+    builder
+        .build()
+        .map_err(|e| Error::Tls(format!("failed to build client certificate verifier: {e}")))
+}
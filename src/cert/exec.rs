@@ -0,0 +1,117 @@
+use std::process::Stdio;
+
+use async_trait::async_trait;
+use time::OffsetDateTime;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+use tokio::time::timeout;
+use tracing::{debug, info, instrument};
+
+use crate::cert::hook::captured;
+use crate::cert::info::{parse_pem_chain, CertInfo};
+use crate::cert::issuer::{CertBundle, CertRequest, Issuer};
+use crate::cert::keygen;
+use crate::cert::manager::certified_key;
+use crate::cert::store::split_leaf;
+use crate::config::{CertNames, Config};
+use crate::error::{Error, Result};
+
+/// `EXEC_ISSUER_CMD` (`CERT_ISSUER=exec`). The command brings its own
+/// credentials, so there is nothing to prepare or refresh.
+pub struct ExecIssuer;
+
+#[async_trait]
+impl Issuer for ExecIssuer {
+    async fn issue(&self, req: CertRequest<'_>) -> Result<CertBundle> {
+        issue_certificate(req.config, req.names).await
+    }
+}
+
+/// Issue a certificate for `names` by running `EXEC_ISSUER_CMD` with
+/// `sh -c`.
+///
+/// The key is always generated here and never handed to the command. It
+/// gets the PEM CSR on stdin, and the names and `CERT_TTL` in its
+/// environment, and must print the PEM chain, leaf first, and exit zero
+/// within `EXEC_ISSUER_TIMEOUT`, or it is killed.
+#[instrument(name = "exec_issue", skip_all, fields(common_name = %names.common_name))]
+pub async fn issue_certificate(config: &Config, names: &CertNames) -> Result<CertBundle> {
+    let key = keygen::generate(config, names)?;
+    let mut child = Command::new("/bin/sh")
+        .arg("-c")
+        .arg(&config.exec_issuer_cmd)
+        .env("CERT_KEEPER_COMMON_NAME", &names.common_name)
+        .env("CERT_KEEPER_ALT_NAMES", names.alt_names.as_deref().unwrap_or_default())
+        .env("CERT_KEEPER_IP_SANS", names.ip_sans.as_deref().unwrap_or_default())
+        .env("CERT_KEEPER_URI_SANS", names.uri_sans.as_deref().unwrap_or_default())
+        .env("CERT_KEEPER_TTL", &config.cert_ttl)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| Error::ExecIssuer(format!("failed to start EXEC_ISSUER_CMD: {e}")))?;
+
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    let write = async {
+        // A command that exits without reading the CSR closes the pipe
+        // first; its exit status says why.
+        let _ = stdin.write_all(key.csr_pem.as_bytes()).await;
+        drop(stdin);
+    };
+    // Read while writing, so a command that prints before it has read all
+    // of the CSR does not block on a full pipe.
+    let run = async { tokio::join!(write, child.wait_with_output()).1 };
+    // Dropping the child on timeout kills it.
+    let output = match timeout(config.exec_issuer_timeout, run).await {
+        Ok(Ok(output)) => output,
+        Ok(Err(e)) => {
+            return Err(Error::ExecIssuer(format!(
+                "failed to wait for EXEC_ISSUER_CMD: {e}"
+            )))
+        }
+        Err(_) => {
+            return Err(Error::ExecIssuer(format!(
+                "EXEC_ISSUER_CMD did not finish within {}s, see EXEC_ISSUER_TIMEOUT",
+                config.exec_issuer_timeout.as_secs()
+            )))
+        }
+    };
+
+    // Only stderr is shown: stdout is the chain.
+    let (_, stderr) = captured(&output);
+    if !output.status.success() {
+        return Err(Error::ExecIssuer(format!(
+            "EXEC_ISSUER_CMD failed with {}: {stderr}",
+            output.status
+        )));
+    }
+    if !stderr.is_empty() {
+        debug!(stderr, "EXEC_ISSUER_CMD wrote to stderr");
+    }
+
+    let chain = String::from_utf8_lossy(&output.stdout).into_owned();
+    let leaf = parse_pem_chain(&chain)
+        .and_then(|c| CertInfo::from_der(&c[0]))
+        .map_err(|e| Error::ExecIssuer(format!("EXEC_ISSUER_CMD printed no usable chain: {e}")))?;
+    // Loading checks the leaf is for our key; a chain for some other key
+    // would otherwise only fail once served.
+    certified_key(&chain, &key.private_key_pem).map_err(|_| {
+        Error::ExecIssuer(
+            "EXEC_ISSUER_CMD printed a certificate that does not match the CSR's key".into(),
+        )
+    })?;
+    let lease = (leaf.not_after - OffsetDateTime::now_utc())
+        .whole_seconds()
+        .max(0) as u64;
+    let (certificate, ca_certificate) = split_leaf(&chain);
+    info!(serial = %leaf.serial, not_after = %leaf.not_after, "certificate issued by EXEC_ISSUER_CMD");
+
+    Ok(CertBundle {
+        certificate: format!("{certificate}{ca_certificate}"),
+        private_key: key.private_key_pem,
+        ca_certificate,
+        lease_duration_secs: lease,
+        request_id: None,
+    })
+}
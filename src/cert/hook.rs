@@ -0,0 +1,87 @@
+use std::path::Path;
+use std::process::{Output, Stdio};
+use std::time::Duration;
+
+use tokio::process::Command;
+use tokio::time::timeout;
+use tracing::{info, instrument, warn};
+
+use crate::config::Config;
+use crate::metrics;
+
+/// Longest tail of stdout or stderr logged from one run.
+const MAX_OUTPUT_BYTES: usize = 4 * 1024;
+
+/// Run `RENEWAL_HOOK_CMD`, if set, with `sh -c` once the renewed
+/// certificate for `common_name` has been written to `dir`, killing it
+/// after `RENEWAL_HOOK_TIMEOUT`.
+///
+/// Failures are logged and counted in `renewal_hook_failures` but not
+/// returned: the certificate itself was renewed.
+pub async fn run(config: &Config, common_name: &str, dir: &Path) {
+    if let Some(ref cmd) = config.renewal_hook_cmd {
+        execute(cmd, config.renewal_hook_timeout, common_name, dir).await;
+    }
+}
+
+#[instrument(name = "renewal_hook", skip_all)]
+async fn execute(cmd: &str, limit: Duration, common_name: &str, dir: &Path) {
+    let child = Command::new("/bin/sh")
+        .arg("-c")
+        .arg(cmd)
+        .env("CERT_KEEPER_COMMON_NAME", common_name)
+        .env("CERT_KEEPER_CERT_DIR", dir)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn();
+    let child = match child {
+        Ok(child) => child,
+        Err(e) => {
+            let count = metrics::inc("renewal_hook_failures", "spawn");
+            warn!(error = %e, count, "failed to start renewal hook");
+            return;
+        }
+    };
+
+    // Dropping the child on timeout kills it.
+    let output = match timeout(limit, child.wait_with_output()).await {
+        Ok(Ok(output)) => output,
+        Ok(Err(e)) => {
+            let count = metrics::inc("renewal_hook_failures", "spawn");
+            warn!(error = %e, count, "failed to wait for renewal hook");
+            return;
+        }
+        Err(_) => {
+            let count = metrics::inc("renewal_hook_failures", "timeout");
+            warn!(
+                timeout_secs = limit.as_secs(),
+                count, "renewal hook timed out and was killed"
+            );
+            return;
+        }
+    };
+
+    let (stdout, stderr) = captured(&output);
+    if output.status.success() {
+        info!(stdout, stderr, "renewal hook succeeded");
+    } else {
+        let count = metrics::inc("renewal_hook_failures", "exit_status");
+        warn!(status = %output.status, stdout, stderr, count, "renewal hook failed");
+    }
+}
+
+/// The end of the hook's stdout and stderr, where errors usually are.
+pub fn captured(output: &Output) -> (String, String) {
+    let tail = |bytes: &[u8]| {
+        let text = String::from_utf8_lossy(bytes);
+        let text = text.trim();
+        let mut start = text.len().saturating_sub(MAX_OUTPUT_BYTES);
+        while !text.is_char_boundary(start) {
+            start += 1;
+        }
+        text[start..].to_string()
+    };
+    (tail(&output.stdout), tail(&output.stderr))
+}
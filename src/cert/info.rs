@@ -0,0 +1,137 @@
+use std::net::IpAddr;
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use ring::digest;
+use rustls::pki_types::CertificateDer;
+use serde::Serialize;
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+use x509_parser::extensions::GeneralName;
+use x509_parser::prelude::{FromDer, X509Certificate};
+
+use crate::error::{Error, Result};
+
+/// Human-relevant fields extracted from an X.509 certificate.
+#[derive(Debug, Clone, Serialize)]
+pub struct CertInfo {
+    pub subject: String,
+    pub issuer: String,
+    pub serial: String,
+    #[serde(serialize_with = "time::serde::rfc3339::serialize")]
+    pub not_before: OffsetDateTime,
+    #[serde(serialize_with = "time::serde::rfc3339::serialize")]
+    pub not_after: OffsetDateTime,
+    pub dns_names: Vec<String>,
+    pub ip_addresses: Vec<IpAddr>,
+    pub uris: Vec<String>,
+    pub is_ca: bool,
+    pub self_signed: bool,
+    pub sha256_fingerprint: String,
+    pub sha1_fingerprint: String,
+}
+
+impl CertInfo {
+    /// Parse a single DER-encoded certificate.
+    pub fn from_der(der: &[u8]) -> Result<Self> {
+        let (_, cert) = X509Certificate::from_der(der)
+            .map_err(|e| Error::CertParse(format!("failed to parse X.509 certificate: {e}")))?;
+
+        let mut dns_names = Vec::new();
+        let mut ip_addresses = Vec::new();
+        let mut uris = Vec::new();
+
+        if let Ok(Some(san)) = cert.subject_alternative_name() {
+            for name in &san.value.general_names {
+                match name {
+                    GeneralName::DNSName(dns) => dns_names.push(dns.to_string()),
+                    GeneralName::URI(uri) => uris.push(uri.to_string()),
+                    GeneralName::IPAddress(bytes) => {
+                        if let Some(ip) = ip_from_bytes(bytes) {
+                            ip_addresses.push(ip);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        let is_ca = cert
+            .basic_constraints()
+            .ok()
+            .flatten()
+            .is_some_and(|bc| bc.value.ca);
+
+        Ok(Self {
+            subject: cert.subject().to_string(),
+            issuer: cert.issuer().to_string(),
+            serial: format_hex(cert.raw_serial(), ':'),
+            not_before: cert.validity().not_before.to_datetime(),
+            not_after: cert.validity().not_after.to_datetime(),
+            dns_names,
+            ip_addresses,
+            uris,
+            is_ca,
+            self_signed: cert.subject() == cert.issuer(),
+            sha256_fingerprint: fingerprint(&digest::SHA256, der),
+            sha1_fingerprint: fingerprint(&digest::SHA1_FOR_LEGACY_USE_ONLY, der),
+        })
+    }
+
+    /// Seconds until the certificate expires (negative once expired).
+    pub fn remaining_secs(&self) -> i64 {
+        (self.not_after - OffsetDateTime::now_utc()).whole_seconds()
+    }
+}
+
+/// Parse every certificate in a PEM string, leaf first.
+pub fn parse_pem_chain(pem: &str) -> Result<Vec<CertificateDer<'static>>> {
+    let certs = rustls_pemfile::certs(&mut pem.as_bytes())
+        .collect::<std::result::Result<Vec<_>, _>>()
+        .map_err(|e| Error::CertParse(format!("failed to parse certificate PEM: {e}")))?;
+
+    if certs.is_empty() {
+        return Err(Error::CertParse("no certificates found in PEM".into()));
+    }
+
+    Ok(certs)
+}
+
+/// PEM-encode `der` under `label`, such as `CERTIFICATE`.
+pub fn pem_encode(label: &str, der: &[u8]) -> String {
+    let b64 = STANDARD.encode(der);
+    let mut pem = format!("-----BEGIN {label}-----\n");
+    for line in b64.as_bytes().chunks(64) {
+        pem.push_str(&String::from_utf8_lossy(line));
+        pem.push('\n');
+    }
+    pem.push_str(&format!("-----END {label}-----\n"));
+    pem
+}
+
+/// Format a timestamp as RFC 3339 for display.
+pub fn format_time(t: OffsetDateTime) -> String {
+    t.format(&Rfc3339).unwrap_or_else(|_| t.to_string())
+}
+
+/// Colon-separated uppercase hex digest of a DER certificate, as printed by
+/// `openssl x509 -fingerprint`.
+fn fingerprint(algorithm: &'static digest::Algorithm, der: &[u8]) -> String {
+    format_hex(digest::digest(algorithm, der).as_ref(), ':').to_uppercase()
+}
+
+pub(crate) fn format_hex(bytes: &[u8], sep: char) -> String {
+    bytes
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect::<Vec<_>>()
+        .join(&sep.to_string())
+}
+
+fn ip_from_bytes(bytes: &[u8]) -> Option<IpAddr> {
+    match bytes.len() {
+        4 => <[u8; 4]>::try_from(bytes).ok().map(IpAddr::from),
+        16 => <[u8; 16]>::try_from(bytes).ok().map(IpAddr::from),
+        _ => None,
+    }
+}
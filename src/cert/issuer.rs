@@ -0,0 +1,207 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use time::OffsetDateTime;
+use tracing::debug;
+
+#[cfg(feature = "acme")]
+use crate::acme::client::AcmeIssuer;
+#[cfg(feature = "exec-issuer")]
+use crate::cert::exec::ExecIssuer;
+#[cfg(feature = "k8s-csr")]
+use crate::cert::k8s_csr::KubernetesCsrIssuer;
+use crate::cert::info::{parse_pem_chain, pem_encode};
+use crate::cert::resolver::AlpnChallenges;
+use crate::cert::secret::KeyPem;
+use crate::config::{CertIssuer, CertNames, Config};
+use crate::dns::Resolver;
+use crate::error::{Error, Result};
+use crate::vault::auth;
+use crate::vault::client::VaultClient;
+use crate::vault::{health, pki};
+
+/// A certificate bundle returned by the issuer.
+pub struct CertBundle {
+    /// PEM-encoded certificate (leaf + CA chain).
+    pub certificate: String,
+    /// PEM-encoded private key, wiped once the bundle is dropped.
+    pub private_key: KeyPem,
+    /// PEM-encoded issuing CA certificate; from an ACME CA, the whole
+    /// chain above the leaf.
+    pub ca_certificate: String,
+    /// Lease duration in seconds (used for renewal scheduling).
+    pub lease_duration_secs: u64,
+    /// The issuer's reference for the request, for auditing: Vault's
+    /// request ID, the ACME order URL, or the CertificateSigningRequest.
+    pub request_id: Option<String>,
+}
+
+/// A certificate to issue: its names, and the settings it is issued with,
+/// a profile's own for a `CERT_PROFILES` certificate.
+#[derive(Clone, Copy)]
+pub struct CertRequest<'a> {
+    pub config: &'a Config,
+    pub names: &'a CertNames,
+}
+
+/// Where certificates come from. `CERT_ISSUER` picks one of the built-in
+/// issuers, Vault's PKI secrets engine by default; a program that embeds
+/// cert-keeper can hand [`CertManager::new`] its own, to issue from a CA
+/// cert-keeper has no support for, and keep the renewal, storage, and
+/// serving.
+///
+/// [`CertManager::new`]: crate::cert::manager::CertManager::new
+#[async_trait]
+pub trait Issuer: Send + Sync {
+    /// Get ready to issue, such as by logging in or registering an
+    /// account. Called once, before the first certificate is issued.
+    async fn prepare(&self, _config: &Config) -> Result<()> {
+        Ok(())
+    }
+
+    /// Make sure the issuer's credentials last at least `valid_for`, the
+    /// time until the next renewal.
+    async fn refresh(&self, _config: &Config, _valid_for: Duration) -> Result<()> {
+        Ok(())
+    }
+
+    /// Issue a certificate for `req.names`.
+    async fn issue(&self, req: CertRequest<'_>) -> Result<CertBundle>;
+
+    /// The PEM certificates of `CERT_EXTRA_CHAIN_ISSUERS`, which config
+    /// validation only allows with Vault.
+    async fn extra_chain(&self, _config: &Config) -> Result<String> {
+        Ok(String::new())
+    }
+}
+
+/// The issuer `CERT_ISSUER` names. `challenges` receives the
+/// `tls-alpn-01` certificates for the proxy to answer with; without it
+/// only `http-01` and `dns-01` work.
+#[cfg_attr(not(feature = "acme"), allow(unused_variables))]
+pub fn from_config(
+    config: &Config,
+    client: Arc<VaultClient>,
+    resolver: Arc<Resolver>,
+    challenges: Option<AlpnChallenges>,
+) -> Result<Arc<dyn Issuer>> {
+    match config.cert_issuer {
+        CertIssuer::Vault => Ok(Arc::new(VaultIssuer(client))),
+        #[cfg(feature = "acme")]
+        CertIssuer::Acme => Ok(Arc::new(AcmeIssuer::new(config, resolver, challenges)?)),
+        // Config validation already rejects this.
+        #[cfg(not(feature = "acme"))]
+        CertIssuer::Acme => Err(Error::Config(
+            "CERT_ISSUER=acme requires a build with the acme feature".into(),
+        )),
+        #[cfg(feature = "k8s-csr")]
+        CertIssuer::KubernetesCsr => Ok(Arc::new(KubernetesCsrIssuer)),
+        #[cfg(not(feature = "k8s-csr"))]
+        CertIssuer::KubernetesCsr => Err(Error::Config(
+            "CERT_ISSUER=kubernetes-csr requires a build with the k8s-csr feature".into(),
+        )),
+        #[cfg(feature = "exec-issuer")]
+        CertIssuer::Exec => Ok(Arc::new(ExecIssuer)),
+        #[cfg(not(feature = "exec-issuer"))]
+        CertIssuer::Exec => Err(Error::Config(
+            "CERT_ISSUER=exec requires a build with the exec-issuer feature".into(),
+        )),
+        CertIssuer::External => Ok(Arc::new(ExternalIssuer)),
+    }
+}
+
+/// Issue through `issuer`, then append the extra chain to the bundle.
+pub async fn issue(issuer: &dyn Issuer, req: CertRequest<'_>) -> Result<CertBundle> {
+    let mut bundle = issuer.issue(req).await?;
+    append_extra_chain(issuer, req.config, &mut bundle).await?;
+    Ok(bundle)
+}
+
+/// Vault's PKI secrets engine, the default issuer.
+pub struct VaultIssuer(pub Arc<VaultClient>);
+
+#[async_trait]
+impl Issuer for VaultIssuer {
+    /// Wait for Vault and log in, or resume the cached token.
+    async fn prepare(&self, config: &Config) -> Result<()> {
+        health::wait_until_ready(&self.0, config.vault_startup_timeout).await;
+        if !auth::resume_cached_token(&self.0, config).await {
+            auth::login(&self.0, config).await?;
+        }
+        Ok(())
+    }
+
+    async fn refresh(&self, config: &Config, valid_for: Duration) -> Result<()> {
+        auth::refresh(&self.0, config, valid_for).await
+    }
+
+    async fn issue(&self, req: CertRequest<'_>) -> Result<CertBundle> {
+        pki::issue_certificate(&self.0, req.config, req.names).await
+    }
+
+    async fn extra_chain(&self, config: &Config) -> Result<String> {
+        let mut pem = String::new();
+        for issuer in &config.cert_extra_chain_issuers {
+            pem.push('\n');
+            pem.push_str(&pki::fetch_issuer(&self.0, &config.vault_pki_mount, issuer).await?);
+        }
+        Ok(pem)
+    }
+}
+
+/// None: another agent writes the certificates to the store
+/// (`CERT_ISSUER=external`).
+pub struct ExternalIssuer;
+
+#[async_trait]
+impl Issuer for ExternalIssuer {
+    async fn issue(&self, req: CertRequest<'_>) -> Result<CertBundle> {
+        // The manager never asks; it serves what is in the store.
+        Err(Error::Config(format!(
+            "'{}' is not issued by cert-keeper with CERT_ISSUER=external",
+            req.names.common_name
+        )))
+    }
+}
+
+/// Append `CERT_EXTRA_CHAIN_FILE` and the certificates of
+/// `CERT_EXTRA_CHAIN_ISSUERS` to the chain of `bundle`, leaving out any it
+/// already has, until `CERT_EXTRA_CHAIN_UNTIL`. Clients that only trust
+/// the old root can then still build a path during a CA rotation.
+async fn append_extra_chain(
+    issuer: &dyn Issuer,
+    config: &Config,
+    bundle: &mut CertBundle,
+) -> Result<()> {
+    if config
+        .cert_extra_chain_until
+        .is_some_and(|until| until <= OffsetDateTime::now_utc())
+    {
+        return Ok(());
+    }
+    let mut pem = match config.cert_extra_chain_file {
+        Some(ref path) => tokio::fs::read_to_string(path).await.map_err(|e| {
+            Error::Config(format!("failed to read CERT_EXTRA_CHAIN_FILE '{path}': {e}"))
+        })?,
+        None => String::new(),
+    };
+    pem.push_str(&issuer.extra_chain(config).await?);
+    if pem.trim().is_empty() {
+        return Ok(());
+    }
+
+    let mut chain = parse_pem_chain(&bundle.certificate)?;
+    let mut added = 0;
+    for cert in parse_pem_chain(&pem)? {
+        if chain.contains(&cert) {
+            continue;
+        }
+        bundle.certificate.push('\n');
+        bundle.certificate.push_str(pem_encode("CERTIFICATE", &cert).trim());
+        chain.push(cert);
+        added += 1;
+    }
+    debug!(added, "appended extra CA chain");
+    Ok(())
+}
@@ -0,0 +1,179 @@
+use std::time::Duration;
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use reqwest::header::CONTENT_TYPE;
+use reqwest::{Certificate, Client, Method, RequestBuilder, Response, StatusCode};
+use serde_json::{json, Value};
+use tokio::fs;
+use tracing::{info, instrument};
+
+use crate::error::{Error, Result};
+use crate::vault::auth::{SA_NAMESPACE_PATH, SA_TOKEN_PATH};
+
+const SA_CA_PATH: &str = "/var/run/secrets/kubernetes.io/serviceaccount/ca.crt";
+
+const API_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The PEM contents of a `kubernetes.io/tls` Secret.
+pub struct TlsSecret<'a> {
+    pub cert: &'a str,
+    pub key: &'a str,
+    pub ca: &'a str,
+}
+
+/// Create or update the `kubernetes.io/tls` Secret `name` with a server-side
+/// apply PATCH, authenticating as the pod's service account.
+///
+/// cert-keeper owns the `tls.crt`, `tls.key`, and `ca.crt` keys and the
+/// type; other keys, labels, and annotations set by someone else are kept.
+/// The service account token and CA are read on every call, since the
+/// kubelet rotates them.
+#[instrument(name = "secret_apply", skip_all, fields(secret = name))]
+pub async fn apply_tls_secret(
+    name: &str,
+    namespace: Option<&str>,
+    secret: TlsSecret<'_>,
+) -> Result<()> {
+    let namespace = namespace_or_own(namespace).await?;
+    let api = Api::connect().await?;
+
+    let path = format!(
+        "/api/v1/namespaces/{namespace}/secrets/{name}?fieldManager={}&force=true",
+        env!("CARGO_PKG_NAME")
+    );
+    let body = json!({
+        "apiVersion": "v1",
+        "kind": "Secret",
+        "metadata": {
+            "name": name,
+            "namespace": namespace,
+            "labels": { "app.kubernetes.io/managed-by": env!("CARGO_PKG_NAME") },
+        },
+        "type": "kubernetes.io/tls",
+        "data": {
+            "tls.crt": STANDARD.encode(secret.cert),
+            "tls.key": STANDARD.encode(secret.key),
+            "ca.crt": STANDARD.encode(secret.ca),
+        },
+    });
+
+    let response = api
+        .request(Method::PATCH, &path)
+        // JSON is valid YAML, which is what apply takes.
+        .header(CONTENT_TYPE, "application/apply-patch+yaml")
+        .body(body.to_string())
+        .send()
+        .await?;
+    checked(response, &format!("applying Secret {namespace}/{name}")).await?;
+
+    info!(namespace = %namespace, "certificate Secret applied");
+    Ok(())
+}
+
+/// The certificate chain and key PEM in the `kubernetes.io/tls` Secret
+/// `name`, as [`apply_tls_secret`] left them, or `None` if there is no
+/// such Secret or it lacks either.
+#[cfg(feature = "secret-sink")]
+#[instrument(name = "secret_read", skip_all, fields(secret = name))]
+pub async fn read_tls_secret(
+    name: &str,
+    namespace: Option<&str>,
+) -> Result<Option<(String, String)>> {
+    let namespace = namespace_or_own(namespace).await?;
+    let api = Api::connect().await?;
+    let path = format!("/api/v1/namespaces/{namespace}/secrets/{name}");
+    let response = api.request(Method::GET, &path).send().await?;
+    if response.status() == StatusCode::NOT_FOUND {
+        return Ok(None);
+    }
+    let secret: Value = checked(response, &format!("reading Secret {namespace}/{name}"))
+        .await?
+        .json()
+        .await?;
+    let field = |key: &str| {
+        secret["data"][key]
+            .as_str()
+            .and_then(|value| STANDARD.decode(value).ok())
+            .and_then(|value| String::from_utf8(value).ok())
+    };
+    Ok(field("tls.crt").zip(field("tls.key")))
+}
+
+/// `namespace`, or the pod's own.
+pub async fn namespace_or_own(namespace: Option<&str>) -> Result<String> {
+    match namespace {
+        Some(namespace) => Ok(namespace.to_string()),
+        None => Ok(read_sa_file(SA_NAMESPACE_PATH).await?.trim().to_string()),
+    }
+}
+
+/// An API server client authenticated as the pod's service account.
+pub struct Api {
+    http: Client,
+    base: String,
+    token: String,
+}
+
+impl Api {
+    /// Read the service account token and CA, which the kubelet rotates,
+    /// so connect again for each batch of requests rather than keeping
+    /// one around.
+    pub async fn connect() -> Result<Self> {
+        let token = read_sa_file(SA_TOKEN_PATH).await?;
+        let ca = read_sa_file(SA_CA_PATH).await?;
+        let ca = Certificate::from_pem(ca.as_bytes())
+            .map_err(|e| Error::Kubernetes(format!("invalid service account CA: {e}")))?;
+        let http = Client::builder()
+            .add_root_certificate(ca)
+            .timeout(API_TIMEOUT)
+            .build()
+            .map_err(|e| Error::Kubernetes(format!("failed to build HTTP client: {e}")))?;
+
+        Ok(Self {
+            http,
+            base: api_server()?,
+            token: token.trim().to_string(),
+        })
+    }
+
+    /// A request for `path`, such as `/api/v1/...`.
+    pub fn request(&self, method: Method, path: &str) -> RequestBuilder {
+        self.http
+            .request(method, format!("{}{path}", self.base))
+            .bearer_auth(&self.token)
+    }
+}
+
+/// `response` if it succeeded, else an error naming `what` with the API
+/// server's status message.
+pub async fn checked(response: Response, what: &str) -> Result<Response> {
+    if response.status().is_success() {
+        return Ok(response);
+    }
+    let status = response.status();
+    let body = response.text().await.unwrap_or_default();
+    Err(Error::Kubernetes(format!(
+        "{what} returned {status}: {body}"
+    )))
+}
+
+/// The in-cluster API server address the kubelet puts in every pod's
+/// environment.
+fn api_server() -> Result<String> {
+    let host = std::env::var("KUBERNETES_SERVICE_HOST").map_err(|_| {
+        Error::Kubernetes("KUBERNETES_SERVICE_HOST is not set; not running in a pod?".into())
+    })?;
+    let port = std::env::var("KUBERNETES_SERVICE_PORT").unwrap_or_else(|_| "443".into());
+    if host.contains(':') {
+        Ok(format!("https://[{host}]:{port}"))
+    } else {
+        Ok(format!("https://{host}:{port}"))
+    }
+}
+
+async fn read_sa_file(path: &str) -> Result<String> {
+    fs::read_to_string(path)
+        .await
+        .map_err(|e| Error::Kubernetes(format!("failed to read {path}: {e}")))
+}
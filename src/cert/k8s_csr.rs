@@ -0,0 +1,222 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use reqwest::Method;
+use serde::Deserialize;
+use serde_json::json;
+use time::OffsetDateTime;
+use tokio::time::{sleep, Instant};
+use tracing::{debug, info, instrument};
+
+use crate::cert::info::{parse_pem_chain, CertInfo};
+use crate::cert::issuer::{CertBundle, CertRequest, Issuer};
+use crate::cert::k8s::{checked, Api};
+use crate::cert::keygen;
+use crate::cert::store::split_leaf;
+use crate::config::{BackendClientCert, CertNames, Config};
+use crate::error::{Error, Result};
+
+const CSR_PATH: &str = "/apis/certificates.k8s.io/v1/certificatesigningrequests";
+
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Deserialize)]
+struct Csr {
+    metadata: Metadata,
+    #[serde(default)]
+    status: CsrStatus,
+}
+
+#[derive(Debug, Deserialize)]
+struct Metadata {
+    name: String,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct CsrStatus {
+    #[serde(default)]
+    conditions: Vec<Condition>,
+    /// Base64 of the PEM chain, set by the signer after approval.
+    #[serde(default)]
+    certificate: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Condition {
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(default)]
+    reason: String,
+    #[serde(default)]
+    message: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct AccessReview {
+    status: AccessReviewStatus,
+}
+
+#[derive(Debug, Deserialize)]
+struct AccessReviewStatus {
+    allowed: bool,
+}
+
+/// A Kubernetes CertificateSigningRequest signer
+/// (`CERT_ISSUER=kubernetes-csr`). The service account token is read fresh
+/// for every CSR, so there is nothing to prepare or refresh.
+pub struct KubernetesCsrIssuer;
+
+#[async_trait]
+impl Issuer for KubernetesCsrIssuer {
+    async fn issue(&self, req: CertRequest<'_>) -> Result<CertBundle> {
+        issue_certificate(req.config, req.names).await
+    }
+}
+
+/// Issue a certificate for `names` by submitting a CertificateSigningRequest
+/// for `K8S_CSR_SIGNER_NAME` and waiting for it to be approved and signed.
+///
+/// The key is always generated here. Approval is up to whatever approver
+/// the cluster runs for the signer; cert-keeper only waits for it, and
+/// gives up after `K8S_CSR_TIMEOUT`.
+#[instrument(name = "csr_issue", skip_all, fields(common_name = %names.common_name))]
+pub async fn issue_certificate(config: &Config, names: &CertNames) -> Result<CertBundle> {
+    let deadline = Instant::now() + config.k8s_csr_timeout;
+    let key = keygen::generate(config, names)?;
+    let api = Api::connect().await?;
+
+    let mut usages = vec!["digital signature", "key encipherment", "server auth"];
+    if matches!(config.backend_tls_client_cert, BackendClientCert::Issued) {
+        usages.push("client auth");
+    }
+    let mut spec = json!({
+        "request": STANDARD.encode(&key.csr_pem),
+        "signerName": config.k8s_csr_signer_name,
+        "usages": usages,
+    });
+    if let Some(expiration) = config.k8s_csr_expiration {
+        spec["expirationSeconds"] = json!(expiration.as_secs());
+    }
+    let body = json!({
+        "apiVersion": "certificates.k8s.io/v1",
+        "kind": "CertificateSigningRequest",
+        "metadata": {
+            "generateName": format!("{}-", env!("CARGO_PKG_NAME")),
+            "labels": { "app.kubernetes.io/managed-by": env!("CARGO_PKG_NAME") },
+        },
+        "spec": spec,
+    });
+
+    let response = api
+        .request(Method::POST, CSR_PATH)
+        .json(&body)
+        .send()
+        .await?;
+    let csr: Csr = checked(response, "creating CertificateSigningRequest")
+        .await?
+        .json()
+        .await?;
+    let name = csr.metadata.name;
+    info!(csr = %name, signer = %config.k8s_csr_signer_name, "CertificateSigningRequest created");
+
+    let chain = wait_for_certificate(&api, &name, deadline).await?;
+    let leaf = parse_pem_chain(&chain).and_then(|c| CertInfo::from_der(&c[0]))?;
+    let lease = (leaf.not_after - OffsetDateTime::now_utc())
+        .whole_seconds()
+        .max(0) as u64;
+    let (certificate, ca_certificate) = split_leaf(&chain);
+    info!(csr = %name, serial = %leaf.serial, not_after = %leaf.not_after, "CertificateSigningRequest signed");
+
+    Ok(CertBundle {
+        certificate: format!("{certificate}{ca_certificate}"),
+        private_key: key.private_key_pem,
+        ca_certificate,
+        lease_duration_secs: lease,
+        request_id: Some(name),
+    })
+}
+
+/// Poll the CSR `name` until the signer has filled in its certificate,
+/// failing if it is denied, fails, or `deadline` passes first.
+async fn wait_for_certificate(api: &Api, name: &str, deadline: Instant) -> Result<String> {
+    let path = format!("{CSR_PATH}/{name}");
+    let mut approved = false;
+    loop {
+        let response = api.request(Method::GET, &path).send().await?;
+        let csr: Csr = checked(
+            response,
+            &format!("reading CertificateSigningRequest {name}"),
+        )
+        .await?
+        .json()
+        .await?;
+
+        for condition in &csr.status.conditions {
+            match condition.kind.as_str() {
+                "Denied" | "Failed" => {
+                    return Err(Error::KubernetesCsr(format!(
+                        "{name} {}: {} {}",
+                        condition.kind.to_lowercase(),
+                        condition.reason,
+                        condition.message
+                    )))
+                }
+                "Approved" if !approved => {
+                    approved = true;
+                    info!(csr = %name, "CertificateSigningRequest approved, waiting for the signer");
+                }
+                _ => {}
+            }
+        }
+
+        if let Some(encoded) = csr.status.certificate.filter(|c| !c.is_empty()) {
+            let pem = STANDARD.decode(encoded).map_err(|e| {
+                Error::KubernetesCsr(format!("{name} has an invalid certificate: {e}"))
+            })?;
+            return String::from_utf8(pem).map_err(|e| {
+                Error::KubernetesCsr(format!("{name} has an invalid certificate: {e}"))
+            });
+        }
+
+        if Instant::now() + POLL_INTERVAL > deadline {
+            let state = if approved { "signed" } else { "approved" };
+            return Err(Error::KubernetesCsr(format!(
+                "{name} was not {state} in time, see K8S_CSR_TIMEOUT"
+            )));
+        }
+        debug!(csr = %name, approved, "waiting for CertificateSigningRequest");
+        sleep(POLL_INTERVAL).await;
+    }
+}
+
+/// Whether the service account may create CertificateSigningRequests,
+/// asked with a SelfSubjectAccessReview.
+pub async fn can_create_csr() -> Result<bool> {
+    let api = Api::connect().await?;
+    let body = json!({
+        "apiVersion": "authorization.k8s.io/v1",
+        "kind": "SelfSubjectAccessReview",
+        "spec": {
+            "resourceAttributes": {
+                "group": "certificates.k8s.io",
+                "resource": "certificatesigningrequests",
+                "verb": "create",
+            },
+        },
+    });
+    let response = api
+        .request(
+            Method::POST,
+            "/apis/authorization.k8s.io/v1/selfsubjectaccessreviews",
+        )
+        .json(&body)
+        .send()
+        .await?;
+    let review: AccessReview = checked(response, "SelfSubjectAccessReview")
+        .await?
+        .json()
+        .await?;
+    Ok(review.status.allowed)
+}
@@ -0,0 +1,199 @@
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use pkcs8::der::pem::LineEnding;
+use pkcs8::der::SecretDocument;
+use pkcs8::pkcs5::pbes2;
+use pkcs8::{EncryptedPrivateKeyInfo, PrivateKeyInfo};
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN};
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::{Deserialize, Serialize};
+use zeroize::Zeroizing;
+
+use crate::cert::keystore::pkcs8_der;
+use crate::cert::secret::KeyPem;
+use crate::cert::store::read_password;
+use crate::config::KeyEncryption;
+use crate::error::{Error, Result};
+use crate::vault::client::VaultClient;
+use crate::vault::transit;
+
+const ENCRYPTED_LABEL: &str = "ENCRYPTED PRIVATE KEY";
+const PLAINTEXT_LABEL: &str = "PRIVATE KEY";
+
+/// OWASP's recommendation for PBKDF2-HMAC-SHA256. Only paid when a key is
+/// written or read back, not per connection.
+const PBKDF2_ITERATIONS: u32 = 600_000;
+
+/// A `key` file under `CERT_KEY_ENCRYPTION=transit`: the key PEM sealed with
+/// AES-256-GCM under a data key, which is kept wrapped by the Transit key.
+#[derive(Debug, Serialize, Deserialize)]
+struct Envelope {
+    mount: String,
+    key: String,
+    /// The data key as Transit wrapped it (`vault:v1:...`).
+    data_key: String,
+    nonce: String,
+    ciphertext: String,
+}
+
+/// The contents of a `key` file holding `key_pem` encrypted as configured.
+///
+/// `vault` is only used for Transit, where each call gets a new data key.
+pub async fn encrypt(
+    encryption: &KeyEncryption,
+    vault: Option<&VaultClient>,
+    key_pem: &str,
+) -> Result<String> {
+    match encryption {
+        KeyEncryption::Passphrase(passphrase) => {
+            let passphrase = read_password(passphrase, "key passphrase").await?;
+            let key_pem = Zeroizing::new(key_pem.to_string());
+            blocking(move || encrypt_pkcs8(&key_pem, &passphrase)).await
+        }
+        KeyEncryption::Transit { mount, key } => {
+            let (data_key, wrapped) = transit::data_key(transit_client(vault)?, mount, key).await?;
+            let mut nonce = [0u8; NONCE_LEN];
+            SystemRandom::new()
+                .fill(&mut nonce)
+                .map_err(|_| Error::KeyEncryption("failed to generate nonce".into()))?;
+            let mut ciphertext = key_pem.as_bytes().to_vec();
+            aead_key(&data_key)?
+                .seal_in_place_append_tag(
+                    Nonce::assume_unique_for_key(nonce),
+                    Aad::empty(),
+                    &mut ciphertext,
+                )
+                .map_err(|_| Error::KeyEncryption("failed to seal private key".into()))?;
+            let envelope = Envelope {
+                mount: mount.clone(),
+                key: key.clone(),
+                data_key: wrapped,
+                nonce: STANDARD.encode(nonce),
+                ciphertext: STANDARD.encode(ciphertext),
+            };
+            Ok(format!("{}\n", serde_json::to_string_pretty(&envelope)?))
+        }
+    }
+}
+
+/// The key PEM in `stored`, the contents of a `key` file that [`encrypt`]
+/// wrote.
+///
+/// A Transit envelope is opened with the mount and key recorded in it, so
+/// one written before `VAULT_TRANSIT_KEY` changed still reads.
+pub async fn decrypt(
+    encryption: &KeyEncryption,
+    vault: Option<&VaultClient>,
+    stored: &str,
+) -> Result<KeyPem> {
+    if stored.contains(&format!("-----BEGIN {ENCRYPTED_LABEL}-----")) {
+        let KeyEncryption::Passphrase(passphrase) = encryption else {
+            return Err(Error::KeyEncryption(
+                "the key is encrypted with a passphrase, but CERT_KEY_ENCRYPTION is not \
+                 passphrase"
+                    .into(),
+            ));
+        };
+        let passphrase = read_password(passphrase, "key passphrase").await?;
+        let stored = stored.to_string();
+        return blocking(move || decrypt_pkcs8(&stored, &passphrase)).await;
+    }
+
+    let envelope: Envelope = serde_json::from_str(stored)
+        .map_err(|e| Error::KeyEncryption(format!("invalid key envelope: {e}")))?;
+    let data_key = transit::decrypt(
+        transit_client(vault)?,
+        &envelope.mount,
+        &envelope.key,
+        &envelope.data_key,
+    )
+    .await?;
+    let decode = |field: &str, value: &str| {
+        STANDARD
+            .decode(value)
+            .map_err(|e| Error::KeyEncryption(format!("invalid {field} in key envelope: {e}")))
+    };
+    let nonce: [u8; NONCE_LEN] = decode("nonce", &envelope.nonce)?
+        .try_into()
+        .map_err(|_| Error::KeyEncryption("invalid nonce length in key envelope".into()))?;
+    let mut ciphertext = Zeroizing::new(decode("ciphertext", &envelope.ciphertext)?);
+    let plaintext = aead_key(&data_key)?
+        .open_in_place(
+            Nonce::assume_unique_for_key(nonce),
+            Aad::empty(),
+            &mut ciphertext,
+        )
+        .map_err(|_| {
+            Error::KeyEncryption("key envelope failed authentication with its data key".into())
+        })?;
+    String::from_utf8(plaintext.to_vec())
+        .map(KeyPem::from)
+        .map_err(|_| Error::KeyEncryption("decrypted private key is not PEM".into()))
+}
+
+/// Run `f`, which derives a key from a passphrase, off the async workers:
+/// it takes a good fraction of a second by design.
+async fn blocking<T: Send + 'static>(f: impl FnOnce() -> Result<T> + Send + 'static) -> Result<T> {
+    tokio::task::spawn_blocking(f)
+        .await
+        .map_err(|e| Error::KeyEncryption(format!("key derivation task failed: {e}")))?
+}
+
+/// PKCS#8 `ENCRYPTED PRIVATE KEY` PEM of `key_pem`, with PBES2 (PBKDF2 with
+/// HMAC-SHA256 and AES-256-CBC), which OpenSSL and Java both read.
+fn encrypt_pkcs8(key_pem: &str, passphrase: &str) -> Result<String> {
+    let der = pkcs8_der(key_pem)?;
+    let info = PrivateKeyInfo::try_from(der.as_slice())
+        .map_err(|e| Error::KeyEncryption(format!("failed to decode private key: {e}")))?;
+
+    let random = SystemRandom::new();
+    let mut salt = [0u8; 16];
+    let mut iv = [0u8; 16];
+    random
+        .fill(&mut salt)
+        .and_then(|()| random.fill(&mut iv))
+        .map_err(|_| Error::KeyEncryption("failed to generate salt".into()))?;
+    let params = pbes2::Parameters::pbkdf2_sha256_aes256cbc(PBKDF2_ITERATIONS, &salt, &iv)
+        .map_err(|e| Error::KeyEncryption(format!("invalid PBES2 parameters: {e}")))?;
+    let encrypted = info
+        .encrypt_with_params(params, passphrase)
+        .map_err(|e| Error::KeyEncryption(format!("failed to encrypt private key: {e}")))?;
+    to_pem(&encrypted, ENCRYPTED_LABEL)
+}
+
+fn decrypt_pkcs8(pem: &str, passphrase: &str) -> Result<KeyPem> {
+    let (_, document) = SecretDocument::from_pem(pem)
+        .map_err(|e| Error::KeyEncryption(format!("invalid encrypted private key PEM: {e}")))?;
+    let decrypted = EncryptedPrivateKeyInfo::try_from(document.as_bytes())
+        .and_then(|info| info.decrypt(passphrase))
+        .map_err(|e| {
+            Error::KeyEncryption(format!(
+                "failed to decrypt private key, is the passphrase right? {e}"
+            ))
+        })?;
+    // Taken out of the zeroizing PEM rather than copied, so no stray copy
+    // of the key is left to wipe.
+    decrypted
+        .to_pem(PLAINTEXT_LABEL, LineEnding::LF)
+        .map(|mut pem| KeyPem::from(std::mem::take(&mut *pem)))
+        .map_err(|e| Error::KeyEncryption(format!("failed to encode private key PEM: {e}")))
+}
+
+fn to_pem(document: &SecretDocument, label: &'static str) -> Result<String> {
+    document
+        .to_pem(label, LineEnding::LF)
+        .map(|pem| pem.to_string())
+        .map_err(|e| Error::KeyEncryption(format!("failed to encode private key PEM: {e}")))
+}
+
+fn aead_key(data_key: &[u8]) -> Result<LessSafeKey> {
+    UnboundKey::new(&AES_256_GCM, data_key)
+        .map(LessSafeKey::new)
+        .map_err(|_| {
+            Error::KeyEncryption("vault transit returned a data key of the wrong size".into())
+        })
+}
+
+fn transit_client(vault: Option<&VaultClient>) -> Result<&VaultClient> {
+    vault.ok_or_else(|| Error::KeyEncryption("no Vault client to reach Transit with".into()))
+}
@@ -0,0 +1,124 @@
+use std::net::IpAddr;
+use std::time::Duration;
+
+use rcgen::{
+    CertificateParams, DistinguishedName, DnType, Ia5String, KeyPair, SanType,
+    PKCS_ECDSA_P256_SHA256, PKCS_ECDSA_P384_SHA384, PKCS_ED25519,
+};
+
+use time::OffsetDateTime;
+
+use crate::cert::secret::KeyPem;
+use crate::config::{CertNames, Config, KeyType};
+use crate::error::{Error, Result};
+
+/// A freshly generated private key and the CSR to have Vault sign for it.
+pub struct LocalKey {
+    /// PEM-encoded PKCS#10 request.
+    pub csr_pem: String,
+    /// The same request, DER-encoded.
+    pub csr_der: Vec<u8>,
+    /// PEM-encoded PKCS#8 private key.
+    pub private_key_pem: KeyPem,
+}
+
+/// Generate a new key (ECDSA P-256 unless `CERT_KEY_TYPE`/`CERT_KEY_BITS`
+/// say otherwise) and a CSR carrying the common name and SANs in `names`.
+pub fn generate(config: &Config, names: &CertNames) -> Result<LocalKey> {
+    // Config validation has already rejected combinations ring cannot
+    // generate.
+    let algorithm = match (config.cert_key_type, config.cert_key_bits) {
+        (Some(KeyType::Ed25519), _) => &PKCS_ED25519,
+        (_, Some(384)) => &PKCS_ECDSA_P384_SHA384,
+        _ => &PKCS_ECDSA_P256_SHA256,
+    };
+    let key_pair = KeyPair::generate_for(algorithm)
+        .map_err(|e| Error::KeyGen(format!("failed to generate key pair: {e}")))?;
+    let params = params(names)?;
+
+    let csr = params
+        .serialize_request(&key_pair)
+        .map_err(|e| Error::KeyGen(format!("failed to build CSR: {e}")))?;
+    let csr_pem = csr
+        .pem()
+        .map_err(|e| Error::KeyGen(format!("failed to build CSR: {e}")))?;
+
+    Ok(LocalKey {
+        csr_pem,
+        csr_der: csr.der().to_vec(),
+        private_key_pem: key_pair.serialize_pem().into(),
+    })
+}
+
+/// A self-signed certificate for `names` with a new P-256 key, valid for
+/// `valid_for` from now, as certificate and key PEM. The common name is
+/// also a DNS SAN, so SNI finds it.
+pub fn self_signed(names: &CertNames, valid_for: Duration) -> Result<(String, KeyPem)> {
+    let failed =
+        |e: rcgen::Error| Error::KeyGen(format!("failed to build self-signed certificate: {e}"));
+    let key_pair = KeyPair::generate_for(&PKCS_ECDSA_P256_SHA256).map_err(failed)?;
+    let params = leaf_params(names, true, valid_for)?;
+    let cert = params.self_signed(&key_pair).map_err(failed)?;
+    Ok((cert.pem(), key_pair.serialize_pem().into()))
+}
+
+/// Parameters of a certificate for `names` valid for `valid_for` from now,
+/// with the common name also a DNS SAN when `cn_in_sans`, as Vault issues
+/// them by default.
+pub(crate) fn leaf_params(
+    names: &CertNames,
+    cn_in_sans: bool,
+    valid_for: Duration,
+) -> Result<CertificateParams> {
+    let mut params = params(names)?;
+    if cn_in_sans && names.common_name.parse::<IpAddr>().is_err() {
+        let common_name = Ia5String::try_from(names.common_name.as_str()).map_err(|e| {
+            Error::KeyGen(format!("invalid common name {}: {e}", names.common_name))
+        })?;
+        params
+            .subject_alt_names
+            .insert(0, SanType::DnsName(common_name));
+    }
+    let now = OffsetDateTime::now_utc();
+    // Allow for clients whose clocks are slightly behind.
+    params.not_before = now - Duration::from_secs(60);
+    params.not_after = now + valid_for;
+    Ok(params)
+}
+
+/// Certificate parameters with the common name and SANs in `names`.
+fn params(names: &CertNames) -> Result<CertificateParams> {
+    let dns_names = split_list(names.alt_names.as_deref())
+        .map(String::from)
+        .collect::<Vec<_>>();
+    let mut params = CertificateParams::new(dns_names).map_err(|e| {
+        Error::KeyGen(format!("invalid alt name for {}: {e}", names.common_name))
+    })?;
+
+    for ip in split_list(names.ip_sans.as_deref()) {
+        let ip: IpAddr = ip.parse().map_err(|e| {
+            Error::KeyGen(format!("invalid IP SAN '{ip}' for {}: {e}", names.common_name))
+        })?;
+        params.subject_alt_names.push(SanType::IpAddress(ip));
+    }
+
+    for uri in split_list(names.uri_sans.as_deref()) {
+        let uri = Ia5String::try_from(uri).map_err(|e| {
+            Error::KeyGen(format!("invalid URI SAN '{uri}' for {}: {e}", names.common_name))
+        })?;
+        params.subject_alt_names.push(SanType::URI(uri));
+    }
+
+    let mut subject = DistinguishedName::new();
+    subject.push(DnType::CommonName, names.common_name.as_str());
+    params.distinguished_name = subject;
+    Ok(params)
+}
+
+fn split_list(value: Option<&str>) -> impl Iterator<Item = &str> {
+    value
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+}
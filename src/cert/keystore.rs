@@ -0,0 +1,182 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use der::asn1::{AnyRef, ObjectIdentifier, OctetStringRef};
+use der::{Decode, Encode, Sequence};
+use p12_keystore::{Certificate, KeyStore, KeyStoreEntry, PrivateKeyChain};
+use pkcs8::spki::AlgorithmIdentifierRef;
+use pkcs8::PrivateKeyInfo;
+use ring::digest::{self, SHA1_FOR_LEGACY_USE_ONLY};
+use ring::rand::{SecureRandom, SystemRandom};
+use rustls::pki_types::PrivateKeyDer;
+use sec1::EcPrivateKey;
+use zeroize::Zeroizing;
+
+use crate::cert::info::parse_pem_chain;
+use crate::cert::issuer::CertBundle;
+use crate::cert::manager::parse_private_key;
+use crate::error::{Error, Result};
+
+const RSA_ENCRYPTION: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113549.1.1.1");
+const EC_PUBLIC_KEY: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.10045.2.1");
+
+const JKS_MAGIC: u32 = 0xFEED_FEED;
+const JKS_VERSION: u32 = 2;
+const JKS_PRIVATE_KEY_ENTRY: u32 = 1;
+/// Sun's proprietary key protection algorithm, the only one JKS supports.
+const JKS_KEY_PROTECTOR: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.3.6.1.4.1.42.2.17.1.1");
+const JKS_SALT_LEN: usize = 20;
+
+/// A PKCS#12 keystore holding the private key and certificate chain under
+/// `alias`, encrypted with PBES2 (AES-256) and MACed with HMAC-SHA256.
+///
+/// Java reads these natively since 8u301.
+pub fn pkcs12(bundle: &CertBundle, alias: &str, password: &str) -> Result<Vec<u8>> {
+    let chain = parse_pem_chain(&bundle.certificate)?
+        .iter()
+        .map(|cert| Certificate::from_der(cert))
+        .collect::<std::result::Result<Vec<_>, _>>()
+        .map_err(|e| Error::Keystore(format!("invalid certificate: {e}")))?;
+    let key = pkcs8_der(&bundle.private_key)?;
+    // Ties the key to the leaf, the way OpenSSL and keytool do.
+    let local_key_id = digest::digest(&SHA1_FOR_LEGACY_USE_ONLY, chain[0].as_der());
+
+    let mut store = KeyStore::new();
+    store.add_entry(
+        alias,
+        KeyStoreEntry::PrivateKeyChain(PrivateKeyChain::new(key, local_key_id, chain)),
+    );
+    store
+        .writer(password)
+        .write()
+        .map_err(|e| Error::Keystore(format!("failed to write PKCS#12: {e}")))
+}
+
+/// A JKS keystore holding one private key entry under `alias`, for Java
+/// runtimes too old for PKCS#12.
+///
+/// JKS can only protect keys with Sun's SHA-1 based scheme, so prefer
+/// [`pkcs12`] where the runtime allows it.
+pub fn jks(bundle: &CertBundle, alias: &str, password: &str) -> Result<Vec<u8>> {
+    let chain = parse_pem_chain(&bundle.certificate)?;
+    let key = pkcs8_der(&bundle.private_key)?;
+    // Java's JKS passwords are UTF-16 code units, hashed big-endian.
+    let password: Vec<u8> = password.encode_utf16().flat_map(u16::to_be_bytes).collect();
+    let protected = protect_key(&key, &password)?;
+    let created = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64;
+
+    let mut out = Vec::new();
+    out.extend(JKS_MAGIC.to_be_bytes());
+    out.extend(JKS_VERSION.to_be_bytes());
+    out.extend(1u32.to_be_bytes());
+    out.extend(JKS_PRIVATE_KEY_ENTRY.to_be_bytes());
+    // keytool lower-cases aliases and would not find a mixed-case one.
+    put_utf(&mut out, &alias.to_lowercase())?;
+    out.extend(created.to_be_bytes());
+    put_bytes(&mut out, &protected)?;
+    out.extend((chain.len() as u32).to_be_bytes());
+    for cert in &chain {
+        put_utf(&mut out, "X.509")?;
+        put_bytes(&mut out, cert)?;
+    }
+
+    let mut integrity = digest::Context::new(&SHA1_FOR_LEGACY_USE_ONLY);
+    integrity.update(&password);
+    integrity.update(b"Mighty Aphrodite");
+    integrity.update(&out);
+    out.extend(integrity.finish().as_ref());
+    Ok(out)
+}
+
+/// The private key as PKCS#8, which both formats require, as does an
+/// encrypted `key` file. Vault returns
+/// PKCS#1 for RSA and SEC1 for EC keys unless asked otherwise.
+pub fn pkcs8_der(key_pem: &str) -> Result<Zeroizing<Vec<u8>>> {
+    let der = match parse_private_key(key_pem)? {
+        PrivateKeyDer::Pkcs8(key) => Ok(key.secret_pkcs8_der().to_vec()),
+        PrivateKeyDer::Pkcs1(key) => {
+            wrap_pkcs8(RSA_ENCRYPTION, AnyRef::NULL, key.secret_pkcs1_der())
+        }
+        PrivateKeyDer::Sec1(key) => {
+            let curve = EcPrivateKey::from_der(key.secret_sec1_der())
+                .ok()
+                .and_then(|ec| ec.parameters)
+                .and_then(|params| params.named_curve())
+                .ok_or_else(|| Error::Keystore("EC private key does not name its curve".into()))?;
+            wrap_pkcs8(EC_PUBLIC_KEY, AnyRef::from(&curve), key.secret_sec1_der())
+        }
+        _ => Err(Error::Keystore("unsupported private key format".into())),
+    };
+    der.map(Zeroizing::new)
+}
+
+fn wrap_pkcs8(oid: ObjectIdentifier, parameters: AnyRef<'_>, key: &[u8]) -> Result<Vec<u8>> {
+    let algorithm = AlgorithmIdentifierRef {
+        oid,
+        parameters: Some(parameters),
+    };
+    PrivateKeyInfo::new(algorithm, key)
+        .to_der()
+        .map_err(|e| Error::Keystore(format!("failed to encode private key: {e}")))
+}
+
+#[derive(Sequence)]
+struct EncryptedPrivateKeyInfo<'a> {
+    algorithm: AlgorithmIdentifierRef<'a>,
+    encrypted_data: OctetStringRef<'a>,
+}
+
+/// Encrypt `key` the way `sun.security.provider.KeyProtector` does: XOR
+/// with a chain of salted SHA-1 digests of the password, followed by a
+/// SHA-1 check over the password and plaintext.
+fn protect_key(key: &[u8], password: &[u8]) -> Result<Vec<u8>> {
+    let mut salt = [0u8; JKS_SALT_LEN];
+    SystemRandom::new()
+        .fill(&mut salt)
+        .map_err(|_| Error::Keystore("failed to generate salt".into()))?;
+
+    let mut protected = salt.to_vec();
+    let mut block = salt.to_vec();
+    for chunk in key.chunks(JKS_SALT_LEN) {
+        let mut ctx = digest::Context::new(&SHA1_FOR_LEGACY_USE_ONLY);
+        ctx.update(password);
+        ctx.update(&block);
+        block = ctx.finish().as_ref().to_vec();
+        protected.extend(chunk.iter().zip(&block).map(|(k, x)| k ^ x));
+    }
+    let mut check = digest::Context::new(&SHA1_FOR_LEGACY_USE_ONLY);
+    check.update(password);
+    check.update(key);
+    protected.extend(check.finish().as_ref());
+
+    let encode_error =
+        |e: der::Error| Error::Keystore(format!("failed to encode private key: {e}"));
+    EncryptedPrivateKeyInfo {
+        algorithm: AlgorithmIdentifierRef {
+            oid: JKS_KEY_PROTECTOR,
+            parameters: Some(AnyRef::NULL),
+        },
+        encrypted_data: OctetStringRef::new(&protected).map_err(encode_error)?,
+    }
+    .to_der()
+    .map_err(encode_error)
+}
+
+/// A string as `DataOutputStream.writeUTF` writes it.
+fn put_utf(out: &mut Vec<u8>, s: &str) -> Result<()> {
+    let len = u16::try_from(s.len())
+        .map_err(|_| Error::Keystore(format!("'{s}' is too long for a JKS string")))?;
+    out.extend(len.to_be_bytes());
+    out.extend(s.as_bytes());
+    Ok(())
+}
+
+fn put_bytes(out: &mut Vec<u8>, bytes: &[u8]) -> Result<()> {
+    let len = u32::try_from(bytes.len())
+        .map_err(|_| Error::Keystore("entry is too large for a JKS keystore".into()))?;
+    out.extend(len.to_be_bytes());
+    out.extend(bytes);
+    Ok(())
+}
@@ -0,0 +1,289 @@
+use std::time::Duration;
+
+use reqwest::{Method, StatusCode};
+use serde::Deserialize;
+use serde_json::json;
+use time::OffsetDateTime;
+use tokio::sync::watch;
+use tokio::time::Instant;
+use tracing::{debug, info, instrument, warn};
+
+use crate::cert::k8s::{namespace_or_own, Api};
+use crate::config::Config;
+use crate::error::{Error, Result};
+use crate::metrics;
+
+#[derive(Debug, Deserialize)]
+struct Lease {
+    metadata: Metadata,
+    #[serde(default)]
+    spec: LeaseSpec,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Metadata {
+    resource_version: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LeaseSpec {
+    #[serde(default)]
+    holder_identity: Option<String>,
+    #[serde(default)]
+    lease_duration_seconds: Option<u64>,
+    #[serde(default)]
+    acquire_time: Option<String>,
+    #[serde(default)]
+    renew_time: Option<String>,
+    #[serde(default)]
+    lease_transitions: Option<u64>,
+}
+
+/// One replica's part in the election for `LEADER_ELECTION_LEASE`, a
+/// `coordination.k8s.io` Lease.
+///
+/// Whoever holds the Lease leads. The leader renews it every third of
+/// `LEADER_ELECTION_LEASE_DURATION`; the others try to take it over as
+/// often, and do once it has gone that long without being renewed. How
+/// long is measured from when this replica last saw the Lease change, not
+/// from its `renewTime`, so clock skew between nodes does not matter.
+pub struct Elector {
+    lease: String,
+    namespace: Option<String>,
+    identity: String,
+    duration: Duration,
+    /// The Lease as last seen held by someone else, and when it was seen
+    /// to change.
+    observed: Option<(LeaseSpec, Instant)>,
+}
+
+impl Elector {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            lease: config.leader_election_lease.clone(),
+            namespace: config.leader_election_namespace.clone(),
+            identity: config.leader_election_identity.clone(),
+            duration: config.leader_election_lease_duration,
+            observed: None,
+        }
+    }
+
+    /// Take part in the election until shutdown, sending on `leader`
+    /// whether this replica leads, then give the Lease up if it holds it
+    /// so another replica takes over straight away. The outcome of the
+    /// first attempt is always sent, even if this replica does not lead.
+    ///
+    /// A leader that cannot renew the Lease for two thirds of its duration
+    /// steps down, before anyone else can take it over.
+    pub async fn run(mut self, leader: watch::Sender<bool>, mut shutdown: watch::Receiver<bool>) {
+        let retry = self.duration / 3;
+        let mut renewed: Option<Instant> = None;
+        let mut decided = false;
+        loop {
+            match self.try_acquire_or_renew().await {
+                Ok(true) => renewed = Some(Instant::now()),
+                Ok(false) => renewed = None,
+                Err(e) => {
+                    warn!(error = %e, code = e.code(), lease = %self.lease, "failed to update the leader election Lease")
+                }
+            }
+            let leads = renewed.is_some_and(|at| at.elapsed() < self.duration * 2 / 3);
+            if !leads {
+                renewed = None;
+            }
+            let first = !std::mem::replace(&mut decided, true);
+            leader.send_if_modified(|current| {
+                match (*current, leads) {
+                    (false, true) => info!(lease = %self.lease, identity = %self.identity, "became the leader"),
+                    (true, false) => warn!(lease = %self.lease, identity = %self.identity, "no longer the leader"),
+                    (false, false) if first => info!(lease = %self.lease, identity = %self.identity, "another replica leads, standing by"),
+                    _ => return false,
+                }
+                *current = leads;
+                true
+            });
+            metrics::set("leader", &self.lease, if leads { 1.0 } else { 0.0 });
+
+            tokio::select! {
+                _ = tokio::time::sleep(retry) => {}
+                _ = shutdown.changed() => break,
+            }
+        }
+
+        if *leader.borrow() {
+            leader.send_replace(false);
+            match self.release().await {
+                Ok(()) => info!(lease = %self.lease, "leader election Lease released"),
+                Err(e) => {
+                    warn!(error = %e, code = e.code(), lease = %self.lease, "failed to release the leader election Lease")
+                }
+            }
+        }
+    }
+
+    /// Take the Lease if it is free or has expired, or renew it if this
+    /// replica holds it. Returns whether it does now.
+    #[instrument(name = "lease_update", skip_all, fields(lease = %self.lease))]
+    async fn try_acquire_or_renew(&mut self) -> Result<bool> {
+        let api = Api::connect().await?;
+        let namespace = namespace_or_own(self.namespace.as_deref()).await?;
+        let path = self.path(&namespace);
+        let now = micro_time(OffsetDateTime::now_utc());
+
+        let response = api.request(Method::GET, &path).send().await?;
+        if response.status() == StatusCode::NOT_FOUND {
+            let body = json!({
+                "apiVersion": "coordination.k8s.io/v1",
+                "kind": "Lease",
+                "metadata": { "name": self.lease, "namespace": namespace },
+                "spec": {
+                    "holderIdentity": self.identity,
+                    "leaseDurationSeconds": self.duration.as_secs(),
+                    "acquireTime": now,
+                    "renewTime": now,
+                    "leaseTransitions": 0,
+                },
+            });
+            let path = format!("/apis/coordination.k8s.io/v1/namespaces/{namespace}/leases");
+            let response = api.request(Method::POST, &path).json(&body).send().await?;
+            // Another replica created it first.
+            if response.status() == StatusCode::CONFLICT {
+                return Ok(false);
+            }
+            checked(response, "creating", &namespace, &self.lease).await?;
+            return Ok(true);
+        }
+        let lease: Lease = checked(response, "reading", &namespace, &self.lease)
+            .await?
+            .json()
+            .await?;
+
+        let spec = lease.spec;
+        let holder = spec.holder_identity.as_deref().filter(|h| !h.is_empty());
+        let ours = holder == Some(self.identity.as_str());
+        if !ours {
+            let unchanged = self
+                .observed
+                .as_ref()
+                .is_some_and(|(seen, _)| *seen == spec);
+            if !unchanged {
+                self.observed = Some((spec.clone(), Instant::now()));
+            }
+            let duration = spec
+                .lease_duration_seconds
+                .map_or(self.duration, Duration::from_secs);
+            let seen_at = self.observed.as_ref().map(|(_, at)| *at);
+            let expired = seen_at.is_some_and(|at| at.elapsed() >= duration);
+            if holder.is_some() && !expired {
+                debug!(
+                    holder = holder.unwrap_or_default(),
+                    "the Lease is held by another replica"
+                );
+                return Ok(false);
+            }
+        }
+
+        let transitions = spec.lease_transitions.unwrap_or(0);
+        let body = json!({
+            "apiVersion": "coordination.k8s.io/v1",
+            "kind": "Lease",
+            // The resource version makes the update fail if another replica
+            // got there first.
+            "metadata": {
+                "name": self.lease,
+                "namespace": namespace,
+                "resourceVersion": lease.metadata.resource_version,
+            },
+            "spec": {
+                "holderIdentity": self.identity,
+                "leaseDurationSeconds": self.duration.as_secs(),
+                "acquireTime": if ours { spec.acquire_time.unwrap_or_else(|| now.clone()) } else { now.clone() },
+                "renewTime": now,
+                "leaseTransitions": if ours || holder.is_none() { transitions } else { transitions + 1 },
+            },
+        });
+        let response = api.request(Method::PUT, &path).json(&body).send().await?;
+        if response.status() == StatusCode::CONFLICT {
+            return Ok(false);
+        }
+        checked(response, "updating", &namespace, &self.lease).await?;
+        if !ours {
+            self.observed = None;
+        }
+        Ok(true)
+    }
+
+    /// Clear the holder of the Lease, if it is still this replica.
+    async fn release(&self) -> Result<()> {
+        let api = Api::connect().await?;
+        let namespace = namespace_or_own(self.namespace.as_deref()).await?;
+        let path = self.path(&namespace);
+        let response = api.request(Method::GET, &path).send().await?;
+        let lease: Lease = checked(response, "reading", &namespace, &self.lease)
+            .await?
+            .json()
+            .await?;
+        if lease.spec.holder_identity.as_deref() != Some(self.identity.as_str()) {
+            return Ok(());
+        }
+        let body = json!({
+            "apiVersion": "coordination.k8s.io/v1",
+            "kind": "Lease",
+            "metadata": {
+                "name": self.lease,
+                "namespace": namespace,
+                "resourceVersion": lease.metadata.resource_version,
+            },
+            "spec": {
+                "holderIdentity": "",
+                "leaseDurationSeconds": 1,
+                "acquireTime": lease.spec.acquire_time,
+                "renewTime": micro_time(OffsetDateTime::now_utc()),
+                "leaseTransitions": lease.spec.lease_transitions.unwrap_or(0),
+            },
+        });
+        let response = api.request(Method::PUT, &path).json(&body).send().await?;
+        checked(response, "releasing", &namespace, &self.lease).await?;
+        Ok(())
+    }
+
+    fn path(&self, namespace: &str) -> String {
+        format!(
+            "/apis/coordination.k8s.io/v1/namespaces/{namespace}/leases/{}",
+            self.lease
+        )
+    }
+}
+
+/// `response` if it succeeded, else an error for `action` on the Lease.
+async fn checked(
+    response: reqwest::Response,
+    action: &str,
+    namespace: &str,
+    lease: &str,
+) -> Result<reqwest::Response> {
+    if response.status().is_success() {
+        return Ok(response);
+    }
+    let status = response.status();
+    let body = response.text().await.unwrap_or_default();
+    Err(Error::LeaderElection(format!(
+        "{action} Lease {namespace}/{lease} returned {status}: {body}"
+    )))
+}
+
+/// A Kubernetes `MicroTime`, which must have exactly six fractional digits.
+fn micro_time(at: OffsetDateTime) -> String {
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:06}Z",
+        at.year(),
+        u8::from(at.month()),
+        at.day(),
+        at.hour(),
+        at.minute(),
+        at.second(),
+        at.microsecond()
+    )
+}
@@ -1,143 +1,1475 @@
 use std::sync::Arc;
 use std::time::Duration;
 
-use rustls::ServerConfig;
-use tokio::sync::watch;
-use tracing::{error, info, warn};
+use ring::rand::{SecureRandom, SystemRandom};
+use rustls::pki_types::PrivateKeyDer;
+use rustls::{RootCertStore, ServerConfig, SupportedCipherSuite, SupportedProtocolVersion};
+use rustls::server::danger::ClientCertVerifier;
+use rustls::sign::CertifiedKey;
+use serde::Serialize;
+use time::OffsetDateTime;
+use tokio::sync::{mpsc, oneshot, watch};
+use tokio::time::{Instant, MissedTickBehavior};
+use tracing::{debug, error, info, info_span, warn, Instrument};
 
-use crate::config::Config;
+use crate::config::{CertIssuer, CertNames, CertProfile, Config, KeyEncryption, TlsVersion};
 use crate::error::{Error, Result};
+use crate::cert::audit::{self, AuditEvent, AuditLog, AuditRecord, Trigger};
+use crate::cert::client_auth::{self, Crls};
+use crate::cert::hook;
+use crate::cert::info::{format_time, parse_pem_chain, CertInfo};
+use crate::cert::issuer::{self, CertBundle, CertRequest, Issuer};
+use crate::cert::keygen;
+use crate::cert::resolver::{CertResolver, SniResolver};
+use crate::cert::resumption::Resumption;
 use crate::cert::store::CertStore;
-use crate::vault::auth;
+use crate::cert::validate::{self, covers};
+use crate::cert::webhook::{Event, EventError, EventKind, Webhook};
+use crate::dns::Resolver;
+use crate::metrics;
 use crate::vault::client::VaultClient;
 use crate::vault::pki;
 
 /// Manages the certificate lifecycle: initial fetch, hot-reload, and renewal.
 pub struct CertManager {
     client: Arc<VaultClient>,
+    issuer: Arc<dyn Issuer>,
     config: Config,
-    store: CertStore,
+    /// The primary certificate first, then one per `CERT_SNI_GROUPS` entry,
+    /// then one per `CERT_PROFILES` entry.
+    certs: Vec<ManagedCert>,
     tx: watch::Sender<Option<Arc<ServerConfig>>>,
+    /// The primary certificate and key, for presenting to a TLS backend.
+    issued_tx: watch::Sender<Option<Arc<CertifiedKey>>>,
+    /// The served certificates without the TLS policy, for [`CertResolver`].
+    resolver_tx: watch::Sender<Option<Arc<SniResolver>>>,
+    client_roots: Option<Arc<RootCertStore>>,
+    client_crls: Crls,
+    /// Kept across server configs, so renewals do not end sessions.
+    resumption: Resumption,
+    status_tx: watch::Sender<Vec<CertStatus>>,
+    webhook: Option<Webhook>,
+    audit: Option<AuditLog>,
+    /// Superseded certificates waiting to be revoked, with
+    /// `CERT_REVOKE_SUPERSEDED`.
+    revocations: Vec<Revocation>,
+    /// Whether this replica leads, with `LEADER_ELECTION`.
+    leader: Option<watch::Receiver<bool>>,
+    command_tx: mpsc::Sender<ManagerCommand>,
+    commands: mpsc::Receiver<ManagerCommand>,
+}
+
+/// Requests to the renewal loop from outside it.
+pub enum ManagerCommand {
+    /// Re-issue every certificate now. The reply carries the first failure.
+    Renew(oneshot::Sender<Result<()>>),
+    /// Serve with this `TLS_MIN_VERSION` and these `TLS_CIPHER_SUITES`
+    /// from now on, as reloaded from the config file. Handshakes already
+    /// started keep the old policy.
+    SetTlsPolicy(TlsVersion, Vec<SupportedCipherSuite>),
+}
+
+/// What the admin API reports about one certificate.
+#[derive(Debug, Clone, Serialize)]
+pub struct CertStatus {
+    pub common_name: String,
+    pub serial: Option<String>,
+    pub not_after: Option<String>,
+    pub next_renewal: String,
+    pub last_renewal: Option<RenewalResult>,
+    /// A self-signed bootstrap certificate is being served.
+    pub bootstrap: bool,
+    /// The `CERT_PROFILES` name, for a certificate that is not served.
+    pub profile: Option<String>,
+    /// The served leaf in full, for `/cert`; `/status` keeps to the above.
+    #[serde(skip)]
+    pub certificate: Option<CertInfo>,
+}
+
+/// Outcome of the most recent renewal attempt; the initial issuance does
+/// not count.
+#[derive(Debug, Clone, Serialize)]
+pub struct RenewalResult {
+    pub at: String,
+    pub ok: bool,
+    pub error: Option<String>,
+    pub code: Option<&'static str>,
+}
+
+/// One certificate and its own renewal schedule.
+struct ManagedCert {
+    names: CertNames,
+    store: CertStore,
+    /// For a `CERT_PROFILES` certificate, its name and the configuration it
+    /// is issued with. These are written out, and only served by the
+    /// `LISTENERS` that name them.
+    profile: Option<(String, Box<Config>)>,
+    /// For a `CERT_PROFILES` certificate, the server config of the listeners
+    /// that serve it.
+    profile_tx: Option<watch::Sender<Option<Arc<ServerConfig>>>>,
+    /// Currently being served, kept so the server config can be rebuilt
+    /// when another certificate or the client CRL changes.
+    served: Option<Arc<CertifiedKey>>,
+    /// `served` is a self-signed bootstrap certificate.
+    bootstrap: bool,
+    /// Unjittered delay the current schedule was computed from; the Vault
+    /// token must last this long.
+    renew_after: Duration,
+    renew_at: Instant,
+    /// Why the issuance at `renew_at` happens, for the audit log.
+    trigger: Trigger,
+    backoff: Duration,
+    last_renewal: Option<RenewalResult>,
+    /// Renewals that failed since the last one that did not.
+    failures: u32,
+    /// Serial of the served certificate last reported as expiring, so each
+    /// one is reported once.
+    expiry_reported: Option<String>,
+}
+
+/// A certificate a renewal replaced, revoked once `CERT_REVOKE_DELAY` has
+/// passed so that anything still holding it has moved on.
+struct Revocation {
+    /// The managed certificate it was served as.
+    index: usize,
+    certificate: CertInfo,
+    /// What triggered the renewal that replaced it.
+    trigger: Trigger,
+    revoke_at: Instant,
+}
+
+const INITIAL_BACKOFF: Duration = Duration::from_secs(5);
+const MAX_BACKOFF: Duration = Duration::from_secs(300);
+/// Replaced once half of it has passed; retries come at least every
+/// `MAX_BACKOFF`, so it is never served expired.
+const BOOTSTRAP_VALIDITY: Duration = Duration::from_secs(3600);
+/// How long the Vault token must stay valid for a call outside a renewal:
+/// decrypting Transit-encrypted keys on a warm start, or revoking a
+/// superseded certificate.
+const TOKEN_VALIDITY: Duration = Duration::from_secs(60);
+/// How often a replica that does not lead checks the store for
+/// certificates the leader renewed.
+const FOLLOW_INTERVAL: Duration = Duration::from_secs(10);
+
+impl ManagedCert {
+    fn new(names: CertNames, dir: &str, config: &Config, client: &Arc<VaultClient>) -> Self {
+        let store = CertStore::new(dir, config)
+            .with_secret(config.secret_name(&names.common_name))
+            .with_vault(client.clone());
+        Self {
+            names,
+            store,
+            profile: None,
+            profile_tx: None,
+            served: None,
+            bootstrap: false,
+            renew_after: Duration::ZERO,
+            renew_at: Instant::now(),
+            trigger: Trigger::Startup,
+            backoff: INITIAL_BACKOFF,
+            last_renewal: None,
+            failures: 0,
+            expiry_reported: None,
+        }
+    }
+
+    fn for_profile(profile: &CertProfile, config: &Config, client: &Arc<VaultClient>) -> Self {
+        let store = CertStore::new(&profile.dir, config)
+            .with_secret(config.profile_secret_name(profile))
+            .with_vault(client.clone());
+        Self {
+            store,
+            profile: Some((profile.name.clone(), Box::new(config.for_profile(profile)))),
+            profile_tx: Some(watch::Sender::new(None)),
+            ..Self::new(profile.names.clone(), &profile.dir, config, client)
+        }
+    }
+
+    /// The configuration to issue this certificate with.
+    fn config<'a>(&'a self, config: &'a Config) -> &'a Config {
+        self.profile.as_ref().map_or(config, |(_, config)| config)
+    }
+
+    /// Whether the proxy serves this certificate on `LISTEN_ADDR`.
+    fn serves(&self) -> bool {
+        self.profile.is_none()
+    }
+
+    fn schedule(&mut self, renew_after: Duration, jitter: f64) {
+        // Replicas issued at the same moment would otherwise all renew at
+        // the same moment too.
+        let sleep_for = jittered(renew_after, jitter);
+        info!(
+            common_name = %self.names.common_name,
+            renew_in_secs = sleep_for.as_secs(),
+            "scheduling next certificate renewal"
+        );
+        self.renew_after = renew_after;
+        self.renew_at = Instant::now() + sleep_for;
+        self.trigger = Trigger::Scheduled;
+        self.backoff = INITIAL_BACKOFF;
+    }
+
+    /// The certificate a previous run left in the store, if it is still
+    /// valid and was issued for these names.
+    async fn previous(&self) -> Result<Option<(CertifiedKey, CertInfo)>> {
+        let Some((chain, key)) = self.store.read().await? else {
+            return Ok(None);
+        };
+        let key = certified_key(&chain, &key)?;
+        let leaf = CertInfo::from_der(&parse_pem_chain(&chain)?[0])?;
+        let reason = if leaf.remaining_secs() <= 0 {
+            "it has expired"
+        } else if !covers(&leaf, &self.names) {
+            "it was issued for other names"
+        } else {
+            return Ok(Some((key, leaf)));
+        };
+        info!(
+            common_name = %self.names.common_name,
+            serial = %leaf.serial,
+            "not serving the previous certificate, {reason}"
+        );
+        Ok(None)
+    }
+
+    fn retry_later(&mut self, jitter: f64) {
+        self.renew_at = Instant::now() + jittered(self.backoff, jitter);
+        self.trigger = Trigger::Retry;
+        self.backoff = (self.backoff * 2).min(MAX_BACKOFF);
+    }
+
+    /// The served leaf certificate, parsed.
+    fn leaf(&self) -> Option<CertInfo> {
+        let key = self.served.as_ref()?;
+        CertInfo::from_der(key.end_entity_cert().ok()?).ok()
+    }
+
+    /// Record a successful issuance, the first one included, for alerting
+    /// on renewals that keep failing.
+    fn record_success(&self) {
+        let now = OffsetDateTime::now_utc().unix_timestamp() as f64;
+        metrics::set(
+            "cert_last_renewal_success_timestamp_seconds",
+            &self.names.common_name,
+            now,
+        );
+    }
+
+    /// An audit record of `event` for this certificate, to be filled in.
+    fn audit_record(&self, event: AuditEvent, trigger: Trigger) -> AuditRecord<'_> {
+        AuditRecord {
+            event,
+            common_name: &self.names.common_name,
+            profile: self.profile.as_ref().map(|(name, _)| name.as_str()),
+            trigger,
+            cert_issuer: None,
+            request_id: None,
+            certificate: None,
+            error: None,
+        }
+    }
+
+    fn status(&self) -> CertStatus {
+        let leaf = self.leaf();
+        let next_renewal =
+            OffsetDateTime::now_utc() + self.renew_at.saturating_duration_since(Instant::now());
+        CertStatus {
+            common_name: self.names.common_name.clone(),
+            serial: leaf.as_ref().map(|l| l.serial.clone()),
+            not_after: leaf.as_ref().map(|l| format_time(l.not_after)),
+            next_renewal: format_time(next_renewal),
+            last_renewal: self.last_renewal.clone(),
+            bootstrap: self.bootstrap,
+            profile: self.profile.as_ref().map(|(name, _)| name.clone()),
+            certificate: leaf,
+        }
+    }
 }
 
 impl CertManager {
     pub fn new(
         client: Arc<VaultClient>,
+        issuer: Arc<dyn Issuer>,
         config: Config,
         tx: watch::Sender<Option<Arc<ServerConfig>>>,
+        webhook: Option<Webhook>,
+        audit: Option<AuditLog>,
     ) -> Self {
-        let store = CertStore::new(&config.cert_dir);
+        let mut certs = vec![ManagedCert::new(
+            config.primary_cert(),
+            &config.cert_dir,
+            &config,
+            &client,
+        )];
+        for group in &config.cert_sni_groups {
+            let dir = format!("{}/{}", config.cert_dir, group.common_name);
+            certs.push(ManagedCert::new(group.clone(), &dir, &config, &client));
+        }
+        for profile in &config.cert_profiles {
+            certs.push(ManagedCert::for_profile(profile, &config, &client));
+        }
+
+        let (command_tx, commands) = mpsc::channel(8);
+        let resumption = Resumption::new(&config);
+
         Self {
             client,
+            issuer,
             config,
-            store,
+            certs,
             tx,
+            issued_tx: watch::Sender::new(None),
+            resolver_tx: watch::Sender::new(None),
+            client_roots: None,
+            client_crls: Vec::new(),
+            resumption,
+            status_tx: watch::Sender::new(Vec::new()),
+            webhook,
+            audit,
+            revocations: Vec::new(),
+            leader: None,
+            command_tx,
+            commands,
         }
     }
 
-    /// Perform initial Vault login and certificate fetch, then return.
-    pub async fn init(&self) -> Result<u64> {
-        auth::kubernetes_login(&self.client, &self.config).await?;
-        let bundle = pki::issue_certificate(&self.client, &self.config).await?;
+    /// A manager with its own resolver, Vault client, and issuer, for
+    /// programs that embed cert-keeper and serve the configs sent on `tx`
+    /// themselves. `tls-alpn-01` cannot be answered without the proxy.
+    pub fn from_config(
+        config: Config,
+        tx: watch::Sender<Option<Arc<ServerConfig>>>,
+    ) -> Result<Self> {
+        let resolver = Resolver::from_config(&config)?;
+        let client = Arc::new(VaultClient::new(&config, resolver.clone())?);
+        let issuer = issuer::from_config(&config, client.clone(), resolver.clone(), None)?;
+        let webhook = Webhook::new(&config, resolver)?;
+        let audit = AuditLog::new(&config)?;
+        Ok(Self::new(client, issuer, config, tx, webhook, audit))
+    }
+
+    /// Get the issuer ready (for Vault, wait for it and log in) and fetch
+    /// every certificate, scheduling each one's first renewal.
+    ///
+    /// With `LEADER_ELECTION`, a replica that does not lead serves what the
+    /// leader writes instead, waiting for it if need be, as is done with
+    /// `CERT_ISSUER=external` for what the external issuer writes.
+    pub async fn init(&mut self) -> Result<()> {
+        self.issuer.prepare(&self.config).await?;
+        if self.follow().await {
+            return Ok(());
+        }
+        self.load_client_trust().await?;
 
-        self.store.write(&bundle).await?;
-        let server_config = build_server_config(&bundle.certificate, &bundle.private_key)?;
-        let _ = self.tx.send(Some(Arc::new(server_config)));
+        for index in 0..self.certs.len() {
+            let cert = &self.certs[index];
+            let req = CertRequest {
+                config: cert.config(&self.config),
+                names: &cert.names,
+            };
+            let result = issuer::issue(&*self.issuer, req).await;
+            self.audit_issue(index, Trigger::Startup, result.as_ref());
+            let bundle = result?;
+            cert.store.write(&bundle).await?;
+            let key = certified_key(&bundle.certificate, &bundle.private_key)?;
+            let delay = self.renewal_delay(&bundle, &cert.names.common_name);
+            drop(bundle);
+            let cert = &mut self.certs[index];
+            cert.served = Some(Arc::new(key));
+            cert.schedule(delay, self.config.renewal_jitter);
+            cert.record_success();
+        }
 
-        Ok(bundle.lease_duration_secs)
+        self.publish()?;
+        self.report();
+        Ok(())
     }
 
-    /// Run the renewal loop. This should be spawned as a background task.
-    pub async fn run_renewal_loop(self, initial_lease_secs: u64, mut shutdown: watch::Receiver<bool>) {
-        let mut lease_secs = initial_lease_secs;
-        let mut backoff = Duration::from_secs(5);
-        let max_backoff = Duration::from_secs(300);
+    /// Serve the certificates a previous run left in `CERT_DIR`, so the
+    /// proxy has something to serve while the issuer is unreachable.
+    ///
+    /// Only done if every served certificate is there, unexpired, and
+    /// issued for its current names, and the client CA can be loaded.
+    /// Returns whether it was; if so, [`init`](Self::init) is skipped and
+    /// the renewal loop replaces each certificate straight away, retrying
+    /// until the issuer answers. A `CERT_PROFILES` certificate that is
+    /// missing is simply issued then.
+    pub async fn warm_start(&mut self) -> bool {
+        self.config.cert_warm_start && self.serve_stored().await
+    }
 
-        loop {
-            let renew_after = Duration::from_secs(
-                (lease_secs as f64 * self.config.renewal_threshold) as u64
-            );
+    /// Serve the certificates in the store, as for
+    /// [`warm_start`](Self::warm_start).
+    async fn serve_stored(&mut self) -> bool {
+        let transit = matches!(
+            self.config.cert_key_encryption,
+            Some(KeyEncryption::Transit { .. })
+        );
+        if transit {
+            if let Err(e) = self.issuer.refresh(&self.config, TOKEN_VALIDITY).await {
+                warn!(error = %e, code = e.code(), "could not log in to Vault to decrypt the previous keys, waiting for new certificates");
+                return false;
+            }
+        }
+        let mut previous = Vec::with_capacity(self.certs.len());
+        for cert in &self.certs {
+            let found = match cert.previous().await {
+                Ok(found) => found,
+                Err(e) => {
+                    warn!(
+                        error = %e,
+                        code = e.code(),
+                        common_name = %cert.names.common_name,
+                        "could not load the previous certificate, waiting for a new one"
+                    );
+                    None
+                }
+            };
+            if found.is_none() && cert.serves() {
+                return false;
+            }
+            previous.push(found);
+        }
+        // Without the client CA, client certificates would go unchecked.
+        if let Err(e) = self.load_client_trust().await {
+            warn!(error = %e, code = e.code(), "could not load the client CA, not serving the previous certificates");
+            return false;
+        }
 
-            info!(
-                renew_in_secs = renew_after.as_secs(),
-                lease_secs,
-                "scheduling next certificate renewal"
-            );
+        let threshold = self.config.renewal_threshold;
+        let leads = self.leads();
+        let external = self.external();
+        for (cert, found) in self.certs.iter_mut().zip(previous) {
+            let Some((key, leaf)) = found else {
+                continue;
+            };
+            if cert.serves() && external {
+                info!(
+                    common_name = %cert.names.common_name,
+                    serial = %leaf.serial,
+                    not_after = %format_time(leaf.not_after),
+                    "serving the externally issued certificate"
+                );
+            } else if cert.serves() && !leads {
+                info!(
+                    common_name = %cert.names.common_name,
+                    serial = %leaf.serial,
+                    not_after = %format_time(leaf.not_after),
+                    "serving the certificate the leader issued"
+                );
+            } else if cert.serves() {
+                info!(
+                    common_name = %cert.names.common_name,
+                    serial = %leaf.serial,
+                    not_after = %format_time(leaf.not_after),
+                    "serving the certificate from a previous run until a new one is issued"
+                );
+            }
+            let remaining = leaf.not_after - OffsetDateTime::now_utc();
+            cert.served = Some(Arc::new(key));
+            cert.renew_after = (remaining * threshold).try_into().unwrap_or(Duration::ZERO);
+            cert.renew_at = Instant::now();
+            cert.trigger = Trigger::WarmStart;
+        }
+        if let Err(e) = self.publish() {
+            warn!(error = %e, code = e.code(), "could not serve the previous certificates");
+            for cert in &mut self.certs {
+                cert.served = None;
+            }
+            return false;
+        }
+        for index in 0..self.certs.len() {
+            if self.certs[index].served.is_some() {
+                self.audit_served(index, AuditEvent::Loaded, Trigger::Startup);
+            }
+        }
+        self.report();
+        true
+    }
+
+    /// Only issue certificates while `leader` is true, with
+    /// `LEADER_ELECTION`. Otherwise the ones the leader writes to the store
+    /// are served, and the renewal hook runs whenever they change.
+    pub fn follow_leader(&mut self, leader: watch::Receiver<bool>) {
+        self.leader = Some(leader);
+    }
+
+    /// Whether this replica issues certificates: it leads, or there is no
+    /// leader election, and they are not issued externally.
+    fn leads(&self) -> bool {
+        !self.external() && self.leader.as_ref().is_none_or(|leader| *leader.borrow())
+    }
+
+    /// Whether another agent issues the certificates, with
+    /// `CERT_ISSUER=external`, and they are only ever read from the store.
+    fn external(&self) -> bool {
+        self.config.cert_issuer == CertIssuer::External
+    }
+
+    /// How often the store is checked for certificates this replica does
+    /// not issue.
+    fn follow_interval(&self) -> Duration {
+        if self.external() {
+            self.config.cert_watch_interval
+        } else {
+            FOLLOW_INTERVAL
+        }
+    }
+
+    /// Until this replica leads, serve the certificates the leader wrote,
+    /// waiting for it to write them. Returns whether they are served. With
+    /// `CERT_ISSUER=external` that is forever, and they are written by the
+    /// external issuer.
+    async fn follow(&mut self) -> bool {
+        let interval = self.follow_interval();
+        while !self.leads() {
+            if self.serve_stored().await {
+                return true;
+            }
+            if self.external() {
+                info!(
+                    cert_dir = %self.config.cert_dir,
+                    "waiting for the external issuer to write the certificates"
+                );
+                tokio::time::sleep(interval).await;
+                continue;
+            }
+            info!("waiting for the leader to write the certificates");
+            let Some(ref mut leader) = self.leader else {
+                return false;
+            };
+            tokio::select! {
+                _ = tokio::time::sleep(interval) => {}
+                Ok(_) = leader.wait_for(|leads| *leads) => {}
+            }
+        }
+        false
+    }
+
+    /// Serve a self-signed certificate for each configured certificate's
+    /// names, with `CERT_BOOTSTRAP_SELF_SIGNED`, so health checks and
+    /// clients that skip verification get a handshake while the issuer is
+    /// unreachable. Returns whether it did; if so, [`init`](Self::init) is
+    /// skipped and the renewal loop replaces each certificate straight
+    /// away, retrying until the issuer answers.
+    ///
+    /// The bootstrap certificates are never written to `CERT_DIR` or
+    /// presented to a backend.
+    pub async fn bootstrap(&mut self) -> bool {
+        if !self.config.cert_bootstrap_self_signed {
+            return false;
+        }
+        if let Err(e) = self.load_client_trust().await {
+            warn!(error = %e, code = e.code(), "could not load the client CA, not serving bootstrap certificates");
+            return false;
+        }
+        for index in 0..self.certs.len() {
+            if !self.certs[index].serves() {
+                continue;
+            }
+            if let Err(e) = self.serve_bootstrap(index) {
+                warn!(error = %e, code = e.code(), "could not build bootstrap certificates");
+                for cert in &mut self.certs {
+                    cert.served = None;
+                    cert.bootstrap = false;
+                }
+                return false;
+            }
+            let cert = &mut self.certs[index];
+            cert.renew_after = BOOTSTRAP_VALIDITY;
+            cert.renew_at = Instant::now();
+            cert.trigger = Trigger::Bootstrap;
+        }
+        if let Err(e) = self.publish() {
+            warn!(error = %e, code = e.code(), "could not serve bootstrap certificates");
+            return false;
+        }
+        for index in 0..self.certs.len() {
+            if self.certs[index].bootstrap {
+                self.audit_served(index, AuditEvent::Bootstrap, Trigger::Startup);
+            }
+        }
+        self.report();
+        true
+    }
+
+    /// Serve a new bootstrap certificate as certificate `index`, without
+    /// publishing it.
+    fn serve_bootstrap(&mut self, index: usize) -> Result<()> {
+        let cert = &mut self.certs[index];
+        let (chain, key) = keygen::self_signed(&cert.names, BOOTSTRAP_VALIDITY)?;
+        cert.served = Some(Arc::new(certified_key(&chain, &key)?));
+        cert.bootstrap = true;
+        metrics::inc("bootstrap_certificates", "generated");
+        warn!(
+            common_name = %cert.names.common_name,
+            valid_secs = BOOTSTRAP_VALIDITY.as_secs(),
+            "serving a self-signed bootstrap certificate until one is issued"
+        );
+        Ok(())
+    }
+
+    /// Replace bootstrap certificate `index` before it expires, when the
+    /// issuer still has not answered.
+    fn keep_bootstrap(&mut self, index: usize) {
+        let cert = &self.certs[index];
+        if !cert.bootstrap {
+            return;
+        }
+        let fresh = cert
+            .leaf()
+            .is_some_and(|leaf| leaf.remaining_secs() > (BOOTSTRAP_VALIDITY / 2).as_secs() as i64);
+        if fresh {
+            return;
+        }
+        let previous = cert.served.clone();
+        match self.serve_bootstrap(index).and_then(|()| self.publish()) {
+            Ok(()) => self.audit_served(index, AuditEvent::Bootstrap, Trigger::Scheduled),
+            Err(e) => {
+                error!(error = %e, code = e.code(), "could not replace the bootstrap certificate");
+                self.certs[index].served = previous;
+            }
+        }
+    }
+
+    /// Record the outcome of issuing certificate `index` in the audit log.
+    fn audit_issue(
+        &self,
+        index: usize,
+        trigger: Trigger,
+        result: std::result::Result<&CertBundle, &Error>,
+    ) {
+        let Some(ref log) = self.audit else {
+            return;
+        };
+        let cert = &self.certs[index];
+        let base = cert.audit_record(AuditEvent::Issued, trigger);
+        let cert_issuer = Some(cert.config(&self.config).cert_issuer.name());
+        match result {
+            Ok(bundle) => {
+                let leaf = audit::bundle_leaf(bundle);
+                log.record(&AuditRecord {
+                    cert_issuer,
+                    request_id: bundle.request_id.as_deref(),
+                    certificate: leaf.as_ref(),
+                    ..base
+                });
+            }
+            Err(e) => log.record(&AuditRecord {
+                event: AuditEvent::IssueFailed,
+                cert_issuer,
+                error: Some(EventError {
+                    code: e.code(),
+                    message: e.to_string(),
+                }),
+                ..base
+            }),
+        }
+    }
+
+    /// Record in the audit log that a superseded certificate was revoked,
+    /// or that revoking it failed with `error`.
+    fn audit_revocation(&self, revocation: &Revocation, error: Option<&Error>) {
+        let Some(ref log) = self.audit else {
+            return;
+        };
+        let cert = &self.certs[revocation.index];
+        let event = match error {
+            None => AuditEvent::Revoked,
+            Some(_) => AuditEvent::RevokeFailed,
+        };
+        log.record(&AuditRecord {
+            cert_issuer: Some(cert.config(&self.config).cert_issuer.name()),
+            certificate: Some(&revocation.certificate),
+            error: error.map(|e| EventError {
+                code: e.code(),
+                message: e.to_string(),
+            }),
+            ..cert.audit_record(event, revocation.trigger)
+        });
+    }
+
+    /// Record in the audit log that certificate `index` is now served
+    /// without having been issued.
+    fn audit_served(&self, index: usize, event: AuditEvent, trigger: Trigger) {
+        let Some(ref log) = self.audit else {
+            return;
+        };
+        let cert = &self.certs[index];
+        let leaf = cert.leaf();
+        log.record(&AuditRecord {
+            certificate: leaf.as_ref(),
+            ..cert.audit_record(event, trigger)
+        });
+    }
+
+    /// Load the client CA and CRL that client certificates are checked
+    /// against.
+    async fn load_client_trust(&mut self) -> Result<()> {
+        self.client_roots = client_auth::load_roots(&self.client, &self.config).await?;
+        self.client_crls = client_auth::fetch_crls(&self.client, &self.config).await?;
+        Ok(())
+    }
+
+    /// Follow the primary certificate. A bootstrap certificate is left out.
+    pub fn issued_key(&self) -> watch::Receiver<Option<Arc<CertifiedKey>>> {
+        self.issued_tx.subscribe()
+    }
+
+    /// A resolver that serves the current certificates in a `ServerConfig`
+    /// of the caller's own, picking them by SNI as the proxy does.
+    pub fn resolver(&self) -> CertResolver {
+        CertResolver::new(self.resolver_tx.subscribe())
+    }
+
+    /// Follow the server config that serves the `CERT_PROFILES` certificate
+    /// `name` alone, for a listener of its own. `None` if there is no such
+    /// profile.
+    pub fn profile_server_config(
+        &self,
+        name: &str,
+    ) -> Option<watch::Receiver<Option<Arc<ServerConfig>>>> {
+        self.certs
+            .iter()
+            .find(|c| {
+                c.profile
+                    .as_ref()
+                    .is_some_and(|(profile, _)| profile == name)
+            })
+            .and_then(|c| c.profile_tx.as_ref())
+            .map(watch::Sender::subscribe)
+    }
+
+    /// Follow the status of every managed certificate.
+    pub fn status(&self) -> watch::Receiver<Vec<CertStatus>> {
+        self.status_tx.subscribe()
+    }
+
+    /// Send commands to the renewal loop.
+    pub fn commands(&self) -> mpsc::Sender<ManagerCommand> {
+        self.command_tx.clone()
+    }
+
+    /// Run the renewal loop. This should be spawned as a background task.
+    ///
+    /// It runs until shutdown, unless `FAIL_IF_EXPIRING_WITHIN` is set and
+    /// a certificate gets that close to expiry with its last renewal
+    /// failed; then it returns [`Error::Expiring`].
+    pub async fn run_renewal_loop(mut self, mut shutdown: watch::Receiver<bool>) -> Result<()> {
+        let crl_period = self.config.client_crl_refresh_interval;
+        let mut crl_refresh =
+            tokio::time::interval_at(Instant::now() + crl_period, crl_period);
+        let follow_period = self.follow_interval();
+        let mut follow = tokio::time::interval_at(Instant::now() + follow_period, follow_period);
+        follow.set_missed_tick_behavior(MissedTickBehavior::Delay);
 
+        // Leadership may have changed while the first certificates were
+        // being served.
+        let unseen = self.leader.as_ref().map(watch::Receiver::has_changed);
+        if let Some(Ok(true)) = unseen {
+            self.leadership_changed().await;
+        }
+        loop {
+            // Each certificate renews on its own schedule; handle whichever
+            // is due first.
+            let (index, deadline) = self
+                .certs
+                .iter()
+                .enumerate()
+                .map(|(i, cert)| (i, cert.renew_at))
+                .min_by_key(|(_, at)| *at)
+                .expect("the primary certificate is always managed");
+            let revoke_at = self.revocations.iter().map(|r| r.revoke_at).min();
+            let leads = self.leads();
             tokio::select! {
-                _ = tokio::time::sleep(renew_after) => {}
+                _ = tokio::time::sleep_until(deadline), if leads => {
+                    let _ = self.attempt_renewal(index).await;
+                }
+                _ = follow.tick(), if !leads => self.reload_stored().await,
+                true = changed(&mut self.leader) => self.leadership_changed().await,
+                _ = tokio::time::sleep_until(revoke_at.unwrap_or_else(Instant::now)), if revoke_at.is_some() => {
+                    self.revoke_due().await;
+                }
+                _ = crl_refresh.tick(), if self.config.client_crl_check => {
+                    self.refresh_crl().await;
+                }
+                Some(command) = self.commands.recv() => self.handle(command).await,
                 _ = shutdown.changed() => {
+                    if !self.revocations.is_empty() {
+                        warn!(
+                            pending = self.revocations.len(),
+                            "shutting down before superseded certificates were revoked"
+                        );
+                    }
                     info!("renewal loop shutting down");
-                    return;
+                    return Ok(());
                 }
             }
+            if let Some(e) = self.expiring() {
+                return Err(e);
+            }
+        }
+    }
+
+    /// With `FAIL_IF_EXPIRING_WITHIN`, the error for the first certificate
+    /// whose last `FAIL_IF_EXPIRING_FAILURES` renewals failed and that
+    /// expires within it. Bootstrap certificates are left out; they are
+    /// replaced on their own.
+    fn expiring(&self) -> Option<Error> {
+        let within = self.config.fail_if_expiring_within?;
+        self.certs.iter().find_map(|cert| {
+            if cert.failures < self.config.fail_if_expiring_failures {
+                return None;
+            }
+            let failed = cert.last_renewal.as_ref().filter(|r| !r.ok)?;
+            let leaf = cert.leaf().filter(|_| !cert.bootstrap)?;
+            if leaf.remaining_secs() >= within.as_secs() as i64 {
+                return None;
+            }
+            Some(Error::Expiring(format!(
+                "'{}' expires at {}, within FAIL_IF_EXPIRING_WITHIN, and renewing it failed {} times in a row, most recently: {}",
+                cert.names.common_name,
+                format_time(leaf.not_after),
+                cert.failures,
+                failed.error.as_deref().unwrap_or("unknown error"),
+            )))
+        })
+    }
 
-            // Re-authenticate in case the Vault token has expired.
-            match auth::kubernetes_login(&self.client, &self.config).await {
-                Ok(_) => {
-                    backoff = Duration::from_secs(5);
+    async fn handle(&mut self, command: ManagerCommand) {
+        match command {
+            ManagerCommand::Renew(reply) if self.external() => {
+                info!("reload requested, checking the store for new certificates");
+                self.reload_stored().await;
+                let _ = reply.send(Ok(()));
+            }
+            ManagerCommand::Renew(reply) if !self.leads() => {
+                warn!("forced renewal refused, this replica is not the leader");
+                let _ = reply.send(Err(Error::LeaderElection(format!(
+                    "this replica is not the leader, renew on the holder of Lease {}",
+                    self.config.leader_election_lease
+                ))));
+            }
+            ManagerCommand::Renew(reply) => {
+                info!("forced renewal requested");
+                let mut result = Ok(());
+                for index in 0..self.certs.len() {
+                    self.certs[index].trigger = Trigger::Forced;
+                    if let Err(e) = self.attempt_renewal(index).await {
+                        result = result.and(Err(e));
+                    }
                 }
-                Err(e) => {
-                    warn!(error = %e, "vault re-authentication failed, will retry");
-                    tokio::select! {
-                        _ = tokio::time::sleep(backoff) => {}
-                        _ = shutdown.changed() => return,
+                let _ = reply.send(result);
+            }
+            ManagerCommand::SetTlsPolicy(min_version, cipher_suites) => {
+                let old_version = std::mem::replace(&mut self.config.tls_min_version, min_version);
+                let old_suites = std::mem::replace(&mut self.config.tls_cipher_suites, cipher_suites);
+                match self.publish() {
+                    Ok(()) => info!("TLS policy reloaded"),
+                    Err(e) => {
+                        error!(error = %e, code = e.code(), "failed to apply reloaded TLS policy, keeping the previous one");
+                        self.config.tls_min_version = old_version;
+                        self.config.tls_cipher_suites = old_suites;
                     }
-                    backoff = (backoff * 2).min(max_backoff);
+                }
+            }
+        }
+    }
+
+    /// Renew certificate `index`, backing off if that fails, and record the
+    /// outcome for the admin API.
+    async fn attempt_renewal(&mut self, index: usize) -> Result<()> {
+        // A root span, so each renewal is its own trace rather than part
+        // of one that lasts as long as the process.
+        let span = info_span!(
+            parent: None,
+            "renew",
+            common_name = %self.certs[index].names.common_name
+        );
+        let result = self.renew(index).instrument(span).await;
+        if result.is_err() {
+            self.keep_bootstrap(index);
+        }
+        let cert = &mut self.certs[index];
+        match result {
+            Ok(()) => {
+                metrics::inc("cert_renewal_attempts", "success");
+                cert.record_success();
+                cert.failures = 0;
+            }
+            Err(_) => {
+                metrics::inc("cert_renewal_attempts", "failure");
+                cert.retry_later(self.config.renewal_jitter);
+                cert.failures += 1;
+            }
+        }
+        cert.last_renewal = Some(RenewalResult {
+            at: format_time(OffsetDateTime::now_utc()),
+            ok: result.is_ok(),
+            error: result.as_ref().err().map(ToString::to_string),
+            code: result.as_ref().err().map(Error::code),
+        });
+        self.report();
+        self.notify(index, &result);
+        result
+    }
+
+    /// Post the outcome of a renewal of certificate `index` to the webhook,
+    /// and warn once if a failure leaves it close to expiry.
+    fn notify(&mut self, index: usize, result: &Result<()>) {
+        let Some(ref webhook) = self.webhook else {
+            return;
+        };
+        let cert = &mut self.certs[index];
+        let leaf = cert.leaf();
+        let event = |kind| Event {
+            serial: leaf.as_ref().map(|l| l.serial.clone()),
+            not_after: leaf.as_ref().map(|l| format_time(l.not_after)),
+            error: result.as_ref().err().map(|e| EventError {
+                code: e.code(),
+                message: e.to_string(),
+            }),
+            ..Event::new(kind, &cert.names.common_name)
+        };
+
+        if result.is_ok() {
+            webhook.send(event(EventKind::RenewalSucceeded));
+            return;
+        }
+        webhook.send(event(EventKind::RenewalFailed));
+
+        let Some(ref leaf) = leaf else {
+            return;
+        };
+        let remaining = leaf.not_after - OffsetDateTime::now_utc();
+        let reported = cert.expiry_reported.as_ref() == Some(&leaf.serial);
+        if remaining < self.config.webhook_expiry_warning && !reported {
+            warn!(
+                common_name = %cert.names.common_name,
+                not_after = %format_time(leaf.not_after),
+                "certificate expires soon and could not be renewed"
+            );
+            webhook.send(event(EventKind::Expiring));
+            cert.expiry_reported = Some(leaf.serial.clone());
+        }
+    }
+
+    /// Re-issue certificate `index` and serve it, scheduling its next
+    /// renewal.
+    async fn renew(&mut self, index: usize) -> Result<()> {
+        // Make sure the Vault token lasts until the next renewal,
+        // extending it with renew-self where possible. With a static
+        // token this only re-reads the token file, which whoever manages
+        // the token (e.g. Vault Agent) keeps fresh.
+        let renew_after = self.certs[index].renew_after;
+        let trigger = self.certs[index].trigger;
+        if let Err(e) = self.issuer.refresh(&self.config, renew_after).await {
+            warn!(error = %e, code = e.code(), "vault re-authentication failed, will retry");
+            self.audit_issue(index, trigger, Err(&e));
+            return Err(e);
+        }
+
+        let cert = &self.certs[index];
+        let common_name = cert.names.common_name.clone();
+        // A profile's own settings, for issuing and validating alike.
+        let config = cert.config(&self.config);
+        let req = CertRequest {
+            config,
+            names: &cert.names,
+        };
+        let result = issuer::issue(&*self.issuer, req).await;
+        self.audit_issue(index, trigger, result.as_ref());
+        let bundle = match result {
+            Ok(bundle) => bundle,
+            Err(e) => {
+                error!(error = %e, code = e.code(), common_name = %common_name, "certificate renewal failed, will retry");
+                return Err(e);
+            }
+        };
+        if config.cert_validate {
+            if let Err(e) = validate::test_handshake(config, &bundle, &cert.names).await {
+                metrics::inc("cert_validations", "failure");
+                error!(error = %e, code = e.code(), common_name = %common_name, "renewed certificate failed validation, keeping the current one");
+                return Err(e);
+            }
+            metrics::inc("cert_validations", "success");
+        }
+        match cert.store.write(&bundle).await {
+            Ok(()) => hook::run(&self.config, &common_name, cert.store.dir()).await,
+            Err(e) => {
+                error!(error = %e, code = e.code(), common_name = %common_name, "failed to write renewed certificate");
+            }
+        }
+
+        // Pick up a rotated client CA; keep trusting the old one
+        // if it cannot be fetched right now.
+        match client_auth::load_roots(&self.client, &self.config).await {
+            Ok(roots) => self.client_roots = roots,
+            Err(e) => {
+                warn!(error = %e, code = e.code(), "failed to reload client CA, keeping the previous one");
+            }
+        }
+
+        let bootstrap = self.certs[index].bootstrap;
+        let superseded = if self.config.cert_revoke_superseded && !bootstrap {
+            self.certs[index].leaf()
+        } else {
+            None
+        };
+        let delay = self.renewal_delay(&bundle, &common_name);
+        if let Err(e) = self.swap_served(index, &bundle) {
+            error!(error = %e, code = e.code(), common_name = %common_name, "failed to parse renewed certificate, will retry");
+            return Err(e);
+        }
+        // The served key is parsed; wipe the PEM now rather than at the
+        // end of the renewal.
+        drop(bundle);
+        info!(common_name = %common_name, "certificate renewed and hot-reloaded");
+        if bootstrap {
+            metrics::inc("bootstrap_certificates", "replaced");
+            info!(common_name = %common_name, "bootstrap certificate replaced by an issued one");
+        }
+        if let Some(certificate) = superseded {
+            debug!(
+                serial = %certificate.serial,
+                revoke_in_secs = self.config.cert_revoke_delay.as_secs(),
+                "superseded certificate will be revoked"
+            );
+            self.revocations.push(Revocation {
+                index,
+                certificate,
+                trigger,
+                revoke_at: Instant::now() + self.config.cert_revoke_delay,
+            });
+        }
+
+        self.certs[index].schedule(delay, self.config.renewal_jitter);
+        Ok(())
+    }
+
+    /// Take over renewals on becoming the leader, scheduling them from the
+    /// certificates the previous leader wrote, or stand by on losing
+    /// leadership.
+    async fn leadership_changed(&mut self) {
+        let leads = self
+            .leader
+            .as_mut()
+            .is_some_and(|leader| *leader.borrow_and_update());
+        if !leads {
+            return;
+        }
+        // The previous leader may have renewed them since they were loaded.
+        self.reload_stored().await;
+        let threshold = self.config.renewal_threshold;
+        for cert in &mut self.certs {
+            let leaf = cert.leaf().filter(|_| !cert.bootstrap);
+            let Some(leaf) = leaf else {
+                cert.renew_at = Instant::now();
+                continue;
+            };
+            let remaining = leaf.not_after - OffsetDateTime::now_utc();
+            let delay = (remaining * threshold).try_into().unwrap_or(Duration::ZERO);
+            let delay = bounded_renewal(&self.config, delay, &cert.names.common_name);
+            cert.schedule(delay, self.config.renewal_jitter);
+        }
+        self.report();
+    }
+
+    /// Serve the certificates the leader, or the external issuer, has
+    /// replaced in the store since they were loaded, and run the renewal
+    /// hook for each of them.
+    async fn reload_stored(&mut self) {
+        if self.config.cert_key_encryption.is_some() {
+            // Decrypting the new keys may need Transit.
+            if let Err(e) = self.refresh_token().await {
+                warn!(error = %e, code = e.code(), "vault re-authentication failed, will retry");
+                return;
+            }
+        }
+        let mut reloaded = Vec::new();
+        for index in 0..self.certs.len() {
+            let cert = &self.certs[index];
+            // Only the chain, so an unchanged key is not decrypted again.
+            let serial = match cert.store.read_chain().await {
+                Ok(Some(chain)) => parse_pem_chain(&chain)
+                    .and_then(|chain| CertInfo::from_der(&chain[0]))
+                    .map(|leaf| leaf.serial)
+                    .ok(),
+                Ok(None) => None,
+                Err(e) => {
+                    warn!(error = %e, code = e.code(), common_name = %cert.names.common_name, "could not read the leader's certificate");
+                    None
+                }
+            };
+            let served = cert
+                .leaf()
+                .filter(|_| !cert.bootstrap)
+                .map(|leaf| leaf.serial);
+            if serial.is_none() || serial == served {
+                continue;
+            }
+            let (key, leaf) = match cert.previous().await {
+                Ok(Some(found)) => found,
+                Ok(None) => continue,
+                Err(e) => {
+                    warn!(error = %e, code = e.code(), common_name = %cert.names.common_name, "could not load the leader's certificate");
                     continue;
                 }
+            };
+            if self.external() {
+                info!(
+                    common_name = %cert.names.common_name,
+                    serial = %leaf.serial,
+                    not_after = %format_time(leaf.not_after),
+                    "serving the externally issued certificate"
+                );
+            } else {
+                info!(
+                    common_name = %cert.names.common_name,
+                    serial = %leaf.serial,
+                    not_after = %format_time(leaf.not_after),
+                    "serving the certificate the leader issued"
+                );
             }
+            let cert = &mut self.certs[index];
+            cert.served = Some(Arc::new(key));
+            cert.bootstrap = false;
+            reloaded.push(index);
+        }
+        if reloaded.is_empty() {
+            return;
+        }
+        if let Err(e) = self.publish() {
+            error!(error = %e, code = e.code(), "failed to serve the leader's certificates");
+            return;
+        }
+        self.report();
+        let trigger = if self.external() {
+            Trigger::External
+        } else {
+            Trigger::Leader
+        };
+        for index in reloaded {
+            self.audit_served(index, AuditEvent::Loaded, trigger);
+            let cert = &self.certs[index];
+            hook::run(&self.config, &cert.names.common_name, cert.store.dir()).await;
+        }
+    }
 
-            match pki::issue_certificate(&self.client, &self.config).await {
-                Ok(bundle) => {
-                    if let Err(e) = self.store.write(&bundle).await {
-                        error!(error = %e, "failed to write renewed certs to disk");
-                    }
+    /// Make sure the Vault token lasts a little longer, for a call outside
+    /// a renewal, which would have refreshed it.
+    async fn refresh_token(&self) -> Result<()> {
+        let expiring = self
+            .client
+            .token_lease()
+            .await
+            .is_none_or(|lease| lease.remaining() < TOKEN_VALIDITY);
+        if expiring {
+            self.issuer.refresh(&self.config, TOKEN_VALIDITY).await?;
+        }
+        Ok(())
+    }
 
-                    match build_server_config(&bundle.certificate, &bundle.private_key) {
-                        Ok(config) => {
-                            let _ = self.tx.send(Some(Arc::new(config)));
-                            info!("certificate renewed and hot-reloaded");
-                        }
-                        Err(e) => {
-                            error!(error = %e, "failed to parse renewed certificate");
-                        }
-                    }
+    /// Revoke the superseded certificates whose `CERT_REVOKE_DELAY` has
+    /// passed. A failed revocation is retried after `MAX_BACKOFF` until the
+    /// certificate expires, after which revoking it makes no difference.
+    async fn revoke_due(&mut self) {
+        let now = Instant::now();
+        let (due, pending): (Vec<_>, Vec<_>) = std::mem::take(&mut self.revocations)
+            .into_iter()
+            .partition(|r| r.revoke_at <= now);
+        self.revocations = pending;
 
-                    lease_secs = bundle.lease_duration_secs;
-                    backoff = Duration::from_secs(5);
+        for mut revocation in due {
+            let common_name = &self.certs[revocation.index].names.common_name;
+            let serial = &revocation.certificate.serial;
+            if revocation.certificate.remaining_secs() <= 0 {
+                debug!(common_name = %common_name, serial = %serial, "superseded certificate expired, not revoking it");
+                continue;
+            }
+            let result = self.revoke(&revocation).await;
+            self.audit_revocation(&revocation, result.as_ref().err());
+            match result {
+                Ok(()) => {
+                    metrics::inc("cert_revocations", "success");
+                    info!(common_name = %common_name, serial = %serial, "superseded certificate revoked");
                 }
                 Err(e) => {
-                    error!(error = %e, "certificate renewal failed, will retry");
-                    tokio::select! {
-                        _ = tokio::time::sleep(backoff) => {}
-                        _ = shutdown.changed() => return,
-                    }
-                    backoff = (backoff * 2).min(max_backoff);
+                    metrics::inc("cert_revocations", "failure");
+                    error!(error = %e, code = e.code(), common_name = %common_name, serial = %serial, "failed to revoke superseded certificate, will retry");
+                    revocation.revoke_at = now + MAX_BACKOFF;
+                    self.revocations.push(revocation);
                 }
             }
         }
     }
+
+    /// Revoke `revocation`'s certificate on the PKI mount it was issued
+    /// from.
+    async fn revoke(&self, revocation: &Revocation) -> Result<()> {
+        // The token was last refreshed to last until the next renewal,
+        // which CERT_REVOKE_DELAY may be past.
+        self.refresh_token().await?;
+        let config = self.certs[revocation.index].config(&self.config);
+        pki::revoke_certificate(&self.client, config, &revocation.certificate.serial).await
+    }
+
+    /// Serve `bundle` in place of certificate `index`, keeping the previous
+    /// one if the new server config cannot be built.
+    fn swap_served(&mut self, index: usize, bundle: &CertBundle) -> Result<()> {
+        let key = Arc::new(certified_key(&bundle.certificate, &bundle.private_key)?);
+        let cert = &mut self.certs[index];
+        let previous = cert.served.replace(key);
+        let bootstrap = std::mem::replace(&mut cert.bootstrap, false);
+        let result = self.publish();
+        match result {
+            // Sessions resumed from here on were set up with the new
+            // certificate. The old key is freed as soon as the connections
+            // still using it are done, not kept until the next renewal.
+            Ok(()) => {
+                self.resumption.rotate();
+                drop(previous);
+            }
+            Err(_) => {
+                let cert = &mut self.certs[index];
+                cert.served = previous;
+                cert.bootstrap = bootstrap;
+            }
+        }
+        result
+    }
+
+    /// Build a server config for the served certificates and the current
+    /// client trust, and hand it to the proxy.
+    fn publish(&self) -> Result<()> {
+        let identities: Vec<Arc<CertifiedKey>> = self
+            .certs
+            .iter()
+            .filter(|c| c.serves())
+            .filter_map(|c| c.served.clone())
+            .collect();
+        let verifier = match self.client_roots {
+            Some(ref roots) => Some(client_auth::build_verifier(
+                self.config.client_auth,
+                roots.clone(),
+                self.client_crls.clone(),
+            )?),
+            None => None,
+        };
+        let resolver = Arc::new(SniResolver::new(&identities)?);
+        let server_config = tls_server_config(
+            &self.config,
+            resolver.clone(),
+            verifier.clone(),
+            &self.resumption,
+        )?;
+        let profiles = self
+            .certs
+            .iter()
+            .filter_map(|c| Some((c.profile_tx.as_ref()?, c.served.clone()?)))
+            .map(|(tx, key)| {
+                let resolver = Arc::new(SniResolver::new(&[key])?);
+                let config =
+                    tls_server_config(&self.config, resolver, verifier.clone(), &self.resumption)?;
+                Ok((tx, config))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let _ = self.tx.send(Some(Arc::new(server_config)));
+        for (tx, config) in profiles {
+            tx.send_replace(Some(Arc::new(config)));
+        }
+        self.resolver_tx.send_replace(Some(resolver));
+        // A backend would not accept a bootstrap certificate either.
+        let issued = self.certs.first().filter(|c| !c.bootstrap);
+        self.issued_tx
+            .send_replace(issued.and_then(|c| c.served.clone()));
+        Ok(())
+    }
+
+    /// Publish every certificate's status, and its expiry as a gauge.
+    fn report(&self) {
+        for cert in &self.certs {
+            if let Some(leaf) = cert.leaf() {
+                metrics::set(
+                    "cert_not_after_timestamp_seconds",
+                    &cert.names.common_name,
+                    leaf.not_after.unix_timestamp() as f64,
+                );
+            }
+        }
+        self.status_tx
+            .send_replace(self.certs.iter().map(ManagedCert::status).collect());
+    }
+
+    /// Fetch the current CRL and swap it into the served config. On failure
+    /// the previous CRL stays in effect.
+    async fn refresh_crl(&mut self) {
+        match client_auth::fetch_crls(&self.client, &self.config).await {
+            Ok(crls) => self.client_crls = crls,
+            Err(e) => {
+                warn!(error = %e, code = e.code(), "failed to refresh client CRL, keeping the previous one");
+                return;
+            }
+        }
+
+        match self.publish() {
+            Ok(()) => debug!("client CRL refreshed"),
+            Err(e) => error!(error = %e, code = e.code(), "failed to apply refreshed client CRL"),
+        }
+    }
+
+    /// Time until `renewal_threshold` of the leaf certificate's remaining
+    /// validity has passed.
+    ///
+    /// Vault's lease can differ from the real validity (role TTL caps, or no
+    /// lease at all), so it is only used if the certificate cannot be parsed.
+    /// NotBefore is not used because Vault backdates it. Either way the
+    /// delay is kept within `MIN_RENEW_INTERVAL` and `MAX_RENEW_INTERVAL`.
+    fn renewal_delay(&self, bundle: &CertBundle, common_name: &str) -> Duration {
+        let threshold = self.config.renewal_threshold;
+        let leaf = parse_pem_chain(&bundle.certificate)
+            .and_then(|chain| CertInfo::from_der(&chain[0]));
+
+        match leaf {
+            Ok(leaf) => {
+                let remaining = leaf.not_after - OffsetDateTime::now_utc();
+                debug!(not_after = %leaf.not_after, "computed renewal time from certificate expiry");
+                // Already expired (clock skew): renew right away.
+                let delay = (remaining * threshold).try_into().unwrap_or(Duration::ZERO);
+                bounded_renewal(&self.config, delay, common_name)
+            }
+            Err(e) => {
+                warn!(
+                    error = %e,
+                    code = e.code(),
+                    lease_secs = bundle.lease_duration_secs,
+                    "could not read certificate expiry, scheduling renewal from the vault lease"
+                );
+                // Vault's PKI engine only returns a lease when the role has
+                // generate_lease set.
+                if bundle.lease_duration_secs == 0 {
+                    warn!(common_name, "vault returned no lease either");
+                }
+                let delay =
+                    Duration::from_secs((bundle.lease_duration_secs as f64 * threshold) as u64);
+                bounded_renewal(&self.config, delay, common_name)
+            }
+        }
+    }
 }
 
-/// Parse PEM certificate chain and private key, then build a rustls ServerConfig.
-fn build_server_config(cert_pem: &str, key_pem: &str) -> Result<ServerConfig> {
-    let certs = rustls_pemfile::certs(&mut cert_pem.as_bytes())
-        .collect::<std::result::Result<Vec<_>, _>>()
-        .map_err(|e| Error::CertParse(format!("failed to parse certificate PEM: {e}")))?;
+/// `delay` kept within `MIN_RENEW_INTERVAL` and `MAX_RENEW_INTERVAL`, each
+/// decision counted in `renewal_schedules`. A renewal due sooner than the
+/// floor means a certificate far shorter-lived than intended, which is
+/// worth a warning; a ceiling is a deliberate choice.
+fn bounded_renewal(config: &Config, delay: Duration, common_name: &str) -> Duration {
+    let floor = config.min_renew_interval;
+    if delay < floor {
+        metrics::inc("renewal_schedules", "min_interval");
+        warn!(
+            common_name,
+            due_in_secs = delay.as_secs(),
+            renew_in_secs = floor.as_secs(),
+            "certificate lifetime is suspiciously short, holding renewal off until MIN_RENEW_INTERVAL; check the role's TTL"
+        );
+        return floor;
+    }
+    match config.max_renew_interval {
+        Some(ceiling) if delay > ceiling => {
+            metrics::inc("renewal_schedules", "max_interval");
+            debug!(
+                common_name,
+                due_in_secs = delay.as_secs(),
+                renew_in_secs = ceiling.as_secs(),
+                "renewing early, at MAX_RENEW_INTERVAL"
+            );
+            ceiling
+        }
+        _ => {
+            metrics::inc("renewal_schedules", "threshold");
+            delay
+        }
+    }
+}
 
-    if certs.is_empty() {
-        return Err(Error::CertParse("no certificates found in PEM".into()));
+/// Resolves with `true` when `leader` changes, or `false` once it no longer
+/// can. Never resolves without leader election.
+async fn changed(leader: &mut Option<watch::Receiver<bool>>) -> bool {
+    match leader {
+        Some(leader) => leader.changed().await.is_ok(),
+        None => std::future::pending().await,
     }
+}
 
-    let key = rustls_pemfile::private_key(&mut key_pem.as_bytes())
-        .map_err(|e| Error::CertParse(format!("failed to parse private key PEM: {e}")))?
-        .ok_or_else(|| Error::CertParse("no private key found in PEM".into()))?;
+/// Spread `delay` uniformly over ±`fraction` of itself.
+fn jittered(delay: Duration, fraction: f64) -> Duration {
+    let mut buf = [0u8; 4];
+    // Without randomness, renewing on schedule is still correct.
+    if fraction == 0.0 || SystemRandom::new().fill(&mut buf).is_err() {
+        return delay;
+    }
+    let unit = u32::from_be_bytes(buf) as f64 / u32::MAX as f64 * 2.0 - 1.0;
+    delay.mul_f64(1.0 + unit * fraction)
+}
 
-    let config = ServerConfig::builder()
-        .with_no_client_auth()
-        .with_single_cert(certs, key)
-        .map_err(|e| Error::Tls(format!("failed to build TLS server config: {e}")))?;
+/// Build a rustls ServerConfig serving `identities`, picked by SNI with the
+/// first as the default, with the TLS versions, cipher suites, and session
+/// resumption settings from `config`. Sessions are not shared with any
+/// other server config.
+///
+/// `client_verifier` enables client certificate authentication.
+pub fn build_server_config(
+    config: &Config,
+    identities: &[Arc<CertifiedKey>],
+    client_verifier: Option<Arc<dyn ClientCertVerifier>>,
+) -> Result<ServerConfig> {
+    let resolver = Arc::new(SniResolver::new(identities)?);
+    tls_server_config(config, resolver, client_verifier, &Resumption::new(config))
+}
 
-    Ok(config)
+/// A ServerConfig with the TLS versions, cipher suites, and ALPN protocols
+/// from `config`, presenting what `resolver` picks and resuming sessions
+/// from `resumption`.
+fn tls_server_config(
+    config: &Config,
+    resolver: Arc<SniResolver>,
+    client_verifier: Option<Arc<dyn ClientCertVerifier>>,
+    resumption: &Resumption,
+) -> Result<ServerConfig> {
+    let mut provider = rustls::crypto::ring::default_provider();
+    if !config.tls_cipher_suites.is_empty() {
+        provider.cipher_suites = config.tls_cipher_suites.clone();
+    }
+    let versions: &[&SupportedProtocolVersion] = match config.tls_min_version {
+        TlsVersion::Tls12 => rustls::ALL_VERSIONS,
+        TlsVersion::Tls13 => &[&rustls::version::TLS13],
+    };
+    let builder = ServerConfig::builder_with_provider(Arc::new(provider))
+        .with_protocol_versions(versions)
+        .map_err(|e| Error::Tls(format!("invalid TLS policy: {e}")))?;
+    let builder = match client_verifier {
+        Some(verifier) => builder.with_client_cert_verifier(verifier),
+        None => builder.with_no_client_auth(),
+    };
+    let mut server_config = builder.with_cert_resolver(resolver);
+    server_config.alpn_protocols = config.alpn_protocols.clone();
+    resumption.apply(&mut server_config);
+
+    Ok(server_config)
+}
+
+/// Parse a PEM certificate chain and private key into a signing identity,
+/// checking that the key belongs to the leaf certificate.
+pub fn certified_key(cert_pem: &str, key_pem: &str) -> Result<CertifiedKey> {
+    let certs = parse_pem_chain(cert_pem)?;
+    let key = parse_private_key(key_pem)?;
+    CertifiedKey::from_der(certs, key, &rustls::crypto::ring::default_provider())
+        .map_err(|e| Error::Tls(format!("failed to load certificate and key: {e}")))
+}
+
+pub fn parse_private_key(key_pem: &str) -> Result<PrivateKeyDer<'static>> {
+    rustls_pemfile::private_key(&mut key_pem.as_bytes())
+        .map_err(|e| Error::CertParse(format!("failed to parse private key PEM: {e}")))?
+        .ok_or_else(|| Error::CertParse("no private key found in PEM".into()))
 }
@@ -1,2 +1,26 @@
+pub mod audit;
+pub mod client_auth;
+#[cfg(feature = "exec-issuer")]
+pub mod exec;
+pub mod hook;
+pub mod info;
+pub mod issuer;
+#[cfg(any(feature = "secret-sink", feature = "k8s-csr", feature = "leader-election"))]
+pub mod k8s;
+#[cfg(feature = "k8s-csr")]
+pub mod k8s_csr;
+#[cfg(feature = "key-encryption")]
+pub mod key_encryption;
+pub mod keygen;
+#[cfg(feature = "keystore")]
+pub mod keystore;
+#[cfg(feature = "leader-election")]
+pub mod leader;
 pub mod manager;
+pub mod resolver;
+pub mod resumption;
+pub mod roots;
+pub mod secret;
 pub mod store;
+pub mod validate;
+pub mod webhook;
@@ -0,0 +1,129 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::{CertifiedKey, SingleCertAndKey};
+use rustls::ServerConfig;
+use tokio::sync::watch;
+
+use crate::cert::info::CertInfo;
+use crate::error::{Error, Result};
+
+/// Picks the certificate for each handshake by SNI, falling back to the
+/// primary certificate for unknown names and clients that send no SNI.
+#[derive(Debug)]
+pub struct SniResolver {
+    default: Arc<CertifiedKey>,
+    /// Lower-cased DNS names from each certificate's SANs, in the order the
+    /// certificates were given, so the primary wins overlaps.
+    names: Vec<(String, Arc<CertifiedKey>)>,
+}
+
+impl SniResolver {
+    /// `identities[0]` is the primary certificate.
+    pub fn new(identities: &[Arc<CertifiedKey>]) -> Result<Self> {
+        let default = identities
+            .first()
+            .ok_or_else(|| Error::Tls("no certificate to serve".into()))?
+            .clone();
+
+        let mut names = Vec::new();
+        for key in identities {
+            let leaf = key
+                .end_entity_cert()
+                .map_err(|e| Error::Tls(format!("certificate chain is empty: {e}")))?;
+            for name in CertInfo::from_der(leaf)?.dns_names {
+                names.push((name.to_ascii_lowercase(), key.clone()));
+            }
+        }
+
+        Ok(Self { default, names })
+    }
+
+    /// An exact SAN match, or else a `*.` wildcard covering the first label.
+    fn lookup(&self, server_name: &str) -> Option<&Arc<CertifiedKey>> {
+        let server_name = server_name.to_ascii_lowercase();
+        let exact = self.names.iter().find(|(name, _)| *name == server_name);
+        let wildcard = || {
+            let parent = server_name.split_once('.')?.1;
+            self.names
+                .iter()
+                .find(|(name, _)| name.strip_prefix("*.") == Some(parent))
+        };
+        exact.or_else(wildcard).map(|(_, key)| key)
+    }
+}
+
+impl ResolvesServerCert for SniResolver {
+    fn resolve(&self, client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        let key = client_hello
+            .server_name()
+            .and_then(|name| self.lookup(name))
+            .unwrap_or(&self.default);
+        Some(key.clone())
+    }
+}
+
+/// Resolves each handshake to the certificates a
+/// [`CertManager`](crate::cert::manager::CertManager) currently serves, by
+/// SNI as [`SniResolver`] does, following every renewal.
+///
+/// For embedding the certificates in a `ServerConfig` built elsewhere, with
+/// its own client authentication and ALPN. Handshakes fail until the first
+/// certificates are issued.
+#[derive(Debug, Clone)]
+pub struct CertResolver(watch::Receiver<Option<Arc<SniResolver>>>);
+
+impl CertResolver {
+    pub fn new(current: watch::Receiver<Option<Arc<SniResolver>>>) -> Self {
+        Self(current)
+    }
+}
+
+impl ResolvesServerCert for CertResolver {
+    fn resolve(&self, client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        let current = self.0.borrow().clone();
+        current?.resolve(client_hello)
+    }
+}
+
+/// The protocol an ACME CA negotiates to validate `tls-alpn-01` (RFC 8737).
+pub const ACME_TLS_ALPN: &[u8] = b"acme-tls/1";
+
+/// Certificates answering pending `tls-alpn-01` challenges, by domain.
+///
+/// The ACME issuer fills it in while an order is being validated and the
+/// proxy answers from it.
+#[derive(Debug, Clone, Default)]
+pub struct AlpnChallenges(Arc<Mutex<HashMap<String, Arc<CertifiedKey>>>>);
+
+impl AlpnChallenges {
+    pub fn insert(&self, domain: &str, key: CertifiedKey) {
+        self.lock()
+            .insert(domain.to_ascii_lowercase(), Arc::new(key));
+    }
+
+    pub fn remove(&self, domain: &str) {
+        self.lock().remove(&domain.to_ascii_lowercase());
+    }
+
+    /// A config presenting the challenge certificate, if this handshake is
+    /// a validation request for a pending domain.
+    pub fn server_config(&self, client_hello: &ClientHello<'_>) -> Option<Arc<ServerConfig>> {
+        if !client_hello.alpn()?.any(|p| p == ACME_TLS_ALPN) {
+            return None;
+        }
+        let name = client_hello.server_name()?.to_ascii_lowercase();
+        let key = self.lock().get(&name)?.clone();
+
+        let mut config = ServerConfig::builder()
+            .with_no_client_auth()
+            .with_cert_resolver(Arc::new(SingleCertAndKey::from(key)));
+        config.alpn_protocols = vec![ACME_TLS_ALPN.to_vec()];
+        Some(Arc::new(config))
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<String, Arc<CertifiedKey>>> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
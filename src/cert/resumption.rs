@@ -0,0 +1,209 @@
+use std::fmt;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, CHACHA20_POLY1305, NONCE_LEN};
+use ring::rand::{SecureRandom, SystemRandom};
+use rustls::pki_types::UnixTime;
+use rustls::server::{
+    NoServerSessionStorage, ProducesTickets, ServerSessionMemoryCache, StoresServerSessions,
+};
+use rustls::ServerConfig;
+
+use crate::config::Config;
+
+/// Session resumption for the server configs cert-keeper builds, shared
+/// between them so that serving a renewed certificate does not end the
+/// sessions clients hold: the session cache, and the keys session tickets
+/// are encrypted with.
+#[derive(Clone)]
+pub struct Resumption {
+    enabled: bool,
+    cache: Arc<dyn StoresServerSessions>,
+    tickets: Option<Arc<TicketKeys>>,
+}
+
+impl Resumption {
+    pub fn new(config: &Config) -> Self {
+        if !config.tls_session_resumption {
+            return Self {
+                enabled: false,
+                cache: Arc::new(NoServerSessionStorage {}),
+                tickets: None,
+            };
+        }
+        Self {
+            enabled: true,
+            cache: ServerSessionMemoryCache::new(config.tls_session_cache_size),
+            tickets: config
+                .tls_session_tickets
+                .then(|| Arc::new(TicketKeys::new(config.tls_session_ticket_lifetime))),
+        }
+    }
+
+    pub fn apply(&self, server_config: &mut ServerConfig) {
+        server_config.session_storage = self.cache.clone();
+        if let Some(ref tickets) = self.tickets {
+            server_config.ticketer = tickets.clone();
+        }
+        if !self.enabled {
+            server_config.send_tls13_tickets = 0;
+        }
+    }
+
+    /// Start encrypting tickets with a new key. Tickets from the previous
+    /// one can still be resumed from until they expire.
+    pub fn rotate(&self) {
+        if let Some(ref tickets) = self.tickets {
+            tickets.rotate(now());
+        }
+    }
+}
+
+/// The keys session tickets are encrypted with. The current key is
+/// replaced once it has issued tickets for a ticket lifetime, or on
+/// [`rotate`](Self::rotate); the ones it replaced are kept until their
+/// last tickets expire.
+///
+/// A ticket is the key's name, the time it was issued, a nonce, and the
+/// session encrypted with ChaCha20-Poly1305. The name and time are
+/// authenticated along with it, and tickets older than the lifetime are
+/// refused.
+struct TicketKeys {
+    lifetime: u64,
+    keys: RwLock<Keys>,
+}
+
+#[derive(Default)]
+struct Keys {
+    current: Option<TicketKey>,
+    /// With the time each stopped issuing tickets.
+    retired: Vec<(TicketKey, u64)>,
+}
+
+struct TicketKey {
+    name: [u8; 16],
+    key: LessSafeKey,
+    created: u64,
+}
+
+impl TicketKey {
+    fn generate(created: u64) -> Option<Self> {
+        let rng = SystemRandom::new();
+        let mut name = [0u8; 16];
+        let mut key = [0u8; 32];
+        rng.fill(&mut name).ok()?;
+        rng.fill(&mut key).ok()?;
+        let key = LessSafeKey::new(UnboundKey::new(&CHACHA20_POLY1305, &key).ok()?);
+        Some(Self { name, key, created })
+    }
+}
+
+/// Key name, issue time, nonce.
+const HEADER_LEN: usize = 16 + 8 + NONCE_LEN;
+
+impl TicketKeys {
+    fn new(lifetime: Duration) -> Self {
+        let keys = TicketKeys {
+            lifetime: lifetime.as_secs().max(1),
+            keys: RwLock::default(),
+        };
+        keys.rotate(now());
+        keys
+    }
+
+    fn rotate(&self, now: u64) {
+        let mut keys = self.keys.write().unwrap_or_else(|e| e.into_inner());
+        let lifetime = self.lifetime;
+        keys.retired.retain(|(_, retired)| retired + lifetime > now);
+        // Without randomness no tickets are issued, and clients fall back
+        // to the session cache or a full handshake.
+        let previous = std::mem::replace(&mut keys.current, TicketKey::generate(now));
+        if let Some(previous) = previous {
+            keys.retired.push((previous, now));
+        }
+    }
+}
+
+impl ProducesTickets for TicketKeys {
+    fn enabled(&self) -> bool {
+        true
+    }
+
+    fn lifetime(&self) -> u32 {
+        self.lifetime.try_into().unwrap_or(u32::MAX)
+    }
+
+    fn encrypt(&self, plain: &[u8]) -> Option<Vec<u8>> {
+        let now = now();
+        let expired = {
+            let keys = self.keys.read().unwrap_or_else(|e| e.into_inner());
+            keys.current
+                .as_ref()
+                .is_none_or(|key| key.created + self.lifetime <= now)
+        };
+        if expired {
+            self.rotate(now);
+        }
+
+        let keys = self.keys.read().unwrap_or_else(|e| e.into_inner());
+        let key = keys.current.as_ref()?;
+        let mut nonce = [0u8; NONCE_LEN];
+        SystemRandom::new().fill(&mut nonce).ok()?;
+
+        let mut ticket = Vec::with_capacity(HEADER_LEN + plain.len() + CHACHA20_POLY1305.tag_len());
+        ticket.extend_from_slice(&key.name);
+        ticket.extend_from_slice(&now.to_be_bytes());
+        ticket.extend_from_slice(&nonce);
+        let mut sealed = plain.to_vec();
+        key.key
+            .seal_in_place_append_tag(
+                Nonce::assume_unique_for_key(nonce),
+                Aad::from(&ticket[..24]),
+                &mut sealed,
+            )
+            .ok()?;
+        ticket.extend_from_slice(&sealed);
+        Some(ticket)
+    }
+
+    fn decrypt(&self, ticket: &[u8]) -> Option<Vec<u8>> {
+        if ticket.len() < HEADER_LEN + CHACHA20_POLY1305.tag_len() {
+            return None;
+        }
+        let (header, sealed) = ticket.split_at(HEADER_LEN);
+        let issued = u64::from_be_bytes(header[16..24].try_into().ok()?);
+        let now = now();
+        if issued > now || now - issued >= self.lifetime {
+            return None;
+        }
+
+        let keys = self.keys.read().unwrap_or_else(|e| e.into_inner());
+        let key = keys
+            .current
+            .iter()
+            .chain(keys.retired.iter().map(|(key, _)| key))
+            .find(|key| key.name[..] == header[..16])?;
+        let nonce = Nonce::try_assume_unique_for_key(&header[24..]).ok()?;
+        let mut plain = sealed.to_vec();
+        let len = key
+            .key
+            .open_in_place(nonce, Aad::from(&header[..24]), &mut plain)
+            .ok()?
+            .len();
+        plain.truncate(len);
+        Some(plain)
+    }
+}
+
+impl fmt::Debug for TicketKeys {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TicketKeys")
+            .field("lifetime", &self.lifetime)
+            .finish_non_exhaustive()
+    }
+}
+
+fn now() -> u64 {
+    UnixTime::now().as_secs()
+}
@@ -0,0 +1,38 @@
+use rustls::RootCertStore;
+use tracing::debug;
+
+use crate::cert::info::parse_pem_chain;
+use crate::cert::store::read_extra_ca_files;
+use crate::config::Config;
+use crate::error::{Error, Result};
+use crate::vault::client::VaultClient;
+use crate::vault::pki;
+
+/// Trust roots from `ca_file` (or the PKI mount's CA chain when unset) plus
+/// any `EXTRA_CA_FILES`. `var` names the setting `ca_file` comes from.
+pub async fn load(
+    client: &VaultClient,
+    config: &Config,
+    var: &str,
+    ca_file: Option<&str>,
+) -> Result<RootCertStore> {
+    let mut pem = match ca_file {
+        Some(path) => tokio::fs::read_to_string(path)
+            .await
+            .map_err(|e| Error::Config(format!("failed to read {var} '{path}': {e}")))?,
+        None => pki::fetch_ca_chain(client, config).await?,
+    };
+    pem.push('\n');
+    pem.push_str(&read_extra_ca_files(&config.extra_ca_files).await?);
+
+    let mut roots = RootCertStore::empty();
+    let (added, ignored) = roots.add_parsable_certificates(parse_pem_chain(&pem)?);
+    if added == 0 {
+        return Err(Error::CertParse(format!(
+            "no usable CA certificates found for {var}"
+        )));
+    }
+    debug!(var, added, ignored, "loaded CA certificates");
+
+    Ok(roots)
+}
@@ -0,0 +1,46 @@
+use std::fmt;
+use std::ops::Deref;
+
+use serde::{Deserialize, Deserializer};
+use zeroize::Zeroizing;
+
+/// A PEM-encoded private key.
+///
+/// The buffer is wiped when dropped, so the key does not linger in freed
+/// memory once the proxy has parsed it, and `Debug` leaves it out, so no
+/// log level can print it. It is deliberately not `Clone`: every copy would
+/// be one more to wipe.
+#[derive(Default)]
+pub struct KeyPem(Zeroizing<String>);
+
+impl KeyPem {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<String> for KeyPem {
+    fn from(pem: String) -> Self {
+        Self(Zeroizing::new(pem))
+    }
+}
+
+impl Deref for KeyPem {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Debug for KeyPem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("KeyPem(<redacted>)")
+    }
+}
+
+impl<'de> Deserialize<'de> for KeyPem {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer).map(Self::from)
+    }
+}
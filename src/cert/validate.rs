@@ -0,0 +1,145 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use rustls::pki_types::ServerName;
+use rustls::{ClientConfig, RootCertStore};
+use tokio_rustls::{TlsAcceptor, TlsConnector};
+use tracing::debug;
+
+use crate::cert::info::{parse_pem_chain, CertInfo};
+use crate::cert::issuer::CertBundle;
+use crate::cert::manager::{build_server_config, certified_key};
+use crate::config::{CertNames, Config};
+use crate::error::{Error, Result};
+
+/// Limit on the test handshake, which never leaves the process.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Whether `leaf` names everything in `names`, so a changed configuration
+/// is not answered with an old certificate. The common name may be in the
+/// subject only.
+pub fn covers(leaf: &CertInfo, names: &CertNames) -> bool {
+    let list = |value: &Option<String>| -> Vec<String> {
+        value
+            .as_deref()
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .map(String::from)
+            .collect()
+    };
+    let has_dns = |name: &str| leaf.dns_names.iter().any(|d| d.eq_ignore_ascii_case(name));
+    let common_name = format!("CN={}", names.common_name);
+    let in_subject = leaf
+        .subject
+        .split(", ")
+        .any(|rdn| rdn.eq_ignore_ascii_case(&common_name));
+
+    (in_subject || has_dns(&names.common_name))
+        && list(&names.alt_names).iter().all(|name| has_dns(name))
+        && list(&names.ip_sans)
+            .iter()
+            .all(|ip| ip.parse().is_ok_and(|ip| leaf.ip_addresses.contains(&ip)))
+        && list(&names.uri_sans)
+            .iter()
+            .all(|uri| leaf.uris.contains(uri))
+}
+
+/// Check a newly issued `bundle` before it replaces the served certificate:
+/// the leaf must name everything in `names`, and a client trusting the top
+/// of the chain must complete a handshake with a server config built from
+/// it, under the TLS policy in `config`. That catches a role that issued
+/// the wrong SANs, a chain that does not lead to its CA, and a key that
+/// does not belong to the certificate.
+pub async fn test_handshake(config: &Config, bundle: &CertBundle, names: &CertNames) -> Result<()> {
+    let failed =
+        |message: String| Error::CertValidation(format!("{}: {message}", names.common_name));
+    let chain = parse_pem_chain(&bundle.certificate)?;
+    let leaf = CertInfo::from_der(&chain[0])?;
+    if !covers(&leaf, names) {
+        return Err(failed(format!(
+            "certificate does not name everything requested; it has subject {}, DNS names [{}], IP SANs [{}], URI SANs [{}]",
+            leaf.subject,
+            leaf.dns_names.join(", "),
+            leaf.ip_addresses
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(", "),
+            leaf.uris.join(", ")
+        )));
+    }
+
+    // The chain is trusted as far as its top certificate, usually the root;
+    // a leaf-only chain is trusted as far as its issuing CA.
+    let anchor = match chain[1..].last() {
+        Some(top) => Some(top.clone()),
+        None => parse_pem_chain(&bundle.ca_certificate)
+            .ok()
+            .and_then(|cas| cas.into_iter().next_back()),
+    };
+    let Some(anchor) = anchor else {
+        debug!("no CA certificate to verify the chain against, skipping the test handshake");
+        return Ok(());
+    };
+    let mut roots = RootCertStore::empty();
+    roots
+        .add(anchor)
+        .map_err(|e| failed(format!("unusable CA certificate: {e}")))?;
+
+    let Some(server_name) = server_name(names, config.cert_exclude_cn_from_sans) else {
+        debug!("no DNS name or IP address to verify, skipping the test handshake");
+        return Ok(());
+    };
+
+    let key = Arc::new(certified_key(&bundle.certificate, &bundle.private_key)?);
+    let server = build_server_config(config, &[key], None)?;
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let client = ClientConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .map_err(|e| Error::Tls(format!("failed to set up the test handshake: {e}")))?
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+
+    let (client_io, server_io) = tokio::io::duplex(16 * 1024);
+    let accept = TlsAcceptor::from(Arc::new(server)).accept(server_io);
+    let connect = TlsConnector::from(Arc::new(client)).connect(server_name.clone(), client_io);
+    let (accepted, connected) =
+        tokio::time::timeout(HANDSHAKE_TIMEOUT, async { tokio::join!(accept, connect) })
+            .await
+            .map_err(|_| failed("test handshake timed out".into()))?;
+    // The client's error says why; the server only sees the alert.
+    connected.map_err(|e| {
+        failed(format!("test handshake as {} failed: {e}", server_name.to_str()))
+    })?;
+    accepted.map_err(|e| failed(format!("test handshake failed on the server side: {e}")))?;
+    debug!(server_name = %server_name.to_str(), "test handshake succeeded");
+    Ok(())
+}
+
+/// The name a client would verify the certificate for: the first of the
+/// common name, unless it is kept out of the SANs, and the DNS SANs that is
+/// a hostname, with a wildcard filled in, or else the first IP SAN.
+fn server_name(names: &CertNames, exclude_cn: bool) -> Option<ServerName<'static>> {
+    let common_name = (!exclude_cn).then_some(names.common_name.as_str());
+    let dns = common_name
+        .into_iter()
+        .chain(names.alt_names.iter().flat_map(|n| n.split(',')))
+        .map(str::trim)
+        .find_map(|name| {
+            let name = match name.strip_prefix("*.") {
+                Some(domain) => format!("cert-keeper-test.{domain}"),
+                None => name.to_string(),
+            };
+            ServerName::try_from(name).ok().filter(|n| matches!(n, ServerName::DnsName(_)))
+        });
+    dns.or_else(|| {
+        names
+            .ip_sans
+            .iter()
+            .flat_map(|ips| ips.split(','))
+            .find_map(|ip| ip.trim().parse::<std::net::IpAddr>().ok())
+            .map(ServerName::from)
+    })
+}
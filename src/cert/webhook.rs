@@ -0,0 +1,157 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use reqwest::{Client, StatusCode};
+use serde::Serialize;
+use time::OffsetDateTime;
+use tracing::{debug, warn, Instrument};
+
+use crate::cert::info::format_time;
+use crate::config::Config;
+use crate::dns::Resolver;
+use crate::error::{Error, Result};
+use crate::metrics;
+
+/// Delay before the first retry; doubled after each further failure.
+const INITIAL_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// Posts certificate lifecycle events to `WEBHOOK_URL` as JSON.
+#[derive(Clone)]
+pub struct Webhook {
+    http: Client,
+    url: String,
+    retries: u32,
+}
+
+/// What happened to a certificate.
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    RenewalSucceeded,
+    RenewalFailed,
+    /// A renewal failed and the served certificate expires within
+    /// `WEBHOOK_EXPIRY_WARNING`. Sent once per certificate serial.
+    Expiring,
+}
+
+impl EventKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            EventKind::RenewalSucceeded => "renewal_succeeded",
+            EventKind::RenewalFailed => "renewal_failed",
+            EventKind::Expiring => "expiring",
+        }
+    }
+}
+
+/// The JSON body of one notification.
+#[derive(Debug, Clone, Serialize)]
+pub struct Event {
+    pub event: EventKind,
+    pub common_name: String,
+    /// The certificate now being served, if any.
+    pub serial: Option<String>,
+    pub not_after: Option<String>,
+    pub error: Option<EventError>,
+    pub time: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct EventError {
+    pub code: &'static str,
+    pub message: String,
+}
+
+impl Event {
+    pub fn new(event: EventKind, common_name: &str) -> Self {
+        Self {
+            event,
+            common_name: common_name.to_string(),
+            serial: None,
+            not_after: None,
+            error: None,
+            time: format_time(OffsetDateTime::now_utc()),
+        }
+    }
+}
+
+impl Webhook {
+    /// Build the webhook client, or `None` when `WEBHOOK_URL` is not set.
+    pub fn new(config: &Config, resolver: Arc<Resolver>) -> Result<Option<Self>> {
+        let Some(ref url) = config.webhook_url else {
+            return Ok(None);
+        };
+
+        let mut headers = HeaderMap::new();
+        for (name, value) in &config.webhook_headers {
+            let name = HeaderName::try_from(name.as_str()).map_err(|e| {
+                Error::Config(format!("invalid WEBHOOK_HEADERS name '{name}': {e}"))
+            })?;
+            let mut value = HeaderValue::try_from(value.as_str()).map_err(|e| {
+                Error::Config(format!("invalid WEBHOOK_HEADERS value for {name}: {e}"))
+            })?;
+            // Typically an Authorization token; keep it out of debug logs.
+            value.set_sensitive(true);
+            headers.insert(name, value);
+        }
+
+        let http = Client::builder()
+            .dns_resolver(resolver)
+            .default_headers(headers)
+            .timeout(config.webhook_timeout)
+            .build()
+            .map_err(|e| Error::Config(format!("failed to build webhook HTTP client: {e}")))?;
+
+        Ok(Some(Self {
+            http,
+            url: url.clone(),
+            retries: config.webhook_retries,
+        }))
+    }
+
+    /// Deliver `event` in the background so a slow receiver never delays
+    /// renewals.
+    pub fn send(&self, event: Event) {
+        let webhook = self.clone();
+        tokio::spawn(async move { webhook.deliver(event).await }.in_current_span());
+    }
+
+    /// Post `event`, retrying connection failures, 5xx, and 429 responses
+    /// up to `WEBHOOK_RETRIES` times with exponential backoff. Other
+    /// responses are not retried since resending would not change them.
+    async fn deliver(&self, event: Event) {
+        let mut delay = INITIAL_RETRY_DELAY;
+        for attempt in 0..=self.retries {
+            if attempt > 0 {
+                tokio::time::sleep(delay).await;
+                delay *= 2;
+            }
+            let retryable = match self.http.post(&self.url).json(&event).send().await {
+                Ok(response) if response.status().is_success() => {
+                    debug!(event = event.event.as_str(), common_name = %event.common_name, "webhook delivered");
+                    return;
+                }
+                Ok(response) => {
+                    let status = response.status();
+                    warn!(event = event.event.as_str(), status = %status, attempt, "webhook rejected the event");
+                    status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS
+                }
+                Err(e) => {
+                    warn!(event = event.event.as_str(), error = %e, attempt, "failed to send webhook");
+                    true
+                }
+            };
+            if !retryable {
+                break;
+            }
+        }
+        let count = metrics::inc("webhook_failures", event.event.as_str());
+        warn!(
+            event = event.event.as_str(),
+            common_name = %event.common_name,
+            count,
+            "giving up on webhook delivery"
+        );
+    }
+}
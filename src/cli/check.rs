@@ -0,0 +1,271 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use clap::Args;
+
+use cert_keeper::cert::info::{format_time, parse_pem_chain, CertInfo};
+use cert_keeper::cert::issuer;
+#[cfg(feature = "k8s-csr")]
+use cert_keeper::cert::k8s_csr;
+use cert_keeper::cert::resolver::AlpnChallenges;
+use cert_keeper::config::{parse_duration, CertIssuer, CertNames, Config};
+use cert_keeper::dns::Resolver;
+use cert_keeper::error::{Error, Result};
+use cert_keeper::vault::auth;
+use cert_keeper::vault::client::VaultClient;
+use cert_keeper::vault::pki;
+
+use crate::cli::Report;
+
+#[derive(Debug, Args)]
+pub struct CheckArgs {
+    /// Also issue a short-lived certificate from each PKI role and discard
+    /// it, which proves the role accepts the configured names. Only with
+    /// `CERT_ISSUER=vault`.
+    #[arg(long)]
+    pub issue: bool,
+
+    /// Validity of the certificates `--issue` requests.
+    #[arg(long, default_value = "5m", value_parser = parse_duration, requires = "issue")]
+    pub issue_ttl: Duration,
+}
+
+/// Check that a sidecar with this configuration could start: Vault accepts
+/// the login, the PKI role exists, the token may issue from it, and the
+/// mount's CA chain can be read. With `CERT_ISSUER=acme`, that the CA
+/// accepts the account instead; with `CERT_ISSUER=kubernetes-csr`, that the
+/// service account may create CertificateSigningRequests. With
+/// `CERT_ISSUER=external` there is no issuer to check. Nothing is
+/// written, and nothing is issued without `--issue`.
+///
+/// Returns the first failure's error, so the exit code says what kind of
+/// problem it was.
+pub async fn run(config: Config, args: CheckArgs) -> Result<()> {
+    if args.issue && config.cert_issuer != CertIssuer::Vault {
+        return Err(Error::Config(format!(
+            "check --issue requires CERT_ISSUER=vault, not {}",
+            config.cert_issuer
+        )));
+    }
+    let mut report = Report::default();
+    report.pass(format!(
+        "configuration is valid ({} certificate(s))",
+        1 + config.cert_sni_groups.len() + config.cert_profiles.len()
+    ));
+
+    if config.cert_issuer == CertIssuer::Acme {
+        return check_acme(config, report).await;
+    }
+    if config.cert_issuer == CertIssuer::KubernetesCsr {
+        return check_kubernetes_csr(report).await;
+    }
+    if config.cert_issuer == CertIssuer::External {
+        report.pass("certificates are issued externally, no issuer to check");
+        return Ok(());
+    }
+    if config.cert_issuer == CertIssuer::Exec {
+        report.skip("EXEC_ISSUER_CMD is only run to issue, not checked");
+        return Ok(());
+    }
+
+    let client = VaultClient::new(&config, Resolver::from_config(&config)?)?;
+    if let Err(e) = auth::login(&client, &config).await {
+        report.fail(format!("vault login at {} failed: {e}", config.vault_addr));
+        return Err(e);
+    }
+    report.pass(format!(
+        "logged in to vault at {} ({})",
+        config.vault_addr, config.vault_auth_method
+    ));
+
+    // Profiles may issue from other roles and mounts; check each once.
+    let certs: Vec<(Config, CertNames)> = std::iter::once((config.clone(), config.primary_cert()))
+        .chain(
+            config
+                .cert_profiles
+                .iter()
+                .map(|p| (config.for_profile(p), p.names.clone())),
+        )
+        .collect();
+    let mut paths = Vec::new();
+    let mut mounts = Vec::new();
+    let mut result = Ok(());
+    for (config, names) in &certs {
+        let path = pki::issue_path(config);
+        if !paths.contains(&path) {
+            result = result.and(check_role(&client, config, &mut report).await);
+            let allowed = check_issue(&client, config, &path, &mut report).await;
+            if args.issue && allowed.is_ok() {
+                result = result.and(
+                    check_test_issue(&client, config, names, args.issue_ttl, &path, &mut report)
+                        .await,
+                );
+            }
+            result = result.and(allowed);
+            paths.push(path);
+        }
+        if !mounts.contains(&config.vault_pki_mount) {
+            result = result.and(check_ca_chain(&client, config, &mut report).await);
+            mounts.push(config.vault_pki_mount.clone());
+        }
+    }
+
+    result
+}
+
+async fn check_role(client: &VaultClient, config: &Config, report: &mut Report) -> Result<()> {
+    let role = format!("{}/roles/{}", config.vault_pki_mount, config.vault_pki_role);
+    match pki::role_exists(client, config).await {
+        Ok(Some(true)) => {
+            report.pass(format!("PKI role {role} exists"));
+            Ok(())
+        }
+        Ok(Some(false)) => {
+            report.fail(format!("PKI role {role} does not exist"));
+            Err(Error::VaultPki(format!("no PKI role at {role}")))
+        }
+        Ok(None) => {
+            report.skip(format!(
+                "token may not read {role}, so the role is not known to exist"
+            ));
+            Ok(())
+        }
+        Err(e) => {
+            report.fail(format!("could not read PKI role {role}: {e}"));
+            Err(e)
+        }
+    }
+}
+
+async fn check_issue(
+    client: &VaultClient,
+    config: &Config,
+    path: &str,
+    report: &mut Report,
+) -> Result<()> {
+    match pki::can_issue(client, config).await {
+        Ok(true) => {
+            report.pass(format!("token may issue from {path}"));
+            Ok(())
+        }
+        Ok(false) => {
+            report.fail(format!("token may not issue from {path}"));
+            Err(Error::VaultPki(format!(
+                "the vault token has no update capability on {path}"
+            )))
+        }
+        Err(e) => {
+            report.fail(format!("could not look up capabilities on {path}: {e}"));
+            Err(e)
+        }
+    }
+}
+
+/// Issue a certificate for `names` valid for `ttl` from `path`, and throw
+/// it away.
+async fn check_test_issue(
+    client: &VaultClient,
+    config: &Config,
+    names: &CertNames,
+    ttl: Duration,
+    path: &str,
+    report: &mut Report,
+) -> Result<()> {
+    let mut config = config.clone();
+    config.cert_ttl = format!("{}s", ttl.as_secs());
+    config.cert_not_after = None;
+    let issued = pki::issue_certificate(client, &config, names)
+        .await
+        .and_then(|bundle| CertInfo::from_der(&parse_pem_chain(&bundle.certificate)?[0]));
+    match issued {
+        Ok(leaf) => {
+            report.pass(format!(
+                "issued a test certificate for {} from {path} (serial {}, expires {})",
+                names.common_name,
+                leaf.serial,
+                format_time(leaf.not_after)
+            ));
+            Ok(())
+        }
+        Err(e) => {
+            report.fail(format!(
+                "could not issue a test certificate for {} from {path}: {e}",
+                names.common_name
+            ));
+            Err(e)
+        }
+    }
+}
+
+async fn check_ca_chain(client: &VaultClient, config: &Config, report: &mut Report) -> Result<()> {
+    match pki::fetch_ca_chain(client, config).await {
+        Ok(_) => {
+            report.pass(format!(
+                "CA chain of {} is readable",
+                config.vault_pki_mount
+            ));
+            Ok(())
+        }
+        Err(e) => {
+            report.fail(format!(
+                "could not read the CA chain of {}: {e}",
+                config.vault_pki_mount
+            ));
+            Err(e)
+        }
+    }
+}
+
+/// Registering the account is as far as an ACME CA can be checked without
+/// placing an order.
+async fn check_acme(config: Config, mut report: Report) -> Result<()> {
+    let resolver = Resolver::from_config(&config)?;
+    let client = Arc::new(VaultClient::new(&config, resolver.clone())?);
+    // No order is placed, so no challenge needs answering.
+    let challenges = Some(AlpnChallenges::default());
+    let issuer = issuer::from_config(&config, client, resolver, challenges)?;
+    if let Err(e) = issuer.prepare(&config).await {
+        report.fail(format!(
+            "ACME account registration at {} failed: {e}",
+            config.acme_issuer_directory
+        ));
+        return Err(e);
+    }
+    report.pass(format!(
+        "ACME account registered at {}",
+        config.acme_issuer_directory
+    ));
+    Ok(())
+}
+
+/// Whether the signer's approver will approve cannot be known in advance,
+/// so only the permission to submit is checked.
+#[cfg(feature = "k8s-csr")]
+async fn check_kubernetes_csr(mut report: Report) -> Result<()> {
+    match k8s_csr::can_create_csr().await {
+        Ok(true) => {
+            report.pass("service account may create CertificateSigningRequests");
+            Ok(())
+        }
+        Ok(false) => {
+            report.fail("service account may not create CertificateSigningRequests");
+            Err(Error::KubernetesCsr(
+                "the service account has no create permission on certificatesigningrequests".into(),
+            ))
+        }
+        Err(e) => {
+            report.fail(format!(
+                "could not review CertificateSigningRequest access: {e}"
+            ));
+            Err(e)
+        }
+    }
+}
+
+// Config validation already rejects this.
+#[cfg(not(feature = "k8s-csr"))]
+async fn check_kubernetes_csr(_report: Report) -> Result<()> {
+    Err(Error::Config(
+        "CERT_ISSUER=kubernetes-csr requires a build with the k8s-csr feature".into(),
+    ))
+}
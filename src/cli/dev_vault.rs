@@ -0,0 +1,52 @@
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use clap::Args;
+use tokio::net::TcpListener;
+use tokio::sync::watch;
+
+use cert_keeper::config::LogFormat;
+use cert_keeper::error::Result;
+use cert_keeper::vault::dev::{self, DevVault};
+
+#[derive(Debug, Args)]
+pub struct DevVaultArgs {
+    /// Address to serve the API on, over plain HTTP.
+    #[arg(long, value_name = "ADDR", default_value = "127.0.0.1:8200")]
+    pub listen: SocketAddr,
+
+    /// Token every request but a login must carry, and that logins return.
+    #[arg(long, default_value = "dev")]
+    pub token: String,
+
+    /// Also write the CA certificate to this file, for clients to trust.
+    #[arg(long, value_name = "PATH")]
+    pub ca_file: Option<PathBuf>,
+}
+
+/// Serve the stand-in Vault until interrupted, after printing the settings
+/// that point cert-keeper at it.
+pub async fn run(args: DevVaultArgs) -> Result<()> {
+    crate::logging::init(&LogFormat::Pretty, "info", None);
+    let server = Arc::new(DevVault::new(args.token.clone())?);
+    if let Some(ref path) = args.ca_file {
+        std::fs::write(path, server.ca_pem())?;
+    }
+    let listener = TcpListener::bind(args.listen).await?;
+
+    println!("Point cert-keeper at it with:");
+    println!(
+        "  VAULT_ADDR=http://{} VAULT_AUTH_METHOD=token VAULT_TOKEN={} VAULT_PKI_ROLE=dev",
+        listener.local_addr()?,
+        args.token
+    );
+    println!("Logins on any auth mount succeed as well and return the same token.");
+
+    let (shutdown_tx, shutdown_rx) = watch::channel(false);
+    tokio::spawn(async move {
+        crate::shutdown_signal().await;
+        let _ = shutdown_tx.send(true);
+    });
+    dev::run(server, listener, shutdown_rx).await
+}
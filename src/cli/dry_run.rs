@@ -0,0 +1,309 @@
+use std::sync::Arc;
+
+use tokio::sync::watch;
+
+use cert_keeper::cert::client_auth;
+use cert_keeper::cert::info::{format_time, parse_pem_chain, CertInfo};
+use cert_keeper::cert::issuer::{self, CertRequest};
+use cert_keeper::cert::manager::{build_server_config, certified_key};
+use cert_keeper::cert::store::CertStore;
+use cert_keeper::config::{
+    AcmeChallenge, AuthMethod, BackendClientCert, CertIssuer, ClientAuth, Config,
+};
+use cert_keeper::dns::Resolver;
+use cert_keeper::error::{Error, Result};
+use cert_keeper::vault::auth;
+use cert_keeper::vault::client::VaultClient;
+use cert_keeper::vault::pki;
+
+use crate::proxy::backend_tls;
+
+/// Authenticate and issue a certificate, then print a summary of the files
+/// and listener that a real run would produce. Nothing is written to disk.
+pub async fn run(config: Config) -> Result<()> {
+    if config.cert_issuer == CertIssuer::External {
+        return Err(Error::Config(
+            "dry-run issues a certificate, which CERT_ISSUER=external leaves to another agent"
+                .into(),
+        ));
+    }
+    let resolver = Resolver::from_config(&config)?;
+    let client = Arc::new(VaultClient::new(&config, resolver.clone())?);
+    // No proxy runs, so nothing can answer tls-alpn-01.
+    let issuer = issuer::from_config(&config, client.clone(), resolver, None)?;
+
+    if config.cert_issuer == CertIssuer::Vault {
+        auth::login(&client, &config).await?;
+    }
+    let names = config.primary_cert();
+    let req = CertRequest {
+        config: &config,
+        names: &names,
+    };
+    let bundle = issuer::issue(&*issuer, req).await?;
+    let mut groups = Vec::new();
+    for names in &config.cert_sni_groups {
+        let req = CertRequest {
+            config: &config,
+            names,
+        };
+        groups.push((names, issuer::issue(&*issuer, req).await?));
+    }
+    let mut profiles = Vec::new();
+    for profile in &config.cert_profiles {
+        let profile_config = config.for_profile(profile);
+        let req = CertRequest {
+            config: &profile_config,
+            names: &profile.names,
+        };
+        let profile_bundle = issuer::issue(&*issuer, req).await?;
+        profiles.push((profile, profile_config, profile_bundle));
+    }
+
+    // Make sure the proxy would actually be able to load what Vault returned.
+    let client_verifier = client_auth::verifier(&client, &config).await?;
+    let identities = std::iter::once(&bundle)
+        .chain(groups.iter().map(|(_, b)| b))
+        .map(|b| Ok(Arc::new(certified_key(&b.certificate, &b.private_key)?)))
+        .collect::<Result<Vec<_>>>()?;
+    build_server_config(&config, &identities, client_verifier)?;
+    if config.proxy_enabled {
+        backend_tls::load(&client, &config, watch::channel(None).1).await?;
+    }
+
+    let chain = parse_pem_chain(&bundle.certificate)?;
+    let leaf = CertInfo::from_der(&chain[0])?;
+    let store = CertStore::new(&config.cert_dir, &config)
+        .with_secret(config.secret_name(&config.cert_common_name));
+    let files = store.render(&bundle).await?;
+
+    println!("dry run: no files were written and no listener was started");
+    println!();
+    match config.cert_issuer {
+        CertIssuer::Vault => print_vault(&config),
+        CertIssuer::Acme => print_acme(&config),
+        CertIssuer::KubernetesCsr => print_kubernetes_csr(&config),
+        CertIssuer::Exec => print_exec(&config),
+        CertIssuer::External => {}
+    }
+    println!();
+    println!("certificate");
+    println!("  subject:     {}", leaf.subject);
+    println!("  issuer:      {}", leaf.issuer);
+    println!("  serial:      {}", leaf.serial);
+    println!("  not before:  {}", format_time(leaf.not_before));
+    println!("  not after:   {}", format_time(leaf.not_after));
+    println!("  dns names:   {}", leaf.dns_names.join(", "));
+    println!("  ip sans:     {}", join_display(&leaf.ip_addresses));
+    println!("  uri sans:    {}", leaf.uris.join(", "));
+    println!("  chain:       {} certificate(s)", chain.len());
+    println!("  lease:       {}s", bundle.lease_duration_secs);
+    println!();
+    if store.writes_files() {
+        println!("files that would be written");
+        for (path, _, contents) in &files {
+            println!("  {}  ({} bytes)", path.display(), contents.len());
+        }
+        if let Some(path) = store.pkcs12_path() {
+            println!("  {}  (PKCS#12)", path.display());
+        }
+        if let Some(path) = store.jks_path() {
+            println!("  {}  (JKS)", path.display());
+        }
+    }
+    let namespace = config
+        .cert_secret_namespace
+        .as_deref()
+        .unwrap_or("the pod's namespace");
+    if let Some(name) = store.secret_name() {
+        println!("secret that would be applied");
+        println!("  {name} in {namespace}  (kubernetes.io/tls)");
+    }
+    if !groups.is_empty() {
+        println!();
+        println!("sni certificates");
+        for (names, group_bundle) in &groups {
+            let leaf = CertInfo::from_der(&parse_pem_chain(&group_bundle.certificate)?[0])?;
+            println!(
+                "  {}  ({}, not after {})",
+                names.common_name,
+                leaf.dns_names.join(", "),
+                format_time(leaf.not_after)
+            );
+            if config.cert_output_files {
+                println!("    files in {}/{}/", config.cert_dir, names.common_name);
+            }
+            if let Some(name) = config.secret_name(&names.common_name) {
+                println!("    secret {name} in {namespace}");
+            }
+        }
+    }
+    if !profiles.is_empty() {
+        println!();
+        println!("profile certificates (not served)");
+        for (profile, profile_config, profile_bundle) in &profiles {
+            let leaf = CertInfo::from_der(&parse_pem_chain(&profile_bundle.certificate)?[0])?;
+            println!(
+                "  {}  ({}, not after {})",
+                profile.name,
+                leaf.subject,
+                format_time(leaf.not_after)
+            );
+            let path = pki::issue_path(profile_config);
+            if config.cert_issuer == CertIssuer::Vault && path != pki::issue_path(&config) {
+                println!("    issued from {path}");
+            }
+            if config.cert_output_files {
+                println!("    files in {}/", profile.dir);
+            }
+            if let Some(name) = config.profile_secret_name(profile) {
+                println!("    secret {name} in {namespace}");
+            }
+        }
+    }
+    println!();
+    if !config.proxy_enabled {
+        println!("proxy disabled (PROXY_ENABLED=false)");
+        return Ok(());
+    }
+    println!("proxy that would be served");
+    println!(
+        "  {} -> {}",
+        config.listen_addr,
+        config.backend_addrs.join(", ")
+    );
+    println!("  tls:         {} and newer", config.tls_min_version);
+    if !config.tls_cipher_suites.is_empty() {
+        let suites: Vec<_> = config
+            .tls_cipher_suites
+            .iter()
+            .map(|s| format!("{:?}", s.suite()))
+            .collect();
+        println!("  ciphers:     {}", suites.join(", "));
+    }
+    if !config.alpn_protocols.is_empty() {
+        let protocols: Vec<_> = config
+            .alpn_protocols
+            .iter()
+            .map(|p| String::from_utf8_lossy(p))
+            .collect();
+        println!("  alpn:        {}", protocols.join(", "));
+    }
+    if config.backend_addrs.len() > 1 {
+        println!("  balancing:   {:?}", config.backend_lb_policy);
+    }
+    if config.client_auth != ClientAuth::None {
+        println!(
+            "  client auth: {:?}, CAs from {}",
+            config.client_auth,
+            config
+                .client_ca_file
+                .as_deref()
+                .unwrap_or("the PKI mount's CA chain")
+        );
+    }
+    if config.backend_tls {
+        println!(
+            "  backend tls: server name {}, CAs from {}",
+            config
+                .backend_tls_server_name
+                .as_deref()
+                .unwrap_or("from BACKEND_ADDR"),
+            config
+                .backend_tls_ca_file
+                .as_deref()
+                .unwrap_or("the PKI mount's CA chain")
+        );
+        match config.backend_tls_client_cert {
+            BackendClientCert::None => {}
+            BackendClientCert::Issued => println!("  backend client cert: the issued certificate"),
+            BackendClientCert::File { ref cert_file, .. } => {
+                println!("  backend client cert: {cert_file}")
+            }
+        }
+        if !config.backend_tls_pins.is_empty() {
+            println!("  backend pins: {}", config.backend_tls_pins.len());
+        }
+    }
+
+    Ok(())
+}
+
+fn print_vault(config: &Config) {
+    println!("vault");
+    println!("  address:     {}", config.vault_addr);
+    match config.vault_auth_method {
+        AuthMethod::Token { ref token_file } => println!(
+            "  auth:        static token from {}",
+            token_file.as_deref().unwrap_or("VAULT_TOKEN")
+        ),
+        _ => println!(
+            "  auth:        auth/{} ({}, role {})",
+            config.vault_auth_mount, config.vault_auth_method, config.vault_auth_role
+        ),
+    }
+    if let AuthMethod::Kubernetes { ref token_file } | AuthMethod::Jwt { ref token_file } =
+        config.vault_auth_method
+    {
+        println!("  jwt from:    {token_file}");
+    }
+    if config.vault_auth_namespace.is_some() || config.vault_pki_namespace.is_some() {
+        let ns = |ns: &Option<String>| ns.clone().unwrap_or_else(|| "root".into());
+        println!(
+            "  namespaces:  auth {}, pki {}",
+            ns(&config.vault_auth_namespace),
+            ns(&config.vault_pki_namespace)
+        );
+    }
+    println!("  issue path:  {}", pki::issue_path(config));
+    if let Some(ttl) = config.vault_wrap_ttl {
+        println!("  wrapped:     yes, ttl {}s", ttl.as_secs());
+    }
+}
+
+fn print_acme(config: &Config) {
+    println!("acme");
+    println!("  directory:   {}", config.acme_issuer_directory);
+    match config.acme_issuer_challenge {
+        AcmeChallenge::Http01 => {
+            println!("  challenge:   http-01 on {}", config.acme_issuer_http_addr)
+        }
+        AcmeChallenge::Dns01 => println!(
+            "  challenge:   dns-01 via {}",
+            config.acme_issuer_dns_hook.as_deref().unwrap_or("-")
+        ),
+        AcmeChallenge::TlsAlpn01 => {
+            println!("  challenge:   tls-alpn-01 on {}", config.listen_addr)
+        }
+    }
+    println!(
+        "  account key: {}",
+        config
+            .acme_issuer_account_key_file
+            .as_deref()
+            .unwrap_or("new for every start")
+    );
+}
+
+fn print_kubernetes_csr(config: &Config) {
+    println!("kubernetes-csr");
+    println!("  signer:      {}", config.k8s_csr_signer_name);
+    match config.k8s_csr_expiration {
+        Some(expiration) => println!("  expiration:  {}s", expiration.as_secs()),
+        None => println!("  expiration:  signer's default"),
+    }
+}
+
+fn print_exec(config: &Config) {
+    println!("exec");
+    println!("  command:     {}", config.exec_issuer_cmd);
+    println!("  timeout:     {}s", config.exec_issuer_timeout.as_secs());
+}
+
+fn join_display<T: std::fmt::Display>(items: &[T]) -> String {
+    items
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
@@ -0,0 +1,44 @@
+use std::sync::Arc;
+
+use tokio::sync::watch;
+use tracing::info;
+
+use cert_keeper::cert::audit::AuditLog;
+use cert_keeper::cert::issuer;
+use cert_keeper::cert::manager::CertManager;
+use cert_keeper::config::Config;
+use cert_keeper::dns::Resolver;
+use cert_keeper::error::Result;
+use cert_keeper::vault::client::VaultClient;
+use cert_keeper::vault::kv;
+use cert_keeper::vault::trust_bundle;
+
+/// Log in, issue every configured certificate, and write the files to
+/// `CERT_DIR`, those of `KV_SECRETS` to `SECRETS_DIR`, and the trust bundle
+/// to `TRUST_BUNDLE_FILE`, the same way the sidecar does at startup, then
+/// return without renewing or serving anything.
+pub async fn run(config: Config) -> Result<()> {
+    let resolver = Resolver::from_config(&config)?;
+    let client = Arc::new(VaultClient::new(&config, resolver.clone())?);
+    // Without the proxy nothing can answer tls-alpn-01.
+    let issuer = issuer::from_config(&config, client.clone(), resolver, None)?;
+    let cert_dir = config.cert_dir.clone();
+
+    // Startup also builds the server config, so a certificate the proxy
+    // could not load fails here too.
+    let (tx, _) = watch::channel(None);
+    let audit = AuditLog::new(&config)?;
+    // Nothing is renewed, so there are no webhook events to send.
+    let vault_config = config.clone();
+    let mut manager = CertManager::new(client.clone(), issuer, config, tx, None, audit);
+    manager.init().await?;
+
+    info!(cert_dir = %cert_dir, "certificates fetched");
+    if !vault_config.kv_secrets.is_empty() {
+        kv::write_secrets(&client, &vault_config).await?;
+    }
+    if let Some(ref path) = vault_config.trust_bundle_file {
+        trust_bundle::write_bundle(&client, &vault_config, path.as_ref()).await?;
+    }
+    Ok(())
+}
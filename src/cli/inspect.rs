@@ -0,0 +1,187 @@
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use clap::Args;
+use rustls::pki_types::CertificateDer;
+use serde::Serialize;
+
+use cert_keeper::cert::info::{format_time, parse_pem_chain, CertInfo};
+use cert_keeper::cert::store::layout_from_env;
+use cert_keeper::config::{FileContent, DEFAULT_CERT_DIR};
+use cert_keeper::error::{Error, Result};
+
+use crate::cli::OutputFormat;
+
+#[derive(Debug, Args)]
+pub struct InspectArgs {
+    /// PEM or DER certificate file, a directory laid out by `$CERT_FILES`,
+    /// or `-` for stdin. Defaults to `$CERT_DIR`.
+    pub path: Option<String>,
+
+    /// Output format.
+    #[arg(long, value_enum, default_value_t = OutputFormat::Human)]
+    pub format: OutputFormat,
+}
+
+#[derive(Serialize)]
+struct ChainEntry<'a> {
+    position: usize,
+    role: &'static str,
+    #[serde(flatten)]
+    info: &'a CertInfo,
+    /// Whether the next certificate in the file is this one's issuer.
+    /// `None` for the last certificate.
+    issued_by_next: Option<bool>,
+}
+
+/// Print the details and chain structure of a certificate file.
+pub fn run(args: InspectArgs) -> Result<()> {
+    let (source, certs) = load(args.path.as_deref())?;
+    let infos = certs
+        .iter()
+        .map(|der| CertInfo::from_der(der))
+        .collect::<Result<Vec<_>>>()?;
+
+    let entries: Vec<ChainEntry> = infos
+        .iter()
+        .enumerate()
+        .map(|(i, info)| ChainEntry {
+            position: i,
+            role: role(info),
+            info,
+            issued_by_next: infos.get(i + 1).map(|next| next.subject == info.issuer),
+        })
+        .collect();
+
+    match args.format {
+        OutputFormat::Json => {
+            let out = serde_json::json!({ "source": source, "chain": entries });
+            println!("{}", serde_json::to_string_pretty(&out)?);
+        }
+        OutputFormat::Human => print_human(&source, &entries),
+    }
+
+    Ok(())
+}
+
+fn print_human(source: &str, entries: &[ChainEntry]) {
+    println!("{source}: {} certificate(s)", entries.len());
+
+    for entry in entries {
+        let info = entry.info;
+        println!();
+        println!("[{}] {}", entry.position, entry.role);
+        println!("  subject:      {}", info.subject);
+        println!("  issuer:       {}", info.issuer);
+        println!("  serial:       {}", info.serial);
+        println!("  not before:   {}", format_time(info.not_before));
+        println!(
+            "  not after:    {} ({})",
+            format_time(info.not_after),
+            describe_remaining(info.remaining_secs())
+        );
+        if !info.dns_names.is_empty() {
+            println!("  dns names:    {}", info.dns_names.join(", "));
+        }
+        if !info.ip_addresses.is_empty() {
+            let ips: Vec<String> = info.ip_addresses.iter().map(ToString::to_string).collect();
+            println!("  ip sans:      {}", ips.join(", "));
+        }
+        if !info.uris.is_empty() {
+            println!("  uri sans:     {}", info.uris.join(", "));
+        }
+        println!("  sha256:       {}", info.sha256_fingerprint);
+        println!("  sha1:         {}", info.sha1_fingerprint);
+    }
+
+    println!();
+    println!("chain");
+    for entry in entries {
+        let link = match entry.issued_by_next {
+            Some(true) => "issued by next",
+            Some(false) => "NOT issued by next certificate",
+            None if entry.info.self_signed => "self-signed",
+            None => "issuer not included",
+        };
+        println!("  [{}] {} ({link})", entry.position, entry.info.subject);
+    }
+}
+
+/// Read certificates from the given source, returning a display name for it
+/// along with the DER-encoded certificates.
+fn load(path: Option<&str>) -> Result<(String, Vec<CertificateDer<'static>>)> {
+    let (source, bytes) = match path {
+        Some("-") => {
+            let mut buf = Vec::new();
+            std::io::stdin().read_to_end(&mut buf)?;
+            ("<stdin>".to_string(), buf)
+        }
+        Some(p) => read_path(Path::new(p))?,
+        None => {
+            let dir = std::env::var("CERT_DIR").unwrap_or_else(|_| DEFAULT_CERT_DIR.into());
+            read_path(Path::new(&dir))?
+        }
+    };
+
+    let certs = if bytes.windows(10).any(|w| w == b"-----BEGIN") {
+        let pem = String::from_utf8(bytes)
+            .map_err(|e| Error::CertParse(format!("PEM input is not valid UTF-8: {e}")))?;
+        parse_pem_chain(&pem)?
+    } else {
+        vec![CertificateDer::from(bytes)]
+    };
+
+    Ok((source, certs))
+}
+
+fn read_path(path: &Path) -> Result<(String, Vec<u8>)> {
+    let file: PathBuf = if path.is_dir() {
+        let name = layout_from_env()?
+            .into_iter()
+            .find(|f| {
+                matches!(
+                    f.content,
+                    FileContent::FullChain | FileContent::Combined | FileContent::Leaf
+                )
+            })
+            .ok_or_else(|| Error::Config("CERT_FILES has no file holding the certificate".into()))?
+            .name;
+        path.join(name)
+    } else {
+        path.to_path_buf()
+    };
+
+    let bytes = std::fs::read(&file)
+        .map_err(|e| Error::CertParse(format!("failed to read {}: {e}", file.display())))?;
+    Ok((file.display().to_string(), bytes))
+}
+
+fn role(info: &CertInfo) -> &'static str {
+    match (info.is_ca, info.self_signed) {
+        (true, true) => "root",
+        (true, false) => "intermediate",
+        (false, _) => "leaf",
+    }
+}
+
+/// Render remaining validity as e.g. `expires in 3d 4h` or `expired 2h ago`.
+pub fn describe_remaining(secs: i64) -> String {
+    let span = format_span(secs.unsigned_abs());
+    if secs >= 0 {
+        format!("expires in {span}")
+    } else {
+        format!("expired {span} ago")
+    }
+}
+
+fn format_span(secs: u64) -> String {
+    let days = secs / 86_400;
+    let hours = (secs % 86_400) / 3_600;
+    let minutes = (secs % 3_600) / 60;
+
+    match (days, hours) {
+        (0, 0) => format!("{minutes}m"),
+        (0, _) => format!("{hours}h {minutes}m"),
+        _ => format!("{days}d {hours}h"),
+    }
+}
@@ -0,0 +1,99 @@
+pub mod check;
+#[cfg(feature = "dev-vault")]
+pub mod dev_vault;
+pub mod dry_run;
+pub mod fetch;
+pub mod inspect;
+pub mod probe;
+pub mod verify;
+
+use std::path::PathBuf;
+
+use clap::{Parser, Subcommand, ValueEnum};
+
+/// Kubernetes sidecar for Vault PKI TLS certificate management and termination.
+///
+/// Runtime settings come from environment variables and, optionally, a
+/// config file; the other flags here only select how the process behaves.
+#[derive(Debug, Parser)]
+#[command(name = "cert-keeper", version, about)]
+pub struct Cli {
+    /// TOML or YAML file of settings keyed by environment variable name.
+    /// Environment variables override the file.
+    #[arg(long = "config", env = "CONFIG_FILE", value_name = "PATH")]
+    pub config_file: Option<PathBuf>,
+
+    /// Log in and issue a certificate, print what would be written and
+    /// served, then exit without touching disk or binding a listener.
+    #[arg(long)]
+    pub dry_run: bool,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Keep the certificates renewed and serve the TLS proxy. This is what
+    /// runs when no command is given.
+    Run,
+
+    /// Issue every configured certificate, write it to `CERT_DIR`, and exit.
+    Fetch,
+
+    /// Check the configuration, the Vault login, that the PKI role exists,
+    /// and that the token may issue from it, without serving anything.
+    /// Exits non-zero on failure.
+    Check(check::CheckArgs),
+
+    /// Print subject, SANs, validity, fingerprints, and chain structure of a
+    /// certificate file or the managed certificate directory.
+    Inspect(inspect::InspectArgs),
+
+    /// Check that the managed bundle is usable: key matches certificate,
+    /// chain builds to the CA bundle, configured names are covered, and the
+    /// certificate is not about to expire. Exits non-zero on failure.
+    Verify(verify::VerifyArgs),
+
+    /// Exit non-zero unless `READY_FILE`, or `HEALTHY_FILE` with
+    /// `--healthy`, exists, for exec probes.
+    Probe(probe::ProbeArgs),
+
+    /// Serve a stand-in for Vault's login and PKI endpoints, issuing from
+    /// a CA generated at startup, for running cert-keeper locally without
+    /// a Vault server.
+    #[cfg(feature = "dev-vault")]
+    DevVault(dev_vault::DevVaultArgs),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    Human,
+    Json,
+}
+
+/// Outcome of the individual checks, printed one per line.
+#[derive(Default)]
+pub struct Report {
+    failed: bool,
+}
+
+impl Report {
+    pub fn pass(&mut self, msg: impl AsRef<str>) {
+        println!("ok    {}", msg.as_ref());
+    }
+
+    /// A check that could not be made; it does not fail the command.
+    pub fn skip(&mut self, msg: impl AsRef<str>) {
+        println!("skip  {}", msg.as_ref());
+    }
+
+    pub fn fail(&mut self, msg: impl AsRef<str>) {
+        println!("FAIL  {}", msg.as_ref());
+        self.failed = true;
+    }
+
+    pub fn failed(&self) -> bool {
+        self.failed
+    }
+}
@@ -0,0 +1,35 @@
+use std::io;
+use std::path::PathBuf;
+
+use clap::Args;
+
+use cert_keeper::error::{Error, Result};
+
+#[derive(Debug, Args)]
+pub struct ProbeArgs {
+    /// Check `$HEALTHY_FILE` instead of `$READY_FILE`.
+    #[arg(long)]
+    pub healthy: bool,
+
+    /// The file to check, for when it is set in the config file rather
+    /// than the environment.
+    #[arg(long, value_name = "PATH")]
+    pub file: Option<PathBuf>,
+}
+
+/// Fail unless the probe file exists. The container image has no shell
+/// to run `test -f` with, so this is what exec probes call.
+pub fn run(args: ProbeArgs) -> Result<()> {
+    let var = if args.healthy {
+        "HEALTHY_FILE"
+    } else {
+        "READY_FILE"
+    };
+    let path = args
+        .file
+        .or_else(|| std::env::var_os(var).map(PathBuf::from))
+        .ok_or_else(|| Error::Config(format!("{var} is not set, pass --file")))?;
+    std::fs::metadata(&path)
+        .map(|_| ())
+        .map_err(|e| io::Error::new(e.kind(), format!("{var} {}: {e}", path.display())).into())
+}
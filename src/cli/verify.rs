@@ -0,0 +1,219 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use clap::Args;
+use rustls::client::danger::ServerCertVerifier;
+use rustls::client::WebPkiServerVerifier;
+use rustls::crypto::CryptoProvider;
+use rustls::pki_types::{ServerName, UnixTime};
+use rustls::sign::CertifiedKey;
+use rustls::RootCertStore;
+
+use cert_keeper::cert::info::{parse_pem_chain, CertInfo};
+use cert_keeper::cert::store::{is_encrypted_key, layout_from_env};
+use cert_keeper::config::{parse_duration, templated_env, FileContent, DEFAULT_CERT_DIR};
+use cert_keeper::error::{Error, Result};
+
+use crate::cli::inspect::describe_remaining;
+use crate::cli::Report;
+
+#[derive(Debug, Args)]
+pub struct VerifyArgs {
+    /// Directory containing the files laid out by `$CERT_FILES`
+    /// (`tls.crt`, `tls.key`, and `ca.crt` by default). Defaults to
+    /// `$CERT_DIR`.
+    #[arg(long)]
+    pub cert_dir: Option<String>,
+
+    /// Fail if the certificate expires within this duration.
+    #[arg(long, default_value = "1h", value_parser = parse_duration)]
+    pub min_validity: Duration,
+}
+
+/// Validate the managed certificate bundle. Returns an error if any check
+/// fails so the process exits non-zero.
+///
+/// The configured names come from `CERT_COMMON_NAME`, `CERT_ALT_NAMES`,
+/// `CERT_IP_SANS`, and `CERT_URI_SANS`, so the command can run as an exec probe in the sidecar
+/// container without any extra arguments.
+pub fn run(args: VerifyArgs) -> Result<()> {
+    let dir = PathBuf::from(
+        args.cert_dir
+            .or_else(|| std::env::var("CERT_DIR").ok())
+            .unwrap_or_else(|| DEFAULT_CERT_DIR.into()),
+    );
+
+    let (cert_pem, key_pem, ca_pem) = read_bundle(&dir)?;
+
+    let chain = parse_pem_chain(&cert_pem)?;
+    let ca_certs = parse_pem_chain(&ca_pem)?;
+    let leaf = CertInfo::from_der(&chain[0])?;
+
+    let mut report = Report::default();
+
+    // Key and certificate match.
+    match key_pem {
+        None => report.skip("private key is kept in memory only (STORE_MODE=memory)"),
+        Some(key_pem) if is_encrypted_key(&key_pem) => {
+            report.skip("private key is encrypted, not matched against the certificate")
+        }
+        Some(key_pem) => {
+            let key = rustls_pemfile::private_key(&mut key_pem.as_bytes())
+                .map_err(|e| Error::CertParse(format!("failed to parse private key PEM: {e}")))?
+                .ok_or_else(|| Error::CertParse("no private key found in PEM".into()))?;
+            let provider = CryptoProvider::get_default()
+                .ok_or_else(|| Error::Tls("no crypto provider installed".into()))?;
+            match CertifiedKey::from_der(chain.clone(), key, provider)
+                .and_then(|ck| ck.keys_match())
+            {
+                Ok(()) => report.pass("private key matches certificate"),
+                Err(e) => report.fail(format!("private key does not match certificate: {e}")),
+            }
+        }
+    }
+
+    // Chain builds to ca.crt, and every configured name is covered.
+    let mut roots = RootCertStore::empty();
+    let (added, _) = roots.add_parsable_certificates(ca_certs);
+    if added == 0 {
+        return Err(Error::CertParse(
+            "ca.crt contains no usable CA certificates".into(),
+        ));
+    }
+    let verifier = WebPkiServerVerifier::builder(Arc::new(roots))
+        .build()
+        .map_err(|e| Error::Tls(format!("failed to build chain verifier: {e}")))?;
+
+    let names = configured_names()?;
+    let mut chain_checked = false;
+    for name in &names {
+        if name.starts_with("*.") {
+            // Wildcards are not valid server names; check presence literally.
+            if leaf.dns_names.iter().any(|n| n == name) {
+                report.pass(format!("certificate covers {name}"));
+            } else {
+                report.fail(format!("certificate does not include SAN {name}"));
+            }
+            continue;
+        }
+
+        let server_name = match ServerName::try_from(name.as_str()) {
+            Ok(n) => n.to_owned(),
+            Err(e) => {
+                report.fail(format!("configured name {name} is invalid: {e}"));
+                continue;
+            }
+        };
+
+        match verifier.verify_server_cert(
+            &chain[0],
+            &chain[1..],
+            &server_name,
+            &[],
+            UnixTime::now(),
+        ) {
+            Ok(_) => report.pass(format!("chain builds to ca.crt and covers {name}")),
+            Err(e) => report.fail(format!("verification for {name} failed: {e}")),
+        }
+        chain_checked = true;
+    }
+
+    // URI SANs are identities rather than server names; check presence.
+    for uri in list_var("CERT_URI_SANS")? {
+        if leaf.uris.contains(&uri) {
+            report.pass(format!("certificate covers {uri}"));
+        } else {
+            report.fail(format!("certificate does not include URI SAN {uri}"));
+        }
+    }
+
+    if !chain_checked {
+        println!("skip  no configured names to verify the chain against");
+    }
+
+    // Expiry margin.
+    let remaining = leaf.remaining_secs();
+    let msg = format!(
+        "certificate {} (minimum {}s)",
+        describe_remaining(remaining),
+        args.min_validity.as_secs()
+    );
+    if remaining >= args.min_validity.as_secs() as i64 {
+        report.pass(msg);
+    } else {
+        report.fail(msg);
+    }
+
+    if report.failed() {
+        return Err(Error::CertParse(
+            "certificate bundle verification failed".into(),
+        ));
+    }
+    Ok(())
+}
+
+/// Names from the certificate request configuration that the served
+/// certificate must cover, with their pod templates filled in.
+fn configured_names() -> Result<Vec<String>> {
+    let mut names = Vec::new();
+    if let Some(cn) = templated_env("CERT_COMMON_NAME")? {
+        names.push(cn);
+    }
+    for key in ["CERT_ALT_NAMES", "CERT_IP_SANS"] {
+        names.extend(list_var(key)?);
+    }
+    names.dedup();
+    Ok(names)
+}
+
+/// The entries of a comma-separated list variable.
+fn list_var(key: &str) -> Result<Vec<String>> {
+    Ok(templated_env(key)?
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(String::from)
+        .collect())
+}
+
+/// The certificate chain, private key, and CA bundle from the files
+/// `CERT_FILES` lays out in `dir`. There is no key with
+/// `STORE_MODE=memory`.
+fn read_bundle(dir: &Path) -> Result<(String, Option<String>, String)> {
+    let files = layout_from_env()?;
+    let find = |contents: &[FileContent]| {
+        files
+            .iter()
+            .find(|f| contents.contains(&f.content))
+            .map(|f| dir.join(&f.name))
+    };
+    let missing = |what: &str| Error::Config(format!("CERT_FILES has no file holding the {what}"));
+
+    let cert_pem = match find(&[FileContent::FullChain, FileContent::Combined]) {
+        Some(path) => read(&path)?,
+        None => {
+            let leaf = find(&[FileContent::Leaf]).ok_or_else(|| missing("certificate"))?;
+            let mut pem = read(&leaf)?;
+            if let Some(chain) = find(&[FileContent::Chain]) {
+                pem.push_str(&read(&chain)?);
+            }
+            pem
+        }
+    };
+    let key = if std::env::var("STORE_MODE").is_ok_and(|mode| mode == "memory") {
+        None
+    } else {
+        let key = find(&[FileContent::Key, FileContent::Combined])
+            .ok_or_else(|| missing("private key"))?;
+        Some(read(&key)?)
+    };
+    let ca = find(&[FileContent::Ca]).ok_or_else(|| missing("CA bundle"))?;
+    Ok((cert_pem, key, read(&ca)?))
+}
+
+fn read(path: &Path) -> Result<String> {
+    std::fs::read_to_string(path)
+        .map_err(|e| Error::CertParse(format!("failed to read {}: {e}", path.display())))
+}
@@ -1,26 +1,1017 @@
+use std::collections::BTreeMap;
 use std::env;
-use std::net::SocketAddr;
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
 
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use rustls::crypto::ring::ALL_CIPHER_SUITES;
+use rustls::pki_types::ServerName;
+use rustls::{CipherSuite, ProtocolVersion, SupportedCipherSuite};
+use serde::Deserialize;
+use time::format_description::well_known::Rfc3339;
+use time::{OffsetDateTime, UtcOffset};
+use tracing_subscriber::EnvFilter;
+
+use crate::dns;
 use crate::error::{Error, Result};
+use crate::vault::auth::{SA_NAMESPACE_PATH, SA_TOKEN_PATH};
+
+/// Directory certificate files are written to when `CERT_DIR` is not set.
+pub const DEFAULT_CERT_DIR: &str = "/certs";
+
+/// Files written to each certificate's directory when `CERT_FILES` is not
+/// set.
+pub const DEFAULT_CERT_FILES: &str = "tls.crt=fullchain,tls.key=key,ca.crt=ca";
+
+const DEFAULT_RENEWAL_JITTER: f64 = 0.1;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Config {
+    pub mode: Mode,
     pub vault_addr: String,
+    pub vault_auth_method: AuthMethod,
     pub vault_auth_role: String,
     pub vault_auth_mount: String,
     pub vault_pki_role: String,
     pub vault_pki_mount: String,
+    /// Issuer name or ID to sign with instead of the mount's default.
+    pub vault_pki_issuer: Option<String>,
     pub vault_namespace: Option<String>,
+    /// Namespace of the auth mount, and so of the token; `None` is the
+    /// root namespace.
+    pub vault_auth_namespace: Option<String>,
+    /// Namespace of the PKI mount.
+    pub vault_pki_namespace: Option<String>,
     pub vault_cacert: Option<String>,
+    /// Certificate and key presented to Vault listeners that require
+    /// client certificates.
+    pub vault_client_cert: Option<String>,
+    pub vault_client_key: Option<String>,
+    /// Name Vault's certificate is checked against instead of the host in
+    /// `vault_addr`.
+    pub vault_tls_server_name: Option<String>,
+    /// Accept any certificate from Vault. For lab setups only.
+    pub vault_skip_verify: bool,
+    pub vault_token_cache: Option<String>,
+    pub vault_token_cache_key_file: Option<String>,
+    /// Further attempts after a Vault request fails for lack of a working
+    /// Vault.
+    pub vault_retries: u32,
+    pub vault_retry_backoff: Duration,
+    /// Consecutive failed requests that open the circuit; 0 never opens it.
+    pub vault_circuit_threshold: u32,
+    pub vault_circuit_cooldown: Duration,
+    /// How long startup waits for Vault to report itself healthy; zero
+    /// skips the check.
+    pub vault_startup_timeout: Duration,
+    pub vault_connect_timeout: Duration,
+    /// Limit on each attempt at a Vault request, reading the response
+    /// included.
+    pub vault_request_timeout: Duration,
+    /// Limit on a Vault request with all its retries.
+    pub vault_operation_timeout: Duration,
+    pub vault_pool_idle_timeout: Duration,
+    /// Idle connections kept to Vault; `None` keeps any number.
+    pub vault_pool_max_idle: Option<usize>,
+    /// When set, certificates are requested response-wrapped with this TTL
+    /// and unwrapped with `sys/wrapping/unwrap`.
+    pub vault_wrap_ttl: Option<Duration>,
+    pub vault_kv_mount: String,
+    /// Vault KV v2 secrets written to `secrets_dir`.
+    pub kv_secrets: Vec<KvSecret>,
+    pub secrets_dir: String,
     pub cert_common_name: String,
     pub cert_alt_names: Option<String>,
     pub cert_ip_sans: Option<String>,
+    pub cert_uri_sans: Option<String>,
     pub cert_ttl: String,
+    /// Fixed expiry requested instead of `cert_ttl`, for certificates that
+    /// must all expire at the same time.
+    pub cert_not_after: Option<OffsetDateTime>,
+    /// Keep the common name out of the DNS and email SANs, for a CN that is
+    /// not a hostname.
+    pub cert_exclude_cn_from_sans: bool,
+    /// Comma-separated `<oid>;<type>:<value>` otherName SANs, as Vault
+    /// takes them.
+    pub cert_other_sans: Option<String>,
+    /// Encoding of a key Vault generates; `None` leaves it to Vault.
+    pub cert_private_key_format: Option<PrivateKeyFormat>,
+    /// The subject's `serialNumber` attribute, not the certificate serial.
+    pub cert_serial_number: Option<String>,
+    /// Additional certificates, selected by SNI.
+    pub cert_sni_groups: Vec<CertNames>,
+    /// Certificates that are only written out, never served.
+    pub cert_profiles: Vec<CertProfile>,
+    pub cert_key_source: KeySource,
+    pub cert_key_type: Option<KeyType>,
+    pub cert_key_bits: Option<u32>,
     pub cert_dir: String,
-    pub listen_addr: SocketAddr,
-    pub backend_addr: SocketAddr,
+    pub extra_ca_files: Vec<String>,
+    /// CA certificates served after the issued chain, such as a new
+    /// intermediate cross-signed by the old root during a CA rotation.
+    pub cert_extra_chain_file: Option<String>,
+    /// Issuers of `vault_pki_mount` whose certificates are served the same
+    /// way.
+    pub cert_extra_chain_issuers: Vec<String>,
+    /// End of the overlap window; certificates issued later get no extra
+    /// chain.
+    pub cert_extra_chain_until: Option<OffsetDateTime>,
+    /// Check a renewed certificate with a test handshake before serving it.
+    pub cert_validate: bool,
+    /// Where the CA certificates of `trust_bundle_mounts` are written.
+    pub trust_bundle_file: Option<String>,
+    /// PKI mounts whose issuers go into the trust bundle.
+    pub trust_bundle_mounts: Vec<String>,
+    pub trust_bundle_refresh_interval: Duration,
+    /// Write the certificate files to `CERT_DIR`; off when certificates
+    /// only go to a Secret, or nowhere.
+    pub cert_output_files: bool,
+    /// Whether the private key may leave the process.
+    pub store_mode: StoreMode,
+    /// `kubernetes.io/tls` Secret the primary certificate is applied to.
+    pub cert_secret_name: Option<String>,
+    /// Namespace of the Secrets; the pod's own when `None`.
+    pub cert_secret_namespace: Option<String>,
+    /// PEM files written to each certificate's directory.
+    pub cert_files: Vec<CertFile>,
+    /// Serve the certificates a previous run left in `CERT_DIR` until the
+    /// issuer answers.
+    pub cert_warm_start: bool,
+    /// Serve short-lived self-signed certificates until the first ones are
+    /// issued.
+    pub cert_bootstrap_self_signed: bool,
+    /// How often the store is checked for new certificates with
+    /// `CERT_ISSUER=external`.
+    pub cert_watch_interval: Duration,
+    /// Only issue while holding the `leader_election_lease` Lease, serving
+    /// the certificates the leader wrote otherwise.
+    pub leader_election: bool,
+    pub leader_election_lease: String,
+    /// Namespace of the Lease; the pod's own when `None`.
+    pub leader_election_namespace: Option<String>,
+    pub leader_election_lease_duration: Duration,
+    /// This replica's name in the Lease, the pod name by default.
+    pub leader_election_identity: String,
+    /// PKCS#12 keystore written next to the PEM files, as a file name in
+    /// each certificate's directory.
+    pub cert_pkcs12_file: Option<String>,
+    /// Java KeyStore written next to the PEM files.
+    pub cert_jks_file: Option<String>,
+    pub cert_keystore_alias: String,
+    /// Set whenever a keystore is.
+    pub cert_keystore_password: Option<KeystorePassword>,
+    /// How `key` files are encrypted; `None` writes them in the clear.
+    pub cert_key_encryption: Option<KeyEncryption>,
+    /// Mode of files without the private key.
+    pub cert_file_mode: u32,
+    /// Mode of files with the private key, including the keystores.
+    pub cert_key_file_mode: u32,
+    /// Owner given to every written file; `None` keeps the process's.
+    pub cert_file_uid: Option<u32>,
+    pub cert_file_gid: Option<u32>,
+    /// `false` only keeps the certificate files fresh; nothing is bound on
+    /// `listen_addr`.
+    pub proxy_enabled: bool,
+    pub listen_addr: ListenAddr,
+    /// TLS listeners from `LISTENERS`, each with its own backend.
+    pub listeners: Vec<ExtraListener>,
+    /// Plain-HTTP listener that redirects every request to HTTPS.
+    pub http_redirect_addr: Option<SocketAddr>,
+    /// HTTPS port the redirects point to.
+    pub http_redirect_port: u16,
+    /// UDP address that serves HTTP/3, next to the TLS listener.
+    pub quic_listen_addr: Option<SocketAddr>,
+    /// UDP address that terminates DTLS, forwarding the datagrams to
+    /// `dtls_backend_addr`.
+    pub dtls_listen_addr: Option<SocketAddr>,
+    /// UDP backend of the DTLS listener, as `host:port`.
+    pub dtls_backend_addr: Option<String>,
+    /// How long shutdown waits for open connections to finish.
+    pub drain_timeout: Duration,
+    /// How long certificates keep being renewed after a shutdown signal,
+    /// once the proxy has stopped accepting; zero stops both at once.
+    pub shutdown_grace: Duration,
+    /// Times in a row a listener or server task that fails is restarted
+    /// before the process exits; `0` exits on the first failure.
+    pub task_restart_limit: u32,
+    /// Delay before the first restart, doubled for each one after it.
+    pub task_restart_backoff: Duration,
+    /// Connections beyond this many are closed right after accept.
+    pub max_connections: usize,
+    /// Tasks accepting connections on each listener; on a TCP one each
+    /// gets a socket of its own, bound with `SO_REUSEPORT`.
+    pub acceptor_threads: usize,
+    pub tls_handshake_timeout: Duration,
+    /// Close connections with no traffic in either direction for this
+    /// long. `None` keeps them open indefinitely.
+    pub idle_timeout: Option<Duration>,
+    /// Log every proxied connection at the `access` target.
+    pub access_log: bool,
+    /// Socket options for proxied TCP connections, client and backend side.
+    pub tcp: TcpOptions,
+    /// How fast each proxied connection may move data.
+    pub bandwidth_limit: Bandwidth,
+    /// Limits for connections by SNI, instead of `bandwidth_limit`.
+    pub bandwidth_routes: Vec<BandwidthRoute>,
+    /// Clients outside these ranges are refused; empty admits everyone.
+    pub allow_cidrs: Vec<Cidr>,
+    /// Clients inside these ranges are refused, even if allowed.
+    pub deny_cidrs: Vec<Cidr>,
+    /// Offered in ALPN, in order of preference. Empty disables ALPN.
+    pub alpn_protocols: Vec<Vec<u8>>,
+    pub tls_min_version: TlsVersion,
+    /// In order of preference. Empty keeps the rustls defaults.
+    pub tls_cipher_suites: Vec<SupportedCipherSuite>,
+    /// Let clients resume earlier sessions instead of doing a full
+    /// handshake.
+    pub tls_session_resumption: bool,
+    /// Resume from stateless tickets, as well as from the session cache.
+    pub tls_session_tickets: bool,
+    /// How long a session ticket can be resumed from, and how long one
+    /// ticket key issues tickets at most.
+    pub tls_session_ticket_lifetime: Duration,
+    /// Sessions kept in memory for clients without tickets.
+    pub tls_session_cache_size: usize,
+    pub client_auth: ClientAuth,
+    pub client_ca_file: Option<String>,
+    pub client_crl_check: bool,
+    pub client_crl_refresh_interval: Duration,
+    pub backend_addrs: Vec<String>,
+    pub backend_lb_policy: LbPolicy,
+    pub backend_failure_threshold: u32,
+    pub backend_failure_cooldown: Duration,
+    pub backend_connect_timeout: Duration,
+    pub backend_proxy_protocol: ProxyProtocol,
+    pub proxy_mode: ProxyMode,
+    /// Answer with a `503` page, behind a self-signed certificate, until
+    /// the first certificate is issued, instead of not listening yet.
+    pub holding_page: bool,
+    /// HTML to serve as that page instead of the built-in one.
+    pub holding_page_file: Option<String>,
+    /// With `PROXY_MODE=passthrough`, backends chosen by SNI.
+    pub passthrough_routes: Vec<PassthroughRoute>,
+    /// Also send a copy of what each client sends here, and discard what
+    /// comes back.
+    pub shadow_backend_addr: Option<String>,
+    /// Extend the client's `X-Forwarded-For` and keep its `X-Request-Id`
+    /// instead of replacing them, in HTTP mode and over HTTP/3.
+    pub http_trust_forwarded: bool,
+    /// Pass the verified client certificate's identity to the backend: in
+    /// `X-Forwarded-Client-Cert` over HTTP, else in PROXY protocol v2 TLVs.
+    pub forward_client_cert: bool,
+    pub backend_tls: bool,
+    /// `None` sends each backend's own host name.
+    pub backend_tls_server_name: Option<String>,
+    pub backend_tls_ca_file: Option<String>,
+    pub backend_tls_client_cert: BackendClientCert,
+    /// SHA-256 digests of pinned SubjectPublicKeyInfos.
+    pub backend_tls_pins: Vec<Vec<u8>>,
     pub renewal_threshold: f64,
+    pub renewal_jitter: f64,
+    /// Renewals are never scheduled sooner than this after an issuance,
+    /// however short the certificate, so a misconfigured role cannot make
+    /// the loop hammer Vault. Zero turns the floor off.
+    pub min_renew_interval: Duration,
+    /// Renewals are scheduled no later than this after an issuance,
+    /// however long the certificate.
+    pub max_renew_interval: Option<Duration>,
+    /// Run with `sh -c` after renewed certificate files are written.
+    pub renewal_hook_cmd: Option<String>,
+    pub renewal_hook_timeout: Duration,
+    /// Exit once a renewal fails with less than this much validity left
+    /// on a certificate, so the pod is restarted instead of serving it
+    /// until it expires.
+    pub fail_if_expiring_within: Option<Duration>,
+    /// Consecutive failed renewals of a certificate before
+    /// `fail_if_expiring_within` applies to it.
+    pub fail_if_expiring_failures: u32,
+    /// Revoke a certificate in Vault once its replacement has been served
+    /// for `cert_revoke_delay`.
+    pub cert_revoke_superseded: bool,
+    pub cert_revoke_delay: Duration,
+    /// Receives certificate lifecycle events; `None` sends none.
+    pub webhook_url: Option<String>,
+    pub webhook_headers: Vec<(String, String)>,
+    pub webhook_timeout: Duration,
+    /// Further attempts after a failed delivery.
+    pub webhook_retries: u32,
+    /// Report a certificate as expiring once a renewal fails with less
+    /// than this much validity left.
+    pub webhook_expiry_warning: Duration,
+    /// Where certificate lifecycle audit records go; `None` keeps none.
+    pub audit_log: Option<AuditTarget>,
     pub log_format: LogFormat,
+    /// Log filter directives used when `RUST_LOG` is not set.
+    pub log_level: String,
+    pub log_control_file: Option<String>,
+    /// How often the config file is checked for changes; `None` never
+    /// checks.
+    pub config_reload_interval: Option<Duration>,
+    /// OTLP collector receiving spans; `None` leaves span export off.
+    pub otel_endpoint: Option<String>,
+    pub pid_file: Option<String>,
+    /// Exists while every certificate is loaded, for exec readiness probes.
+    pub ready_file: Option<String>,
+    /// Exists while every certificate is loaded and none failed its last
+    /// renewal, for exec liveness probes.
+    pub healthy_file: Option<String>,
+    pub backend_health_check: BackendHealthCheck,
+    pub backend_health_interval: Duration,
+    pub backend_health_timeout: Duration,
+    pub acme_enabled: bool,
+    pub acme_listen_addr: SocketAddr,
+    pub acme_pki_role: String,
+    pub acme_cert_ttl: String,
+    pub acme_validate_challenges: bool,
+    pub cert_issuer: CertIssuer,
+    pub acme_issuer_directory: String,
+    pub acme_issuer_email: Option<String>,
+    /// Where the ACME account key is kept; `None` registers a new account
+    /// on every start.
+    pub acme_issuer_account_key_file: Option<String>,
+    pub acme_issuer_challenge: AcmeChallenge,
+    pub acme_issuer_http_addr: SocketAddr,
+    pub acme_issuer_dns_hook: Option<String>,
+    /// How long to wait after publishing a `dns-01` record before asking
+    /// the CA to look for it.
+    pub acme_issuer_dns_delay: Duration,
+    /// How long one order may take, from creation to the issued chain.
+    pub acme_issuer_timeout: Duration,
+    pub acme_issuer_cacert: Option<String>,
+    pub k8s_csr_signer_name: String,
+    /// Validity asked for in the CSR's `expirationSeconds`; signers may
+    /// ignore it.
+    pub k8s_csr_expiration: Option<Duration>,
+    /// How long to wait for a CSR to be approved and signed.
+    pub k8s_csr_timeout: Duration,
+    /// Run with `sh -c` to sign each CSR, with `CERT_ISSUER=exec`.
+    pub exec_issuer_cmd: String,
+    /// How long the command may take before it is killed.
+    pub exec_issuer_timeout: Duration,
+    /// Plain-HTTP admin API; loopback addresses only.
+    pub admin_listen_addr: Option<SocketAddr>,
+    /// Also answer `grpc.health.v1.Health/Check` on the admin API, over
+    /// HTTP/2 without TLS.
+    pub admin_grpc_health: bool,
+    pub dns_nameservers: Vec<SocketAddr>,
+    pub dns_timeout: Duration,
+    pub dns_cache_min_ttl: Option<Duration>,
+    pub dns_cache_max_ttl: Option<Duration>,
+}
+
+/// Where the proxy listens.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ListenAddr {
+    /// One or more comma-separated addresses, such as `0.0.0.0:8443` and
+    /// `[::]:8443` for both IPv4 and IPv6.
+    Tcp(Vec<SocketAddr>),
+    /// `unix:/path/to/socket`
+    Unix(PathBuf),
+    /// `unix:@name`, a Linux abstract-namespace socket.
+    UnixAbstract(String),
+    /// `systemd:name`, the socket systemd passed with that
+    /// `FileDescriptorName=`, or `systemd:` for the first one.
+    Systemd(String),
+}
+
+impl ListenAddr {
+    fn parse(value: &str) -> std::result::Result<Self, String> {
+        if let Some(name) = value.strip_prefix("systemd:") {
+            return Ok(Self::Systemd(name.to_string()));
+        }
+        match value.strip_prefix("unix:") {
+            Some(name) if name.starts_with('@') && name.len() > 1 => {
+                Ok(Self::UnixAbstract(name[1..].to_string()))
+            }
+            Some(path) if !path.is_empty() && !path.starts_with('@') => {
+                Ok(Self::Unix(PathBuf::from(path)))
+            }
+            Some(_) => Err(format!("'{value}' is missing a socket path or name")),
+            None => {
+                let mut addrs = Vec::new();
+                for addr in value.split(',').map(str::trim) {
+                    let addr: SocketAddr = addr.parse().map_err(|e| {
+                        if addr.starts_with("unix:") || addr.starts_with("systemd:") {
+                            format!("'{addr}': only TCP addresses can be listed")
+                        } else {
+                            format!("'{addr}': {e}")
+                        }
+                    })?;
+                    if addrs.contains(&addr) {
+                        return Err(format!("'{addr}' is listed more than once"));
+                    }
+                    addrs.push(addr);
+                }
+                Ok(Self::Tcp(addrs))
+            }
+        }
+    }
+
+    /// Whether both take the same address, which only one can listen on.
+    fn overlaps(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::Tcp(addrs), Self::Tcp(others)) => addrs.iter().any(|a| others.contains(a)),
+            _ => self == other,
+        }
+    }
+}
+
+impl fmt::Display for ListenAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Tcp(addrs) => {
+                let addrs: Vec<_> = addrs.iter().map(SocketAddr::to_string).collect();
+                f.write_str(&addrs.join(","))
+            }
+            Self::Unix(path) => write!(f, "unix:{}", path.display()),
+            Self::UnixAbstract(name) => write!(f, "unix:@{name}"),
+            Self::Systemd(name) => write!(f, "systemd:{name}"),
+        }
+    }
+}
+
+/// A TLS listener from `LISTENERS`, next to the one on `LISTEN_ADDR`.
+///
+/// Everything but its address, backend, and certificate is shared with the
+/// main listener.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExtraListener {
+    pub name: String,
+    pub addr: ListenAddr,
+    pub backend_addrs: Vec<String>,
+    /// The `CERT_PROFILES` certificate it serves instead of the certificates
+    /// of `LISTEN_ADDR`.
+    pub cert_profile: Option<String>,
+}
+
+/// An address range such as `10.0.0.0/8` or `fd00::/8`. A bare address is
+/// a range of one.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Cidr {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    fn parse(value: &str) -> std::result::Result<Self, String> {
+        let (addr, prefix) = match value.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (value, None),
+        };
+        let addr: IpAddr = addr.parse().map_err(|e| format!("'{value}': {e}"))?;
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            None => max,
+            Some(prefix) => prefix
+                .parse()
+                .ok()
+                .filter(|prefix| *prefix <= max)
+                .ok_or_else(|| format!("'{value}': prefix length must be 0 to {max}"))?,
+        };
+        if network(addr, prefix) != addr {
+            return Err(format!(
+                "'{value}' has host bits set; did you mean {}/{prefix}?",
+                network(addr, prefix)
+            ));
+        }
+        Ok(Self { addr, prefix })
+    }
+
+    /// Whether `ip` is in the range. An IPv4 address a dual-stack listener
+    /// reports as IPv4-mapped IPv6 counts as IPv4.
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        let ip = ip.to_canonical();
+        ip.is_ipv4() == self.addr.is_ipv4() && network(ip, self.prefix) == self.addr
+    }
+}
+
+/// The first address of the range of length `prefix` that `addr` is in.
+fn network(addr: IpAddr, prefix: u8) -> IpAddr {
+    match addr {
+        IpAddr::V4(addr) => {
+            let mask = u32::MAX.checked_shl(32 - u32::from(prefix)).unwrap_or(0);
+            IpAddr::V4((u32::from(addr) & mask).into())
+        }
+        IpAddr::V6(addr) => {
+            let mask = u128::MAX.checked_shl(128 - u32::from(prefix)).unwrap_or(0);
+            IpAddr::V6((u128::from(addr) & mask).into())
+        }
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix)
+    }
+}
+
+/// How cert-keeper authenticates to Vault.
+#[derive(Debug, Clone, PartialEq)]
+pub enum AuthMethod {
+    /// Service account JWT against the Kubernetes auth method: the pod's
+    /// default token, or a projected one bound to a custom audience.
+    /// Re-read on every login.
+    Kubernetes { token_file: String },
+    /// Arbitrary JWT (projected token with a custom audience, CI OIDC token,
+    /// ...) against the JWT/OIDC auth method. Re-read on every login.
+    Jwt { token_file: String },
+    /// Signed `sts:GetCallerIdentity` request against the AWS auth method
+    /// (IAM type), using the task or instance role credentials.
+    Aws {
+        /// STS region to sign for; `None` uses the global endpoint.
+        region: Option<String>,
+        /// Value of the `X-Vault-AWS-IAM-Server-ID` header, if the mount
+        /// requires one.
+        iam_server_id: Option<String>,
+    },
+    /// Identity JWT against the GCP auth method, from the metadata server
+    /// of a GCE instance or a GKE pod.
+    Gcp {
+        role_type: GcpRoleType,
+        /// Service account an `iam` login signs as; `None` uses the one the
+        /// metadata server runs as.
+        service_account: Option<String>,
+    },
+    /// An existing token from `VAULT_TOKEN`, or from `token_file` (e.g.
+    /// written by Vault Agent). No login is performed; the file is re-read
+    /// whenever cert-keeper would otherwise log in again.
+    Token { token_file: Option<String> },
+}
+
+impl AuthMethod {
+    /// Method name as used in `VAULT_AUTH_METHOD`, which is also the path
+    /// Vault mounts the method at by default.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Kubernetes { .. } => "kubernetes",
+            Self::Jwt { .. } => "jwt",
+            Self::Aws { .. } => "aws",
+            Self::Gcp { .. } => "gcp",
+            Self::Token { .. } => "token",
+        }
+    }
+
+    /// Secret the token cache key is derived from when
+    /// `VAULT_TOKEN_CACHE_KEY_FILE` is not set.
+    fn default_cache_key_file(&self) -> Option<&str> {
+        match self {
+            Self::Kubernetes { token_file } | Self::Jwt { token_file } => Some(token_file),
+            Self::Aws { .. } | Self::Gcp { .. } | Self::Token { .. } => None,
+        }
+    }
+}
+
+impl fmt::Display for AuthMethod {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// The type of the role a GCP login is for, which decides how its JWT is
+/// obtained.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GcpRoleType {
+    /// Signed through the IAM Credentials API as a service account, which
+    /// also works with Workload Identity.
+    Iam,
+    /// An instance identity token, on GCE only.
+    Gce,
+}
+
+/// Where certificates are issued from.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CertIssuer {
+    /// Vault's PKI secrets engine.
+    Vault,
+    /// An ACME CA such as Let's Encrypt.
+    Acme,
+    /// A Kubernetes `CertificateSigningRequest` and whichever signer
+    /// handles its signer name.
+    KubernetesCsr,
+    /// Not issued here: another agent, such as cert-manager, writes them
+    /// to the store, and they are served from there.
+    External,
+    /// A command that signs a CSR from stdin and prints the chain, for
+    /// CAs cert-keeper has no client for.
+    Exec,
+}
+
+impl CertIssuer {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Vault => "vault",
+            Self::Acme => "acme",
+            Self::KubernetesCsr => "kubernetes-csr",
+            Self::External => "external",
+            Self::Exec => "exec",
+        }
+    }
+}
+
+impl fmt::Display for CertIssuer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// How the ACME issuer proves control of each domain.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AcmeChallenge {
+    /// Answered by the proxy listener during the TLS handshake.
+    TlsAlpn01,
+    /// Answered over plain HTTP on `ACME_ISSUER_HTTP_ADDR`.
+    Http01,
+    /// A TXT record published by `ACME_ISSUER_DNS_HOOK`.
+    Dns01,
+}
+
+impl AcmeChallenge {
+    /// The challenge type, as ACME names it.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::TlsAlpn01 => "tls-alpn-01",
+            Self::Http01 => "http-01",
+            Self::Dns01 => "dns-01",
+        }
+    }
+}
+
+/// Whether the proxy asks TLS clients for a certificate.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ClientAuth {
+    None,
+    /// Verify a certificate if the client sends one, but accept clients
+    /// that do not.
+    Optional,
+    /// Reject clients without a valid certificate.
+    Required,
+}
+
+/// How the proxy picks a backend for each connection.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LbPolicy {
+    RoundRobin,
+    /// The backend with the fewest open connections, round-robin among ties.
+    LeastConnections,
+}
+
+/// Oldest TLS version the listener accepts.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TlsVersion {
+    Tls12,
+    Tls13,
+}
+
+impl fmt::Display for TlsVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TlsVersion::Tls12 => f.write_str("1.2"),
+            TlsVersion::Tls13 => f.write_str("1.3"),
+        }
+    }
+}
+
+/// PROXY protocol header sent to the backend ahead of each connection.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ProxyProtocol {
+    None,
+    /// Human-readable `PROXY TCP4 ...` line.
+    V1,
+    /// Binary header.
+    V2,
+}
+
+/// How the proxy treats the decrypted stream.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ProxyMode {
+    /// Copy bytes in both directions without looking at them.
+    Tcp,
+    /// Parse HTTP/1.1 requests and add `X-Forwarded-For`,
+    /// `X-Forwarded-Proto`, and `X-Request-Id` before forwarding them.
+    Http,
+    /// Do not terminate TLS: read the SNI from the ClientHello and forward
+    /// the encrypted stream to the backend routed for it.
+    Passthrough,
+}
+
+/// A `PASSTHROUGH_ROUTES` entry: connections whose SNI matches `host` go to
+/// `backend_addr` instead of `BACKEND_ADDR`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PassthroughRoute {
+    /// A lowercase host name, or `*.` and a domain for any name one label
+    /// below it.
+    pub host: String,
+    pub backend_addr: String,
+}
+
+impl PassthroughRoute {
+    fn parse(entry: &str) -> Result<Self> {
+        let invalid = |reason: &str| {
+            Error::Config(format!(
+                "invalid PASSTHROUGH_ROUTES entry '{entry}': {reason}"
+            ))
+        };
+        let (host, backend_addr) = entry
+            .split_once('=')
+            .ok_or_else(|| invalid("expected host=backend"))?;
+        let host = host.trim().to_lowercase();
+        let backend_addr = backend_addr.trim();
+        if !is_route_host(&host) {
+            return Err(invalid("expected a host name or *.domain"));
+        }
+        dns::split_host_port(backend_addr).map_err(|_| invalid("expected host:port"))?;
+        Ok(Self {
+            host,
+            backend_addr: backend_addr.to_string(),
+        })
+    }
+
+    /// Whether a ClientHello for `sni` takes this route.
+    pub fn matches(&self, sni: &str) -> bool {
+        route_matches(&self.host, sni)
+    }
+
+    pub fn is_wildcard(&self) -> bool {
+        self.host.starts_with("*.")
+    }
+}
+
+/// Bytes per second a proxied connection may move in each direction;
+/// `None` leaves that direction unlimited.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Bandwidth {
+    /// From the client to the backend.
+    pub upload: Option<u64>,
+    /// From the backend to the client.
+    pub download: Option<u64>,
+}
+
+/// A `BANDWIDTH_LIMIT_ROUTES` entry: connections whose SNI matches `host`
+/// are held to `limit` instead of the global one.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BandwidthRoute {
+    /// A lowercase host name, or `*.` and a domain for any name one label
+    /// below it.
+    pub host: String,
+    pub limit: Bandwidth,
+}
+
+impl BandwidthRoute {
+    fn parse(entry: &str) -> Result<Self> {
+        let invalid = |reason: &str| {
+            Error::Config(format!(
+                "invalid BANDWIDTH_LIMIT_ROUTES entry '{entry}': {reason}"
+            ))
+        };
+        let (host, rates) = entry
+            .split_once('=')
+            .ok_or_else(|| invalid("expected host=rate or host=upload/download"))?;
+        let host = host.trim().to_lowercase();
+        if !is_route_host(&host) {
+            return Err(invalid("expected a host name or *.domain"));
+        }
+        let (upload, download) = rates.split_once('/').unwrap_or((rates, rates));
+        let rate = |value: &str| {
+            parse_rate(value).map_err(|_| invalid("expected bytes per second like 512KiB"))
+        };
+        Ok(Self {
+            host,
+            limit: Bandwidth {
+                upload: rate(upload)?,
+                download: rate(download)?,
+            },
+        })
+    }
+
+    /// Whether a connection for `sni` takes this route.
+    pub fn matches(&self, sni: &str) -> bool {
+        route_matches(&self.host, sni)
+    }
+
+    pub fn is_wildcard(&self) -> bool {
+        self.host.starts_with("*.")
+    }
+}
+
+/// Whether `host` is a host name, or `*.` and a domain.
+fn is_route_host(host: &str) -> bool {
+    let name = host.strip_prefix("*.").unwrap_or(host);
+    matches!(ServerName::try_from(name), Ok(ServerName::DnsName(_)))
+}
+
+/// Whether `sni` is the route host `host`, or one label below the domain of
+/// a `*.` one.
+fn route_matches(host: &str, sni: &str) -> bool {
+    match host.strip_prefix("*.") {
+        Some(domain) => sni.split_once('.').is_some_and(|(label, rest)| {
+            !label.is_empty() && rest.eq_ignore_ascii_case(domain)
+        }),
+        None => sni.eq_ignore_ascii_case(host),
+    }
+}
+
+/// Which certificate, if any, the proxy presents to a TLS backend.
+#[derive(Debug, Clone, PartialEq)]
+pub enum BackendClientCert {
+    None,
+    /// The certificate cert-keeper obtained from Vault, including renewals.
+    Issued,
+    /// A fixed certificate chain and key read from disk at startup.
+    File { cert_file: String, key_file: String },
+}
+
+/// One PEM file in each certificate's directory.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CertFile {
+    pub name: String,
+    pub content: FileContent,
+}
+
+/// A file in `SECRETS_DIR` filled from a Vault KV v2 secret.
+#[derive(Debug, Clone, PartialEq)]
+pub struct KvSecret {
+    /// File name in `SECRETS_DIR`.
+    pub file: String,
+    /// Secret path under `VAULT_KV_MOUNT`.
+    pub path: String,
+    /// Write only this key's value; `None` writes every key as a JSON
+    /// object.
+    pub field: Option<String>,
+}
+
+/// What a [`CertFile`] holds.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FileContent {
+    /// Leaf certificate followed by the issuing CA.
+    FullChain,
+    Leaf,
+    /// The issuing CA certificates served after the leaf.
+    Chain,
+    Key,
+    /// Issuing CA followed by any `EXTRA_CA_FILES`.
+    Ca,
+    /// Full chain followed by the key, as HAProxy takes it.
+    Combined,
+}
+
+impl FileContent {
+    pub fn has_key(self) -> bool {
+        matches!(self, FileContent::Key | FileContent::Combined)
+    }
+}
+
+/// The passphrase protecting the keystores and their private key, or an
+/// encrypted `key` file.
+#[derive(Clone, PartialEq)]
+pub enum KeystorePassword {
+    Literal(String),
+    /// Re-read on every write, so it can be rotated without a restart.
+    File(String),
+}
+
+impl fmt::Debug for KeystorePassword {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KeystorePassword::Literal(_) => f.write_str("Literal(<redacted>)"),
+            KeystorePassword::File(path) => f.debug_tuple("File").field(path).finish(),
+        }
+    }
+}
+
+/// How the private key in `key` files is encrypted at rest.
+#[derive(Debug, Clone, PartialEq)]
+pub enum KeyEncryption {
+    /// A PKCS#8 `ENCRYPTED PRIVATE KEY` under this passphrase.
+    Passphrase(KeystorePassword),
+    /// Encrypted with a fresh data key from Vault's Transit engine, kept
+    /// next to it wrapped by the named Transit key.
+    Transit { mount: String, key: String },
+}
+
+/// A named certificate from `CERT_PROFILES`, such as a client certificate,
+/// issued with its own PKI settings into its own directory.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CertProfile {
+    pub name: String,
+    pub names: CertNames,
+    pub pki_role: String,
+    pub pki_mount: String,
+    pub ttl: String,
+    pub dir: String,
+}
+
+/// Names one certificate is requested for.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CertNames {
+    pub common_name: String,
+    /// Comma-separated, as Vault takes them.
+    pub alt_names: Option<String>,
+    pub ip_sans: Option<String>,
+    /// Comma-separated URI SANs, such as SPIFFE IDs.
+    pub uri_sans: Option<String>,
+}
+
+/// Where the certificate's private key comes from.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum KeySource {
+    /// Vault generates it (`pki/issue`) and returns it in the response.
+    Vault,
+    /// cert-keeper generates it and has Vault sign a CSR (`pki/sign`), so
+    /// the key never leaves the process.
+    Local,
+}
+
+/// PEM encoding of a key Vault generates, as named by Vault's
+/// `private_key_format`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PrivateKeyFormat {
+    /// PKCS#1 for RSA keys, SEC1 for EC keys.
+    Pem,
+    Pkcs8,
+}
+
+impl PrivateKeyFormat {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Pem => "pem",
+            Self::Pkcs8 => "pkcs8",
+        }
+    }
+}
+
+/// Certificate key algorithm, as named by Vault's `key_type`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum KeyType {
+    Rsa,
+    Ec,
+    Ed25519,
+}
+
+impl KeyType {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Rsa => "rsa",
+            Self::Ec => "ec",
+            Self::Ed25519 => "ed25519",
+        }
+    }
+
+    /// Key sizes Vault accepts for this type.
+    fn valid_bits(&self) -> &'static [u32] {
+        match self {
+            Self::Rsa => &[2048, 3072, 4096, 8192],
+            Self::Ec => &[224, 256, 384, 521],
+            Self::Ed25519 => &[],
+        }
+    }
+}
+
+/// Socket options set on TCP connections the proxy accepts and makes.
+/// `None` leaves the system default.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TcpOptions {
+    pub nodelay: bool,
+    /// Idle time before the first keepalive probe; `None` leaves keepalive
+    /// off.
+    pub keepalive_time: Option<Duration>,
+    pub keepalive_interval: Option<Duration>,
+    /// Unanswered probes after which the connection is dropped.
+    pub keepalive_retries: Option<u32>,
+    /// `SO_SNDBUF`, in bytes.
+    pub send_buffer: Option<u32>,
+    /// `SO_RCVBUF`, in bytes.
+    pub recv_buffer: Option<u32>,
+}
+
+/// What the process does when started without a command.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mode {
+    /// Keep the certificates renewed and serve the proxy.
+    Sidecar,
+    /// Issue and write the certificates once, then exit, as `fetch` does.
+    Oneshot,
+}
+
+/// Where the private key is kept (`STORE_MODE`).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StoreMode {
+    /// Written with the certificates, per `CERT_FILES` and `CERT_OUTPUT`.
+    Files,
+    /// Only ever held by the proxy; files with the key are not written.
+    Memory,
+}
+
+/// Destination of the `AUDIT_LOG` records.
+#[derive(Debug, Clone, PartialEq)]
+pub enum AuditTarget {
+    /// Kept apart from the general logs, which go to stdout.
+    Stderr,
+    /// Appended to, and the hash chain continued across restarts.
+    File(String),
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -29,78 +1020,2773 @@ pub enum LogFormat {
     Pretty,
 }
 
-impl Config {
-    pub fn from_env() -> Result<Self> {
-        let vault_addr = required_env("VAULT_ADDR")?;
-        let vault_auth_role = required_env("VAULT_AUTH_ROLE")?;
-        let vault_pki_role = required_env("VAULT_PKI_ROLE")?;
-        let cert_common_name = required_env("CERT_COMMON_NAME")?;
-
-        let vault_auth_mount = env::var("VAULT_AUTH_MOUNT").unwrap_or_else(|_| "kubernetes".into());
-        let vault_pki_mount = env::var("VAULT_PKI_MOUNT").unwrap_or_else(|_| "pki".into());
-        let vault_namespace = env::var("VAULT_NAMESPACE").ok();
-        let vault_cacert = env::var("VAULT_CACERT").ok();
-        let cert_alt_names = env::var("CERT_ALT_NAMES").ok();
-        let cert_ip_sans = env::var("CERT_IP_SANS").ok();
-        let cert_ttl = env::var("CERT_TTL").unwrap_or_else(|_| "24h".into());
-        let cert_dir = env::var("CERT_DIR").unwrap_or_else(|_| "/certs".into());
-
-        let listen_addr: SocketAddr = env::var("LISTEN_ADDR")
-            .unwrap_or_else(|_| "0.0.0.0:8443".into())
-            .parse()
-            .map_err(|e| Error::Config(format!("invalid LISTEN_ADDR: {e}")))?;
+impl LogFormat {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "json" => Some(LogFormat::Json),
+            "pretty" => Some(LogFormat::Pretty),
+            _ => None,
+        }
+    }
+}
 
-        let backend_addr: SocketAddr = env::var("BACKEND_ADDR")
-            .unwrap_or_else(|_| "127.0.0.1:8080".into())
-            .parse()
-            .map_err(|e| Error::Config(format!("invalid BACKEND_ADDR: {e}")))?;
+/// How the proxy decides whether the backend is ready for traffic.
+#[derive(Debug, Clone, PartialEq)]
+pub enum BackendHealthCheck {
+    /// Always forward; connection errors surface per connection.
+    None,
+    /// Open a TCP connection to each backend address.
+    Tcp,
+    /// `GET path` over HTTP/1.1, passing on a `2xx`.
+    Http { path: String },
+    /// Call `grpc.health.v1.Health/Check` for `service` over h2c.
+    Grpc { service: String },
+}
 
-        let renewal_threshold: f64 = env::var("RENEWAL_THRESHOLD")
-            .unwrap_or_else(|_| "0.66".into())
-            .parse()
-            .map_err(|e| Error::Config(format!("invalid RENEWAL_THRESHOLD: {e}")))?;
+impl Config {
+    /// Read settings from the environment and, if given, a TOML or YAML
+    /// file. An environment variable overrides the same setting in the file.
+    pub fn load(config_file: Option<&Path>) -> Result<Self> {
+        Self::from_vars(Vars::load(config_file)?)
+    }
 
-        if !(0.0..1.0).contains(&renewal_threshold) {
+    /// Start a [`ConfigBuilder`], for configuring cert-keeper in code.
+    pub fn builder() -> ConfigBuilder {
+        ConfigBuilder::default()
+    }
+
+    fn from_vars(vars: Vars) -> Result<Self> {
+        let mode = match vars
+            .var("MODE")
+            .unwrap_or_else(|_| "sidecar".into())
+            .to_lowercase()
+            .as_str()
+        {
+            "sidecar" => Mode::Sidecar,
+            "oneshot" => Mode::Oneshot,
+            other => {
+                return Err(Error::Config(format!(
+                    "invalid MODE '{other}': must be 'sidecar' or 'oneshot'"
+                )))
+            }
+        };
+        let cert_issuer = match vars.var("CERT_ISSUER")
+            .unwrap_or_else(|_| "vault".into())
+            .to_lowercase()
+            .as_str()
+        {
+            "vault" => CertIssuer::Vault,
+            "acme" => CertIssuer::Acme,
+            "kubernetes-csr" => CertIssuer::KubernetesCsr,
+            "external" => CertIssuer::External,
+            "exec" => CertIssuer::Exec,
+            other => {
+                return Err(Error::Config(format!(
+                    "invalid CERT_ISSUER '{other}': must be 'vault', 'acme', 'kubernetes-csr', 'exec', or 'external'"
+                )))
+            }
+        };
+        if cert_issuer == CertIssuer::Acme && !cfg!(feature = "acme") {
             return Err(Error::Config(
-                "RENEWAL_THRESHOLD must be between 0.0 and 1.0".into(),
+                "CERT_ISSUER=acme requires a build with the acme feature".into(),
+            ));
+        }
+        if cert_issuer == CertIssuer::KubernetesCsr && !cfg!(feature = "k8s-csr") {
+            return Err(Error::Config(
+                "CERT_ISSUER=kubernetes-csr requires a build with the k8s-csr feature".into(),
+            ));
+        }
+        if cert_issuer == CertIssuer::Exec && !cfg!(feature = "exec-issuer") {
+            return Err(Error::Config(
+                "CERT_ISSUER=exec requires a build with the exec-issuer feature".into(),
             ));
         }
+        // Other issuers never talk to Vault.
+        let vault_setting = |key: &str| match cert_issuer {
+            CertIssuer::Vault => vars.required(key),
+            _ => Ok(vars.var(key).unwrap_or_default()),
+        };
+        let vault_addr = vault_setting("VAULT_ADDR")?;
+        let vault_pki_role = vault_setting("VAULT_PKI_ROLE")?;
+        let cert_common_name = vars
+            .templated("CERT_COMMON_NAME")?
+            .ok_or_else(|| Error::Config("required setting CERT_COMMON_NAME is not set".into()))?;
 
-        let log_format = match env::var("LOG_FORMAT")
-            .unwrap_or_else(|_| "json".into())
+        let vault_auth_method = match vars.var("VAULT_AUTH_METHOD")
+            .unwrap_or_else(|_| "kubernetes".into())
             .to_lowercase()
             .as_str()
         {
-            "json" => LogFormat::Json,
-            "pretty" => LogFormat::Pretty,
+            "kubernetes" => AuthMethod::Kubernetes {
+                token_file: vars.var("VAULT_AUTH_TOKEN_PATH")
+                    .unwrap_or_else(|_| SA_TOKEN_PATH.into()),
+            },
+            "jwt" => AuthMethod::Jwt {
+                token_file: vars.required("VAULT_JWT_FILE")?,
+            },
+            "aws" => AuthMethod::Aws {
+                region: vars.var("VAULT_AWS_REGION").ok(),
+                iam_server_id: vars.var("VAULT_AWS_HEADER_VALUE").ok(),
+            },
+            "gcp" => AuthMethod::Gcp {
+                role_type: match vars.var("VAULT_GCP_ROLE_TYPE")
+                    .unwrap_or_else(|_| "iam".into())
+                    .to_lowercase()
+                    .as_str()
+                {
+                    "iam" => GcpRoleType::Iam,
+                    "gce" => GcpRoleType::Gce,
+                    other => {
+                        return Err(Error::Config(format!(
+                            "invalid VAULT_GCP_ROLE_TYPE '{other}': must be 'iam' or 'gce'"
+                        )))
+                    }
+                },
+                service_account: vars.var("VAULT_GCP_SERVICE_ACCOUNT").ok(),
+            },
+            "token" => {
+                let token_file = vars.var("VAULT_TOKEN_FILE").ok();
+                if token_file.is_none() && env::var("VAULT_TOKEN").is_err() {
+                    return Err(Error::Config(
+                        "VAULT_AUTH_METHOD=token requires VAULT_TOKEN or VAULT_TOKEN_FILE".into(),
+                    ));
+                }
+                AuthMethod::Token { token_file }
+            }
             other => {
                 return Err(Error::Config(format!(
-                    "invalid LOG_FORMAT '{other}': must be 'json' or 'pretty'"
+                    "invalid VAULT_AUTH_METHOD '{other}': must be 'kubernetes', 'jwt', 'aws', 'gcp', or 'token'"
                 )))
             }
         };
+        // A static token needs no login, so there is no role to log in as.
+        let vault_auth_role = match vault_auth_method {
+            AuthMethod::Token { .. } => vars.var("VAULT_AUTH_ROLE").unwrap_or_default(),
+            _ => vault_setting("VAULT_AUTH_ROLE")?,
+        };
+        let vault_auth_mount = vars.var("VAULT_AUTH_MOUNT")
+            .unwrap_or_else(|_| vault_auth_method.name().into());
+        let vault_pki_mount = vars.var("VAULT_PKI_MOUNT").unwrap_or_else(|_| "pki".into());
+        let vault_pki_issuer = vars.var("VAULT_PKI_ISSUER").ok();
+        if let Some(ref issuer) = vault_pki_issuer {
+            if issuer.is_empty() || issuer.contains('/') {
+                return Err(Error::Config(format!(
+                    "invalid VAULT_PKI_ISSUER '{issuer}': expected an issuer name or ID"
+                )));
+            }
+        }
+        let vault_kv_mount = vars.var("VAULT_KV_MOUNT").unwrap_or_else(|_| "secret".into());
+        let kv_secrets = match vars.var("KV_SECRETS") {
+            Ok(value) => parse_kv_secrets(&value)?,
+            Err(_) => Vec::new(),
+        };
+        if !kv_secrets.is_empty() && cert_issuer != CertIssuer::Vault {
+            return Err(Error::Config(
+                "KV_SECRETS requires CERT_ISSUER=vault".into(),
+            ));
+        }
+        let secrets_dir = vars.var("SECRETS_DIR").unwrap_or_else(|_| "/secrets".into());
+        let vault_namespace = vars.var("VAULT_NAMESPACE").ok().filter(|ns| !ns.is_empty());
+        // Set but empty for the root namespace, under a `VAULT_NAMESPACE`.
+        let namespace = |key: &str| match vars.var(key) {
+            Ok(ns) => Some(ns).filter(|ns| !ns.is_empty()),
+            Err(_) => vault_namespace.clone(),
+        };
+        let vault_auth_namespace = namespace("VAULT_AUTH_NAMESPACE");
+        let vault_pki_namespace = namespace("VAULT_PKI_NAMESPACE");
+        let vault_cacert = vars.var("VAULT_CACERT").ok();
+        let vault_client_cert = vars.var("VAULT_CLIENT_CERT").ok();
+        let vault_client_key = vars.var("VAULT_CLIENT_KEY").ok();
+        if vault_client_cert.is_some() != vault_client_key.is_some() {
+            return Err(Error::Config(
+                "VAULT_CLIENT_CERT and VAULT_CLIENT_KEY must be set together".into(),
+            ));
+        }
+        let vault_tls_server_name = vars.var("VAULT_TLS_SERVER_NAME").ok();
+        let vault_skip_verify = vars.bool("VAULT_SKIP_VERIFY", false)?;
+        if vault_skip_verify && vault_tls_server_name.is_some() {
+            return Err(Error::Config(
+                "VAULT_TLS_SERVER_NAME cannot be used with VAULT_SKIP_VERIFY".into(),
+            ));
+        }
+        let vault_token_cache = vars.var("VAULT_TOKEN_CACHE").ok();
+        let vault_token_cache_key_file = vars.var("VAULT_TOKEN_CACHE_KEY_FILE")
+            .ok()
+            .or_else(|| vault_auth_method.default_cache_key_file().map(Into::into));
+        let vault_retries: u32 = vars.var("VAULT_RETRIES")
+            .unwrap_or_else(|_| "3".into())
+            .parse()
+            .map_err(|e| Error::Config(format!("invalid VAULT_RETRIES: {e}")))?;
+        let vault_retry_backoff = vars.duration("VAULT_RETRY_BACKOFF", "500ms")?;
+        if vault_retry_backoff.is_zero() {
+            return Err(Error::Config("VAULT_RETRY_BACKOFF must be greater than zero".into()));
+        }
+        let vault_circuit_threshold: u32 = vars.var("VAULT_CIRCUIT_THRESHOLD")
+            .unwrap_or_else(|_| "5".into())
+            .parse()
+            .map_err(|e| Error::Config(format!("invalid VAULT_CIRCUIT_THRESHOLD: {e}")))?;
+        let vault_circuit_cooldown = vars.duration("VAULT_CIRCUIT_COOLDOWN", "30s")?;
+        let vault_startup_timeout = vars.duration("VAULT_STARTUP_TIMEOUT", "60s")?;
+        let vault_connect_timeout = vars.duration("VAULT_CONNECT_TIMEOUT", "5s")?;
+        let vault_request_timeout = vars.duration("VAULT_REQUEST_TIMEOUT", "30s")?;
+        let vault_operation_timeout = vars.duration("VAULT_OPERATION_TIMEOUT", "2m")?;
+        for (key, timeout) in [
+            ("VAULT_CONNECT_TIMEOUT", vault_connect_timeout),
+            ("VAULT_REQUEST_TIMEOUT", vault_request_timeout),
+            ("VAULT_OPERATION_TIMEOUT", vault_operation_timeout),
+        ] {
+            if timeout.is_zero() {
+                return Err(Error::Config(format!("{key} must be greater than zero")));
+            }
+        }
+        if vault_operation_timeout < vault_request_timeout {
+            return Err(Error::Config(
+                "VAULT_OPERATION_TIMEOUT must be at least VAULT_REQUEST_TIMEOUT".into(),
+            ));
+        }
+        let vault_pool_idle_timeout = vars.duration("VAULT_POOL_IDLE_TIMEOUT", "90s")?;
+        let vault_pool_max_idle = vars.optional_number("VAULT_POOL_MAX_IDLE")?;
+        let vault_wrap_ttl = vars.optional_duration("VAULT_WRAP_TTL")?;
+        if vault_wrap_ttl.is_some_and(|ttl| ttl.as_secs() == 0) {
+            return Err(Error::Config("VAULT_WRAP_TTL must be at least 1s".into()));
+        }
+        if vault_token_cache.is_some() && matches!(vault_auth_method, AuthMethod::Token { .. }) {
+            return Err(Error::Config(
+                "VAULT_TOKEN_CACHE cannot be used with VAULT_AUTH_METHOD=token".into(),
+            ));
+        }
+        if vault_token_cache.is_some() && vault_token_cache_key_file.is_none() {
+            return Err(Error::Config(format!(
+                "VAULT_TOKEN_CACHE requires VAULT_TOKEN_CACHE_KEY_FILE with VAULT_AUTH_METHOD={vault_auth_method}"
+            )));
+        }
+        let cert_alt_names = vars.templated("CERT_ALT_NAMES")?;
+        let cert_ip_sans = vars.templated("CERT_IP_SANS")?;
+        let cert_uri_sans = vars.var("CERT_URI_SANS").ok();
+        for uri in cert_uri_sans.iter().flat_map(|l| l.split(',')).map(str::trim) {
+            if !uri.is_empty() && !is_uri(uri) {
+                return Err(Error::Config(format!(
+                    "invalid CERT_URI_SANS entry '{uri}': expected a URI such as spiffe://example.org/ns/default/sa/app"
+                )));
+            }
+        }
+        let cert_ttl = vars.var("CERT_TTL").unwrap_or_else(|_| "24h".into());
+        let mut cert_sni_groups: Vec<CertNames> = Vec::new();
+        for group in vars.var("CERT_SNI_GROUPS").unwrap_or_default().split(';') {
+            let mut names = group.split(',').map(str::trim).filter(|s| !s.is_empty());
+            let Some(common_name) = names.next() else {
+                continue;
+            };
+            // The common name doubles as the group's directory under CERT_DIR.
+            if common_name.contains('/') || common_name.starts_with('.') {
+                return Err(Error::Config(format!(
+                    "invalid CERT_SNI_GROUPS name '{common_name}'"
+                )));
+            }
+            if common_name == cert_common_name
+                || cert_sni_groups.iter().any(|g| g.common_name == common_name)
+            {
+                return Err(Error::Config(format!(
+                    "CERT_SNI_GROUPS common name '{common_name}' is used more than once"
+                )));
+            }
+            let alt_names = names.collect::<Vec<_>>().join(",");
+            cert_sni_groups.push(CertNames {
+                common_name: common_name.to_string(),
+                alt_names: (!alt_names.is_empty()).then_some(alt_names),
+                ip_sans: None,
+                uri_sans: None,
+            });
+        }
+        let cert_key_source = match vars.var("CERT_KEY_SOURCE")
+            .unwrap_or_else(|_| "vault".into())
+            .to_lowercase()
+            .as_str()
+        {
+            "vault" => KeySource::Vault,
+            "local" => KeySource::Local,
+            other => {
+                return Err(Error::Config(format!(
+                    "invalid CERT_KEY_SOURCE '{other}': must be 'vault' or 'local'"
+                )))
+            }
+        };
+        let cert_key_type = match vars.var("CERT_KEY_TYPE").ok().map(|t| t.to_lowercase()) {
+            None => None,
+            Some(t) if t == "rsa" => Some(KeyType::Rsa),
+            Some(t) if t == "ec" => Some(KeyType::Ec),
+            Some(t) if t == "ed25519" => Some(KeyType::Ed25519),
+            Some(other) => {
+                return Err(Error::Config(format!(
+                    "invalid CERT_KEY_TYPE '{other}': must be 'rsa', 'ec', or 'ed25519'"
+                )))
+            }
+        };
+        let cert_key_bits = match vars.var("CERT_KEY_BITS") {
+            Err(_) => None,
+            Ok(value) => Some(value.parse::<u32>().map_err(|e| {
+                Error::Config(format!("invalid CERT_KEY_BITS '{value}': {e}"))
+            })?),
+        };
+        if let (Some(key_type), Some(bits)) = (cert_key_type, cert_key_bits) {
+            if !key_type.valid_bits().contains(&bits) {
+                return Err(Error::Config(format!(
+                    "CERT_KEY_BITS {bits} is not valid for CERT_KEY_TYPE={}",
+                    key_type.name()
+                )));
+            }
+        }
+        // ACME CAs only ever sign CSRs.
+        if cert_key_source == KeySource::Local || cert_issuer != CertIssuer::Vault {
+            // ring can only generate these; Vault-side generation has no such limit.
+            let supported = match cert_key_type.unwrap_or(KeyType::Ec) {
+                KeyType::Ec => matches!(cert_key_bits, None | Some(256 | 384)),
+                KeyType::Ed25519 => true,
+                KeyType::Rsa => false,
+            };
+            if !supported {
+                return Err(Error::Config(
+                    "CERT_KEY_SOURCE=local supports CERT_KEY_TYPE=ec (256 or 384 bits) or ed25519"
+                        .into(),
+                ));
+            }
+        }
+        let cert_not_after = match vars.var("CERT_NOT_AFTER") {
+            Err(_) => None,
+            Ok(value) => {
+                let not_after = OffsetDateTime::parse(&value, &Rfc3339).map_err(|e| {
+                    Error::Config(format!(
+                        "invalid CERT_NOT_AFTER '{value}': {e}; expected e.g. 2027-01-01T00:00:00Z"
+                    ))
+                })?;
+                if not_after <= OffsetDateTime::now_utc() {
+                    return Err(Error::Config(format!(
+                        "CERT_NOT_AFTER {value} is in the past"
+                    )));
+                }
+                // Vault only takes whole seconds in UTC.
+                Some(not_after.to_offset(UtcOffset::UTC).replace_nanosecond(0).unwrap_or(not_after))
+            }
+        };
+        let cert_exclude_cn_from_sans = vars.bool("CERT_EXCLUDE_CN_FROM_SANS", false)?;
+        let cert_other_sans = vars.var("CERT_OTHER_SANS").ok().filter(|s| !s.trim().is_empty());
+        for san in cert_other_sans.iter().flat_map(|l| l.split(',')).map(str::trim) {
+            let valid = san
+                .split_once(';')
+                .is_some_and(|(oid, value)| !oid.is_empty() && value.contains(':'));
+            if !san.is_empty() && !valid {
+                return Err(Error::Config(format!(
+                    "invalid CERT_OTHER_SANS entry '{san}': expected <oid>;UTF8:<value>"
+                )));
+            }
+        }
+        let cert_private_key_format =
+            match vars.var("CERT_PRIVATE_KEY_FORMAT").ok().map(|f| f.to_lowercase()) {
+                None => None,
+                Some(f) if f == "pem" => Some(PrivateKeyFormat::Pem),
+                Some(f) if f == "pkcs8" => Some(PrivateKeyFormat::Pkcs8),
+                Some(other) => {
+                    return Err(Error::Config(format!(
+                        "invalid CERT_PRIVATE_KEY_FORMAT '{other}': must be 'pem' or 'pkcs8'"
+                    )))
+                }
+            };
+        if cert_private_key_format.is_some() && cert_key_source == KeySource::Local {
+            return Err(Error::Config(
+                "CERT_PRIVATE_KEY_FORMAT requires CERT_KEY_SOURCE=vault; local keys are PKCS#8"
+                    .into(),
+            ));
+        }
+        let cert_serial_number = vars.var("CERT_SERIAL_NUMBER").ok().filter(|s| !s.is_empty());
+        if cert_issuer != CertIssuer::Vault {
+            let set = [
+                ("CERT_NOT_AFTER", cert_not_after.is_some()),
+                ("CERT_EXCLUDE_CN_FROM_SANS", cert_exclude_cn_from_sans),
+                ("CERT_OTHER_SANS", cert_other_sans.is_some()),
+                ("CERT_PRIVATE_KEY_FORMAT", cert_private_key_format.is_some()),
+                ("CERT_SERIAL_NUMBER", cert_serial_number.is_some()),
+            ];
+            if let Some((key, _)) = set.iter().find(|(_, set)| *set) {
+                return Err(Error::Config(format!("{key} requires CERT_ISSUER=vault")));
+            }
+        }
+        let cert_dir = vars.var("CERT_DIR").unwrap_or_else(|_| DEFAULT_CERT_DIR.into());
+        let mut cert_profiles: Vec<CertProfile> = Vec::new();
+        for name in vars.var("CERT_PROFILES")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+        {
+            // The name doubles as part of env var, directory, and Secret names.
+            if !name
+                .bytes()
+                .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'-')
+            {
+                return Err(Error::Config(format!(
+                    "invalid CERT_PROFILES name '{name}': use lowercase letters, digits, and '-'"
+                )));
+            }
+            if cert_profiles.iter().any(|p| p.name == name) {
+                return Err(Error::Config(format!(
+                    "CERT_PROFILES name '{name}' is used more than once"
+                )));
+            }
+            let prefix = format!("CERT_PROFILE_{}_", name.to_uppercase().replace('-', "_"));
+            let key = |setting: &str| format!("{prefix}{setting}");
+            let setting = |setting: &str| vars.var(&key(setting)).ok();
+            for vault_only in ["PKI_ROLE", "PKI_MOUNT", "TTL"] {
+                if cert_issuer != CertIssuer::Vault && setting(vault_only).is_some() {
+                    return Err(Error::Config(format!(
+                        "{} requires CERT_ISSUER=vault",
+                        key(vault_only)
+                    )));
+                }
+            }
+            let dir = setting("DIR").unwrap_or_else(|| format!("{cert_dir}/{name}"));
+            let dirs = std::iter::once(cert_dir.clone())
+                .chain(
+                    cert_sni_groups
+                        .iter()
+                        .map(|g| format!("{cert_dir}/{}", g.common_name)),
+                )
+                .chain(cert_profiles.iter().map(|p| p.dir.clone()));
+            if dirs
+                .map(|d| d.trim_end_matches('/').to_string())
+                .any(|d| d == dir.trim_end_matches('/'))
+            {
+                return Err(Error::Config(format!(
+                    "{} '{dir}' is already used by another certificate",
+                    key("DIR")
+                )));
+            }
+            cert_profiles.push(CertProfile {
+                name: name.to_string(),
+                names: CertNames {
+                    common_name: vars.required(&key("COMMON_NAME"))?,
+                    alt_names: setting("ALT_NAMES"),
+                    ip_sans: setting("IP_SANS"),
+                    uri_sans: setting("URI_SANS"),
+                },
+                pki_role: setting("PKI_ROLE").unwrap_or_else(|| vault_pki_role.clone()),
+                pki_mount: setting("PKI_MOUNT").unwrap_or_else(|| vault_pki_mount.clone()),
+                ttl: setting("TTL").unwrap_or_else(|| cert_ttl.clone()),
+                dir,
+            });
+        }
+        let extra_ca_files = vars.var("EXTRA_CA_FILES")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .map(String::from)
+            .collect();
+        let cert_extra_chain_file = vars.var("CERT_EXTRA_CHAIN_FILE").ok().filter(|f| !f.is_empty());
+        let cert_extra_chain_issuers: Vec<String> = vars.var("CERT_EXTRA_CHAIN_ISSUERS")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .map(String::from)
+            .collect();
+        if let Some(issuer) = cert_extra_chain_issuers.iter().find(|i| i.contains('/')) {
+            return Err(Error::Config(format!(
+                "invalid CERT_EXTRA_CHAIN_ISSUERS entry '{issuer}': expected an issuer name or ID"
+            )));
+        }
+        if !cert_extra_chain_issuers.is_empty() && cert_issuer != CertIssuer::Vault {
+            return Err(Error::Config(
+                "CERT_EXTRA_CHAIN_ISSUERS requires CERT_ISSUER=vault".into(),
+            ));
+        }
+        let cert_extra_chain_until = match vars.var("CERT_EXTRA_CHAIN_UNTIL") {
+            Err(_) => None,
+            Ok(value) => Some(OffsetDateTime::parse(&value, &Rfc3339).map_err(|e| {
+                Error::Config(format!(
+                    "invalid CERT_EXTRA_CHAIN_UNTIL '{value}': {e}; expected e.g. 2027-01-01T00:00:00Z"
+                ))
+            })?),
+        };
+        if cert_extra_chain_until.is_some()
+            && cert_extra_chain_file.is_none()
+            && cert_extra_chain_issuers.is_empty()
+        {
+            return Err(Error::Config(
+                "CERT_EXTRA_CHAIN_UNTIL requires CERT_EXTRA_CHAIN_FILE or CERT_EXTRA_CHAIN_ISSUERS"
+                    .into(),
+            ));
+        }
+        let cert_validate = vars.bool("CERT_VALIDATE", true)?;
+        let trust_bundle_file = vars.var("TRUST_BUNDLE_FILE").ok().filter(|f| !f.is_empty());
+        if trust_bundle_file.is_some() && cert_issuer != CertIssuer::Vault {
+            return Err(Error::Config(
+                "TRUST_BUNDLE_FILE requires CERT_ISSUER=vault".into(),
+            ));
+        }
+        let mut trust_bundle_mounts: Vec<String> = vars.var("TRUST_BUNDLE_MOUNTS")
+            .unwrap_or_default()
+            .split(',')
+            .map(|m| m.trim().trim_matches('/'))
+            .filter(|m| !m.is_empty())
+            .map(String::from)
+            .collect();
+        if trust_bundle_mounts.is_empty() {
+            trust_bundle_mounts.push(vault_pki_mount.clone());
+        }
+        let trust_bundle_refresh_interval =
+            vars.duration("TRUST_BUNDLE_REFRESH_INTERVAL", "10m")?;
+        if trust_bundle_refresh_interval.is_zero() {
+            return Err(Error::Config(
+                "TRUST_BUNDLE_REFRESH_INTERVAL must be greater than zero".into(),
+            ));
+        }
+
+        let store_mode = match vars.var("STORE_MODE").as_deref() {
+            Err(_) | Ok("files") => StoreMode::Files,
+            Ok("memory") => StoreMode::Memory,
+            Ok(other) => {
+                return Err(Error::Config(format!(
+                    "invalid STORE_MODE '{other}': must be files or memory"
+                )))
+            }
+        };
+        let mut cert_output_files = false;
+        let mut cert_output_secret = false;
+        let mut cert_output_none = false;
+        for output in vars.var("CERT_OUTPUT")
+            .unwrap_or_else(|_| "files".into())
+            .split(',')
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+        {
+            match output {
+                "files" => cert_output_files = true,
+                "kubernetes-secret" => cert_output_secret = true,
+                "none" => cert_output_none = true,
+                other => {
+                    return Err(Error::Config(format!(
+                        "invalid CERT_OUTPUT '{other}': must be files, kubernetes-secret, or none"
+                    )))
+                }
+            }
+        }
+        if cert_output_none {
+            if cert_output_files || cert_output_secret {
+                return Err(Error::Config(
+                    "CERT_OUTPUT=none cannot be combined with other outputs".into(),
+                ));
+            }
+            if store_mode != StoreMode::Memory {
+                return Err(Error::Config(
+                    "CERT_OUTPUT=none requires STORE_MODE=memory".into(),
+                ));
+            }
+        } else if !cert_output_files && !cert_output_secret {
+            return Err(Error::Config("CERT_OUTPUT names no outputs".into()));
+        }
+        if cert_output_secret && !cfg!(feature = "secret-sink") {
+            return Err(Error::Config(
+                "CERT_OUTPUT=kubernetes-secret requires a build with the secret-sink feature"
+                    .into(),
+            ));
+        }
+        let cert_secret_name = match vars.var("CERT_SECRET_NAME").ok() {
+            Some(name) if !cert_output_secret => {
+                return Err(Error::Config(format!(
+                    "CERT_SECRET_NAME '{name}' is set but CERT_OUTPUT does not include \
+                     kubernetes-secret"
+                )))
+            }
+            Some(name) => Some(name),
+            None if cert_output_secret => {
+                return Err(Error::Config(
+                    "CERT_OUTPUT=kubernetes-secret requires CERT_SECRET_NAME".into(),
+                ))
+            }
+            None => None,
+        };
+        if let Some(ref name) = cert_secret_name {
+            let names = std::iter::once(name.clone())
+                .chain(
+                    cert_sni_groups
+                        .iter()
+                        .map(|group| sni_secret_name(name, &group.common_name)),
+                )
+                .chain(
+                    cert_profiles
+                        .iter()
+                        .map(|p| profile_secret_name(name, &p.name)),
+                );
+            for secret in names {
+                if !is_dns_subdomain(&secret) {
+                    return Err(Error::Config(format!(
+                        "invalid Secret name '{secret}' from CERT_SECRET_NAME: must be a \
+                         lowercase DNS subdomain"
+                    )));
+                }
+            }
+        }
+        let cert_secret_namespace = vars.var("CERT_SECRET_NAMESPACE").ok();
+        if let Some(ref namespace) = cert_secret_namespace {
+            // Namespaces are DNS labels, which are also single-label subdomains.
+            if namespace.len() > 63 || namespace.contains('.') || !is_dns_subdomain(namespace) {
+                return Err(Error::Config(format!(
+                    "invalid CERT_SECRET_NAMESPACE '{namespace}': must be a lowercase DNS label"
+                )));
+            }
+        }
+
+        let cert_files = parse_cert_files(
+            &vars.var("CERT_FILES").unwrap_or_else(|_| DEFAULT_CERT_FILES.into()),
+        )?;
+        let cert_warm_start = vars.bool("CERT_WARM_START", true)?;
+        let cert_bootstrap_self_signed = vars.bool("CERT_BOOTSTRAP_SELF_SIGNED", false)?;
+        let keystore_file = |name: &str| -> Result<Option<String>> {
+            let Ok(file) = vars.var(name) else {
+                return Ok(None);
+            };
+            check_file_name(name, &file)?;
+            Ok(Some(file))
+        };
+        let cert_pkcs12_file = keystore_file("CERT_PKCS12_FILE")?;
+        let cert_jks_file = keystore_file("CERT_JKS_FILE")?;
+        let keystores = cert_pkcs12_file.is_some() || cert_jks_file.is_some();
+        if keystores && !cfg!(feature = "keystore") {
+            return Err(Error::Config(
+                "CERT_PKCS12_FILE and CERT_JKS_FILE require a build with the keystore feature"
+                    .into(),
+            ));
+        }
+        if keystores && !cert_output_files {
+            return Err(Error::Config(
+                "CERT_PKCS12_FILE and CERT_JKS_FILE require CERT_OUTPUT to include files".into(),
+            ));
+        }
+        if let Some(file) = [&cert_pkcs12_file, &cert_jks_file]
+            .into_iter()
+            .flatten()
+            .find(|file| cert_files.iter().any(|f| f.name == **file))
+        {
+            return Err(Error::Config(format!(
+                "keystore file name '{file}' collides with a PEM file"
+            )));
+        }
+        if cert_pkcs12_file.is_some() && cert_pkcs12_file == cert_jks_file {
+            return Err(Error::Config(
+                "CERT_PKCS12_FILE and CERT_JKS_FILE must be different files".into(),
+            ));
+        }
+        let cert_keystore_alias = vars.var("CERT_KEYSTORE_ALIAS")
+            .unwrap_or_else(|_| env!("CARGO_PKG_NAME").into());
+        if cert_keystore_alias.is_empty() || cert_keystore_alias.chars().any(char::is_control) {
+            return Err(Error::Config(format!(
+                "invalid CERT_KEYSTORE_ALIAS '{cert_keystore_alias}'"
+            )));
+        }
+        let cert_keystore_password = match (
+            vars.var("CERT_KEYSTORE_PASSWORD").ok(),
+            vars.var("CERT_KEYSTORE_PASSWORD_FILE").ok(),
+        ) {
+            _ if !keystores => None,
+            (Some(_), Some(_)) => {
+                return Err(Error::Config(
+                    "set only one of CERT_KEYSTORE_PASSWORD and CERT_KEYSTORE_PASSWORD_FILE".into(),
+                ))
+            }
+            (Some(password), None) => Some(KeystorePassword::Literal(password)),
+            (None, Some(path)) => Some(KeystorePassword::File(path)),
+            (None, None) => {
+                return Err(Error::Config(
+                    "CERT_PKCS12_FILE and CERT_JKS_FILE require CERT_KEYSTORE_PASSWORD or \
+                     CERT_KEYSTORE_PASSWORD_FILE"
+                        .into(),
+                ))
+            }
+        };
+        let cert_key_encryption = match vars.var("CERT_KEY_ENCRYPTION").as_deref() {
+            Err(_) | Ok("none") => None,
+            Ok("passphrase") => match (
+                vars.var("CERT_KEY_PASSPHRASE").ok(),
+                vars.var("CERT_KEY_PASSPHRASE_FILE").ok(),
+            ) {
+                (Some(_), Some(_)) => {
+                    return Err(Error::Config(
+                        "set only one of CERT_KEY_PASSPHRASE and CERT_KEY_PASSPHRASE_FILE".into(),
+                    ))
+                }
+                (Some(passphrase), None) if !passphrase.is_empty() => Some(
+                    KeyEncryption::Passphrase(KeystorePassword::Literal(passphrase)),
+                ),
+                (None, Some(path)) => Some(KeyEncryption::Passphrase(KeystorePassword::File(path))),
+                _ => {
+                    return Err(Error::Config(
+                        "CERT_KEY_ENCRYPTION=passphrase requires CERT_KEY_PASSPHRASE or \
+                         CERT_KEY_PASSPHRASE_FILE"
+                            .into(),
+                    ))
+                }
+            },
+            Ok("transit") => {
+                if cert_issuer != CertIssuer::Vault {
+                    return Err(Error::Config(
+                        "CERT_KEY_ENCRYPTION=transit requires CERT_ISSUER=vault".into(),
+                    ));
+                }
+                let key = vars.var("VAULT_TRANSIT_KEY").map_err(|_| {
+                    Error::Config("CERT_KEY_ENCRYPTION=transit requires VAULT_TRANSIT_KEY".into())
+                })?;
+                let mount = vars.var("VAULT_TRANSIT_MOUNT").unwrap_or_else(|_| "transit".into());
+                Some(KeyEncryption::Transit { mount, key })
+            }
+            Ok(other) => {
+                return Err(Error::Config(format!(
+                    "invalid CERT_KEY_ENCRYPTION '{other}': must be none, passphrase, or transit"
+                )))
+            }
+        };
+        if cert_key_encryption.is_some() {
+            if !cfg!(feature = "key-encryption") {
+                return Err(Error::Config(
+                    "CERT_KEY_ENCRYPTION requires a build with the key-encryption feature".into(),
+                ));
+            }
+            if !cert_output_files {
+                return Err(Error::Config(
+                    "CERT_KEY_ENCRYPTION requires CERT_OUTPUT to include files".into(),
+                ));
+            }
+            // The key would be in the clear after the chain.
+            if cert_files
+                .iter()
+                .any(|f| f.content == FileContent::Combined)
+            {
+                return Err(Error::Config(
+                    "CERT_KEY_ENCRYPTION cannot be used with a combined file in CERT_FILES".into(),
+                ));
+            }
+        }
+        let cert_file_mode = vars.mode("CERT_FILE_MODE", 0o644)?;
+        let cert_key_file_mode = vars.mode("CERT_KEY_FILE_MODE", 0o600)?;
+        let owner_id = |name: &str| -> Result<Option<u32>> {
+            vars.var(name)
+                .ok()
+                .map(|id| {
+                    id.parse().map_err(|_| {
+                        Error::Config(format!("invalid {name} '{id}': must be a numeric id"))
+                    })
+                })
+                .transpose()
+        };
+        let cert_file_uid = owner_id("CERT_FILE_UID")?;
+        let cert_file_gid = owner_id("CERT_FILE_GID")?;
+        let proxy_enabled = vars.bool("PROXY_ENABLED", true)?;
+        // Bootstrap certificates are only served, never written.
+        if cert_bootstrap_self_signed && !proxy_enabled {
+            return Err(Error::Config(
+                "CERT_BOOTSTRAP_SELF_SIGNED requires PROXY_ENABLED=true".into(),
+            ));
+        }
+        if store_mode == StoreMode::Memory {
+            let conflict = if !proxy_enabled {
+                Some("PROXY_ENABLED=false")
+            } else if mode == Mode::Oneshot {
+                Some("MODE=oneshot")
+            } else if cert_output_secret {
+                Some("CERT_OUTPUT=kubernetes-secret")
+            } else if keystores {
+                Some("CERT_PKCS12_FILE or CERT_JKS_FILE")
+            } else if cert_key_encryption.is_some() {
+                Some("CERT_KEY_ENCRYPTION")
+            } else if cert_files
+                .iter()
+                .any(|f| f.content == FileContent::Combined)
+            {
+                Some("a combined file in CERT_FILES")
+            } else {
+                None
+            };
+            if let Some(conflict) = conflict {
+                return Err(Error::Config(format!(
+                    "STORE_MODE=memory cannot be used with {conflict}: the key never leaves \
+                     the proxy"
+                )));
+            }
+        }
+        let leader_election = vars.bool("LEADER_ELECTION", false)?;
+        if leader_election {
+            if !cfg!(feature = "leader-election") {
+                return Err(Error::Config(
+                    "LEADER_ELECTION requires a build with the leader-election feature".into(),
+                ));
+            }
+            let conflict = if mode == Mode::Oneshot {
+                Some("MODE=oneshot")
+            } else if store_mode == StoreMode::Memory {
+                Some("STORE_MODE=memory")
+            } else {
+                None
+            };
+            if let Some(conflict) = conflict {
+                return Err(Error::Config(format!(
+                    "LEADER_ELECTION cannot be used with {conflict}: followers serve the \
+                     certificates the leader writes"
+                )));
+            }
+        }
+        if cert_issuer == CertIssuer::External {
+            let conflict = if mode == Mode::Oneshot {
+                Some("MODE=oneshot")
+            } else if store_mode == StoreMode::Memory {
+                Some("STORE_MODE=memory")
+            } else if leader_election {
+                Some("LEADER_ELECTION")
+            } else if vars.var("CERT_EXTRA_CHAIN_FILE").is_ok() {
+                Some("CERT_EXTRA_CHAIN_FILE")
+            } else {
+                None
+            };
+            if let Some(conflict) = conflict {
+                return Err(Error::Config(format!(
+                    "CERT_ISSUER=external cannot be used with {conflict}: the certificates \
+                     are only read from the store"
+                )));
+            }
+        }
+        let cert_watch_interval = vars.duration("CERT_WATCH_INTERVAL", "10s")?;
+        if cert_watch_interval.is_zero() {
+            return Err(Error::Config("CERT_WATCH_INTERVAL must be greater than zero".into()));
+        }
+        let leader_election_lease = vars.var("LEADER_ELECTION_LEASE").unwrap_or_else(|_| "cert-keeper".into());
+        if !is_dns_subdomain(&leader_election_lease) {
+            return Err(Error::Config(format!(
+                "invalid LEADER_ELECTION_LEASE '{leader_election_lease}': must be a lowercase \
+                 DNS subdomain"
+            )));
+        }
+        let leader_election_namespace = vars.var("LEADER_ELECTION_NAMESPACE").ok();
+        if let Some(ref namespace) = leader_election_namespace {
+            if namespace.len() > 63 || namespace.contains('.') || !is_dns_subdomain(namespace) {
+                return Err(Error::Config(format!(
+                    "invalid LEADER_ELECTION_NAMESPACE '{namespace}': must be a lowercase DNS label"
+                )));
+            }
+        }
+        let leader_election_lease_duration = vars.duration("LEADER_ELECTION_LEASE_DURATION", "15s")?;
+        if leader_election_lease_duration < Duration::from_secs(3) {
+            return Err(Error::Config(
+                "LEADER_ELECTION_LEASE_DURATION must be at least 3s".into(),
+            ));
+        }
+        // A pod's host name is its name.
+        let leader_election_identity = match vars.var("LEADER_ELECTION_IDENTITY") {
+            Ok(identity) => identity,
+            Err(_) if leader_election => std::fs::read_to_string("/proc/sys/kernel/hostname")
+                .map(|name| name.trim().to_string())
+                .map_err(|e| {
+                    Error::Config(format!(
+                        "LEADER_ELECTION_IDENTITY is not set and the host name cannot be read: {e}"
+                    ))
+                })?,
+            Err(_) => String::new(),
+        };
+        if leader_election && leader_election_identity.is_empty() {
+            return Err(Error::Config(
+                "LEADER_ELECTION_IDENTITY must not be empty".into(),
+            ));
+        }
+        let listen_addr = ListenAddr::parse(
+            &vars.var("LISTEN_ADDR").unwrap_or_else(|_| "0.0.0.0:8443".into()),
+        )
+        .map_err(|e| Error::Config(format!("invalid LISTEN_ADDR: {e}")))?;
+        let http_redirect_addr = vars.var("HTTP_REDIRECT_ADDR")
+            .ok()
+            .map(|addr| {
+                addr.parse::<SocketAddr>()
+                    .map_err(|e| Error::Config(format!("invalid HTTP_REDIRECT_ADDR: {e}")))
+            })
+            .transpose()?;
+        let http_redirect_port = vars.optional_number::<u16>("HTTP_REDIRECT_PORT")?.unwrap_or(443);
+        if http_redirect_port == 0 {
+            return Err(Error::Config("HTTP_REDIRECT_PORT must be at least 1".into()));
+        }
+        if http_redirect_addr.is_some() {
+            if !proxy_enabled {
+                return Err(Error::Config(
+                    "HTTP_REDIRECT_ADDR requires PROXY_ENABLED=true".into(),
+                ));
+            }
+            if !cfg!(feature = "http-redirect") {
+                return Err(Error::Config(
+                    "HTTP_REDIRECT_ADDR requires a build with the http-redirect feature".into(),
+                ));
+            }
+        } else if vars.var("HTTP_REDIRECT_PORT").is_ok() {
+            return Err(Error::Config(
+                "HTTP_REDIRECT_PORT requires HTTP_REDIRECT_ADDR".into(),
+            ));
+        }
+        let quic_listen_addr = vars.var("QUIC_LISTEN_ADDR")
+            .ok()
+            .map(|addr| {
+                addr.parse::<SocketAddr>()
+                    .map_err(|e| Error::Config(format!("invalid QUIC_LISTEN_ADDR: {e}")))
+            })
+            .transpose()?;
+        if quic_listen_addr.is_some() {
+            if !proxy_enabled {
+                return Err(Error::Config(
+                    "QUIC_LISTEN_ADDR requires PROXY_ENABLED=true".into(),
+                ));
+            }
+            if !cfg!(feature = "quic") {
+                return Err(Error::Config(
+                    "QUIC_LISTEN_ADDR requires a build with the quic feature".into(),
+                ));
+            }
+        }
+        let dtls_listen_addr = vars.var("DTLS_LISTEN_ADDR")
+            .ok()
+            .map(|addr| {
+                addr.parse::<SocketAddr>()
+                    .map_err(|e| Error::Config(format!("invalid DTLS_LISTEN_ADDR: {e}")))
+            })
+            .transpose()?;
+        let dtls_backend_addr = vars.var("DTLS_BACKEND_ADDR").ok();
+        if dtls_listen_addr.is_some() {
+            let conflict = if !proxy_enabled {
+                Some("PROXY_ENABLED=false")
+            } else if store_mode == StoreMode::Memory {
+                Some("STORE_MODE=memory")
+            } else {
+                None
+            };
+            if let Some(conflict) = conflict {
+                return Err(Error::Config(format!(
+                    "DTLS_LISTEN_ADDR cannot be used with {conflict}: the DTLS listener loads \
+                     the stored certificate and key"
+                )));
+            }
+            if dtls_backend_addr.is_none() {
+                return Err(Error::Config(
+                    "DTLS_LISTEN_ADDR requires DTLS_BACKEND_ADDR".into(),
+                ));
+            }
+            if !cfg!(feature = "dtls") {
+                return Err(Error::Config(
+                    "DTLS_LISTEN_ADDR requires a build with the dtls feature".into(),
+                ));
+            }
+        } else if dtls_backend_addr.is_some() {
+            return Err(Error::Config(
+                "DTLS_BACKEND_ADDR requires DTLS_LISTEN_ADDR".into(),
+            ));
+        }
+        let drain_timeout = vars.duration("DRAIN_TIMEOUT", "20s")?;
+        let shutdown_grace = vars.duration("SHUTDOWN_GRACE", "0s")?;
+        let task_restart_limit = vars.optional_number("TASK_RESTART_LIMIT")?.unwrap_or(0);
+        let task_restart_backoff = vars.duration("TASK_RESTART_BACKOFF", "1s")?;
+        if task_restart_backoff.is_zero() {
+            return Err(Error::Config(
+                "TASK_RESTART_BACKOFF must be greater than zero".into(),
+            ));
+        }
+        let max_connections: usize = vars.var("MAX_CONNECTIONS")
+            .unwrap_or_else(|_| "10000".into())
+            .parse()
+            .map_err(|e| Error::Config(format!("invalid MAX_CONNECTIONS: {e}")))?;
+        if max_connections == 0 {
+            return Err(Error::Config("MAX_CONNECTIONS must be at least 1".into()));
+        }
+        let acceptor_threads = vars.optional_number("ACCEPTOR_THREADS")?.unwrap_or(1);
+        if acceptor_threads == 0 {
+            return Err(Error::Config("ACCEPTOR_THREADS must be at least 1".into()));
+        }
+        let tls_handshake_timeout = vars.duration("TLS_HANDSHAKE_TIMEOUT", "10s")?;
+        if tls_handshake_timeout.is_zero() {
+            return Err(Error::Config(
+                "TLS_HANDSHAKE_TIMEOUT must be greater than zero".into(),
+            ));
+        }
+        let idle_timeout = Some(vars.duration("IDLE_TIMEOUT", "1h")?).filter(|d| !d.is_zero());
+        let access_log = vars.bool("ACCESS_LOG", false)?;
+        let tcp = TcpOptions {
+            nodelay: vars.bool("TCP_NODELAY", false)?,
+            keepalive_time: vars.optional_duration("TCP_KEEPALIVE_TIME")?,
+            keepalive_interval: vars.optional_duration("TCP_KEEPALIVE_INTERVAL")?,
+            keepalive_retries: vars.optional_number("TCP_KEEPALIVE_RETRIES")?,
+            send_buffer: vars.optional_number("TCP_SEND_BUFFER")?,
+            recv_buffer: vars.optional_number("TCP_RECV_BUFFER")?,
+        };
+        for (key, zero) in [
+            (
+                "TCP_KEEPALIVE_TIME",
+                tcp.keepalive_time.is_some_and(|d| d.is_zero()),
+            ),
+            (
+                "TCP_KEEPALIVE_INTERVAL",
+                tcp.keepalive_interval.is_some_and(|d| d.is_zero()),
+            ),
+            ("TCP_KEEPALIVE_RETRIES", tcp.keepalive_retries == Some(0)),
+            ("TCP_SEND_BUFFER", tcp.send_buffer == Some(0)),
+            ("TCP_RECV_BUFFER", tcp.recv_buffer == Some(0)),
+        ] {
+            if zero {
+                return Err(Error::Config(format!("{key} must be greater than zero")));
+            }
+        }
+        if tcp.keepalive_time.is_none()
+            && (tcp.keepalive_interval.is_some() || tcp.keepalive_retries.is_some())
+        {
+            return Err(Error::Config(
+                "TCP_KEEPALIVE_INTERVAL and TCP_KEEPALIVE_RETRIES require TCP_KEEPALIVE_TIME"
+                    .into(),
+            ));
+        }
+        let bandwidth_limit = Bandwidth {
+            upload: vars.rate("BANDWIDTH_LIMIT_UPLOAD")?,
+            download: vars.rate("BANDWIDTH_LIMIT_DOWNLOAD")?,
+        };
+        let bandwidth_routes = match vars.var("BANDWIDTH_LIMIT_ROUTES") {
+            Ok(value) => value
+                .split(',')
+                .map(str::trim)
+                .filter(|s| !s.is_empty())
+                .map(BandwidthRoute::parse)
+                .collect::<Result<Vec<_>>>()?,
+            Err(_) => Vec::new(),
+        };
+        for (i, route) in bandwidth_routes.iter().enumerate() {
+            if bandwidth_routes[..i].iter().any(|r| r.host == route.host) {
+                return Err(Error::Config(format!(
+                    "BANDWIDTH_LIMIT_ROUTES names '{}' more than once",
+                    route.host
+                )));
+            }
+        }
+        let cidrs = |key: &str| {
+            vars.var(key)
+                .unwrap_or_default()
+                .split(',')
+                .map(str::trim)
+                .filter(|s| !s.is_empty())
+                .map(|cidr| {
+                    Cidr::parse(cidr).map_err(|e| Error::Config(format!("invalid {key} entry {e}")))
+                })
+                .collect::<Result<Vec<_>>>()
+        };
+        let allow_cidrs = cidrs("ALLOW_CIDRS")?;
+        let deny_cidrs = cidrs("DENY_CIDRS")?;
+        if !matches!(listen_addr, ListenAddr::Tcp(_) | ListenAddr::Systemd(_))
+            && (!allow_cidrs.is_empty() || !deny_cidrs.is_empty())
+        {
+            return Err(Error::Config(
+                "ALLOW_CIDRS and DENY_CIDRS need a TCP LISTEN_ADDR; Unix socket peers have no address"
+                    .into(),
+            ));
+        }
+        let alpn_protocols = vars.var("ALPN_PROTOCOLS")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .map(|protocol| {
+                if protocol.len() > 255 {
+                    return Err(Error::Config(format!(
+                        "ALPN_PROTOCOLS entry '{protocol}' is longer than 255 bytes"
+                    )));
+                }
+                Ok(protocol.as_bytes().to_vec())
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let tls_min_version = match vars.var("TLS_MIN_VERSION")
+            .unwrap_or_else(|_| "1.2".into())
+            .as_str()
+        {
+            "1.2" => TlsVersion::Tls12,
+            "1.3" => TlsVersion::Tls13,
+            other => {
+                return Err(Error::Config(format!(
+                    "invalid TLS_MIN_VERSION '{other}': must be '1.2' or '1.3'"
+                )))
+            }
+        };
+        let tls_cipher_suites = vars.var("TLS_CIPHER_SUITES")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .map(|name| {
+                let suite = cipher_suite(name).ok_or_else(|| {
+                    Error::Config(format!("unknown TLS_CIPHER_SUITES entry '{name}'"))
+                })?;
+                if tls_min_version == TlsVersion::Tls13
+                    && suite.version().version != ProtocolVersion::TLSv1_3
+                {
+                    return Err(Error::Config(format!(
+                        "TLS_CIPHER_SUITES entry '{name}' is a TLS 1.2 suite, but TLS_MIN_VERSION is 1.3"
+                    )));
+                }
+                Ok(suite)
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let tls_session_resumption = vars.bool("TLS_SESSION_RESUMPTION", true)?;
+        let tls_session_tickets = vars.bool("TLS_SESSION_TICKETS", true)?;
+        let tls_session_ticket_lifetime = vars.duration("TLS_SESSION_TICKET_LIFETIME", "12h")?;
+        // The longest lifetime TLS 1.3 allows a ticket.
+        if tls_session_ticket_lifetime.is_zero()
+            || tls_session_ticket_lifetime > Duration::from_secs(7 * 24 * 60 * 60)
+        {
+            return Err(Error::Config(
+                "TLS_SESSION_TICKET_LIFETIME must be greater than zero and at most 7d".into(),
+            ));
+        }
+        let tls_session_cache_size = vars
+            .optional_number("TLS_SESSION_CACHE_SIZE")?
+            .unwrap_or(256);
+        if tls_session_cache_size == 0 {
+            return Err(Error::Config(
+                "TLS_SESSION_CACHE_SIZE must be at least 1; set TLS_SESSION_RESUMPTION=false to turn resumption off".into(),
+            ));
+        }
+        let unused = if !tls_session_resumption {
+            [
+                "TLS_SESSION_TICKETS",
+                "TLS_SESSION_TICKET_LIFETIME",
+                "TLS_SESSION_CACHE_SIZE",
+            ]
+            .into_iter()
+            .find(|key| vars.var(key).is_ok())
+            .map(|key| (key, "TLS_SESSION_RESUMPTION=true"))
+        } else if !tls_session_tickets && vars.var("TLS_SESSION_TICKET_LIFETIME").is_ok() {
+            Some(("TLS_SESSION_TICKET_LIFETIME", "TLS_SESSION_TICKETS=true"))
+        } else {
+            None
+        };
+        if let Some((key, requires)) = unused {
+            return Err(Error::Config(format!("{key} requires {requires}")));
+        }
+
+        // QUIC protects its first packets with this suite whatever the
+        // handshake then negotiates.
+        if quic_listen_addr.is_some()
+            && !tls_cipher_suites.is_empty()
+            && !tls_cipher_suites
+                .iter()
+                .any(|s| s.suite() == CipherSuite::TLS13_AES_128_GCM_SHA256)
+        {
+            return Err(Error::Config(
+                "QUIC_LISTEN_ADDR requires TLS_CIPHER_SUITES to include TLS13_AES_128_GCM_SHA256"
+                    .into(),
+            ));
+        }
+
+        let client_auth = match vars.var("CLIENT_AUTH")
+            .unwrap_or_else(|_| "none".into())
+            .to_lowercase()
+            .as_str()
+        {
+            "none" => ClientAuth::None,
+            "optional" => ClientAuth::Optional,
+            "required" => ClientAuth::Required,
+            other => {
+                return Err(Error::Config(format!(
+                    "invalid CLIENT_AUTH '{other}': must be 'none', 'optional', or 'required'"
+                )))
+            }
+        };
+        let client_ca_file = vars.var("CLIENT_CA_FILE").ok();
+        let client_crl_check = vars.bool("CLIENT_CRL_CHECK", false)?;
+        if client_crl_check && client_auth == ClientAuth::None {
+            return Err(Error::Config(
+                "CLIENT_CRL_CHECK requires CLIENT_AUTH=optional or required".into(),
+            ));
+        }
+        let client_crl_refresh_interval = vars.duration("CLIENT_CRL_REFRESH_INTERVAL", "5m")?;
+        if client_crl_refresh_interval.is_zero() {
+            return Err(Error::Config(
+                "CLIENT_CRL_REFRESH_INTERVAL must be greater than zero".into(),
+            ));
+        }
+
+        let backend_addrs = parse_backend_addrs(
+            "BACKEND_ADDR",
+            &vars.var("BACKEND_ADDR").unwrap_or_else(|_| "127.0.0.1:8080".into()),
+        )?;
+        let backend_lb_policy = match vars.var("BACKEND_LB_POLICY")
+            .unwrap_or_else(|_| "round-robin".into())
+            .to_lowercase()
+            .as_str()
+        {
+            "round-robin" => LbPolicy::RoundRobin,
+            "least-connections" => LbPolicy::LeastConnections,
+            other => {
+                return Err(Error::Config(format!(
+                    "invalid BACKEND_LB_POLICY '{other}': must be 'round-robin' or 'least-connections'"
+                )))
+            }
+        };
+        let backend_failure_threshold: u32 = vars.var("BACKEND_FAILURE_THRESHOLD")
+            .unwrap_or_else(|_| "3".into())
+            .parse()
+            .map_err(|e| Error::Config(format!("invalid BACKEND_FAILURE_THRESHOLD: {e}")))?;
+        if backend_failure_threshold == 0 {
+            return Err(Error::Config(
+                "BACKEND_FAILURE_THRESHOLD must be at least 1".into(),
+            ));
+        }
+        let backend_failure_cooldown = vars.duration("BACKEND_FAILURE_COOLDOWN", "10s")?;
+
+        let backend_connect_timeout = vars.duration("BACKEND_CONNECT_TIMEOUT", "5s")?;
+
+        let backend_proxy_protocol = match vars.var("BACKEND_PROXY_PROTOCOL")
+            .unwrap_or_else(|_| "none".into())
+            .to_lowercase()
+            .as_str()
+        {
+            "none" => ProxyProtocol::None,
+            "v1" => ProxyProtocol::V1,
+            "v2" => ProxyProtocol::V2,
+            other => {
+                return Err(Error::Config(format!(
+                    "invalid BACKEND_PROXY_PROTOCOL '{other}': must be 'none', 'v1', or 'v2'"
+                )))
+            }
+        };
+
+        let proxy_mode = match vars.var("PROXY_MODE")
+            .unwrap_or_else(|_| "tcp".into())
+            .to_lowercase()
+            .as_str()
+        {
+            "tcp" => ProxyMode::Tcp,
+            "http" => ProxyMode::Http,
+            "passthrough" => ProxyMode::Passthrough,
+            other => {
+                return Err(Error::Config(format!(
+                    "invalid PROXY_MODE '{other}': must be 'tcp', 'http', or 'passthrough'"
+                )))
+            }
+        };
+        if proxy_mode == ProxyMode::Http {
+            if !cfg!(feature = "http-proxy") {
+                return Err(Error::Config(
+                    "PROXY_MODE=http requires a build with the http-proxy feature".into(),
+                ));
+            }
+            // Requests are parsed as HTTP/1.1, so h2 must not be negotiated.
+            if alpn_protocols.iter().any(|p| p != b"http/1.1") {
+                return Err(Error::Config(
+                    "PROXY_MODE=http only speaks HTTP/1.1; ALPN_PROTOCOLS may only offer 'http/1.1'"
+                        .into(),
+                ));
+            }
+        }
+        let holding_page = vars.bool("HOLDING_PAGE", false)?;
+        let holding_page_file = vars.var("HOLDING_PAGE_FILE").ok();
+        if holding_page_file.is_some() && !holding_page {
+            return Err(Error::Config(
+                "HOLDING_PAGE_FILE requires HOLDING_PAGE=true".into(),
+            ));
+        }
+        if holding_page {
+            if !cfg!(feature = "http-proxy") {
+                return Err(Error::Config(
+                    "HOLDING_PAGE requires a build with the http-proxy feature".into(),
+                ));
+            }
+            if !proxy_enabled {
+                return Err(Error::Config("HOLDING_PAGE requires PROXY_ENABLED=true".into()));
+            }
+            // Passthrough listens from the start and has no handshake of
+            // its own to answer with.
+            if proxy_mode == ProxyMode::Passthrough {
+                return Err(Error::Config(
+                    "HOLDING_PAGE cannot be combined with PROXY_MODE=passthrough".into(),
+                ));
+            }
+        }
+        let http_trust_forwarded = vars.bool("HTTP_TRUST_FORWARDED", false)?;
+        if http_trust_forwarded && proxy_mode != ProxyMode::Http && quic_listen_addr.is_none() {
+            return Err(Error::Config(
+                "HTTP_TRUST_FORWARDED requires PROXY_MODE=http or QUIC_LISTEN_ADDR".into(),
+            ));
+        }
+        let forward_client_cert = vars.bool("FORWARD_CLIENT_CERT", false)?;
+        if forward_client_cert && client_auth == ClientAuth::None {
+            return Err(Error::Config(
+                "FORWARD_CLIENT_CERT requires CLIENT_AUTH=optional or required".into(),
+            ));
+        }
+        // Bytes copied as they are leave no room for the identity but the
+        // PROXY protocol header.
+        if forward_client_cert
+            && proxy_mode == ProxyMode::Tcp
+            && backend_proxy_protocol != ProxyProtocol::V2
+        {
+            return Err(Error::Config(
+                "FORWARD_CLIENT_CERT with PROXY_MODE=tcp requires BACKEND_PROXY_PROTOCOL=v2".into(),
+            ));
+        }
+
+        let passthrough_routes = match vars.var("PASSTHROUGH_ROUTES") {
+            Ok(value) => value
+                .split(',')
+                .map(str::trim)
+                .filter(|s| !s.is_empty())
+                .map(PassthroughRoute::parse)
+                .collect::<Result<Vec<_>>>()?,
+            Err(_) => Vec::new(),
+        };
+        for (i, route) in passthrough_routes.iter().enumerate() {
+            if passthrough_routes[..i].iter().any(|r| r.host == route.host) {
+                return Err(Error::Config(format!(
+                    "PASSTHROUGH_ROUTES names '{}' more than once",
+                    route.host
+                )));
+            }
+        }
+        if !passthrough_routes.is_empty() && proxy_mode != ProxyMode::Passthrough {
+            return Err(Error::Config(
+                "PASSTHROUGH_ROUTES requires PROXY_MODE=passthrough".into(),
+            ));
+        }
+
+        let shadow_backend_addr = match vars.var("SHADOW_BACKEND_ADDR") {
+            Ok(value) => {
+                let mut addrs = parse_backend_addrs("SHADOW_BACKEND_ADDR", &value)?;
+                if addrs.len() > 1 {
+                    return Err(Error::Config(
+                        "SHADOW_BACKEND_ADDR takes a single host:port".into(),
+                    ));
+                }
+                addrs.pop()
+            }
+            Err(_) => None,
+        };
+
+        let backend_tls = vars.bool("BACKEND_TLS", false)?;
+        // Passthrough forwards the client's own TLS session, which the
+        // backend terminates.
+        if proxy_mode == ProxyMode::Passthrough {
+            let conflict = if backend_tls {
+                Some("BACKEND_TLS")
+            } else if client_auth != ClientAuth::None {
+                Some("CLIENT_AUTH")
+            } else if quic_listen_addr.is_some() {
+                Some("QUIC_LISTEN_ADDR")
+            } else if shadow_backend_addr.is_some() {
+                Some("SHADOW_BACKEND_ADDR")
+            } else {
+                None
+            };
+            if let Some(conflict) = conflict {
+                return Err(Error::Config(format!(
+                    "PROXY_MODE=passthrough cannot be used with {conflict}: the backend terminates TLS"
+                )));
+            }
+        }
+        if !backend_tls {
+            for key in [
+                "BACKEND_TLS_SERVER_NAME",
+                "BACKEND_TLS_CA_FILE",
+                "BACKEND_TLS_CLIENT_CERT",
+                "BACKEND_TLS_PINS",
+            ] {
+                if vars.var(key).is_ok() {
+                    return Err(Error::Config(format!("{key} requires BACKEND_TLS=true")));
+                }
+            }
+        }
+        let backend_tls_server_name = vars.var("BACKEND_TLS_SERVER_NAME").ok();
+        match backend_tls_server_name {
+            Some(ref name) => {
+                ServerName::try_from(name.as_str()).map_err(|_| {
+                    Error::Config(format!(
+                        "invalid BACKEND_TLS_SERVER_NAME '{name}': expected a DNS name or IP address"
+                    ))
+                })?;
+            }
+            None if backend_tls => {
+                check_server_names("BACKEND_ADDR", &backend_addrs)?;
+                if let Some(ref addr) = shadow_backend_addr {
+                    check_server_names("SHADOW_BACKEND_ADDR", std::slice::from_ref(addr))?;
+                }
+            }
+            None => {}
+        }
+        let backend_tls_ca_file = vars.var("BACKEND_TLS_CA_FILE").ok();
+        let backend_tls_client_cert = match vars.var("BACKEND_TLS_CLIENT_CERT")
+            .unwrap_or_else(|_| "none".into())
+            .to_lowercase()
+            .as_str()
+        {
+            "none" => BackendClientCert::None,
+            "issued" => BackendClientCert::Issued,
+            "file" => BackendClientCert::File {
+                cert_file: vars.required("BACKEND_TLS_CLIENT_CERT_FILE")?,
+                key_file: vars.required("BACKEND_TLS_CLIENT_KEY_FILE")?,
+            },
+            other => {
+                return Err(Error::Config(format!(
+                    "invalid BACKEND_TLS_CLIENT_CERT '{other}': must be 'none', 'issued', or 'file'"
+                )))
+            }
+        };
+        let backend_tls_pins = vars.var("BACKEND_TLS_PINS")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .map(|pin| {
+                let digest = pin.strip_prefix("sha256/").unwrap_or(pin);
+                match STANDARD.decode(digest) {
+                    Ok(bytes) if bytes.len() == 32 => Ok(bytes),
+                    _ => Err(Error::Config(format!(
+                        "invalid BACKEND_TLS_PINS entry '{pin}': expected a base64 SHA-256 digest"
+                    ))),
+                }
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let renewal_threshold: f64 = vars.var("RENEWAL_THRESHOLD")
+            .unwrap_or_else(|_| "0.66".into())
+            .parse()
+            .map_err(|e| Error::Config(format!("invalid RENEWAL_THRESHOLD: {e}")))?;
+
+        if !(0.0..1.0).contains(&renewal_threshold) {
+            return Err(Error::Config(
+                "RENEWAL_THRESHOLD must be between 0.0 and 1.0".into(),
+            ));
+        }
+
+        let renewal_jitter = match vars.var("RENEWAL_JITTER") {
+            Ok(value) => {
+                let jitter: f64 = value
+                    .parse()
+                    .map_err(|e| Error::Config(format!("invalid RENEWAL_JITTER: {e}")))?;
+                if !(0.0..1.0).contains(&jitter) {
+                    return Err(Error::Config(
+                        "RENEWAL_JITTER must be between 0.0 and 1.0".into(),
+                    ));
+                }
+                // The latest jittered renewal must still happen before expiry.
+                if renewal_threshold * (1.0 + jitter) >= 1.0 {
+                    return Err(Error::Config(format!(
+                        "RENEWAL_THRESHOLD {renewal_threshold} with RENEWAL_JITTER {jitter} could schedule renewal after expiry"
+                    )));
+                }
+                jitter
+            }
+            // Shrink the default so existing high thresholds keep working.
+            Err(_) => DEFAULT_RENEWAL_JITTER.min((1.0 / renewal_threshold - 1.0) / 2.0),
+        };
+
+        let min_renew_interval = vars.duration("MIN_RENEW_INTERVAL", "30s")?;
+        let max_renew_interval = vars.optional_duration("MAX_RENEW_INTERVAL")?;
+        match max_renew_interval {
+            Some(max) if max.is_zero() => {
+                return Err(Error::Config(
+                    "MAX_RENEW_INTERVAL must be greater than zero".into(),
+                ));
+            }
+            Some(max) if max < min_renew_interval => {
+                return Err(Error::Config(
+                    "MAX_RENEW_INTERVAL must not be shorter than MIN_RENEW_INTERVAL".into(),
+                ));
+            }
+            _ => {}
+        }
+
+        let renewal_hook_cmd = vars.var("RENEWAL_HOOK_CMD").ok().filter(|cmd| !cmd.trim().is_empty());
+        let renewal_hook_timeout = vars.duration("RENEWAL_HOOK_TIMEOUT", "30s")?;
+        if renewal_hook_timeout.is_zero() {
+            return Err(Error::Config(
+                "RENEWAL_HOOK_TIMEOUT must be greater than zero".into(),
+            ));
+        }
+        let fail_if_expiring_within = vars.optional_duration("FAIL_IF_EXPIRING_WITHIN")?;
+        if fail_if_expiring_within.is_some_and(|within| within.is_zero()) {
+            return Err(Error::Config(
+                "FAIL_IF_EXPIRING_WITHIN must be greater than zero".into(),
+            ));
+        }
+        let fail_if_expiring_failures: u32 = vars.var("FAIL_IF_EXPIRING_FAILURES")
+            .unwrap_or_else(|_| "3".into())
+            .parse()
+            .map_err(|e| Error::Config(format!("invalid FAIL_IF_EXPIRING_FAILURES: {e}")))?;
+        if fail_if_expiring_failures == 0 {
+            return Err(Error::Config(
+                "FAIL_IF_EXPIRING_FAILURES must be at least 1".into(),
+            ));
+        }
+
+        let cert_revoke_superseded = vars.bool("CERT_REVOKE_SUPERSEDED", false)?;
+        if cert_revoke_superseded && cert_issuer != CertIssuer::Vault {
+            return Err(Error::Config(
+                "CERT_REVOKE_SUPERSEDED requires CERT_ISSUER=vault".into(),
+            ));
+        }
+        let cert_revoke_delay = vars.duration("CERT_REVOKE_DELAY", "10m")?;
+
+        let webhook_url = vars.var("WEBHOOK_URL").ok().filter(|url| !url.trim().is_empty());
+        if let Some(ref url) = webhook_url {
+            match reqwest::Url::parse(url) {
+                Ok(parsed) if matches!(parsed.scheme(), "http" | "https") => {}
+                _ => {
+                    return Err(Error::Config(format!(
+                        "invalid WEBHOOK_URL '{url}': must be an http or https URL"
+                    )))
+                }
+            }
+        }
+        let webhook_headers = vars.var("WEBHOOK_HEADERS")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .map(|header| {
+                let (name, value) = header.split_once('=').ok_or_else(|| {
+                    Error::Config(format!(
+                        "invalid WEBHOOK_HEADERS entry '{header}': expected name=value"
+                    ))
+                })?;
+                let name = name.trim();
+                reqwest::header::HeaderName::try_from(name).map_err(|e| {
+                    Error::Config(format!("invalid WEBHOOK_HEADERS name '{name}': {e}"))
+                })?;
+                reqwest::header::HeaderValue::try_from(value.trim()).map_err(|e| {
+                    Error::Config(format!("invalid WEBHOOK_HEADERS value for {name}: {e}"))
+                })?;
+                Ok((name.to_string(), value.trim().to_string()))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let webhook_timeout = vars.duration("WEBHOOK_TIMEOUT", "10s")?;
+        if webhook_timeout.is_zero() {
+            return Err(Error::Config("WEBHOOK_TIMEOUT must be greater than zero".into()));
+        }
+        let webhook_retries: u32 = vars.var("WEBHOOK_RETRIES")
+            .unwrap_or_else(|_| "3".into())
+            .parse()
+            .map_err(|e| Error::Config(format!("invalid WEBHOOK_RETRIES: {e}")))?;
+        let webhook_expiry_warning = vars.duration("WEBHOOK_EXPIRY_WARNING", "6h")?;
+
+        let audit_log = match vars.var("AUDIT_LOG").ok().as_deref().map(str::trim) {
+            None | Some("") => None,
+            Some("stderr") => Some(AuditTarget::Stderr),
+            Some(path) => Some(AuditTarget::File(path.to_string())),
+        };
+
+        let log_format_name = vars.var("LOG_FORMAT").unwrap_or_else(|_| "json".into());
+        let log_format = LogFormat::from_name(&log_format_name).ok_or_else(|| {
+            Error::Config(format!(
+                "invalid LOG_FORMAT '{log_format_name}': must be 'json' or 'pretty'"
+            ))
+        })?;
+        let log_level = vars.var("LOG_LEVEL").unwrap_or_else(|_| "info".into());
+        EnvFilter::try_new(&log_level)
+            .map_err(|e| Error::Config(format!("invalid LOG_LEVEL '{log_level}': {e}")))?;
+        let log_control_file = vars.var("LOG_CONTROL_FILE").ok();
+        let config_reload_interval =
+            Some(vars.duration("CONFIG_RELOAD_INTERVAL", "10s")?).filter(|d| !d.is_zero());
+
+        // The standard OpenTelemetry variables. The exporter reads the rest
+        // (headers, timeout, compression) from the process environment
+        // itself, so none of them are taken from the config file.
+        let otel_exporter = env::var("OTEL_TRACES_EXPORTER").unwrap_or_else(|_| "otlp".into());
+        let otel_disabled = env::var("OTEL_SDK_DISABLED").is_ok_and(|v| v.eq_ignore_ascii_case("true"))
+            || otel_exporter == "none";
+        let otel_endpoint = env::var("OTEL_EXPORTER_OTLP_TRACES_ENDPOINT")
+            .or_else(|_| env::var("OTEL_EXPORTER_OTLP_ENDPOINT"))
+            .ok()
+            .filter(|endpoint| !endpoint.is_empty() && !otel_disabled);
+        if otel_endpoint.is_some() {
+            if !cfg!(feature = "otel") {
+                return Err(Error::Config(
+                    "OTEL_EXPORTER_OTLP_ENDPOINT requires a build with the otel feature \
+                     (set OTEL_SDK_DISABLED=true to ignore it)"
+                        .into(),
+                ));
+            }
+            if otel_exporter != "otlp" {
+                return Err(Error::Config(format!(
+                    "unsupported OTEL_TRACES_EXPORTER '{otel_exporter}': must be 'otlp' or 'none'"
+                )));
+            }
+            let protocol = env::var("OTEL_EXPORTER_OTLP_TRACES_PROTOCOL")
+                .or_else(|_| env::var("OTEL_EXPORTER_OTLP_PROTOCOL"))
+                .ok();
+            if let Some(protocol) = protocol.filter(|p| p != "http/protobuf") {
+                return Err(Error::Config(format!(
+                    "unsupported OTLP protocol '{protocol}': only 'http/protobuf' is supported"
+                )));
+            }
+        }
+
+        let pid_file = vars.var("PID_FILE").ok();
+        let ready_file = vars.var("READY_FILE").ok();
+        let healthy_file = vars.var("HEALTHY_FILE").ok();
+        if ready_file.is_some() && ready_file == healthy_file {
+            return Err(Error::Config(
+                "READY_FILE and HEALTHY_FILE must be different paths".into(),
+            ));
+        }
+
+        let backend_health_check = match vars.var("BACKEND_HEALTH_CHECK")
+            .unwrap_or_else(|_| "none".into())
+            .to_lowercase()
+            .as_str()
+        {
+            "none" => BackendHealthCheck::None,
+            "tcp" => BackendHealthCheck::Tcp,
+            "http" => {
+                let path = vars.var("BACKEND_HEALTH_PATH").unwrap_or_else(|_| "/".into());
+                if !path.starts_with('/') || path.contains(char::is_whitespace) {
+                    return Err(Error::Config(format!(
+                        "invalid BACKEND_HEALTH_PATH '{path}': must be a path starting with '/'"
+                    )));
+                }
+                BackendHealthCheck::Http { path }
+            }
+            "grpc" => BackendHealthCheck::Grpc {
+                service: vars.var("BACKEND_GRPC_HEALTH_SERVICE").unwrap_or_default(),
+            },
+            other => {
+                return Err(Error::Config(format!(
+                    "invalid BACKEND_HEALTH_CHECK '{other}': must be 'none', 'tcp', 'http', or 'grpc'"
+                )))
+            }
+        };
+        let grpc_health = matches!(backend_health_check, BackendHealthCheck::Grpc { .. });
+        if backend_health_check != BackendHealthCheck::None && !proxy_enabled {
+            return Err(Error::Config(
+                "BACKEND_HEALTH_CHECK requires PROXY_ENABLED=true".into(),
+            ));
+        }
+        if grpc_health && !cfg!(feature = "grpc-health") {
+            return Err(Error::Config(
+                "BACKEND_HEALTH_CHECK=grpc requires a build with the grpc-health feature".into(),
+            ));
+        }
+        // The HTTP and gRPC probes speak plain text; a TCP connect does not
+        // care.
+        if backend_tls
+            && !matches!(backend_health_check, BackendHealthCheck::None | BackendHealthCheck::Tcp)
+        {
+            return Err(Error::Config(
+                "BACKEND_HEALTH_CHECK=http and grpc cannot be used with BACKEND_TLS".into(),
+            ));
+        }
+        if grpc_health && backend_addrs.len() > 1 {
+            return Err(Error::Config(
+                "BACKEND_HEALTH_CHECK=grpc requires a single BACKEND_ADDR".into(),
+            ));
+        }
+        let backend_health_interval = vars.duration("BACKEND_HEALTH_INTERVAL", "10s")?;
+        let backend_health_timeout = vars.duration("BACKEND_HEALTH_TIMEOUT", "2s")?;
+
+        let mut listeners: Vec<ExtraListener> = Vec::new();
+        for name in vars.var("LISTENERS")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+        {
+            if !proxy_enabled {
+                return Err(Error::Config("LISTENERS requires PROXY_ENABLED=true".into()));
+            }
+            // The name doubles as part of env var names.
+            if !name
+                .bytes()
+                .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'-')
+            {
+                return Err(Error::Config(format!(
+                    "invalid LISTENERS name '{name}': use lowercase letters, digits, and '-'"
+                )));
+            }
+            if listeners.iter().any(|l| l.name == name) {
+                return Err(Error::Config(format!(
+                    "LISTENERS name '{name}' is used more than once"
+                )));
+            }
+            let prefix = format!("LISTENER_{}_", name.to_uppercase().replace('-', "_"));
+            let key = |setting: &str| format!("{prefix}{setting}");
+
+            let addr = ListenAddr::parse(&vars.required(&key("ADDR"))?)
+                .map_err(|e| Error::Config(format!("invalid {}: {e}", key("ADDR"))))?;
+            if addr.overlaps(&listen_addr) || listeners.iter().any(|l| l.addr.overlaps(&addr)) {
+                return Err(Error::Config(format!(
+                    "{} '{addr}' is already used by another listener",
+                    key("ADDR")
+                )));
+            }
+            if !matches!(addr, ListenAddr::Tcp(_) | ListenAddr::Systemd(_))
+                && (!allow_cidrs.is_empty() || !deny_cidrs.is_empty())
+            {
+                return Err(Error::Config(format!(
+                    "ALLOW_CIDRS and DENY_CIDRS need a TCP {}; Unix socket peers have no address",
+                    key("ADDR")
+                )));
+            }
+
+            let backend_key = key("BACKEND_ADDR");
+            let backend_addrs = parse_backend_addrs(&backend_key, &vars.required(&backend_key)?)?;
+            if backend_tls && backend_tls_server_name.is_none() {
+                check_server_names(&backend_key, &backend_addrs)?;
+            }
+            if grpc_health && backend_addrs.len() > 1 {
+                return Err(Error::Config(format!(
+                    "BACKEND_HEALTH_CHECK=grpc requires a single {backend_key}"
+                )));
+            }
+
+            let cert_profile = vars.var(&key("CERT_PROFILE")).ok();
+            if let Some(ref profile) = cert_profile {
+                if !cert_profiles.iter().any(|p| &p.name == profile) {
+                    return Err(Error::Config(format!(
+                        "{} '{profile}' is not in CERT_PROFILES",
+                        key("CERT_PROFILE")
+                    )));
+                }
+            }
+            listeners.push(ExtraListener {
+                name: name.to_string(),
+                addr,
+                backend_addrs,
+                cert_profile,
+            });
+        }
+
+        let acme_enabled = vars.bool("ACME_ENABLED", false)?;
+        if acme_enabled && !cfg!(feature = "acme") {
+            return Err(Error::Config(
+                "ACME_ENABLED requires a build with the acme feature".into(),
+            ));
+        }
+        let acme_listen_addr: SocketAddr = vars.var("ACME_LISTEN_ADDR")
+            .unwrap_or_else(|_| "0.0.0.0:9443".into())
+            .parse()
+            .map_err(|e| Error::Config(format!("invalid ACME_LISTEN_ADDR: {e}")))?;
+        let acme_pki_role = vars.var("ACME_PKI_ROLE").unwrap_or_else(|_| vault_pki_role.clone());
+        let acme_cert_ttl = vars.var("ACME_CERT_TTL").unwrap_or_else(|_| cert_ttl.clone());
+        let acme_validate_challenges = vars.bool("ACME_VALIDATE_CHALLENGES", true)?;
+
+        let acme_issuer_directory = vars.var("ACME_ISSUER_DIRECTORY")
+            .unwrap_or_else(|_| "https://acme-v02.api.letsencrypt.org/directory".into());
+        if !acme_issuer_directory.starts_with("https://") && !acme_issuer_directory.starts_with("http://") {
+            return Err(Error::Config(format!(
+                "invalid ACME_ISSUER_DIRECTORY '{acme_issuer_directory}': expected an http or https URL"
+            )));
+        }
+        let acme_issuer_email = vars.var("ACME_ISSUER_EMAIL").ok();
+        let acme_issuer_account_key_file = vars.var("ACME_ISSUER_ACCOUNT_KEY_FILE").ok();
+        let acme_issuer_challenge = match vars.var("ACME_ISSUER_CHALLENGE")
+            .unwrap_or_else(|_| "tls-alpn-01".into())
+            .to_lowercase()
+            .as_str()
+        {
+            "tls-alpn-01" => AcmeChallenge::TlsAlpn01,
+            "http-01" => AcmeChallenge::Http01,
+            "dns-01" => AcmeChallenge::Dns01,
+            other => {
+                return Err(Error::Config(format!(
+                    "invalid ACME_ISSUER_CHALLENGE '{other}': must be 'tls-alpn-01', 'http-01', or 'dns-01'"
+                )))
+            }
+        };
+        let acme_issuer_http_addr: SocketAddr = vars.var("ACME_ISSUER_HTTP_ADDR")
+            .unwrap_or_else(|_| "0.0.0.0:8080".into())
+            .parse()
+            .map_err(|e| Error::Config(format!("invalid ACME_ISSUER_HTTP_ADDR: {e}")))?;
+        let acme_issuer_dns_hook = vars.var("ACME_ISSUER_DNS_HOOK").ok();
+        let acme_issuer_dns_delay = vars.duration("ACME_ISSUER_DNS_DELAY", "30s")?;
+        let acme_issuer_timeout = vars.duration("ACME_ISSUER_TIMEOUT", "5m")?;
+        let acme_issuer_cacert = vars.var("ACME_ISSUER_CACERT").ok();
+        if cert_issuer != CertIssuer::Vault {
+            // These default to, or only exist in, the Vault PKI mount.
+            if acme_enabled {
+                return Err(Error::Config("ACME_ENABLED requires CERT_ISSUER=vault".into()));
+            }
+            if client_auth != ClientAuth::None && client_ca_file.is_none() {
+                return Err(Error::Config(format!(
+                    "CLIENT_AUTH requires CLIENT_CA_FILE with CERT_ISSUER={cert_issuer}"
+                )));
+            }
+            if client_crl_check {
+                return Err(Error::Config("CLIENT_CRL_CHECK requires CERT_ISSUER=vault".into()));
+            }
+            if backend_tls && backend_tls_ca_file.is_none() {
+                return Err(Error::Config(format!(
+                    "BACKEND_TLS requires BACKEND_TLS_CA_FILE with CERT_ISSUER={cert_issuer}"
+                )));
+            }
+        }
+        if cert_issuer == CertIssuer::Acme {
+            for key in ["CERT_IP_SANS", "CERT_URI_SANS"] {
+                if vars.var(key).is_ok() {
+                    return Err(Error::Config(format!("{key} is not supported with CERT_ISSUER=acme")));
+                }
+            }
+            let wildcard = std::iter::once(cert_common_name.as_str())
+                .chain(cert_alt_names.iter().flat_map(|n| n.split(',')))
+                .chain(cert_sni_groups.iter().flat_map(|g| {
+                    std::iter::once(g.common_name.as_str())
+                        .chain(g.alt_names.iter().flat_map(|n| n.split(',')))
+                }))
+                .any(|name| name.trim().starts_with("*."));
+            if wildcard && acme_issuer_challenge != AcmeChallenge::Dns01 {
+                return Err(Error::Config(
+                    "wildcard names require ACME_ISSUER_CHALLENGE=dns-01".into(),
+                ));
+            }
+            match acme_issuer_challenge {
+                AcmeChallenge::TlsAlpn01 if !proxy_enabled => {
+                    return Err(Error::Config(
+                        "ACME_ISSUER_CHALLENGE=tls-alpn-01 requires PROXY_ENABLED=true, since the proxy answers it".into(),
+                    ));
+                }
+                AcmeChallenge::TlsAlpn01 if proxy_mode == ProxyMode::Passthrough => {
+                    return Err(Error::Config(
+                        "ACME_ISSUER_CHALLENGE=tls-alpn-01 cannot be used with PROXY_MODE=passthrough, which does not terminate TLS".into(),
+                    ));
+                }
+                // The responder binds its own listener for each order.
+                AcmeChallenge::Http01
+                    if http_redirect_addr
+                        .is_some_and(|addr| addr.port() == acme_issuer_http_addr.port()) =>
+                {
+                    return Err(Error::Config(format!(
+                        "HTTP_REDIRECT_ADDR and ACME_ISSUER_HTTP_ADDR cannot share port {}",
+                        acme_issuer_http_addr.port()
+                    )));
+                }
+                AcmeChallenge::Dns01 if acme_issuer_dns_hook.is_none() => {
+                    return Err(Error::Config(
+                        "ACME_ISSUER_CHALLENGE=dns-01 requires ACME_ISSUER_DNS_HOOK".into(),
+                    ));
+                }
+                _ => {}
+            }
+        }
+
+        let k8s_csr_signer_name = match cert_issuer {
+            CertIssuer::KubernetesCsr => vars.required("K8S_CSR_SIGNER_NAME")?,
+            _ => vars.var("K8S_CSR_SIGNER_NAME").unwrap_or_default(),
+        };
+        // Signer names are qualified: <domain>/<path>.
+        if cert_issuer == CertIssuer::KubernetesCsr && !k8s_csr_signer_name.contains('/') {
+            return Err(Error::Config(format!(
+                "invalid K8S_CSR_SIGNER_NAME '{k8s_csr_signer_name}': expected a name like example.com/my-signer"
+            )));
+        }
+        let k8s_csr_expiration = vars.optional_duration("K8S_CSR_EXPIRATION")?;
+        // The API server rejects anything shorter.
+        if k8s_csr_expiration.is_some_and(|d| d < Duration::from_secs(600)) {
+            return Err(Error::Config("K8S_CSR_EXPIRATION must be at least 10m".into()));
+        }
+        let k8s_csr_timeout = vars.duration("K8S_CSR_TIMEOUT", "5m")?;
+
+        let exec_issuer_cmd = match cert_issuer {
+            CertIssuer::Exec => vars.required("EXEC_ISSUER_CMD")?,
+            _ => vars.var("EXEC_ISSUER_CMD").unwrap_or_default(),
+        };
+        if cert_issuer == CertIssuer::Exec && exec_issuer_cmd.trim().is_empty() {
+            return Err(Error::Config("EXEC_ISSUER_CMD must not be empty".into()));
+        }
+        let exec_issuer_timeout = vars.duration("EXEC_ISSUER_TIMEOUT", "1m")?;
+        if exec_issuer_timeout.is_zero() {
+            return Err(Error::Config(
+                "EXEC_ISSUER_TIMEOUT must be greater than zero".into(),
+            ));
+        }
+
+        let admin_listen_addr = vars.var("ADMIN_LISTEN_ADDR")
+            .ok()
+            .map(|addr| {
+                let addr: SocketAddr = addr
+                    .parse()
+                    .map_err(|e| Error::Config(format!("invalid ADMIN_LISTEN_ADDR: {e}")))?;
+                // The API is unauthenticated.
+                if !addr.ip().is_loopback() {
+                    return Err(Error::Config(format!(
+                        "ADMIN_LISTEN_ADDR must be a loopback address, got {addr}"
+                    )));
+                }
+                Ok(addr)
+            })
+            .transpose()?;
+        if admin_listen_addr.is_some() && !cfg!(feature = "admin") {
+            return Err(Error::Config(
+                "ADMIN_LISTEN_ADDR requires a build with the admin feature".into(),
+            ));
+        }
+        let admin_grpc_health = vars.bool("ADMIN_GRPC_HEALTH", false)?;
+        if admin_grpc_health && admin_listen_addr.is_none() {
+            return Err(Error::Config("ADMIN_GRPC_HEALTH requires ADMIN_LISTEN_ADDR".into()));
+        }
+        if admin_grpc_health && !cfg!(feature = "grpc-health") {
+            return Err(Error::Config(
+                "ADMIN_GRPC_HEALTH requires a build with the grpc-health feature".into(),
+            ));
+        }
+
+        let dns_nameservers = vars.var("DNS_NAMESERVERS")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .map(|s| {
+                s.parse::<SocketAddr>()
+                    .or_else(|_| s.parse::<IpAddr>().map(|ip| SocketAddr::new(ip, 53)))
+                    .map_err(|e| Error::Config(format!("invalid DNS_NAMESERVERS entry '{s}': {e}")))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let dns_timeout = vars.duration("DNS_TIMEOUT", "2s")?;
+        let dns_cache_min_ttl = vars.optional_duration("DNS_CACHE_MIN_TTL")?;
+        let dns_cache_max_ttl = vars.optional_duration("DNS_CACHE_MAX_TTL")?;
+
+        Ok(Config {
+            mode,
+            vault_addr,
+            vault_auth_method,
+            vault_auth_role,
+            vault_auth_mount,
+            vault_pki_role,
+            vault_pki_mount,
+            vault_pki_issuer,
+            vault_namespace,
+            vault_auth_namespace,
+            vault_pki_namespace,
+            vault_cacert,
+            vault_client_cert,
+            vault_client_key,
+            vault_tls_server_name,
+            vault_skip_verify,
+            vault_token_cache,
+            vault_token_cache_key_file,
+            vault_retries,
+            vault_retry_backoff,
+            vault_circuit_threshold,
+            vault_circuit_cooldown,
+            vault_startup_timeout,
+            vault_connect_timeout,
+            vault_request_timeout,
+            vault_operation_timeout,
+            vault_pool_idle_timeout,
+            vault_pool_max_idle,
+            vault_wrap_ttl,
+            vault_kv_mount,
+            kv_secrets,
+            secrets_dir,
+            cert_alt_names,
+            cert_ip_sans,
+            cert_uri_sans,
+            cert_ttl,
+            cert_not_after,
+            cert_exclude_cn_from_sans,
+            cert_other_sans,
+            cert_private_key_format,
+            cert_serial_number,
+            cert_sni_groups,
+            cert_profiles,
+            cert_key_source,
+            cert_key_type,
+            cert_key_bits,
+            cert_dir,
+            extra_ca_files,
+            cert_extra_chain_file,
+            cert_extra_chain_issuers,
+            cert_extra_chain_until,
+            cert_validate,
+            trust_bundle_file,
+            trust_bundle_mounts,
+            trust_bundle_refresh_interval,
+            cert_output_files,
+            store_mode,
+            cert_secret_name,
+            cert_secret_namespace,
+            cert_files,
+            cert_warm_start,
+            cert_bootstrap_self_signed,
+            cert_watch_interval,
+            leader_election,
+            leader_election_lease,
+            leader_election_namespace,
+            leader_election_lease_duration,
+            leader_election_identity,
+            cert_pkcs12_file,
+            cert_jks_file,
+            cert_keystore_alias,
+            cert_keystore_password,
+            cert_key_encryption,
+            cert_file_mode,
+            cert_key_file_mode,
+            cert_file_uid,
+            cert_file_gid,
+            cert_common_name,
+            proxy_enabled,
+            listen_addr,
+            listeners,
+            http_redirect_addr,
+            http_redirect_port,
+            quic_listen_addr,
+            dtls_listen_addr,
+            dtls_backend_addr,
+            drain_timeout,
+            shutdown_grace,
+            task_restart_limit,
+            task_restart_backoff,
+            max_connections,
+            acceptor_threads,
+            tls_handshake_timeout,
+            idle_timeout,
+            access_log,
+            tcp,
+            bandwidth_limit,
+            bandwidth_routes,
+            allow_cidrs,
+            deny_cidrs,
+            alpn_protocols,
+            tls_min_version,
+            tls_cipher_suites,
+            tls_session_resumption,
+            tls_session_tickets,
+            tls_session_ticket_lifetime,
+            tls_session_cache_size,
+            client_auth,
+            client_ca_file,
+            client_crl_check,
+            client_crl_refresh_interval,
+            backend_addrs,
+            backend_lb_policy,
+            backend_failure_threshold,
+            backend_failure_cooldown,
+            backend_connect_timeout,
+            backend_proxy_protocol,
+            proxy_mode,
+            holding_page,
+            holding_page_file,
+            passthrough_routes,
+            shadow_backend_addr,
+            http_trust_forwarded,
+            forward_client_cert,
+            backend_tls,
+            backend_tls_server_name,
+            backend_tls_ca_file,
+            backend_tls_client_cert,
+            backend_tls_pins,
+            renewal_threshold,
+            renewal_jitter,
+            min_renew_interval,
+            max_renew_interval,
+            renewal_hook_cmd,
+            renewal_hook_timeout,
+            fail_if_expiring_within,
+            fail_if_expiring_failures,
+            cert_revoke_superseded,
+            cert_revoke_delay,
+            webhook_url,
+            webhook_headers,
+            webhook_timeout,
+            webhook_retries,
+            webhook_expiry_warning,
+            audit_log,
+            log_format,
+            log_level,
+            log_control_file,
+            config_reload_interval,
+            otel_endpoint,
+            pid_file,
+            ready_file,
+            healthy_file,
+            backend_health_check,
+            backend_health_interval,
+            backend_health_timeout,
+            acme_enabled,
+            acme_listen_addr,
+            acme_pki_role,
+            acme_cert_ttl,
+            acme_validate_challenges,
+            cert_issuer,
+            acme_issuer_directory,
+            acme_issuer_email,
+            acme_issuer_account_key_file,
+            acme_issuer_challenge,
+            acme_issuer_http_addr,
+            acme_issuer_dns_hook,
+            acme_issuer_dns_delay,
+            acme_issuer_timeout,
+            acme_issuer_cacert,
+            k8s_csr_signer_name,
+            k8s_csr_expiration,
+            k8s_csr_timeout,
+            exec_issuer_cmd,
+            exec_issuer_timeout,
+            admin_listen_addr,
+            admin_grpc_health,
+            dns_nameservers,
+            dns_timeout,
+            dns_cache_min_ttl,
+            dns_cache_max_ttl,
+        })
+    }
+
+    /// Names of the default certificate, served when no SNI group matches.
+    pub fn primary_cert(&self) -> CertNames {
+        CertNames {
+            common_name: self.cert_common_name.clone(),
+            alt_names: self.cert_alt_names.clone(),
+            ip_sans: self.cert_ip_sans.clone(),
+            uri_sans: self.cert_uri_sans.clone(),
+        }
+    }
+
+    /// `self` with the PKI role, mount, and TTL of `profile`, for issuing
+    /// its certificate. `VAULT_PKI_ISSUER` and `CERT_EXTRA_CHAIN_ISSUERS`
+    /// name issuers of `VAULT_PKI_MOUNT`, so they are dropped for another
+    /// mount.
+    pub fn for_profile(&self, profile: &CertProfile) -> Config {
+        let mut config = self.clone();
+        if profile.pki_mount != self.vault_pki_mount {
+            config.vault_pki_issuer = None;
+            config.cert_extra_chain_issuers.clear();
+        }
+        config.vault_pki_role = profile.pki_role.clone();
+        config.vault_pki_mount = profile.pki_mount.clone();
+        // A profile TTL of its own takes over from the fixed expiry.
+        if profile.ttl != self.cert_ttl {
+            config.cert_not_after = None;
+        }
+        config.cert_ttl = profile.ttl.clone();
+        config
+    }
+
+    /// `self` with the address and backend of `listener`, for running it.
+    /// The HTTP redirect, HTTP/3, and DTLS listeners belong to `LISTEN_ADDR` only.
+    pub fn for_listener(&self, listener: &ExtraListener) -> Config {
+        let mut config = self.clone();
+        config.listen_addr = listener.addr.clone();
+        config.backend_addrs = listener.backend_addrs.clone();
+        config.http_redirect_addr = None;
+        config.quic_listen_addr = None;
+        config.dtls_listen_addr = None;
+        config.listeners = Vec::new();
+        config
+    }
+
+    /// `self` with the settings of `new` that apply without a restart: the
+    /// listen addresses, the TLS policy, the backend addresses, passthrough
+    /// routes, and timeouts, the listeners' handshake, drain, and address
+    /// filtering settings, the access log, and logging. The rest keep their
+    /// current values.
+    ///
+    /// Backend addresses stay put with a health check, which keeps checking
+    /// the backend it started with, and listen addresses stay put on and
+    /// off sockets passed by systemd, which cannot be bound again.
+    pub fn reloaded(&self, new: &Config) -> Config {
+        let mut config = self.clone();
+        let movable = |old: &ListenAddr, new: &ListenAddr| {
+            !matches!(old, ListenAddr::Systemd(_)) && !matches!(new, ListenAddr::Systemd(_))
+        };
+        if movable(&self.listen_addr, &new.listen_addr) {
+            config.listen_addr = new.listen_addr.clone();
+        }
+        for listener in &mut config.listeners {
+            if let Some(new) = new.listeners.iter().find(|l| l.name == listener.name) {
+                if movable(&listener.addr, &new.addr) {
+                    listener.addr = new.addr.clone();
+                }
+                if self.backend_health_check == BackendHealthCheck::None {
+                    listener.backend_addrs = new.backend_addrs.clone();
+                }
+            }
+        }
+        if self.backend_health_check == BackendHealthCheck::None {
+            config.backend_addrs = new.backend_addrs.clone();
+        }
+        config.tls_min_version = new.tls_min_version;
+        config.tls_cipher_suites = new.tls_cipher_suites.clone();
+        config.passthrough_routes = new.passthrough_routes.clone();
+        config.shadow_backend_addr = new.shadow_backend_addr.clone();
+        config.backend_lb_policy = new.backend_lb_policy;
+        config.backend_connect_timeout = new.backend_connect_timeout;
+        config.backend_failure_threshold = new.backend_failure_threshold;
+        config.backend_failure_cooldown = new.backend_failure_cooldown;
+        config.idle_timeout = new.idle_timeout;
+        config.bandwidth_limit = new.bandwidth_limit;
+        config.bandwidth_routes = new.bandwidth_routes.clone();
+        config.tls_handshake_timeout = new.tls_handshake_timeout;
+        config.drain_timeout = new.drain_timeout;
+        config.access_log = new.access_log;
+        config.allow_cidrs = new.allow_cidrs.clone();
+        config.deny_cidrs = new.deny_cidrs.clone();
+        config.log_format = new.log_format.clone();
+        config.log_level = new.log_level.clone();
+        config
+    }
+
+    /// The Secret the certificate of `profile` is applied to, if
+    /// certificates go to Secrets.
+    pub fn profile_secret_name(&self, profile: &CertProfile) -> Option<String> {
+        let name = self.cert_secret_name.as_ref()?;
+        Some(profile_secret_name(name, &profile.name))
+    }
+
+    /// The Secret the certificate for `common_name` is applied to, if
+    /// certificates go to Secrets.
+    pub fn secret_name(&self, common_name: &str) -> Option<String> {
+        let name = self.cert_secret_name.as_ref()?;
+        if common_name == self.cert_common_name {
+            Some(name.clone())
+        } else {
+            Some(sni_secret_name(name, common_name))
+        }
+    }
+}
+
+/// The comma-separated `host:port` pool in `value`, the setting `key`.
+fn parse_backend_addrs(key: &str, value: &str) -> Result<Vec<String>> {
+    let addrs = value
+        .split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(|addr| {
+            dns::split_host_port(addr).map_err(|_| {
+                Error::Config(format!("invalid {key} entry '{addr}': expected host:port"))
+            })?;
+            Ok(addr.to_string())
+        })
+        .collect::<Result<Vec<_>>>()?;
+    if addrs.is_empty() {
+        return Err(Error::Config(format!("{key} must not be empty")));
+    }
+    Ok(addrs)
+}
+
+/// Check that each host in `addrs` can be sent as the backend TLS server
+/// name, for when `BACKEND_TLS_SERVER_NAME` is not set.
+fn check_server_names(key: &str, addrs: &[String]) -> Result<()> {
+    for addr in addrs {
+        let host = dns::split_host_port(addr)?.0;
+        ServerName::try_from(host).map_err(|_| {
+            Error::Config(format!(
+                "{key} host '{host}' is not a valid TLS server name; set BACKEND_TLS_SERVER_NAME"
+            ))
+        })?;
+    }
+    Ok(())
+}
+
+/// Look up a cipher suite by its rustls name (`TLS13_AES_256_GCM_SHA384`)
+/// or IANA name (`TLS_AES_256_GCM_SHA384`), ignoring case.
+fn cipher_suite(name: &str) -> Option<SupportedCipherSuite> {
+    ALL_CIPHER_SUITES.iter().copied().find(|suite| {
+        let rustls_name = format!("{:?}", suite.suite());
+        let iana_name = rustls_name.replacen("TLS13_", "TLS_", 1);
+        name.eq_ignore_ascii_case(&rustls_name) || name.eq_ignore_ascii_case(&iana_name)
+    })
+}
+
+/// Builds a [`Config`] in code rather than from the environment, for
+/// programs that embed cert-keeper.
+///
+/// Settings are keyed by the environment variable names in the README and
+/// validated exactly as [`Config::load`] does; those names are kept stable.
+/// The environment is not read, so the embedding program decides what is
+/// configurable; only `VAULT_TOKEN` still comes from it, for
+/// `VAULT_AUTH_METHOD=token` without `VAULT_TOKEN_FILE`.
+#[derive(Debug, Clone, Default)]
+pub struct ConfigBuilder {
+    settings: BTreeMap<String, String>,
+}
+
+impl ConfigBuilder {
+    /// Set `key`, an environment variable name in either case, to `value`.
+    /// Lists are comma-separated, as in the environment.
+    pub fn set(mut self, key: &str, value: impl fmt::Display) -> Self {
+        self.settings.insert(key.to_ascii_uppercase(), value.to_string());
+        self
+    }
+
+    pub fn vault_addr(self, addr: &str) -> Self {
+        self.set("VAULT_ADDR", addr)
+    }
+
+    pub fn vault_auth_role(self, role: &str) -> Self {
+        self.set("VAULT_AUTH_ROLE", role)
+    }
+
+    pub fn vault_pki_role(self, role: &str) -> Self {
+        self.set("VAULT_PKI_ROLE", role)
+    }
+
+    pub fn common_name(self, name: &str) -> Self {
+        self.set("CERT_COMMON_NAME", name)
+    }
+
+    pub fn alt_names(self, names: &[&str]) -> Self {
+        self.set("CERT_ALT_NAMES", names.join(","))
+    }
+
+    pub fn cert_dir(self, dir: &str) -> Self {
+        self.set("CERT_DIR", dir)
+    }
+
+    /// Validate the settings. `PROXY_ENABLED` defaults to `false` here,
+    /// since an embedding program serves TLS itself.
+    pub fn build(self) -> Result<Config> {
+        let mut settings = self.settings;
+        settings
+            .entry("PROXY_ENABLED".into())
+            .or_insert_with(|| "false".into());
+        Config::from_vars(Vars {
+            file: settings,
+            env: false,
+        })
+    }
+}
+
+/// Where settings are read from: the process environment, falling back to
+/// the config file.
+struct Vars {
+    /// Settings from the config file or a [`ConfigBuilder`].
+    file: BTreeMap<String, String>,
+    /// Whether the environment overrides `file`.
+    env: bool,
+}
+
+impl Default for Vars {
+    fn default() -> Self {
+        Self {
+            file: BTreeMap::new(),
+            env: true,
+        }
+    }
+}
+
+/// A config file value. Lists are joined with commas, the separator the
+/// environment variables use.
+#[derive(Deserialize)]
+#[serde(untagged, expecting = "a string, number, boolean, or list of them")]
+enum FileValue {
+    Scalar(Scalar),
+    List(Vec<Scalar>),
+}
+
+#[derive(Deserialize)]
+#[serde(untagged, expecting = "a string, number, or boolean")]
+enum Scalar {
+    Bool(bool),
+    Integer(i64),
+    Float(f64),
+    String(String),
+}
+
+impl fmt::Display for Scalar {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Scalar::Bool(b) => write!(f, "{b}"),
+            Scalar::Integer(i) => write!(f, "{i}"),
+            Scalar::Float(x) => write!(f, "{x}"),
+            Scalar::String(s) => f.write_str(s),
+        }
+    }
+}
+
+impl Vars {
+    /// Keys in the file are the environment variable names, in either case.
+    fn load(config_file: Option<&Path>) -> Result<Self> {
+        let Some(path) = config_file else {
+            return Ok(Self::default());
+        };
+        let invalid = |e: &dyn fmt::Display| {
+            Error::Config(format!("invalid config file {}: {e}", path.display()))
+        };
+
+        let contents = std::fs::read_to_string(path).map_err(|e| {
+            Error::Config(format!("failed to read config file {}: {e}", path.display()))
+        })?;
+        let values: BTreeMap<String, FileValue> =
+            match path.extension().and_then(|ext| ext.to_str()) {
+                Some("toml") => toml::from_str(&contents).map_err(|e| invalid(&e))?,
+                Some("yaml" | "yml") => serde_yaml::from_str(&contents).map_err(|e| invalid(&e))?,
+                _ => {
+                    return Err(Error::Config(format!(
+                        "config file {} must end in .toml, .yaml, or .yml",
+                        path.display()
+                    )))
+                }
+            };
+
+        let file = values
+            .into_iter()
+            .map(|(key, value)| {
+                let value = match value {
+                    FileValue::Scalar(value) => value.to_string(),
+                    FileValue::List(values) => values
+                        .iter()
+                        .map(ToString::to_string)
+                        .collect::<Vec<_>>()
+                        .join(","),
+                };
+                (key.to_ascii_uppercase(), value)
+            })
+            .collect();
+        Ok(Self { file, env: true })
+    }
+
+    fn var(&self, key: &str) -> std::result::Result<String, env::VarError> {
+        let from_env = if self.env {
+            env::var(key)
+        } else {
+            Err(env::VarError::NotPresent)
+        };
+        from_env.or_else(|e| self.file.get(key).cloned().ok_or(e))
+    }
+
+    /// `key` with each `{{pod_name}}`, `{{namespace}}`, `{{pod_ip}}`, and
+    /// `{{node_name}}` in it replaced with the pod's own, so one manifest
+    /// can ask for a certificate per pod.
+    fn templated(&self, key: &str) -> Result<Option<String>> {
+        let Ok(value) = self.var(key) else {
+            return Ok(None);
+        };
+        let mut expanded = String::with_capacity(value.len());
+        let mut rest = value.as_str();
+        while let Some(start) = rest.find("{{") {
+            expanded.push_str(&rest[..start]);
+            let Some((field, after)) = rest[start + 2..].split_once("}}") else {
+                return Err(Error::Config(format!(
+                    "invalid {key} '{value}': '{{{{' without a closing '}}}}'"
+                )));
+            };
+            expanded.push_str(&self.pod_field(key, field.trim())?);
+            rest = after;
+        }
+        expanded.push_str(rest);
+        Ok(Some(expanded))
+    }
+
+    /// The value of `{{field}}` in `key`, from the variable the downward API
+    /// sets, or else from what the kubelet gives every pod: its host name,
+    /// the service account's namespace, and the `/etc/hosts` entry for the
+    /// host name. The node name is only known from `NODE_NAME`.
+    fn pod_field(&self, key: &str, field: &str) -> Result<String> {
+        let (var, downward) = match field {
+            "pod_name" => ("POD_NAME", "metadata.name"),
+            "namespace" => ("POD_NAMESPACE", "metadata.namespace"),
+            "pod_ip" => ("POD_IP", "status.podIP"),
+            "node_name" => ("NODE_NAME", "spec.nodeName"),
+            other => {
+                return Err(Error::Config(format!(
+                    "unknown template '{{{{{other}}}}}' in {key}: must be pod_name, namespace, \
+                     pod_ip, or node_name"
+                )))
+            }
+        };
+        let read = |path: &str| {
+            std::fs::read_to_string(path)
+                .map(|s| s.trim().to_string())
+                .ok()
+        };
+        let value = self.var(var).ok().or_else(|| match field {
+            "pod_name" => read("/proc/sys/kernel/hostname"),
+            "namespace" => read(SA_NAMESPACE_PATH),
+            "pod_ip" => {
+                let hostname = read("/proc/sys/kernel/hostname")?;
+                read("/etc/hosts")?.lines().find_map(|line| {
+                    let mut fields = line.split('#').next()?.split_whitespace();
+                    let ip = fields.next()?.parse::<IpAddr>().ok()?;
+                    (!ip.is_loopback() && fields.any(|name| name == hostname))
+                        .then(|| ip.to_string())
+                })
+            }
+            _ => None,
+        });
+        value.filter(|v| !v.is_empty()).ok_or_else(|| {
+            Error::Config(format!(
+                "{{{{{field}}}}} in {key} needs {var}; set it from the downward API's {downward}"
+            ))
+        })
+    }
+
+    fn required(&self, key: &str) -> Result<String> {
+        self.var(key)
+            .map_err(|_| Error::Config(format!("required setting {key} is not set")))
+    }
+
+    fn bool(&self, key: &str, default: bool) -> Result<bool> {
+        match self.var(key) {
+            Err(_) => Ok(default),
+            Ok(value) => match value.to_lowercase().as_str() {
+                "true" | "1" | "yes" => Ok(true),
+                "false" | "0" | "no" => Ok(false),
+                _ => Err(Error::Config(format!(
+                    "invalid {key} '{value}': must be true or false"
+                ))),
+            },
+        }
+    }
+
+    fn duration(&self, key: &str, default: &str) -> Result<Duration> {
+        let value = self.var(key).unwrap_or_else(|_| default.into());
+        parse_duration(&value).map_err(|_| {
+            Error::Config(format!(
+                "invalid {key} '{value}': expected a duration like 500ms, 30s, 5m, or 1h"
+            ))
+        })
+    }
+
+    /// Bytes per second, `None` when unset or `0`.
+    fn rate(&self, key: &str) -> Result<Option<u64>> {
+        let Ok(value) = self.var(key) else {
+            return Ok(None);
+        };
+        parse_rate(&value).map_err(|_| {
+            Error::Config(format!(
+                "invalid {key} '{value}': expected bytes per second like 65536, 512KiB, or 10MB"
+            ))
+        })
+    }
+
+    /// An octal file mode such as `0640`.
+    fn mode(&self, key: &str, default: u32) -> Result<u32> {
+        let Ok(value) = self.var(key) else {
+            return Ok(default);
+        };
+        u32::from_str_radix(&value, 8)
+            .ok()
+            .filter(|mode| *mode <= 0o777)
+            .ok_or_else(|| {
+                Error::Config(format!(
+                    "invalid {key} '{value}': expected an octal mode like 0600"
+                ))
+            })
+    }
+
+    fn optional_duration(&self, key: &str) -> Result<Option<Duration>> {
+        match self.var(key) {
+            Err(_) => Ok(None),
+            Ok(_) => self.duration(key, "").map(Some),
+        }
+    }
+
+    fn optional_number<T: FromStr>(&self, key: &str) -> Result<Option<T>>
+    where
+        T::Err: fmt::Display,
+    {
+        match self.var(key) {
+            Err(_) => Ok(None),
+            Ok(value) => value
+                .trim()
+                .parse()
+                .map(Some)
+                .map_err(|e| Error::Config(format!("invalid {key} '{value}': {e}"))),
+        }
+    }
+}
+
+/// Parse `CERT_FILES`: comma-separated `name=content` pairs such as
+/// `tls.crt=fullchain,tls.key=key`.
+pub fn parse_cert_files(value: &str) -> Result<Vec<CertFile>> {
+    let mut files: Vec<CertFile> = Vec::new();
+    for entry in value.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+        let (name, content) = entry.split_once('=').ok_or_else(|| {
+            Error::Config(format!(
+                "invalid CERT_FILES entry '{entry}': expected name=content"
+            ))
+        })?;
+        let name = name.trim();
+        check_file_name("CERT_FILES", name)?;
+        if files.iter().any(|f| f.name == name) {
+            return Err(Error::Config(format!(
+                "CERT_FILES names '{name}' more than once"
+            )));
+        }
+        let content = match content.trim() {
+            "fullchain" => FileContent::FullChain,
+            "leaf" => FileContent::Leaf,
+            "chain" => FileContent::Chain,
+            "key" => FileContent::Key,
+            "ca" => FileContent::Ca,
+            "combined" => FileContent::Combined,
+            other => {
+                return Err(Error::Config(format!(
+                    "invalid CERT_FILES content '{other}' for {name}: must be fullchain, leaf, \
+                     chain, key, ca, or combined"
+                )))
+            }
+        };
+        files.push(CertFile {
+            name: name.to_string(),
+            content,
+        });
+    }
+    if files.is_empty() {
+        return Err(Error::Config("CERT_FILES names no files".into()));
+    }
+    Ok(files)
+}
+
+/// Parse `KV_SECRETS`: comma-separated `file=path` or `file=path#key`
+/// entries such as `db-password=my-app/db#password`.
+pub fn parse_kv_secrets(value: &str) -> Result<Vec<KvSecret>> {
+    let mut secrets: Vec<KvSecret> = Vec::new();
+    for entry in value.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+        let invalid = || {
+            Error::Config(format!(
+                "invalid KV_SECRETS entry '{entry}': expected file=path or file=path#key"
+            ))
+        };
+        let (file, source) = entry.split_once('=').ok_or_else(invalid)?;
+        let file = file.trim();
+        check_file_name("KV_SECRETS", file)?;
+        if secrets.iter().any(|s| s.file == file) {
+            return Err(Error::Config(format!(
+                "KV_SECRETS names '{file}' more than once"
+            )));
+        }
+        let (path, field) = match source.trim().split_once('#') {
+            Some((_, "")) => return Err(invalid()),
+            Some((path, field)) => (path, Some(field.to_string())),
+            None => (source.trim(), None),
+        };
+        let path = path.trim_matches('/');
+        if path.is_empty() {
+            return Err(invalid());
+        }
+        secrets.push(KvSecret {
+            file: file.to_string(),
+            path: path.to_string(),
+            field,
+        });
+    }
+    Ok(secrets)
+}
+
+/// Output files are named, not given as paths, since the same name is used
+/// in every certificate's directory.
+fn check_file_name(key: &str, name: &str) -> Result<()> {
+    if name.is_empty() || name.contains('/') || name == "." || name == ".." {
+        return Err(Error::Config(format!(
+            "invalid {key} '{name}': must be a file name, not a path"
+        )));
+    }
+    Ok(())
+}
+
+/// A `CERT_SNI_GROUPS` certificate's Secret: `CERT_SECRET_NAME` suffixed
+/// with its common name, with a leading `*.` spelled `wildcard.`.
+fn sni_secret_name(name: &str, common_name: &str) -> String {
+    let common_name = common_name.to_ascii_lowercase();
+    match common_name.strip_prefix("*.") {
+        Some(domain) => format!("{name}-wildcard.{domain}"),
+        None => format!("{name}-{common_name}"),
+    }
+}
+
+/// A `CERT_PROFILES` certificate's Secret: `CERT_SECRET_NAME` suffixed
+/// with the profile name.
+fn profile_secret_name(name: &str, profile: &str) -> String {
+    format!("{name}-{profile}")
+}
+
+/// Whether `name` is valid as the name of most Kubernetes objects: a
+/// lowercase RFC 1123 subdomain of at most 253 characters.
+fn is_dns_subdomain(name: &str) -> bool {
+    name.len() <= 253
+        && name.split('.').all(|label| {
+            !label.is_empty()
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label
+                    .chars()
+                    .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+        })
+}
+
+/// Whether `value` looks like an absolute URI (`scheme:rest`) that fits in
+/// a certificate's URI SAN, which only holds printable ASCII.
+fn is_uri(value: &str) -> bool {
+    let Some((scheme, rest)) = value.split_once(':') else {
+        return false;
+    };
+    scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+        && scheme
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'))
+        && !rest.is_empty()
+        && rest.chars().all(|c| c.is_ascii_graphic())
+}
+
+/// The environment variable `key`, its pod templates filled in as
+/// [`Config::load`] does for the certificate's names.
+pub fn templated_env(key: &str) -> Result<Option<String>> {
+    Vars::default().templated(key)
+}
+
+/// Parse a duration such as `500ms`, `30s`, `15m`, `1h`, or `2d`.
+/// A bare number is interpreted as seconds.
+pub fn parse_duration(value: &str) -> Result<Duration> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (digits, unit) = value.split_at(split);
+
+    let amount: u64 = digits
+        .parse()
+        .map_err(|_| Error::Config(format!("invalid duration '{value}'")))?;
+
+    let scale: u64 = match unit {
+        "ms" => return Ok(Duration::from_millis(amount)),
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 3_600,
+        "d" => 86_400,
+        _ => {
+            return Err(Error::Config(format!(
+                "invalid duration '{value}': unit must be one of ms, s, m, h, d"
+            )))
+        }
+    };
+
+    let secs = amount
+        .checked_mul(scale)
+        .ok_or_else(|| Error::Config(format!("invalid duration '{value}': too large")))?;
+    Ok(Duration::from_secs(secs))
+}
+
+/// Bytes per second, with an optional `kB`, `MB`, `GB`, `KiB`, `MiB`, or
+/// `GiB` unit; `0` is `None`, no limit.
+fn parse_rate(value: &str) -> Result<Option<u64>> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (digits, unit) = value.split_at(split);
+
+    let amount: u64 = digits
+        .parse()
+        .map_err(|_| Error::Config(format!("invalid rate '{value}'")))?;
+
+    let scale: u64 = match unit.trim_end_matches("/s") {
+        "" | "B" => 1,
+        "kB" => 1_000,
+        "MB" => 1_000_000,
+        "GB" => 1_000_000_000,
+        "KiB" => 1 << 10,
+        "MiB" => 1 << 20,
+        "GiB" => 1 << 30,
+        _ => {
+            return Err(Error::Config(format!(
+                "invalid rate '{value}': unit must be one of B, kB, MB, GB, KiB, MiB, GiB"
+            )))
+        }
+    };
 
-        Ok(Config {
-            vault_addr,
-            vault_auth_role,
-            vault_auth_mount,
-            vault_pki_role,
-            vault_pki_mount,
-            vault_namespace,
-            vault_cacert,
-            cert_alt_names,
-            cert_ip_sans,
-            cert_ttl,
-            cert_dir,
-            cert_common_name,
-            listen_addr,
-            backend_addr,
-            renewal_threshold,
-            log_format,
-        })
-    }
-}
-
-fn required_env(key: &str) -> Result<String> {
-    env::var(key).map_err(|_| Error::Config(format!("required environment variable {key} is not set")))
+    let rate = amount
+        .checked_mul(scale)
+        .ok_or_else(|| Error::Config(format!("invalid rate '{value}': too large")))?;
+    Ok(Some(rate).filter(|&r| r > 0))
 }
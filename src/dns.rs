@@ -0,0 +1,92 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use hickory_resolver::config::{NameServerConfig, Protocol, ResolverConfig, ResolverOpts};
+use hickory_resolver::system_conf::read_system_conf;
+use hickory_resolver::TokioAsyncResolver;
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+
+use crate::config::Config;
+use crate::error::{Error, Result};
+
+/// In-process DNS resolver shared by the Vault client and the proxy.
+///
+/// Resolving through hickory instead of the container's glibc resolver gives
+/// us our own nameserver list, timeouts, and a positive-answer cache, so a
+/// degraded node-local DNS cache does not take renewals or proxying down.
+pub struct Resolver {
+    inner: TokioAsyncResolver,
+}
+
+impl Resolver {
+    pub fn from_config(config: &Config) -> Result<Arc<Self>> {
+        let (resolver_config, mut opts) = if config.dns_nameservers.is_empty() {
+            read_system_conf().map_err(|e| {
+                Error::Config(format!("failed to read system DNS configuration: {e}"))
+            })?
+        } else {
+            let mut resolver_config = ResolverConfig::new();
+            for addr in &config.dns_nameservers {
+                resolver_config.add_name_server(NameServerConfig::new(*addr, Protocol::Udp));
+                resolver_config.add_name_server(NameServerConfig::new(*addr, Protocol::Tcp));
+            }
+            (resolver_config, ResolverOpts::default())
+        };
+
+        opts.timeout = config.dns_timeout;
+        if config.dns_cache_min_ttl.is_some() {
+            opts.positive_min_ttl = config.dns_cache_min_ttl;
+        }
+        if config.dns_cache_max_ttl.is_some() {
+            opts.positive_max_ttl = config.dns_cache_max_ttl;
+        }
+
+        Ok(Arc::new(Self {
+            inner: TokioAsyncResolver::tokio(resolver_config, opts),
+        }))
+    }
+
+    /// Resolve a `host:port` address. IP literals are returned without a
+    /// query.
+    pub async fn lookup(&self, addr: &str) -> Result<Vec<SocketAddr>> {
+        if let Ok(addr) = addr.parse::<SocketAddr>() {
+            return Ok(vec![addr]);
+        }
+
+        let (host, port) = split_host_port(addr)?;
+        let ips = self
+            .inner
+            .lookup_ip(host)
+            .await
+            .map_err(|e| Error::Dns(format!("failed to resolve {host}: {e}")))?;
+
+        Ok(ips.iter().map(|ip| SocketAddr::new(ip, port)).collect())
+    }
+}
+
+impl Resolve for Resolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let resolver = self.inner.clone();
+        Box::pin(async move {
+            let ips = resolver.lookup_ip(name.as_str()).await?;
+            // reqwest replaces the port with the one from the URL.
+            let addrs: Addrs = Box::new(ips.into_iter().map(|ip| SocketAddr::new(ip, 0)));
+            Ok(addrs)
+        })
+    }
+}
+
+/// Split `host:port` (or `[v6]:port`) into its parts.
+pub fn split_host_port(addr: &str) -> Result<(&str, u16)> {
+    let invalid = || Error::Config(format!("invalid address '{addr}': expected host:port"));
+
+    let (host, port) = addr.rsplit_once(':').ok_or_else(invalid)?;
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    let port = port.parse().map_err(|_| invalid())?;
+
+    if host.is_empty() {
+        return Err(invalid());
+    }
+
+    Ok((host, port))
+}
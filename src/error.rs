@@ -11,14 +11,68 @@ pub enum Error {
     #[error("vault PKI request failed: {0}")]
     VaultPki(String),
 
+    #[error("vault response unwrapping failed: {0}")]
+    VaultUnwrap(String),
+
+    #[error("vault KV request failed: {0}")]
+    VaultKv(String),
+
+    #[error("vault unavailable: {0}")]
+    VaultUnavailable(String),
+
     #[error("TLS error: {0}")]
     Tls(String),
 
     #[error("certificate parse error: {0}")]
     CertParse(String),
 
+    #[error("key generation failed: {0}")]
+    KeyGen(String),
+
+    #[error("keystore encoding failed: {0}")]
+    Keystore(String),
+
+    #[error("private key encryption failed: {0}")]
+    KeyEncryption(String),
+
+    #[error("certificate validation failed: {0}")]
+    CertValidation(String),
+
+    #[error("certificate expires soon and could not be renewed: {0}")]
+    Expiring(String),
+
+    #[error("failed to bind listener: {0}")]
+    Bind(String),
+
+    #[error("DNS resolution failed: {0}")]
+    Dns(String),
+
+    #[error("backend error: {0}")]
+    Backend(String),
+
+    #[error("ACME error: {0}")]
+    Acme(String),
+
+    #[error("ACME certificate order failed: {0}")]
+    AcmeOrder(String),
+
+    #[error("Kubernetes API request failed: {0}")]
+    Kubernetes(String),
+
+    #[error("Kubernetes certificate signing request failed: {0}")]
+    KubernetesCsr(String),
+
+    #[error("certificate command failed: {0}")]
+    ExecIssuer(String),
+
+    #[error("leader election error: {0}")]
+    LeaderElection(String),
+
+    #[error("request timed out: {0}")]
+    Timeout(String),
+
     #[error("HTTP request error: {0}")]
-    Http(#[from] reqwest::Error),
+    Http(reqwest::Error),
 
     #[error("IO error: {0}")]
     Io(#[from] io::Error),
@@ -27,4 +81,91 @@ pub enum Error {
     Json(#[from] serde_json::Error),
 }
 
+impl Error {
+    /// Process exit code for this error class, so supervisors can tell a
+    /// misconfiguration apart from a Vault or network problem.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::Config(_) => 2,
+            Error::VaultAuth(_) => 3,
+            Error::VaultPki(_)
+            | Error::VaultUnwrap(_)
+            | Error::VaultKv(_)
+            | Error::CertParse(_)
+            | Error::KeyGen(_)
+            | Error::Keystore(_)
+            | Error::KeyEncryption(_)
+            | Error::CertValidation(_)
+            | Error::AcmeOrder(_)
+            | Error::KubernetesCsr(_)
+            | Error::ExecIssuer(_)
+            | Error::Tls(_) => 4,
+            Error::Bind(_) => 5,
+            Error::Expiring(_) => 7,
+            Error::Dns(_)
+            | Error::Backend(_)
+            | Error::Acme(_)
+            | Error::Kubernetes(_)
+            | Error::LeaderElection(_)
+            | Error::VaultUnavailable(_)
+            | Error::Timeout(_)
+            | Error::Http(_)
+            | Error::Io(_)
+            | Error::Json(_) => 1,
+        }
+    }
+
+    /// Stable identifier for this error class, included in logs and API
+    /// responses so alerts and runbooks can match on it instead of the
+    /// message text. Codes are never reused or renumbered.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::Config(_) => "CK-CFG-001",
+            Error::VaultAuth(_) => "CK-AUTH-001",
+            Error::VaultPki(_) => "CK-PKI-001",
+            Error::VaultUnwrap(_) => "CK-PKI-002",
+            Error::VaultKv(_) => "CK-KV-001",
+            Error::Tls(_) => "CK-TLS-001",
+            Error::CertParse(_) => "CK-CERT-001",
+            Error::KeyGen(_) => "CK-CERT-002",
+            Error::Keystore(_) => "CK-CERT-003",
+            Error::KeyEncryption(_) => "CK-CERT-004",
+            Error::Expiring(_) => "CK-CERT-005",
+            Error::CertValidation(_) => "CK-CERT-006",
+            Error::Bind(_) => "CK-NET-001",
+            Error::Dns(_) => "CK-NET-002",
+            Error::Backend(_) => "CK-NET-003",
+            Error::VaultUnavailable(_) => "CK-NET-004",
+            Error::Timeout(_) => "CK-NET-005",
+            Error::Acme(_) => "CK-ACME-001",
+            Error::AcmeOrder(_) => "CK-ACME-002",
+            Error::Kubernetes(_) => "CK-K8S-001",
+            Error::KubernetesCsr(_) => "CK-K8S-002",
+            Error::LeaderElection(_) => "CK-K8S-003",
+            Error::ExecIssuer(_) => "CK-EXEC-001",
+            Error::Http(_) => "CK-HTTP-001",
+            Error::Io(_) => "CK-IO-001",
+            Error::Json(_) => "CK-JSON-001",
+        }
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    /// Timeouts get their own class, so a hung server can be told apart
+    /// from one that refused or failed the request.
+    fn from(e: reqwest::Error) -> Self {
+        if !e.is_timeout() {
+            return Error::Http(e);
+        }
+        match e.url() {
+            Some(url) => Error::Timeout(format!(
+                "no response from {}{} in time",
+                url.host_str().unwrap_or_default(),
+                url.path()
+            )),
+            None => Error::Timeout(e.to_string()),
+        }
+    }
+}
+
 pub type Result<T> = std::result::Result<T, Error>;
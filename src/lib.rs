@@ -0,0 +1,40 @@
+//! Vault-backed certificate issuance and rotation for rustls servers.
+//!
+//! This is the library behind the cert-keeper sidecar, for embedding the
+//! rotation in a program's own server instead. Build a [`Config`] with
+//! [`ConfigBuilder`], create a [`CertManager`] with a
+//! [`tokio::sync::watch`] sender, and call [`CertManager::init`] to issue
+//! the certificates. Then spawn [`CertManager::run_renewal_loop`]: every
+//! renewal sends a fresh [`rustls::ServerConfig`] on the channel, so an
+//! accept loop that reads the receiver for each connection picks up new
+//! certificates without a restart. To keep a `ServerConfig` of your own,
+//! with your own client authentication and ALPN, plug in
+//! [`CertManager::resolver`] as its certificate resolver instead.
+//!
+//! To issue from a CA of your own rather than Vault, implement [`Issuer`]
+//! and pass it to [`CertManager::new`].
+//!
+//! [`CertManager`] writes each certificate through a [`CertStore`] as the
+//! sidecar does; set `CERT_OUTPUT` to `kubernetes-secret` alone, or leave
+//! `CERT_DIR` pointing somewhere private, if nothing else reads the files.
+//!
+//! Settings are named and validated exactly like the sidecar's environment
+//! variables, which are the stable interface. Module paths below the
+//! re-exports may change between minor versions.
+
+#[cfg(feature = "acme")]
+pub mod acme;
+pub mod cert;
+pub mod config;
+pub mod dns;
+pub mod error;
+pub mod metrics;
+pub mod vault;
+
+pub use cert::issuer::{CertBundle, CertRequest, Issuer};
+pub use cert::manager::{CertManager, ManagerCommand};
+pub use cert::resolver::CertResolver;
+pub use cert::store::CertStore;
+pub use config::{Config, ConfigBuilder};
+pub use error::{Error, Result};
+pub use vault::client::VaultClient;
@@ -0,0 +1,168 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use tracing::{info, warn};
+use tracing_subscriber::layer::{Layered, SubscriberExt};
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{fmt, reload, EnvFilter, Layer, Registry};
+
+use cert_keeper::config::LogFormat;
+use cert_keeper::error::{Error, Result};
+
+use crate::telemetry::Telemetry;
+
+type Filtered = Layered<reload::Layer<EnvFilter, Registry>, Registry>;
+type FormatLayer = Box<dyn Layer<Filtered> + Send + Sync>;
+
+/// Handles for changing the log filter and format of the running process.
+///
+/// Lets operators turn on debug logging on a misbehaving sidecar without a
+/// restart that would clear the state they are trying to look at.
+pub struct LogControl {
+    filter: reload::Handle<EnvFilter, Registry>,
+    format: reload::Handle<FormatLayer, Filtered>,
+    /// What turning debug logging off returns to.
+    initial_filter: Mutex<String>,
+    /// `RUST_LOG` is set, and overrides `LOG_LEVEL`.
+    from_env: bool,
+    debug: AtomicBool,
+}
+
+/// Install the global subscriber and return its runtime controls.
+///
+/// The filter is `RUST_LOG` if set, else `level`. With `telemetry`, spans
+/// are also exported; the log filter applies to them too.
+pub fn init(format: &LogFormat, level: &str, telemetry: Option<&Telemetry>) -> Arc<LogControl> {
+    let from_env = EnvFilter::try_from_default_env().ok();
+    let rust_log = from_env.is_some();
+    let filter = from_env.unwrap_or_else(|| EnvFilter::new(level));
+    let initial_filter = filter.to_string();
+
+    let (filter_layer, filter) = reload::Layer::new(filter);
+    let (format_layer, format) = reload::Layer::new(format_layer(format));
+
+    tracing_subscriber::registry()
+        .with(filter_layer)
+        .with(format_layer)
+        .with(telemetry.map(|telemetry| telemetry.layer()))
+        .init();
+
+    Arc::new(LogControl {
+        filter,
+        format,
+        initial_filter: Mutex::new(initial_filter),
+        from_env: rust_log,
+        debug: AtomicBool::new(false),
+    })
+}
+
+impl LogControl {
+    /// Replace the filter with an `EnvFilter` directive string such as
+    /// `debug` or `info,cert_keeper::proxy=trace`.
+    pub fn set_filter(&self, directives: &str) -> Result<()> {
+        let filter = EnvFilter::try_new(directives)
+            .map_err(|e| Error::Config(format!("invalid log filter '{directives}': {e}")))?;
+        self.filter
+            .reload(filter)
+            .map_err(|e| Error::Config(format!("failed to reload log filter: {e}")))?;
+        info!(filter = directives, "log filter changed");
+        Ok(())
+    }
+
+    pub fn set_format(&self, format: &LogFormat) -> Result<()> {
+        self.format
+            .reload(format_layer(format))
+            .map_err(|e| Error::Config(format!("failed to reload log format: {e}")))?;
+        info!(format = ?format, "log format changed");
+        Ok(())
+    }
+
+    /// Switch between `debug` and the filter the process started with.
+    pub fn toggle_debug(&self) -> Result<()> {
+        let debug = !self.debug.fetch_xor(true, Ordering::SeqCst);
+        if debug {
+            self.set_filter("debug")
+        } else {
+            let initial = self.initial_filter().clone();
+            self.set_filter(&initial)
+        }
+    }
+
+    /// Apply a changed `LOG_LEVEL`, unless `RUST_LOG` overrides it. Debug
+    /// logging turned on by signal is turned off.
+    pub fn set_level(&self, level: &str) -> Result<()> {
+        if self.from_env {
+            warn!("RUST_LOG is set, ignoring the new LOG_LEVEL");
+            return Ok(());
+        }
+        *self.initial_filter() = level.to_string();
+        self.debug.store(false, Ordering::SeqCst);
+        self.set_filter(level)
+    }
+
+    fn initial_filter(&self) -> MutexGuard<'_, String> {
+        self.initial_filter
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Apply `filter=` and `format=` lines from a control file.
+    pub fn apply_file(&self, path: &str) -> Result<()> {
+        let contents = std::fs::read_to_string(path)?;
+        self.apply(&contents)
+    }
+
+    /// Apply `filter=` and `format=` lines, in the control file syntax.
+    pub fn apply(&self, settings: &str) -> Result<()> {
+        for line in settings.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            match line.split_once('=') {
+                Some(("filter", value)) => self.set_filter(value.trim())?,
+                Some(("format", value)) => {
+                    let format = LogFormat::from_name(value.trim()).ok_or_else(|| {
+                        Error::Config(format!("invalid log format '{}'", value.trim()))
+                    })?;
+                    self.set_format(&format)?;
+                }
+                _ => warn!(line, "ignoring unrecognised log control line"),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Handle SIGUSR2 for the lifetime of the process.
+///
+/// If `control_file` exists its settings are applied; otherwise the signal
+/// toggles debug logging.
+pub async fn handle_signals(control: Arc<LogControl>, control_file: Option<String>) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut sigusr2 = match signal(SignalKind::user_defined2()) {
+        Ok(s) => s,
+        Err(e) => {
+            warn!(error = %e, "failed to register SIGUSR2 handler, runtime log control disabled");
+            return;
+        }
+    };
+
+    while sigusr2.recv().await.is_some() {
+        let result = match control_file.as_deref() {
+            Some(path) if std::path::Path::new(path).exists() => control.apply_file(path),
+            _ => control.toggle_debug(),
+        };
+        if let Err(e) = result {
+            warn!(error = %e, code = e.code(), "failed to apply log settings");
+        }
+    }
+}
+
+fn format_layer(format: &LogFormat) -> FormatLayer {
+    let layer = fmt::layer().with_target(false);
+    match format {
+        LogFormat::Json => layer.json().boxed(),
+        LogFormat::Pretty => layer.boxed(),
+    }
+}
@@ -1,90 +1,915 @@
-mod cert;
-mod config;
-mod error;
+#[cfg(feature = "admin")]
+mod admin;
+mod cli;
+mod logging;
+mod memlock;
+mod panic;
+mod pidfile;
+mod probe;
 mod proxy;
-mod vault;
+mod reload;
+mod supervisor;
+mod systemd;
+mod telemetry;
 
+use std::net::SocketAddr;
+use std::path::PathBuf;
 use std::sync::Arc;
 
+use clap::{CommandFactory, Parser};
 use rustls::ServerConfig;
-use tokio::sync::watch;
-use tracing::{error, info};
-use tracing_subscriber::EnvFilter;
+use tokio::sync::{mpsc, watch};
+use tokio_rustls::TlsConnector;
+use tracing::{error, info, info_span, warn, Instrument};
 
-use crate::cert::manager::CertManager;
-use crate::config::{Config, LogFormat};
-use crate::vault::client::VaultClient;
+#[cfg(feature = "acme")]
+use cert_keeper::acme::{self, server::AcmeServer};
+use cert_keeper::cert::audit::AuditLog;
+use cert_keeper::cert::issuer;
+#[cfg(feature = "leader-election")]
+use cert_keeper::cert::leader::Elector;
+use cert_keeper::cert::manager::{CertManager, ManagerCommand};
+use cert_keeper::cert::resolver::AlpnChallenges;
+use cert_keeper::cert::webhook::Webhook;
+use cert_keeper::config::{
+    AcmeChallenge, BackendHealthCheck, CertIssuer, Config, ExtraListener, Mode, StoreMode,
+};
+use cert_keeper::dns::Resolver;
+use cert_keeper::error;
+use cert_keeper::vault::client::VaultClient;
+use cert_keeper::vault::kv;
+use cert_keeper::vault::trust_bundle;
+
+#[cfg(feature = "admin")]
+use crate::admin::AdminServer;
+use crate::cli::{Cli, Command};
+use crate::logging::LogControl;
+use crate::pidfile::PidFile;
+use crate::probe::ProbeFiles;
+use crate::proxy::forwarder::Backend;
+use crate::proxy::listener::Listener;
+use crate::supervisor::Supervisor;
 
 #[tokio::main]
 async fn main() {
-    let config = match Config::from_env() {
+    let cli = Cli::parse();
+
+    // Both ring and aws-lc-rs end up enabled through the dependency graph,
+    // so rustls cannot pick a provider on its own.
+    let _ = rustls::crypto::ring::default_provider().install_default();
+
+    // These work on files alone and need no configuration.
+    let command = match cli.command.unwrap_or(Command::Run) {
+        Command::Inspect(args) => exit_offline(cli::inspect::run(args)),
+        Command::Verify(args) => exit_offline(cli::verify::run(args)),
+        Command::Probe(args) => exit_offline(cli::probe::run(args)),
+        #[cfg(feature = "dev-vault")]
+        Command::DevVault(args) => exit_offline(cli::dev_vault::run(args).await),
+        command => command,
+    };
+    if cli.dry_run && !matches!(command, Command::Run) {
+        Cli::command()
+            .error(
+                clap::error::ErrorKind::ArgumentConflict,
+                "--dry-run only applies to run",
+            )
+            .exit();
+    }
+
+    let config = match Config::load(cli.config_file.as_deref()) {
         Ok(c) => c,
         Err(e) => {
-            eprintln!("fatal: {e}");
-            std::process::exit(1);
+            eprintln!("fatal[{}]: {e}", e.code());
+            std::process::exit(e.exit_code());
+        }
+    };
+    // An init container can share the sidecar's settings and only set MODE.
+    let command = match command {
+        Command::Run if config.mode == Mode::Oneshot && !cli.dry_run => Command::Fetch,
+        command => command,
+    };
+
+    let telemetry = match telemetry::init(&config) {
+        Ok(t) => t,
+        Err(e) => {
+            eprintln!("fatal[{}]: {e}", e.code());
+            std::process::exit(e.exit_code());
         }
     };
+    let log_control = logging::init(&config.log_format, &config.log_level, telemetry.as_ref());
+    panic::install_hook();
+
+    if cli.dry_run || !matches!(command, Command::Run) {
+        let (name, result) = match command {
+            Command::Fetch => ("fetch", cli::fetch::run(config).await),
+            Command::Check(args) => ("check", cli::check::run(config, args).await),
+            _ => ("dry run", cli::dry_run::run(config).await),
+        };
+        if let Some(telemetry) = telemetry {
+            telemetry.shutdown();
+        }
+        if let Err(e) = result {
+            error!(error = %e, code = e.code(), "{name} failed");
+            std::process::exit(e.exit_code());
+        }
+        return;
+    }
+
+    tokio::spawn(logging::handle_signals(
+        log_control.clone(),
+        config.log_control_file.clone(),
+    ));
 
-    init_logging(&config.log_format);
     info!(
         listen = %config.listen_addr,
-        backend = %config.backend_addr,
+        backend = %config.backend_addrs.join(","),
         cert_dir = %config.cert_dir,
         "cert-keeper starting"
     );
+    if let Some(ref endpoint) = config.otel_endpoint {
+        info!(endpoint, "exporting spans over OTLP");
+    }
+    if config.store_mode == StoreMode::Memory {
+        memlock::protect();
+    }
+
+    let pid_file = match config.pid_file.as_deref().map(PidFile::create).transpose() {
+        Ok(p) => p,
+        Err(e) => {
+            error!(error = %e, code = e.code(), "cert-keeper exited with error");
+            std::process::exit(e.exit_code());
+        }
+    };
+
+    let result = run(config, cli.config_file, log_control).await;
+    drop(pid_file);
+    // Before exiting, which would drop the spans still queued.
+    if let Some(telemetry) = telemetry {
+        telemetry.shutdown();
+    }
+
+    if let Err(e) = result {
+        error!(error = %e, code = e.code(), exit_code = e.exit_code(), "cert-keeper exited with error");
+        std::process::exit(e.exit_code());
+    }
+}
 
-    if let Err(e) = run(config).await {
-        error!(error = %e, "cert-keeper exited with error");
+/// Report the result of a command that does not need the configuration.
+fn exit_offline(result: error::Result<()>) -> ! {
+    if let Err(e) = result {
+        eprintln!("error[{}]: {e}", e.code());
         std::process::exit(1);
     }
+    std::process::exit(0);
 }
 
-async fn run(config: Config) -> error::Result<()> {
-    let client = Arc::new(VaultClient::new(&config)?);
+async fn run(
+    config: Config,
+    config_file: Option<PathBuf>,
+    log_control: Arc<LogControl>,
+) -> error::Result<()> {
+    let resolver = Resolver::from_config(&config)?;
+    let client = Arc::new(VaultClient::new(&config, resolver.clone())?);
 
     // Watch channel for broadcasting TLS server config updates.
     let (identity_tx, identity_rx) = watch::channel::<Option<Arc<ServerConfig>>>(None);
 
-    // Shutdown signal channel.
+    // Shutdown signal channels: the proxy stops on the first, and the
+    // tasks that keep the certificates fresh on the second, up to
+    // SHUTDOWN_GRACE later.
     let (shutdown_tx, shutdown_rx) = watch::channel(false);
+    let (renewal_shutdown_tx, renewal_shutdown_rx) = watch::channel(false);
+    let mut supervisor = Supervisor::new(&config, shutdown_rx.clone());
+
+    // The settings that can change at runtime, as of the last config file
+    // reload.
+    let (settings_tx, settings_rx) = watch::channel(Arc::new(config.clone()));
+
+    // The proxy answers tls-alpn-01 challenges, so it has to be up before
+    // the first certificate is ordered.
+    let alpn_challenges = (config.cert_issuer == CertIssuer::Acme
+        && config.acme_issuer_challenge == AcmeChallenge::TlsAlpn01)
+        .then(AlpnChallenges::default);
+    let issuer = issuer::from_config(
+        &config,
+        client.clone(),
+        resolver.clone(),
+        alpn_challenges.clone(),
+    )?;
+
+    let probe_files = (config.ready_file.is_some() || config.healthy_file.is_some())
+        .then(|| ProbeFiles::new(&config));
 
     // Initial authentication and certificate fetch.
-    let manager = CertManager::new(client.clone(), config.clone(), identity_tx);
-    let initial_lease = manager.init().await?;
+    let webhook = Webhook::new(&config, resolver.clone())?;
+    let audit = AuditLog::new(&config)?;
+    let mut manager = CertManager::new(
+        client.clone(),
+        issuer,
+        config.clone(),
+        identity_tx,
+        webhook,
+        audit,
+    );
+    if config.leader_election {
+        let leader =
+            spawn_leader_election(&config, renewal_shutdown_rx.clone(), &mut supervisor).await?;
+        manager.follow_leader(leader);
+    }
+    // Certificates from a previous run, or self-signed ones, are served
+    // while the first ones are issued in the background; a holding page
+    // answers until the first one is served.
+    let serving = manager.warm_start().await || manager.bootstrap().await;
+    let early_proxy =
+        alpn_challenges.is_some() || config.holding_page || (serving && config.proxy_enabled);
+    let mut backend_ready = None;
+    if early_proxy {
+        backend_ready = Some(spawn_proxy(
+            settings_rx.clone(),
+            &client,
+            resolver.clone(),
+            &manager,
+            alpn_challenges,
+            identity_rx.clone(),
+            &mut supervisor,
+        )
+        .await?);
+    }
+    if !serving {
+        manager.init().instrument(info_span!("init")).await?;
+    }
 
-    // Spawn certificate renewal loop.
-    let renewal_shutdown = shutdown_rx.clone();
-    let renewal_handle = tokio::spawn(async move {
-        manager.run_renewal_loop(initial_lease, renewal_shutdown).await;
-    });
+    if !config.proxy_enabled {
+        info!("TLS proxy disabled, only keeping the certificate files up to date");
+    } else if !early_proxy {
+        backend_ready = Some(spawn_proxy(
+            settings_rx.clone(),
+            &client,
+            resolver.clone(),
+            &manager,
+            None,
+            identity_rx.clone(),
+            &mut supervisor,
+        )
+        .await?);
+    }
 
-    // Spawn TLS proxy.
-    let proxy_shutdown = shutdown_rx.clone();
-    let proxy_handle = tokio::spawn(async move {
-        if let Err(e) = proxy::tls_acceptor::run(
-            config.listen_addr,
-            config.backend_addr,
-            identity_rx,
-            proxy_shutdown,
+    // Without a proxy, or a health check, there is no backend to wait on.
+    let backend_ready = backend_ready.unwrap_or_else(|| watch::channel(true).1);
+
+    // Only once the proxy, if any, is listening.
+    if let Some(files) = probe_files {
+        let probe = files.run(manager.status(), backend_ready.clone(), supervisor.shutdown());
+        supervisor.spawn_once("probe", info_span!("probe"), async move {
+            probe.await;
+            Ok(())
+        });
+    }
+
+    if let (Some(path), Some(interval)) = (config_file, config.config_reload_interval) {
+        tokio::spawn(reload::run(
+            path,
+            interval,
+            settings_tx,
+            log_control.clone(),
+            shutdown_rx.clone(),
+        ));
+        tokio::spawn(reload_tls_policy(settings_rx.clone(), manager.commands()));
+    }
+
+    if let Some(addr) = config.admin_listen_addr {
+        let grpc_health = config.admin_grpc_health.then(|| backend_ready.clone());
+        spawn_admin(addr, client.clone(), &manager, log_control, grpc_health, &mut supervisor)
+            .await?;
+    }
+
+    tokio::spawn(renew_on_sighup(manager.commands()));
+
+    if !config.kv_secrets.is_empty() {
+        tokio::spawn(kv::run(
+            client.clone(),
+            config.clone(),
+            manager.issued_key(),
+            renewal_shutdown_rx.clone(),
+        ));
+    }
+
+    if config.trust_bundle_file.is_some() {
+        tokio::spawn(trust_bundle::run(
+            client.clone(),
+            config.clone(),
+            manager.issued_key(),
+            renewal_shutdown_rx.clone(),
+        ));
+    }
+
+    // Spawn certificate renewal loop. It cannot be restarted, and only
+    // fails on a certificate about to expire.
+    supervisor.spawn_once(
+        "renewal",
+        info_span!("renewal"),
+        manager.run_renewal_loop(renewal_shutdown_rx.clone()),
+    );
+
+    // Spawn the ACME server if enabled.
+    if config.acme_enabled {
+        spawn_acme(
+            &config,
+            client.clone(),
+            resolver.clone(),
+            identity_rx.clone(),
+            &mut supervisor,
         )
-        .await
-        {
-            error!(error = %e, "TLS proxy failed");
-        }
-    });
+        .await?;
+    }
+
+    if let Some(interval) = systemd::watchdog_interval() {
+        let watchdog = systemd::watchdog(interval, renewal_shutdown_rx.clone());
+        supervisor.spawn_once("watchdog", info_span!("watchdog"), async move {
+            watchdog.await;
+            Ok(())
+        });
+    }
+    systemd::notify("READY=1");
 
-    // Wait for shutdown signal.
-    shutdown_signal().await;
-    info!("shutdown signal received, stopping...");
+    // Wait for shutdown signal, or for a task to fail for good.
+    let mut result = tokio::select! {
+        _ = shutdown_signal() => {
+            info!("shutdown signal received, stopping...");
+            Ok(())
+        }
+        (task, e) = supervisor.failed() => {
+            error!(task, code = e.code(), "{task} task failed, stopping...");
+            Err(e)
+        }
+    };
+    systemd::notify("STOPPING=1");
     let _ = shutdown_tx.send(true);
 
+    // Containers sharing the certificate files may still be using them
+    // while they shut down, so renewals outlast the proxy.
+    if result.is_ok() && !config.shutdown_grace.is_zero() {
+        info!(
+            grace_secs = config.shutdown_grace.as_secs(),
+            "proxy stopped, keeping certificates renewed for SHUTDOWN_GRACE"
+        );
+        tokio::select! {
+            _ = tokio::time::sleep(config.shutdown_grace) => {}
+            _ = shutdown_signal() => info!("shutdown signal received again, stopping renewals"),
+            (task, e) = supervisor.failed() => {
+                error!(task, code = e.code(), "{task} task failed, stopping renewals");
+                result = Err(e);
+            }
+        }
+    }
+    let _ = renewal_shutdown_tx.send(true);
+
     // Wait for tasks to finish.
-    let _ = tokio::join!(renewal_handle, proxy_handle);
+    supervisor.join().await;
     info!("cert-keeper stopped");
 
+    result
+}
+
+/// Start the TLS proxy on `LISTEN_ADDR` and each of `LISTENERS`, with the
+/// listeners that share its backend. With `alpn_challenges` the proxy on
+/// `LISTEN_ADDR` also answers `tls-alpn-01`. Returns the readiness of the
+/// backend of `LISTEN_ADDR`.
+async fn spawn_proxy(
+    settings: watch::Receiver<Arc<Config>>,
+    client: &Arc<VaultClient>,
+    resolver: Arc<Resolver>,
+    manager: &CertManager,
+    alpn_challenges: Option<AlpnChallenges>,
+    identity_rx: watch::Receiver<Option<Arc<ServerConfig>>>,
+    supervisor: &mut Supervisor,
+) -> error::Result<watch::Receiver<bool>> {
+    let config = settings.borrow().clone();
+    let backend_tls = proxy::backend_tls::load(client, &config, manager.issued_key()).await?;
+
+    let (backend, backend_rx) = spawn_listener(
+        &settings,
+        None,
+        resolver.clone(),
+        backend_tls.clone(),
+        identity_rx.clone(),
+        alpn_challenges,
+        supervisor,
+    )
+    .await?;
+
+    for listener in &config.listeners {
+        let identity_rx = match &listener.cert_profile {
+            // Config validation already checks the profile exists.
+            Some(profile) => manager
+                .profile_server_config(profile)
+                .expect("listener certificate profile is managed"),
+            None => identity_rx.clone(),
+        };
+        spawn_listener(
+            &settings,
+            Some(listener),
+            resolver.clone(),
+            backend_tls.clone(),
+            identity_rx,
+            None,
+            supervisor,
+        )
+        .await?;
+    }
+
+    if let Some(addr) = config.http_redirect_addr {
+        spawn_redirect(&config, addr, supervisor).await?;
+    }
+    if let Some(addr) = config.dtls_listen_addr {
+        spawn_dtls(&config, addr, client, manager, supervisor).await?;
+    }
+    if let Some(addr) = config.quic_listen_addr {
+        spawn_quic(
+            &config,
+            addr,
+            backend,
+            identity_rx,
+            backend_rx.clone(),
+            settings,
+            supervisor,
+        )?;
+    }
+    Ok(backend_rx)
+}
+
+/// Start the TLS proxy on `listener`, or on `LISTEN_ADDR` with `None`,
+/// and, if configured, the backend health check that gates it. Returns
+/// the backend and its readiness for the listeners that share them.
+async fn spawn_listener(
+    settings: &watch::Receiver<Arc<Config>>,
+    listener: Option<&ExtraListener>,
+    resolver: Arc<Resolver>,
+    backend_tls: Option<TlsConnector>,
+    identity_rx: watch::Receiver<Option<Arc<ServerConfig>>>,
+    alpn_challenges: Option<AlpnChallenges>,
+    supervisor: &mut Supervisor,
+) -> error::Result<(watch::Receiver<Arc<Backend>>, watch::Receiver<bool>)> {
+    let settings = listener_settings(settings, listener);
+    let config = settings.borrow().as_ref().clone();
+    let span = match listener {
+        Some(listener) => info_span!("proxy", listener = %listener.name),
+        None => info_span!("proxy"),
+    };
+
+    // Bind before spawning so a bind failure ends the process with its own exit code.
+    let sockets =
+        Listener::bind(&config.listen_addr, &config.tcp, config.acceptor_threads).await?;
+    // Only a socket from systemd can turn out not to be TCP here.
+    if matches!(sockets[0], Listener::Unix(_))
+        && (!config.allow_cidrs.is_empty() || !config.deny_cidrs.is_empty())
+    {
+        return Err(error::Error::Config(format!(
+            "ALLOW_CIDRS and DENY_CIDRS need a TCP socket, but {} is a Unix socket",
+            config.listen_addr
+        )));
+    }
+
+    // Backend readiness; stays true unless a health check is configured.
+    let gated = config.backend_health_check != BackendHealthCheck::None;
+    let (backend_tx, backend_rx) = watch::channel(!gated);
+    if gated {
+        // Under the listener's span, so its checks can be told apart.
+        span.in_scope(|| spawn_backend_health(&config, resolver.clone(), backend_tx, supervisor));
+    }
+
+    let backend = Backend::new(&config, resolver.clone(), backend_tls.clone())?;
+    let (backend_tx, backend) = watch::channel(Arc::new(backend));
+    let holding = proxy::tls_acceptor::Holding::from_config(&config)?;
+    tokio::spawn(
+        reload_backend(settings.clone(), resolver, backend_tls, backend_tx)
+            .instrument(span.clone()),
+    );
+
+    // A restart binds the address again, as reloaded since, and takes the
+    // other settings as reloaded too.
+    let (task_backend, task_backend_rx) = (backend.clone(), backend_rx.clone());
+    let mut first = Some((sockets, config));
+    supervisor.spawn("proxy", span, move |shutdown| {
+        let (sockets, config) = match first.take() {
+            Some((sockets, config)) => (Some(sockets), config),
+            None => (None, settings.borrow().as_ref().clone()),
+        };
+        let mut options = proxy::tls_acceptor::Options::from_config(&config);
+        options.alpn_challenges = alpn_challenges.clone();
+        options.holding = holding.clone();
+        let (backend, identity_rx) = (task_backend.clone(), identity_rx.clone());
+        let (backend_rx, settings) = (task_backend_rx.clone(), settings.clone());
+        async move {
+            let sockets = match sockets {
+                Some(sockets) => sockets,
+                None => {
+                    Listener::bind(&config.listen_addr, &config.tcp, config.acceptor_threads)
+                        .await?
+                }
+            };
+            proxy::tls_acceptor::run(
+                sockets,
+                backend,
+                identity_rx,
+                backend_rx,
+                options,
+                settings,
+                shutdown,
+            )
+            .await
+        }
+    });
+    Ok((backend, backend_rx))
+}
+
+/// The settings `listener` runs with, or `LISTEN_ADDR` with `None`.
+fn listener_config(config: &Config, listener: Option<&ExtraListener>) -> Config {
+    match listener {
+        // As reloaded; `LISTENERS` itself only changes with a restart.
+        Some(listener) => {
+            let listener = config
+                .listeners
+                .iter()
+                .find(|l| l.name == listener.name)
+                .unwrap_or(listener);
+            config.for_listener(listener)
+        }
+        None => config.clone(),
+    }
+}
+
+/// The settings `listener` runs with, or `LISTEN_ADDR` with `None`, kept
+/// up to date as `settings` are reloaded.
+fn listener_settings(
+    settings: &watch::Receiver<Arc<Config>>,
+    listener: Option<&ExtraListener>,
+) -> watch::Receiver<Arc<Config>> {
+    let Some(listener) = listener.cloned() else {
+        return settings.clone();
+    };
+    let mut settings = settings.clone();
+    let (tx, rx) = watch::channel(Arc::new(listener_config(
+        &settings.borrow_and_update(),
+        Some(&listener),
+    )));
+    tokio::spawn(async move {
+        while settings.changed().await.is_ok() {
+            let config = listener_config(&settings.borrow_and_update(), Some(&listener));
+            if tx.send(Arc::new(config)).is_err() {
+                return;
+            }
+        }
+    });
+    rx
+}
+
+/// Replace the backend of a listener with one built from its reloaded
+/// `settings` whenever they change. Connections already open keep theirs.
+async fn reload_backend(
+    mut settings: watch::Receiver<Arc<Config>>,
+    resolver: Arc<Resolver>,
+    tls: Option<TlsConnector>,
+    backend_tx: watch::Sender<Arc<Backend>>,
+) {
+    while settings.changed().await.is_ok() {
+        let config = settings.borrow_and_update().clone();
+        match Backend::new(&config, resolver.clone(), tls.clone()) {
+            Ok(backend) => {
+                backend_tx.send_replace(Arc::new(backend));
+            }
+            Err(e) => {
+                error!(error = %e, code = e.code(), "failed to apply reloaded backend settings");
+            }
+        }
+    }
+}
+
+/// Have the renewal loop serve with the TLS policy of the reloaded
+/// `settings` whenever it changes, so new handshakes follow it without a
+/// restart.
+async fn reload_tls_policy(
+    mut settings: watch::Receiver<Arc<Config>>,
+    commands: mpsc::Sender<ManagerCommand>,
+) {
+    let policy = |config: &Config| (config.tls_min_version, config.tls_cipher_suites.clone());
+    let mut current = policy(&settings.borrow_and_update());
+    while settings.changed().await.is_ok() {
+        let reloaded = policy(&settings.borrow_and_update());
+        if reloaded == current {
+            continue;
+        }
+        current = reloaded.clone();
+        let (min_version, cipher_suites) = reloaded;
+        let command = ManagerCommand::SetTlsPolicy(min_version, cipher_suites);
+        if commands.send(command).await.is_err() {
+            return;
+        }
+    }
+}
+
+fn spawn_backend_health(
+    config: &Config,
+    resolver: Arc<Resolver>,
+    backend_tx: watch::Sender<bool>,
+    supervisor: &mut Supervisor,
+) {
+    let health = proxy::health::run(
+        config.backend_health_check.clone(),
+        config.backend_addrs.clone(),
+        resolver,
+        config.backend_health_interval,
+        config.backend_health_timeout,
+        backend_tx,
+        supervisor.shutdown(),
+    );
+    supervisor.spawn_once("health", info_span!("health"), async move {
+        health.await;
+        Ok(())
+    });
+}
+
+#[cfg(feature = "acme")]
+async fn spawn_acme(
+    config: &Config,
+    client: Arc<VaultClient>,
+    resolver: Arc<Resolver>,
+    identity_rx: watch::Receiver<Option<Arc<ServerConfig>>>,
+    supervisor: &mut Supervisor,
+) -> error::Result<()> {
+    let server = Arc::new(AcmeServer::new(client, config.clone(), resolver)?);
+    let addr = config.acme_listen_addr;
+    let mut listener = Some(proxy::tls_acceptor::bind(addr).await?);
+    supervisor.spawn("acme", info_span!("acme"), move |shutdown| {
+        let (server, identity_rx) = (server.clone(), identity_rx.clone());
+        let listener = listener.take();
+        async move {
+            let listener = match listener {
+                Some(listener) => listener,
+                None => proxy::tls_acceptor::bind(addr).await?,
+            };
+            acme::server::run(server, listener, identity_rx, shutdown).await
+        }
+    });
+    Ok(())
+}
+
+/// Config validation already rejects `ACME_ENABLED` in builds without the
+/// feature.
+#[cfg(not(feature = "acme"))]
+async fn spawn_acme(
+    _config: &Config,
+    _client: Arc<VaultClient>,
+    _resolver: Arc<Resolver>,
+    _identity_rx: watch::Receiver<Option<Arc<ServerConfig>>>,
+    _supervisor: &mut Supervisor,
+) -> error::Result<()> {
+    Err(error::Error::Config(
+        "ACME_ENABLED requires a build with the acme feature".into(),
+    ))
+}
+
+/// `grpc_health` is whether the proxy is taking connections, given when
+/// `ADMIN_GRPC_HEALTH` is set; config validation rejects that in builds
+/// without the grpc-health feature.
+#[cfg(feature = "admin")]
+#[cfg_attr(not(feature = "grpc-health"), allow(unused_variables))]
+async fn spawn_admin(
+    addr: SocketAddr,
+    client: Arc<VaultClient>,
+    manager: &CertManager,
+    log_control: Arc<LogControl>,
+    grpc_health: Option<watch::Receiver<bool>>,
+    supervisor: &mut Supervisor,
+) -> error::Result<()> {
+    let server = AdminServer::new(client, manager.status(), manager.commands(), log_control);
+    #[cfg(feature = "grpc-health")]
+    let server = match grpc_health {
+        Some(proxy_ready) => server.with_grpc_health(proxy_ready),
+        None => server,
+    };
+    let server = Arc::new(server);
+    let mut listener = Some(proxy::tls_acceptor::bind(addr).await?);
+    supervisor.spawn("admin", info_span!("admin"), move |shutdown| {
+        let (server, listener) = (server.clone(), listener.take());
+        async move {
+            let listener = match listener {
+                Some(listener) => listener,
+                None => proxy::tls_acceptor::bind(addr).await?,
+            };
+            admin::run(server, listener, shutdown).await
+        }
+    });
     Ok(())
 }
 
+/// Config validation already rejects `ADMIN_LISTEN_ADDR` in builds without
+/// the feature.
+#[cfg(not(feature = "admin"))]
+async fn spawn_admin(
+    _addr: SocketAddr,
+    _client: Arc<VaultClient>,
+    _manager: &CertManager,
+    _log_control: Arc<LogControl>,
+    _grpc_health: Option<watch::Receiver<bool>>,
+    _supervisor: &mut Supervisor,
+) -> error::Result<()> {
+    Err(error::Error::Config(
+        "ADMIN_LISTEN_ADDR requires a build with the admin feature".into(),
+    ))
+}
+
+/// Take part in the leader election for `LEADER_ELECTION_LEASE`, and wait
+/// for the first attempt so nothing is issued before it. Returns whether
+/// this replica leads; the task gives the Lease up on `shutdown`, which
+/// comes no sooner than renewals stop.
+#[cfg(feature = "leader-election")]
+async fn spawn_leader_election(
+    config: &Config,
+    shutdown: watch::Receiver<bool>,
+    supervisor: &mut Supervisor,
+) -> error::Result<watch::Receiver<bool>> {
+    let (leader_tx, mut leader_rx) = watch::channel(false);
+    let election = Elector::new(config).run(leader_tx, shutdown);
+    let span = info_span!("leader_election");
+    supervisor.spawn_once("leader_election", span, async move {
+        election.await;
+        Ok(())
+    });
+    let _ = leader_rx.changed().await;
+    Ok(leader_rx)
+}
+
+/// Config validation already rejects `LEADER_ELECTION` in builds without
+/// the feature.
+#[cfg(not(feature = "leader-election"))]
+async fn spawn_leader_election(
+    _config: &Config,
+    _shutdown: watch::Receiver<bool>,
+    _supervisor: &mut Supervisor,
+) -> error::Result<watch::Receiver<bool>> {
+    Err(error::Error::Config(
+        "LEADER_ELECTION requires a build with the leader-election feature".into(),
+    ))
+}
+
+#[cfg(feature = "http-redirect")]
+async fn spawn_redirect(
+    config: &Config,
+    addr: SocketAddr,
+    supervisor: &mut Supervisor,
+) -> error::Result<()> {
+    let redirect = Arc::new(proxy::redirect::Redirect::from_config(config));
+    let mut listener = Some(proxy::tls_acceptor::bind(addr).await?);
+    supervisor.spawn("redirect", info_span!("redirect"), move |shutdown| {
+        let (redirect, listener) = (redirect.clone(), listener.take());
+        async move {
+            let listener = match listener {
+                Some(listener) => listener,
+                None => proxy::tls_acceptor::bind(addr).await?,
+            };
+            proxy::redirect::run(redirect, listener, shutdown).await
+        }
+    });
+    Ok(())
+}
+
+/// Config validation already rejects `HTTP_REDIRECT_ADDR` in builds
+/// without the feature.
+#[cfg(not(feature = "http-redirect"))]
+async fn spawn_redirect(
+    _config: &Config,
+    _addr: SocketAddr,
+    _supervisor: &mut Supervisor,
+) -> error::Result<()> {
+    Err(error::Error::Config(
+        "HTTP_REDIRECT_ADDR requires a build with the http-redirect feature".into(),
+    ))
+}
+
+#[cfg(feature = "dtls")]
+async fn spawn_dtls(
+    config: &Config,
+    addr: SocketAddr,
+    client: &Arc<VaultClient>,
+    manager: &CertManager,
+    supervisor: &mut Supervisor,
+) -> error::Result<()> {
+    let mut listener = Some(proxy::dtls::bind(addr).await?);
+    let (config, client, issued) = (config.clone(), client.clone(), manager.issued_key());
+    supervisor.spawn("dtls", info_span!("dtls"), move |shutdown| {
+        let listener = listener.take();
+        // The primary certificate's store, which the manager writes first.
+        let store = cert_keeper::cert::store::CertStore::new(&config.cert_dir, &config)
+            .with_secret(config.secret_name(&config.cert_common_name))
+            .with_vault(client.clone());
+        let options = proxy::dtls::Options::from_config(&config);
+        let issued = issued.clone();
+        async move {
+            let listener = match listener {
+                Some(listener) => listener,
+                None => proxy::dtls::bind(addr).await?,
+            };
+            proxy::dtls::run(listener, store, issued, options, shutdown).await
+        }
+    });
+    Ok(())
+}
+
+/// Config validation already rejects `DTLS_LISTEN_ADDR` in builds without
+/// the feature.
+#[cfg(not(feature = "dtls"))]
+async fn spawn_dtls(
+    _config: &Config,
+    _addr: SocketAddr,
+    _client: &Arc<VaultClient>,
+    _manager: &CertManager,
+    _supervisor: &mut Supervisor,
+) -> error::Result<()> {
+    Err(error::Error::Config(
+        "DTLS_LISTEN_ADDR requires a build with the dtls feature".into(),
+    ))
+}
+
+#[cfg(feature = "quic")]
+fn spawn_quic(
+    config: &Config,
+    addr: SocketAddr,
+    backend: watch::Receiver<Arc<Backend>>,
+    identity_rx: watch::Receiver<Option<Arc<ServerConfig>>>,
+    backend_rx: watch::Receiver<bool>,
+    settings: watch::Receiver<Arc<Config>>,
+    supervisor: &mut Supervisor,
+) -> error::Result<()> {
+    let mut endpoint = Some(proxy::quic::bind(addr)?);
+    // The settings of `config`, or as reloaded since for a restart.
+    let mut options = Some(proxy::quic::Options::from_config(config));
+    supervisor.spawn("quic", info_span!("quic"), move |shutdown| {
+        let endpoint = endpoint.take().map_or_else(|| proxy::quic::bind(addr), Ok);
+        let options = options
+            .take()
+            .unwrap_or_else(|| proxy::quic::Options::from_config(&settings.borrow()));
+        let (backend, identity_rx) = (backend.clone(), identity_rx.clone());
+        let (backend_rx, settings) = (backend_rx.clone(), settings.clone());
+        async move {
+            proxy::quic::run(
+                endpoint?,
+                backend,
+                identity_rx,
+                backend_rx,
+                options,
+                settings,
+                shutdown,
+            )
+            .await
+        }
+    });
+    Ok(())
+}
+
+/// Config validation already rejects `QUIC_LISTEN_ADDR` in builds without
+/// the feature.
+#[cfg(not(feature = "quic"))]
+fn spawn_quic(
+    _config: &Config,
+    _addr: SocketAddr,
+    _backend: watch::Receiver<Arc<Backend>>,
+    _identity_rx: watch::Receiver<Option<Arc<ServerConfig>>>,
+    _backend_rx: watch::Receiver<bool>,
+    _settings: watch::Receiver<Arc<Config>>,
+    _supervisor: &mut Supervisor,
+) -> error::Result<()> {
+    Err(error::Error::Config(
+        "QUIC_LISTEN_ADDR requires a build with the quic feature".into(),
+    ))
+}
+
+/// Re-issue every certificate on SIGHUP, for rotating by hand without a
+/// restart. Under systemd it stands for a reload, which ends once the
+/// renewal is queued.
+async fn renew_on_sighup(commands: mpsc::Sender<ManagerCommand>) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut sighup = match signal(SignalKind::hangup()) {
+        Ok(s) => s,
+        Err(e) => {
+            warn!(error = %e, "failed to register SIGHUP handler, manual renewal disabled");
+            return;
+        }
+    };
+
+    while sighup.recv().await.is_some() {
+        info!("SIGHUP received, renewing certificates");
+        // The renewal loop logs the outcome; nobody waits for the reply.
+        systemd::notify_reloading();
+        let (reply, _) = tokio::sync::oneshot::channel();
+        if commands.send(ManagerCommand::Renew(reply)).await.is_err() {
+            return;
+        }
+        systemd::notify("READY=1");
+    }
+}
+
 async fn shutdown_signal() {
     let ctrl_c = tokio::signal::ctrl_c();
 
@@ -104,17 +929,3 @@ async fn shutdown_signal() {
         ctrl_c.await.expect("failed to listen for Ctrl+C");
     }
 }
-
-fn init_logging(format: &LogFormat) {
-    let filter = EnvFilter::try_from_default_env()
-        .unwrap_or_else(|_| EnvFilter::new("info"));
-
-    let subscriber = tracing_subscriber::fmt()
-        .with_env_filter(filter)
-        .with_target(false);
-
-    match format {
-        LogFormat::Json => subscriber.json().init(),
-        LogFormat::Pretty => subscriber.init(),
-    }
-}
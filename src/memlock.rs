@@ -0,0 +1,57 @@
+use std::io;
+
+use tracing::{info, warn};
+
+/// `CAP_IPC_LOCK`, which lifts `RLIMIT_MEMLOCK`.
+const CAP_IPC_LOCK: u32 = 14;
+
+/// Keep the private key off disk for `STORE_MODE=memory`: lock the process
+/// memory so it is never swapped out, and leave it out of core dumps.
+///
+/// Memory is only locked when nothing limits how much can be, since with
+/// `MCL_FUTURE` an allocation over `RLIMIT_MEMLOCK` would fail. Either step
+/// failing is logged and startup carries on.
+pub fn protect() {
+    // Read before the process stops being dumpable, which makes its
+    // `/proc` entries root's.
+    if can_lock_all() {
+        // SAFETY: mlockall takes no pointers and only changes how the
+        // kernel pages this process.
+        if unsafe { libc::mlockall(libc::MCL_CURRENT | libc::MCL_FUTURE) } == 0 {
+            info!("process memory locked, the private key is never swapped out");
+        } else {
+            warn!(error = %io::Error::last_os_error(), "failed to lock process memory, the private key may be swapped out");
+        }
+    } else {
+        warn!("process memory not locked, the private key may be swapped out; grant CAP_IPC_LOCK or lift RLIMIT_MEMLOCK");
+    }
+
+    // SAFETY: PR_SET_DUMPABLE takes integer arguments only.
+    if unsafe { libc::prctl(libc::PR_SET_DUMPABLE, 0, 0, 0, 0) } != 0 {
+        warn!(error = %io::Error::last_os_error(), "failed to exclude the process from core dumps");
+    }
+}
+
+/// Whether the whole process can be locked: with `CAP_IPC_LOCK`, or no
+/// `RLIMIT_MEMLOCK`.
+fn can_lock_all() -> bool {
+    let mut limit = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    // SAFETY: `limit` is a valid rlimit for getrlimit to fill in.
+    let unlimited = unsafe { libc::getrlimit(libc::RLIMIT_MEMLOCK, &mut limit) } == 0
+        && limit.rlim_cur == libc::RLIM_INFINITY;
+    unlimited || has_ipc_lock()
+}
+
+fn has_ipc_lock() -> bool {
+    let Ok(status) = std::fs::read_to_string("/proc/self/status") else {
+        return false;
+    };
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix("CapEff:"))
+        .and_then(|caps| u64::from_str_radix(caps.trim(), 16).ok())
+        .is_some_and(|caps| caps & (1 << CAP_IPC_LOCK) != 0)
+}
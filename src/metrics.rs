@@ -0,0 +1,134 @@
+#[cfg(feature = "metrics")]
+use std::collections::BTreeMap;
+#[cfg(feature = "metrics")]
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+/// Upper bounds of the histogram buckets for requests and handshakes, in
+/// seconds; the last bucket is unbounded.
+pub const DURATION_BUCKETS: &[f64] = &[
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// Upper bounds of the buckets for connection lifetimes, from a refused
+/// handshake to a long-lived stream, in seconds.
+pub const LIFETIME_BUCKETS: &[f64] = &[
+    0.01, 0.1, 1.0, 10.0, 60.0, 300.0, 1800.0, 3600.0, 21600.0, 86400.0,
+];
+
+/// Process-wide counters, gauges, and histograms, read by whatever
+/// exports them through [`snapshot`].
+///
+/// Kept deliberately small: a counter or histogram is identified by a
+/// static name and a static label value, so the set of series is bounded by
+/// the code. Gauges are labelled by certificate, so bounded by the
+/// configuration.
+#[cfg(feature = "metrics")]
+struct Registry {
+    counters: BTreeMap<(&'static str, &'static str), u64>,
+    gauges: BTreeMap<(&'static str, String), f64>,
+    histograms: BTreeMap<(&'static str, &'static str), Histogram>,
+}
+
+#[cfg(feature = "metrics")]
+static REGISTRY: Mutex<Registry> = Mutex::new(Registry {
+    counters: BTreeMap::new(),
+    gauges: BTreeMap::new(),
+    histograms: BTreeMap::new(),
+});
+
+#[cfg(feature = "metrics")]
+fn registry() -> MutexGuard<'static, Registry> {
+    REGISTRY.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Observations counted into buckets.
+#[derive(Debug, Clone, Default)]
+pub struct Histogram {
+    /// Upper bounds of the buckets, such as [`DURATION_BUCKETS`].
+    pub bounds: &'static [f64],
+    /// Observations at or below each bucket's bound, cumulative, followed
+    /// by the total.
+    pub buckets: Vec<u64>,
+    pub sum: f64,
+    pub count: u64,
+}
+
+/// Everything in the registry at one moment, sorted by name then label.
+#[derive(Debug, Clone, Default)]
+pub struct Snapshot {
+    pub counters: Vec<(&'static str, &'static str, u64)>,
+    pub gauges: Vec<(&'static str, String, f64)>,
+    pub histograms: Vec<(&'static str, &'static str, Histogram)>,
+}
+
+/// Increment the counter `name{label}` and return its new value.
+#[cfg(feature = "metrics")]
+pub fn inc(name: &'static str, label: &'static str) -> u64 {
+    let mut registry = registry();
+    let value = registry.counters.entry((name, label)).or_insert(0);
+    *value += 1;
+    *value
+}
+
+/// Set the gauge `name{label}` to `value`.
+#[cfg(feature = "metrics")]
+pub fn set(name: &'static str, label: &str, value: f64) {
+    registry().gauges.insert((name, label.to_string()), value);
+}
+
+/// Add `value` to the histogram `name{label}`, which has the buckets
+/// `bounds`. Every series of one histogram should have the same ones.
+#[cfg(feature = "metrics")]
+pub fn observe(name: &'static str, label: &'static str, bounds: &'static [f64], value: f64) {
+    let mut registry = registry();
+    let histogram = registry.histograms.entry((name, label)).or_default();
+    if histogram.buckets.is_empty() {
+        histogram.bounds = bounds;
+        histogram.buckets = vec![0; bounds.len() + 1];
+    }
+    let first = histogram.bounds.partition_point(|&bound| bound < value);
+    for bucket in &mut histogram.buckets[first..] {
+        *bucket += 1;
+    }
+    histogram.sum += value;
+    histogram.count += 1;
+}
+
+#[cfg(feature = "metrics")]
+pub fn snapshot() -> Snapshot {
+    let registry = registry();
+    Snapshot {
+        counters: registry
+            .counters
+            .iter()
+            .map(|(&(name, label), &value)| (name, label, value))
+            .collect(),
+        gauges: registry
+            .gauges
+            .iter()
+            .map(|((name, label), &value)| (*name, label.clone(), value))
+            .collect(),
+        histograms: registry
+            .histograms
+            .iter()
+            .map(|(&(name, label), histogram)| (name, label, histogram.clone()))
+            .collect(),
+    }
+}
+
+/// Counting is compiled out; always reports zero.
+#[cfg(not(feature = "metrics"))]
+pub fn inc(_name: &'static str, _label: &'static str) -> u64 {
+    0
+}
+
+#[cfg(not(feature = "metrics"))]
+pub fn set(_name: &'static str, _label: &str, _value: f64) {}
+
+#[cfg(not(feature = "metrics"))]
+pub fn observe(_name: &'static str, _label: &'static str, _bounds: &'static [f64], _value: f64) {}
+
+#[cfg(not(feature = "metrics"))]
+pub fn snapshot() -> Snapshot {
+    Snapshot::default()
+}
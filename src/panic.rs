@@ -0,0 +1,46 @@
+use std::backtrace::Backtrace;
+use std::io::Write;
+use std::panic::{self, PanicHookInfo};
+
+use tracing::error;
+
+/// Process exit code after a panic anywhere in the process.
+pub const EXIT_CODE: i32 = 6;
+
+/// Log panics as a structured event and exit.
+///
+/// Tokio catches panics in spawned tasks, so without this a panicked renewal
+/// task would die silently while the proxy keeps serving a certificate that
+/// is about to expire. Exiting instead lets the kubelet restart the
+/// container. The event is emitted inside the panicking task's span, so the
+/// task name shows up alongside the backtrace.
+pub fn install_hook() {
+    panic::set_hook(Box::new(|info| {
+        let thread = std::thread::current();
+        let task_id = tokio::task::try_id().map(|id| id.to_string());
+
+        error!(
+            panic = payload(info),
+            location = info.location().map(|l| l.to_string()),
+            thread = thread.name().unwrap_or("unnamed"),
+            task_id,
+            backtrace = %Backtrace::force_capture(),
+            exit_code = EXIT_CODE,
+            "cert-keeper panicked"
+        );
+
+        let _ = std::io::stdout().flush();
+        let _ = std::io::stderr().flush();
+        std::process::exit(EXIT_CODE);
+    }));
+}
+
+fn payload<'a>(info: &'a PanicHookInfo<'_>) -> &'a str {
+    if let Some(s) = info.payload().downcast_ref::<&str>() {
+        s
+    } else if let Some(s) = info.payload().downcast_ref::<String>() {
+        s
+    } else {
+        "non-string panic payload"
+    }
+}
@@ -0,0 +1,29 @@
+use std::path::PathBuf;
+
+use tracing::warn;
+
+use cert_keeper::error::{Error, Result};
+
+/// A PID file that is removed again when dropped.
+pub struct PidFile {
+    path: PathBuf,
+}
+
+impl PidFile {
+    /// Write the current process ID to `path`.
+    pub fn create(path: &str) -> Result<Self> {
+        std::fs::write(path, format!("{}\n", std::process::id()))
+            .map_err(|e| Error::Config(format!("failed to write PID_FILE '{path}': {e}")))?;
+        Ok(Self {
+            path: PathBuf::from(path),
+        })
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_file(&self.path) {
+            warn!(path = %self.path.display(), error = %e, "failed to remove PID file");
+        }
+    }
+}
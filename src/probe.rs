@@ -0,0 +1,107 @@
+use std::io;
+use std::path::{Path, PathBuf};
+
+use tokio::sync::watch;
+use tracing::{info, warn};
+
+use cert_keeper::cert::manager::CertStatus;
+use cert_keeper::config::Config;
+
+/// `READY_FILE` and `HEALTHY_FILE`, for exec probes where no port can be
+/// opened for HTTP ones:
+///
+/// - `READY_FILE` exists while every certificate has been loaded and, with
+///   `BACKEND_HEALTH_CHECK`, the backend of `LISTEN_ADDR` is healthy.
+/// - `HEALTHY_FILE` exists while every certificate has been loaded and no
+///   certificate's last renewal failed. The backend is left out, since
+///   restarting cert-keeper would not bring it back.
+///
+/// Both are removed on shutdown, and at startup, in case a volume that
+/// outlived the last run still holds them.
+pub struct ProbeFiles {
+    ready: Option<PathBuf>,
+    healthy: Option<PathBuf>,
+}
+
+impl ProbeFiles {
+    /// Remove the files left by an earlier run, so they are not taken as
+    /// this one's until [`run`](Self::run) creates them.
+    pub fn new(config: &Config) -> Self {
+        let files = Self {
+            ready: config.ready_file.as_deref().map(PathBuf::from),
+            healthy: config.healthy_file.as_deref().map(PathBuf::from),
+        };
+        files.update(false, false);
+        files
+    }
+
+    /// Keep the files in step with `status` and `backend_ready` until
+    /// shutdown, then remove them.
+    pub async fn run(
+        self,
+        mut status: watch::Receiver<Vec<CertStatus>>,
+        mut backend_ready: watch::Receiver<bool>,
+        mut shutdown: watch::Receiver<bool>,
+    ) {
+        let mut current = None;
+        loop {
+            let state = {
+                let certs = status.borrow_and_update();
+                let loaded = !certs.is_empty() && certs.iter().all(|c| c.serial.is_some());
+                let ready = loaded && *backend_ready.borrow_and_update();
+                let healthy = loaded
+                    && certs
+                        .iter()
+                        .all(|c| c.last_renewal.as_ref().is_none_or(|r| r.ok));
+                (ready, healthy)
+            };
+            if current != Some(state) {
+                let (ready, healthy) = state;
+                if (ready && healthy) || current.is_none() {
+                    info!(ready, healthy, "probe files updated");
+                } else {
+                    warn!(ready, healthy, "probe files updated");
+                }
+                self.update(ready, healthy);
+                current = Some(state);
+            }
+
+            tokio::select! {
+                changed = status.changed() => {
+                    if changed.is_err() {
+                        break;
+                    }
+                }
+                // Only sent on with a health check.
+                Ok(()) = backend_ready.changed() => {}
+                _ = shutdown.changed() => break,
+            }
+        }
+        self.update(false, false);
+    }
+
+    fn update(&self, ready: bool, healthy: bool) {
+        if let Some(path) = &self.ready {
+            set(path, ready, "READY_FILE");
+        }
+        if let Some(path) = &self.healthy {
+            set(path, healthy, "HEALTHY_FILE");
+        }
+    }
+}
+
+/// Create or remove the probe file at `path`.
+fn set(path: &Path, present: bool, name: &str) {
+    let result = if present {
+        std::fs::write(path, b"")
+    } else {
+        match std::fs::remove_file(path) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            result => result,
+        }
+    };
+    if let Err(e) = result {
+        let action = if present { "create" } else { "remove" };
+        warn!(path = %path.display(), error = %e, "failed to {action} {name}");
+    }
+}
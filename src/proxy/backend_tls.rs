@@ -0,0 +1,217 @@
+use std::sync::Arc;
+
+use ring::digest::{digest, SHA256};
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::client::{ResolvesClientCert, WebPkiServerVerifier};
+use rustls::crypto::WebPkiSupportedAlgorithms;
+use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use rustls::sign::CertifiedKey;
+use rustls::{ClientConfig, DigitallySignedStruct, RootCertStore, SignatureScheme};
+use tokio::sync::watch;
+use tokio_rustls::TlsConnector;
+use tracing::debug;
+
+use cert_keeper::cert::info::parse_pem_chain;
+use cert_keeper::cert::manager::parse_private_key;
+use cert_keeper::cert::roots;
+use cert_keeper::config::{BackendClientCert, Config};
+use cert_keeper::error::{Error, Result};
+use cert_keeper::vault::client::VaultClient;
+
+/// Build the connector for re-encrypting to the backend, or `None` when
+/// `BACKEND_TLS` is off.
+///
+/// `issued` follows the certificate cert-keeper serves, for
+/// `BACKEND_TLS_CLIENT_CERT=issued`. Trust roots and file-based client
+/// certificates are read once, at startup.
+pub async fn load(
+    client: &VaultClient,
+    config: &Config,
+    issued: watch::Receiver<Option<Arc<CertifiedKey>>>,
+) -> Result<Option<TlsConnector>> {
+    if !config.backend_tls {
+        return Ok(None);
+    }
+
+    let roots = Arc::new(
+        roots::load(
+            client,
+            config,
+            "BACKEND_TLS_CA_FILE",
+            config.backend_tls_ca_file.as_deref(),
+        )
+        .await?,
+    );
+    let webpki = WebPkiServerVerifier::builder(roots.clone())
+        .build()
+        .map_err(|e| Error::Tls(format!("failed to build backend certificate verifier: {e}")))?;
+
+    let builder = ClientConfig::builder();
+    let builder = if config.backend_tls_pins.is_empty() {
+        builder.with_webpki_verifier(webpki)
+    } else {
+        builder
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(PinnedVerifier {
+                inner: webpki,
+                roots,
+                pins: config.backend_tls_pins.clone(),
+                algorithms: rustls::crypto::ring::default_provider()
+                    .signature_verification_algorithms,
+            }))
+    };
+
+    let tls_config = match config.backend_tls_client_cert {
+        BackendClientCert::None => builder.with_no_client_auth(),
+        BackendClientCert::Issued => {
+            builder.with_client_cert_resolver(Arc::new(IssuedCert(issued)))
+        }
+        BackendClientCert::File {
+            ref cert_file,
+            ref key_file,
+        } => {
+            let read = |var: &str, path: &str| {
+                std::fs::read_to_string(path)
+                    .map_err(|e| Error::Config(format!("failed to read {var} '{path}': {e}")))
+            };
+            let certs = parse_pem_chain(&read("BACKEND_TLS_CLIENT_CERT_FILE", cert_file)?)?;
+            let key = parse_private_key(&read("BACKEND_TLS_CLIENT_KEY_FILE", key_file)?)?;
+            builder
+                .with_client_auth_cert(certs, key)
+                .map_err(|e| Error::Tls(format!("invalid backend client certificate: {e}")))?
+        }
+    };
+
+    Ok(Some(TlsConnector::from(Arc::new(tls_config))))
+}
+
+/// Presents whatever certificate the proxy is currently serving.
+#[derive(Debug)]
+struct IssuedCert(watch::Receiver<Option<Arc<CertifiedKey>>>);
+
+impl ResolvesClientCert for IssuedCert {
+    fn resolve(
+        &self,
+        _root_hint_subjects: &[&[u8]],
+        _sigschemes: &[SignatureScheme],
+    ) -> Option<Arc<CertifiedKey>> {
+        self.0.borrow().clone()
+    }
+
+    fn has_certs(&self) -> bool {
+        true
+    }
+}
+
+/// Normal WebPKI verification, plus a requirement that some chain to a
+/// trusted root contains one of the pinned keys. This guards third-party
+/// backends against a compromised public CA.
+#[derive(Debug)]
+struct PinnedVerifier {
+    inner: Arc<WebPkiServerVerifier>,
+    roots: Arc<RootCertStore>,
+    pins: Vec<Vec<u8>>,
+    algorithms: WebPkiSupportedAlgorithms,
+}
+
+impl PinnedVerifier {
+    fn is_pinned(&self, spki: &[u8]) -> bool {
+        let hash = digest(&SHA256, spki);
+        self.pins.iter().any(|pin| pin.as_slice() == hash.as_ref())
+    }
+}
+
+impl ServerCertVerifier for PinnedVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> std::result::Result<ServerCertVerified, rustls::Error> {
+        self.inner.verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            ocsp_response,
+            now,
+        )?;
+
+        // Only keys on a path that actually verifies count; an unrelated
+        // certificate appended to the chain must not satisfy the pin.
+        let cert = webpki::EndEntityCert::try_from(end_entity)
+            .map_err(|e| rustls::Error::General(format!("invalid backend certificate: {e}")))?;
+        let pinned_path = |path: &webpki::VerifiedPath<'_>| {
+            let on_path = std::iter::once(path.end_entity().subject_public_key_info())
+                .chain(
+                    path.intermediate_certificates()
+                        .map(|c| c.subject_public_key_info()),
+                )
+                .any(|spki| self.is_pinned(&spki));
+            // Trust anchors keep only the contents of the SPKI SEQUENCE.
+            let anchor = der_sequence(&path.anchor().subject_public_key_info);
+            if on_path || self.is_pinned(&anchor) {
+                Ok(())
+            } else {
+                Err(webpki::Error::UnknownIssuer)
+            }
+        };
+
+        match cert.verify_for_usage(
+            self.algorithms.all,
+            &self.roots.roots,
+            intermediates,
+            now,
+            webpki::KeyUsage::server_auth(),
+            None,
+            Some(&pinned_path),
+        ) {
+            Ok(_) => Ok(ServerCertVerified::assertion()),
+            Err(e) => {
+                debug!(error = %e, "no verified backend chain contains a pinned key");
+                Err(rustls::Error::General(
+                    "backend certificate does not match BACKEND_TLS_PINS".into(),
+                ))
+            }
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> std::result::Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> std::result::Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.inner.supported_verify_schemes()
+    }
+}
+
+/// DER-encode `contents` as a SEQUENCE.
+fn der_sequence(contents: &[u8]) -> Vec<u8> {
+    let len = contents.len();
+    let mut out = vec![0x30];
+    if len < 0x80 {
+        out.push(len as u8);
+    } else {
+        let bytes = len.to_be_bytes();
+        let skip = bytes.iter().take_while(|b| **b == 0).count();
+        out.push(0x80 | (bytes.len() - skip) as u8);
+        out.extend_from_slice(&bytes[skip..]);
+    }
+    out.extend_from_slice(contents);
+    out
+}
@@ -0,0 +1,65 @@
+use rustls::pki_types::CertificateDer;
+use x509_parser::prelude::{FromDer, X509Certificate};
+
+use cert_keeper::cert::info::CertInfo;
+
+/// The identity in a verified client certificate, for `FORWARD_CLIENT_CERT`.
+#[derive(Debug, Clone)]
+pub struct ClientCert {
+    pub subject: String,
+    pub common_name: Option<String>,
+    pub dns_names: Vec<String>,
+    pub uris: Vec<String>,
+    /// Lowercase hex SHA-256 of the DER certificate.
+    pub hash: String,
+}
+
+impl ClientCert {
+    /// The identity in the leaf of `chain`, or `None` if the client sent
+    /// no certificate.
+    pub fn from_chain(chain: Option<&[CertificateDer<'_>]>) -> Option<Self> {
+        let leaf = chain?.first()?;
+        // The handshake verified it, so it parses.
+        let info = CertInfo::from_der(leaf).ok()?;
+        let common_name = X509Certificate::from_der(leaf).ok().and_then(|(_, cert)| {
+            let cn = cert.subject().iter_common_name().next()?;
+            cn.as_str().ok().map(String::from)
+        });
+        Some(Self {
+            subject: info.subject,
+            common_name,
+            dns_names: info.dns_names,
+            uris: info.uris,
+            hash: info.sha256_fingerprint.replace(':', "").to_lowercase(),
+        })
+    }
+
+    /// The `X-Forwarded-Client-Cert` element for this certificate, in the
+    /// format Envoy uses: `Hash=...;Subject="...";URI=...;DNS=...`.
+    pub fn xfcc(&self) -> String {
+        let mut element = format!("Hash={};Subject={}", self.hash, quoted(&self.subject));
+        for uri in &self.uris {
+            element.push_str(";URI=");
+            element.push_str(&value(uri));
+        }
+        for name in &self.dns_names {
+            element.push_str(";DNS=");
+            element.push_str(&value(name));
+        }
+        element
+    }
+}
+
+/// `s` quoted if it has a character that separates XFCC elements, keys,
+/// or values.
+fn value(s: &str) -> String {
+    if s.contains([',', ';', '=', '"']) {
+        quoted(s)
+    } else {
+        s.to_string()
+    }
+}
+
+fn quoted(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
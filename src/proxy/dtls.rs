@@ -0,0 +1,319 @@
+use std::future::Future;
+use std::io;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use rustls::sign::CertifiedKey;
+use tokio::net::{lookup_host, UdpSocket};
+use tokio::sync::{watch, Semaphore};
+use tokio::task::JoinSet;
+use tokio::time::{sleep, timeout};
+use tracing::{debug, error, info, info_span, warn, Instrument};
+use webrtc_dtls::cipher_suite::CipherSuiteId;
+use webrtc_dtls::config::{Config as DtlsConfig, ExtendedMasterSecretType};
+use webrtc_dtls::conn::DTLSConn;
+use webrtc_dtls::crypto::Certificate;
+use webrtc_util::conn::conn_udp_listener::ListenConfig;
+use webrtc_util::conn::{Conn, Listener};
+use zeroize::Zeroizing;
+
+use cert_keeper::cert::info::parse_pem_chain;
+use cert_keeper::cert::keystore::pkcs8_der;
+use cert_keeper::cert::store::CertStore;
+use cert_keeper::config::Config;
+use cert_keeper::error::{Error, Result};
+use cert_keeper::metrics;
+
+use crate::proxy::listener::Peer;
+use crate::proxy::tls_acceptor;
+
+/// Record content type of a DTLS handshake. Only a datagram that starts
+/// one opens a session for a new client address.
+const CONTENT_TYPE_HANDSHAKE: u8 = 22;
+
+/// Largest datagram forwarded in either direction.
+const MAX_DATAGRAM: usize = 65_535;
+
+/// How long a session with no datagrams either way is kept without
+/// `IDLE_TIMEOUT`. UDP has no close, so a client that goes away is only
+/// noticed this way.
+const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// How [`run`] handles sessions.
+pub struct Options {
+    /// Session limit, handshake timeout, access log, and address
+    /// filtering, the same as on the TLS listener.
+    pub listener: tls_acceptor::Options,
+    /// End sessions with no datagrams either way for this long.
+    pub idle_timeout: Duration,
+    /// `DTLS_BACKEND_ADDR`.
+    pub backend: String,
+}
+
+impl Options {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            listener: tls_acceptor::Options::from_config(config),
+            idle_timeout: config.idle_timeout.unwrap_or(DEFAULT_IDLE_TIMEOUT),
+            backend: config.dtls_backend_addr.clone().unwrap_or_default(),
+        }
+    }
+}
+
+/// Bind the DTLS listener. Datagrams from a new client address are only
+/// taken for a session if they start a handshake.
+pub async fn bind(addr: SocketAddr) -> Result<Arc<dyn Listener + Send + Sync>> {
+    let mut listen = ListenConfig {
+        accept_filter: Some(Box::new(
+            |packet: &[u8]| -> Pin<Box<dyn Future<Output = bool> + Send>> {
+                let handshake = packet.first() == Some(&CONTENT_TYPE_HANDSHAKE);
+                Box::pin(async move { handshake })
+            },
+        )),
+        ..Default::default()
+    };
+    let listener = listen
+        .listen(addr)
+        .await
+        .map_err(|e| Error::Bind(format!("{addr} (udp): {e}")))?;
+    Ok(Arc::new(listener))
+}
+
+/// Terminate DTLS on `listener`, forwarding each client's datagrams to
+/// the UDP backend from a socket of its own, and the backend's answers
+/// back to the client.
+///
+/// Sessions present the primary certificate, loaded from `store` whenever
+/// `issued` announces a new one; sessions already open keep the one they
+/// started with. Until the first is issued, clients are not answered.
+/// Clients are turned away by address and beyond the connection limit, as
+/// on the TLS listener.
+///
+/// On shutdown no new sessions are taken, and the open ones get up to the
+/// drain timeout to finish.
+pub async fn run(
+    listener: Arc<dyn Listener + Send + Sync>,
+    store: CertStore,
+    mut issued: watch::Receiver<Option<Arc<CertifiedKey>>>,
+    options: Options,
+    mut shutdown: watch::Receiver<bool>,
+) -> Result<()> {
+    let local = listener.addr().await.map_err(io::Error::other)?;
+    let options = Arc::new(options);
+    let mut identity: Option<(Arc<CertifiedKey>, DtlsConfig)> = None;
+    // Pick up a certificate that is already there.
+    issued.mark_changed();
+
+    let mut sessions = JoinSet::new();
+    let limit = Arc::new(Semaphore::new(options.listener.max_connections));
+    let result = loop {
+        tokio::select! {
+            Ok(()) = issued.changed() => {
+                let Some(key) = issued.borrow_and_update().clone() else {
+                    continue;
+                };
+                // Also announced when another certificate or the CRL
+                // changes.
+                if identity.as_ref().is_some_and(|(served, _)| Arc::ptr_eq(served, &key)) {
+                    continue;
+                }
+                match load(&store).await {
+                    Ok(config) => {
+                        if identity.is_none() {
+                            info!(addr = %local, backend = %options.backend, "DTLS listening");
+                        }
+                        identity = Some((key, config));
+                    }
+                    Err(e) => {
+                        error!(error = %e, code = e.code(), "failed to load certificate for DTLS");
+                    }
+                }
+            }
+            accepted = listener.accept() => {
+                let (conn, addr) = match accepted {
+                    Ok(accepted) => accepted,
+                    Err(e) => break Err(Error::Io(io::Error::other(e))),
+                };
+                let peer = Peer::Tcp { addr, local };
+                debug!(%peer, "accepted DTLS session");
+
+                let Some((_, ref config)) = identity else {
+                    let _ = conn.close().await;
+                    debug!(%peer, "no certificate yet, dropping DTLS session");
+                    continue;
+                };
+
+                if let Some(setting) = options.listener.refused_by(&peer) {
+                    let _ = conn.close().await;
+                    let count = metrics::inc("connections_rejected", setting);
+                    info!(%peer, count, "connection refused by {}", setting.to_uppercase());
+                    continue;
+                }
+
+                let Ok(permit) = limit.clone().try_acquire_owned() else {
+                    let _ = conn.close().await;
+                    let count = metrics::inc("connections_rejected", "max_connections");
+                    debug!(%peer, count, "connection limit reached, dropping DTLS session");
+                    continue;
+                };
+
+                let (config, options) = (config.clone(), options.clone());
+                // Each session is its own trace.
+                let span = info_span!(parent: None, "dtls_session", %peer);
+                sessions.spawn(
+                    async move {
+                        let _permit = permit;
+                        session(conn, peer, config, &options).await;
+                    }
+                    .instrument(span),
+                );
+            }
+            // Reap finished sessions so the set only holds open ones.
+            Some(_) = sessions.join_next(), if !sessions.is_empty() => {}
+            _ = shutdown.changed() => break Ok(()),
+        }
+    };
+
+    info!(
+        sessions = sessions.len(),
+        drain_timeout_secs = options.listener.drain_timeout.as_secs(),
+        "DTLS listener shutting down, draining sessions"
+    );
+    let drained = timeout(options.listener.drain_timeout, async {
+        while sessions.join_next().await.is_some() {}
+    })
+    .await;
+    if drained.is_err() {
+        warn!(
+            sessions = sessions.len(),
+            "drain timeout reached, closing remaining DTLS sessions"
+        );
+        sessions.shutdown().await;
+    }
+    // Only now: until it is closed, the listener passes the open sessions
+    // their datagrams.
+    let _ = listener.close().await;
+    result
+}
+
+/// The DTLS server config presenting the certificate and key in `store`.
+async fn load(store: &CertStore) -> Result<DtlsConfig> {
+    let Some((chain, key)) = store.read().await? else {
+        return Err(Error::Tls("the certificate and key are not stored".into()));
+    };
+    // webrtc-dtls reads a PKCS#8 key under this label, followed by the
+    // chain, and nothing else.
+    let mut pem = Zeroizing::new(pem_block("PRIVATE_KEY", &pkcs8_der(&key)?));
+    for cert in parse_pem_chain(&chain)? {
+        pem.push_str(&pem_block("CERTIFICATE", &cert));
+    }
+    let certificate = Certificate::from_pem(&pem)
+        .map_err(|e| Error::Tls(format!("failed to load certificate and key: {e}")))?;
+    Ok(DtlsConfig {
+        certificates: vec![certificate],
+        // The AEAD suites only; the stack would also offer AES-CBC.
+        cipher_suites: vec![
+            CipherSuiteId::Tls_Ecdhe_Ecdsa_With_Aes_128_Gcm_Sha256,
+            CipherSuiteId::Tls_Ecdhe_Rsa_With_Aes_128_Gcm_Sha256,
+        ],
+        extended_master_secret: ExtendedMasterSecretType::Require,
+        ..Default::default()
+    })
+}
+
+fn pem_block(label: &str, der: &[u8]) -> String {
+    let encoded = STANDARD.encode(der);
+    let mut pem = format!("-----BEGIN {label}-----\n");
+    for line in encoded.as_bytes().chunks(64) {
+        pem.push_str(std::str::from_utf8(line).expect("base64 is ASCII"));
+        pem.push('\n');
+    }
+    pem.push_str(&format!("-----END {label}-----\n"));
+    pem
+}
+
+/// Complete the handshake on `conn` and forward datagrams until either
+/// side closes, or the session is idle.
+async fn session(
+    conn: Arc<dyn Conn + Send + Sync>,
+    peer: Peer,
+    config: DtlsConfig,
+    options: &Options,
+) {
+    let start = Instant::now();
+    let handshake = DTLSConn::new(conn.clone(), config, false, None);
+    let dtls = match timeout(options.listener.handshake_timeout, handshake).await {
+        Ok(Ok(dtls)) => dtls,
+        failed => {
+            let _ = conn.close().await;
+            // webrtc-dtls errors are not classified further.
+            let (reason, error) = match failed {
+                Ok(Err(e)) => ("other", e.to_string()),
+                _ => ("timeout", "handshake timed out".to_string()),
+            };
+            let count = metrics::inc("tls_handshake_failures", reason);
+            info!(reason, count, %error, "DTLS handshake failed");
+            return;
+        }
+    };
+
+    let mut bytes = (0, 0);
+    let result = forward(&dtls, &options.backend, options.idle_timeout, &mut bytes).await;
+    let _ = dtls.close().await;
+    if let Err(ref e) = result {
+        debug!(error = %e, "DTLS session failed");
+    }
+    if options.listener.access_log {
+        info!(
+            target: "access",
+            %peer,
+            backend = %options.backend,
+            bytes_in = bytes.0,
+            bytes_out = bytes.1,
+            duration_ms = start.elapsed().as_millis() as u64,
+            "DTLS session closed"
+        );
+    }
+}
+
+/// Forward between `dtls` and the backend, counting the payload bytes in
+/// `bytes` as they go. Ends after `idle` with no datagram either way.
+async fn forward(
+    dtls: &DTLSConn,
+    backend: &str,
+    idle: Duration,
+    bytes: &mut (u64, u64),
+) -> io::Result<()> {
+    let addr = lookup_host(backend)
+        .await?
+        .next()
+        .ok_or_else(|| io::Error::other(format!("{backend} has no address")))?;
+    let any: SocketAddr = match addr {
+        SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+        SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+    };
+    let socket = UdpSocket::bind(any).await?;
+    socket.connect(addr).await?;
+
+    let mut from_client = vec![0; MAX_DATAGRAM];
+    let mut from_backend = vec![0; MAX_DATAGRAM];
+    loop {
+        tokio::select! {
+            read = dtls.read(&mut from_client, None) => {
+                let n = read.map_err(io::Error::other)?;
+                socket.send(&from_client[..n]).await?;
+                bytes.0 += n as u64;
+            }
+            read = socket.recv(&mut from_backend) => {
+                let n = read?;
+                dtls.write(&from_backend[..n], None).await.map_err(io::Error::other)?;
+                bytes.1 += n as u64;
+            }
+            () = sleep(idle) => return Ok(()),
+        }
+    }
+}
@@ -1,26 +1,576 @@
-use std::net::SocketAddr;
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
 
-use tokio::io::copy_bidirectional;
+use rustls::pki_types::ServerName;
+use tokio::io::{copy_bidirectional, AsyncRead, AsyncWrite, AsyncWriteExt, ReadBuf};
 use tokio::net::TcpStream;
-use tokio_rustls::server::TlsStream;
-use tracing::debug;
+use tokio::time::timeout;
+use tokio_rustls::TlsConnector;
+use tracing::{debug, info, warn};
 
-use crate::error::Result;
+use cert_keeper::config::{
+    Bandwidth, BandwidthRoute, Config, LbPolicy, PassthroughRoute, ProxyMode, ProxyProtocol,
+    TcpOptions,
+};
+use cert_keeper::dns::{self, Resolver};
+use cert_keeper::error::{Error, Result};
+use cert_keeper::metrics;
 
-/// Forward a TLS-terminated connection to the plaintext backend.
+use crate::proxy::client_cert::ClientCert;
+#[cfg(feature = "http-proxy")]
+use crate::proxy::http;
+use crate::proxy::listener::{Io, Peer};
+use crate::proxy::proxy_protocol;
+use crate::proxy::shadow::Mirror;
+use crate::proxy::tcp;
+use crate::proxy::throttle::Throttled;
+
+/// The pool of backends and how to reach them.
+pub struct Backend {
+    targets: Vec<Target>,
+    policy: LbPolicy,
+    resolver: Arc<Resolver>,
+    /// Upper bound on connecting to one target, so an unreachable backend
+    /// does not hold the client's TLS session open for the OS connect
+    /// timeout.
+    connect_timeout: Duration,
+    /// Close connections with no traffic in either direction for this long.
+    idle_timeout: Option<Duration>,
+    /// Consecutive failures after which a target is skipped for `cooldown`.
+    failure_threshold: u32,
+    cooldown: Duration,
+    /// Re-encrypt to the backend instead of forwarding plaintext.
+    tls: Option<TlsConnector>,
+    proxy_protocol: ProxyProtocol,
+    mode: ProxyMode,
+    #[cfg_attr(not(feature = "http-proxy"), allow(dead_code))]
+    trust_forwarded: bool,
+    forward_client_cert: bool,
+    tcp: TcpOptions,
+    bandwidth: Bandwidth,
+    bandwidth_routes: Vec<BandwidthRoute>,
+    next: AtomicUsize,
+    /// With `PROXY_MODE=passthrough`, the backends of `PASSTHROUGH_ROUTES`.
+    routes: Vec<(PassthroughRoute, Backend)>,
+    /// `SHADOW_BACKEND_ADDR`, which gets a copy of what clients send.
+    shadow: Option<Arc<Backend>>,
+}
+
+struct Target {
+    /// `host:port`; hostnames are resolved per connection.
+    addr: String,
+    /// SNI name, with `BACKEND_TLS` only.
+    server_name: Option<ServerName<'static>>,
+    active: AtomicUsize,
+    failures: AtomicU32,
+    unhealthy_until: Mutex<Option<Instant>>,
+}
+
+/// Keeps a target's open connection count up to date.
+pub struct ActiveGuard<'a>(&'a Target);
+
+impl Drop for ActiveGuard<'_> {
+    fn drop(&mut self) {
+        self.0.active.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl ActiveGuard<'_> {
+    /// The target the connection went to.
+    pub fn addr(&self) -> &str {
+        &self.0.addr
+    }
+}
+
+impl Backend {
+    pub fn new(
+        config: &Config,
+        resolver: Arc<Resolver>,
+        tls: Option<TlsConnector>,
+    ) -> Result<Self> {
+        // Config validation already rejects this.
+        #[cfg(not(feature = "http-proxy"))]
+        if config.proxy_mode == ProxyMode::Http {
+            return Err(Error::Config(
+                "PROXY_MODE=http requires a build with the http-proxy feature".into(),
+            ));
+        }
+        let targets = config
+            .backend_addrs
+            .iter()
+            .map(|addr| {
+                let server_name = match tls {
+                    Some(_) => Some(server_name(config, addr)?),
+                    None => None,
+                };
+                Ok(Target {
+                    addr: addr.clone(),
+                    server_name,
+                    active: AtomicUsize::new(0),
+                    failures: AtomicU32::new(0),
+                    unhealthy_until: Mutex::new(None),
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let routes = match config.proxy_mode {
+            ProxyMode::Passthrough => config
+                .passthrough_routes
+                .iter()
+                .map(|route| {
+                    let mut config = config.clone();
+                    config.backend_addrs = vec![route.backend_addr.clone()];
+                    config.passthrough_routes = Vec::new();
+                    Ok((
+                        route.clone(),
+                        Backend::new(&config, resolver.clone(), None)?,
+                    ))
+                })
+                .collect::<Result<Vec<_>>>()?,
+            _ => Vec::new(),
+        };
+        let shadow = match config.shadow_backend_addr {
+            Some(ref addr) => {
+                let mut config = config.clone();
+                config.backend_addrs = vec![addr.clone()];
+                config.shadow_backend_addr = None;
+                Some(Arc::new(Backend::new(&config, resolver.clone(), tls.clone())?))
+            }
+            None => None,
+        };
+
+        Ok(Self {
+            targets,
+            policy: config.backend_lb_policy,
+            resolver,
+            connect_timeout: config.backend_connect_timeout,
+            idle_timeout: config.idle_timeout,
+            failure_threshold: config.backend_failure_threshold,
+            cooldown: config.backend_failure_cooldown,
+            tls,
+            proxy_protocol: config.backend_proxy_protocol,
+            mode: config.proxy_mode,
+            trust_forwarded: config.http_trust_forwarded,
+            forward_client_cert: config.forward_client_cert,
+            tcp: config.tcp.clone(),
+            bandwidth: config.bandwidth_limit,
+            bandwidth_routes: config.bandwidth_routes.clone(),
+            next: AtomicUsize::new(0),
+            routes,
+            shadow,
+        })
+    }
+
+    /// The backend for a ClientHello naming `sni`: the route for that exact
+    /// name, else a wildcard route, else this one.
+    pub fn route(&self, sni: Option<&str>) -> &Backend {
+        let Some(sni) = sni else {
+            return self;
+        };
+        let exact = self
+            .routes
+            .iter()
+            .find(|(route, _)| !route.is_wildcard() && route.matches(sni));
+        exact
+            .or_else(|| self.routes.iter().find(|(route, _)| route.matches(sni)))
+            .map_or(self, |(_, backend)| backend)
+    }
+
+    /// How fast a connection for `sni` may move data: the limit of the
+    /// `BANDWIDTH_LIMIT_ROUTES` entry for that exact name, else of a
+    /// wildcard one, else the global one.
+    fn bandwidth(&self, sni: Option<&str>) -> Bandwidth {
+        let Some(sni) = sni else {
+            return self.bandwidth;
+        };
+        let exact = self
+            .bandwidth_routes
+            .iter()
+            .find(|route| !route.is_wildcard() && route.matches(sni));
+        exact
+            .or_else(|| self.bandwidth_routes.iter().find(|route| route.matches(sni)))
+            .map_or(self.bandwidth, |route| route.limit)
+    }
+
+    /// The PROXY protocol header announcing `peer`, and with
+    /// `FORWARD_CLIENT_CERT` `client_cert`, if one is sent.
+    pub fn proxy_header(&self, peer: Peer, client_cert: Option<&ClientCert>) -> Option<Vec<u8>> {
+        let client_cert = client_cert.filter(|_| self.forward_client_cert);
+        proxy_protocol::header(self.proxy_protocol, peer, client_cert)
+    }
+
+    /// Whether the identity in client certificates is passed on, with
+    /// `FORWARD_CLIENT_CERT`.
+    pub fn forwards_client_cert(&self) -> bool {
+        self.forward_client_cert
+    }
+
+    #[cfg_attr(not(feature = "http-proxy"), allow(dead_code))]
+    pub fn mode(&self) -> ProxyMode {
+        self.mode
+    }
+
+    /// Targets in the order they should be tried: healthy ones by policy,
+    /// then the ones cooling down, so a pool that is entirely marked down
+    /// still gets a chance to recover.
+    fn candidates(&self) -> Vec<&Target> {
+        let start = self.next.fetch_add(1, Ordering::Relaxed);
+        let mut order: Vec<&Target> = (0..self.targets.len())
+            .map(|i| &self.targets[(start + i) % self.targets.len()])
+            .collect();
+        if self.policy == LbPolicy::LeastConnections {
+            // Stable, so ties keep their round-robin order.
+            order.sort_by_key(|t| t.active.load(Ordering::Relaxed));
+        }
+
+        let now = Instant::now();
+        let (healthy, cooling): (Vec<_>, Vec<_>) = order.into_iter().partition(|t| {
+            !t.unhealthy_until
+                .lock()
+                .unwrap()
+                .is_some_and(|until| now < until)
+        });
+        healthy.into_iter().chain(cooling).collect()
+    }
+
+    fn record_success(&self, target: &Target) {
+        if target.failures.swap(0, Ordering::Relaxed) >= self.failure_threshold {
+            info!(backend = %target.addr, "backend recovered");
+        }
+        *target.unhealthy_until.lock().unwrap() = None;
+    }
+
+    fn record_failure(&self, target: &Target) {
+        let failures = target.failures.fetch_add(1, Ordering::Relaxed) + 1;
+        if failures >= self.failure_threshold {
+            *target.unhealthy_until.lock().unwrap() = Some(Instant::now() + self.cooldown);
+            if failures == self.failure_threshold {
+                warn!(
+                    backend = %target.addr,
+                    failures,
+                    cooldown_secs = self.cooldown.as_secs(),
+                    "backend marked unhealthy"
+                );
+            }
+        }
+    }
+}
+
+/// `BACKEND_TLS_SERVER_NAME`, or the host part of `addr`.
+fn server_name(config: &Config, addr: &str) -> Result<ServerName<'static>> {
+    let name = match config.backend_tls_server_name {
+        Some(ref name) => name.as_str(),
+        None => dns::split_host_port(addr)?.0,
+    };
+    ServerName::try_from(name.to_string())
+        .map_err(|_| Error::Config(format!("'{name}' is not a valid TLS server name")))
+}
+
+/// How a forwarded connection went.
+pub struct Forwarded {
+    /// The target that accepted the connection, if any did.
+    pub backend: Option<String>,
+    pub client_to_server: u64,
+    pub server_to_client: u64,
+    pub result: Result<Close>,
+}
+
+/// Why a forwarded connection ended, when it ended without an error.
+#[derive(Debug, Clone, Copy)]
+pub enum Close {
+    /// Both sides finished.
+    Eof,
+    /// Nothing moved for `IDLE_TIMEOUT`.
+    Idle,
+}
+
+impl Close {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Close::Eof => "eof",
+            Close::Idle => "idle_timeout",
+        }
+    }
+}
+
+/// Forward a TLS-terminated connection to the backend, or with
+/// `PROXY_MODE=passthrough` the client's TLS stream itself.
 ///
 /// Uses `copy_bidirectional` for zero-copy L4 proxying. This is
-/// protocol-agnostic: HTTP/1.1, HTTP/2, gRPC, WebSockets all work.
-pub async fn forward(mut tls_stream: TlsStream<TcpStream>, backend_addr: SocketAddr) -> Result<()> {
-    let mut backend = TcpStream::connect(backend_addr).await?;
+/// protocol-agnostic: HTTP/1.1, HTTP/2, gRPC, WebSockets all work. With
+/// `PROXY_MODE=http` requests are relayed one by one instead, see
+/// `http::relay`.
+///
+/// `client_cert` is the client's verified certificate, if it sent one, and
+/// `sni` the server name it asked for, which picks its bandwidth limit.
+pub async fn forward<S>(
+    client: S,
+    backend: &Backend,
+    peer: Peer,
+    client_cert: Option<&ClientCert>,
+    sni: Option<&str>,
+) -> Forwarded
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let header = backend.proxy_header(peer, client_cert);
+    let (stream, active) = match connect(backend, header.as_deref()).await {
+        Ok(connected) => connected,
+        Err(e) => {
+            return Forwarded {
+                backend: None,
+                client_to_server: 0,
+                server_to_client: 0,
+                result: Err(e),
+            }
+        }
+    };
+
+    // Every byte either way passes through the client side, so watching it
+    // is enough to see activity in both directions.
+    let activity = Arc::new(Activity::new());
+    let client = Tracked {
+        inner: Throttled::new(client, backend.bandwidth(sni)),
+        activity: activity.clone(),
+        mirror: backend
+            .shadow
+            .clone()
+            .map(|shadow| Mirror::spawn(shadow, header.clone())),
+    };
+    let target = active.addr().to_string();
+
+    // Counted on the client side rather than taken from
+    // `copy_bidirectional`, so they are also known when it fails.
+    let result = tokio::select! {
+        result = relay(client, (stream, active), backend, (peer, client_cert), header.as_deref()) => result,
+        _ = idle(&activity, backend.idle_timeout) => Ok(Close::Idle),
+    };
+
+    Forwarded {
+        backend: Some(target),
+        client_to_server: activity.read.load(Ordering::Relaxed),
+        server_to_client: activity.written.load(Ordering::Relaxed),
+        result,
+    }
+}
+
+/// Move data between the client and the backend until either side is done.
+#[cfg_attr(not(feature = "http-proxy"), allow(unused_variables))]
+async fn relay<'a, C>(
+    mut client: C,
+    (mut stream, active): (Box<dyn Io>, ActiveGuard<'a>),
+    backend: &'a Backend,
+    (peer, client_cert): (Peer, Option<&'a ClientCert>),
+    header: Option<&'a [u8]>,
+) -> Result<Close>
+where
+    C: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    #[cfg(feature = "http-proxy")]
+    if backend.mode == ProxyMode::Http {
+        let options = http::Options {
+            peer,
+            trust_forwarded: backend.trust_forwarded,
+            client_cert,
+            header,
+        };
+        return http::relay(client, (stream, active), backend, options).await;
+    }
+    copy_bidirectional(&mut client, &mut stream)
+        .await
+        .map(|_| Close::Eof)
+        .map_err(Error::from)
+}
+
+/// Resolve once no data has moved for `limit`; never without a limit.
+async fn idle(activity: &Activity, limit: Option<Duration>) {
+    let Some(limit) = limit else {
+        return std::future::pending().await;
+    };
+    loop {
+        let idle_for = activity.idle_for();
+        if idle_for >= limit {
+            return;
+        }
+        tokio::time::sleep(limit - idle_for).await;
+    }
+}
+
+/// Bytes moved on a connection and when data last moved.
+struct Activity {
+    start: Instant,
+    /// Milliseconds since `start`.
+    last: AtomicU64,
+    read: AtomicU64,
+    written: AtomicU64,
+}
+
+impl Activity {
+    fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            last: AtomicU64::new(0),
+            read: AtomicU64::new(0),
+            written: AtomicU64::new(0),
+        }
+    }
 
-    let (client_bytes, server_bytes) = copy_bidirectional(&mut tls_stream, &mut backend).await?;
+    fn touch(&self) {
+        self.last
+            .store(self.start.elapsed().as_millis() as u64, Ordering::Relaxed);
+    }
 
-    debug!(
-        client_to_server = client_bytes,
-        server_to_client = server_bytes,
-        "connection closed"
-    );
+    fn idle_for(&self) -> Duration {
+        let last = Duration::from_millis(self.last.load(Ordering::Relaxed));
+        self.start.elapsed().saturating_sub(last)
+    }
+}
+
+/// A stream that records its reads and writes in an [`Activity`], and
+/// copies what it reads to the shadow backend, if there is one.
+struct Tracked<S> {
+    inner: S,
+    activity: Arc<Activity>,
+    mirror: Option<Mirror>,
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for Tracked<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let before = buf.filled().len();
+        let result = Pin::new(&mut self.inner).poll_read(cx, buf);
+        let read = buf.filled().len() - before;
+        if read > 0 {
+            self.activity.read.fetch_add(read as u64, Ordering::Relaxed);
+            self.activity.touch();
+            if let Some(mirror) = &mut self.mirror {
+                mirror.send(&buf.filled()[before..]);
+            }
+        }
+        result
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for Tracked<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let result = Pin::new(&mut self.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(written)) = result {
+            if written > 0 {
+                self.activity
+                    .written
+                    .fetch_add(written as u64, Ordering::Relaxed);
+                self.activity.touch();
+            }
+        }
+        result
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+/// Connect to the first target that accepts, counting failures towards
+/// marking targets unhealthy.
+pub async fn connect<'a>(
+    backend: &'a Backend,
+    header: Option<&[u8]>,
+) -> Result<(Box<dyn Io>, ActiveGuard<'a>)> {
+    let mut last_error = None;
+    for target in backend.candidates() {
+        let started = Instant::now();
+        let connected = connect_target(backend, target, header).await;
+        let result = if connected.is_ok() {
+            "success"
+        } else {
+            "failure"
+        };
+        metrics::observe(
+            "backend_connect_duration_seconds",
+            result,
+            metrics::DURATION_BUCKETS,
+            started.elapsed().as_secs_f64(),
+        );
+        match connected {
+            Ok(stream) => {
+                backend.record_success(target);
+                target.active.fetch_add(1, Ordering::Relaxed);
+                return Ok((stream, ActiveGuard(target)));
+            }
+            Err(e) => {
+                debug!(backend = %target.addr, error = %e, "backend connect failed");
+                backend.record_failure(target);
+                last_error = Some(e);
+            }
+        }
+    }
+    // There is always at least one target.
+    Err(last_error.unwrap_or_else(|| Error::Backend("no backends configured".into())))
+}
+
+/// Connect to one target, including the PROXY protocol header and the TLS
+/// handshake in re-encrypt mode.
+async fn connect_target(
+    backend: &Backend,
+    target: &Target,
+    header: Option<&[u8]>,
+) -> Result<Box<dyn Io>> {
+    let attempt = async {
+        let mut stream = connect_tcp(&backend.resolver, &target.addr, &backend.tcp).await?;
+        // Goes ahead of the TLS handshake, as backends expect.
+        if let Some(header) = header {
+            stream.write_all(header).await.map_err(|e| {
+                Error::Backend(format!(
+                    "sending PROXY header to {} failed: {e}",
+                    target.addr
+                ))
+            })?;
+        }
+        let (Some(connector), Some(server_name)) = (&backend.tls, &target.server_name) else {
+            return Ok(Box::new(stream) as Box<dyn Io>);
+        };
+        let stream = connector
+            .connect(server_name.clone(), stream)
+            .await
+            .map_err(|e| {
+                Error::Backend(format!("TLS handshake with {} failed: {e}", target.addr))
+            })?;
+        Ok(Box::new(stream) as Box<dyn Io>)
+    };
+
+    match timeout(backend.connect_timeout, attempt).await {
+        Ok(result) => result,
+        Err(_) => Err(Error::Backend(format!(
+            "connect to {} timed out after {:?}",
+            target.addr, backend.connect_timeout
+        ))),
+    }
+}
 
-    Ok(())
+async fn connect_tcp(resolver: &Resolver, addr: &str, options: &TcpOptions) -> Result<TcpStream> {
+    let mut last_error = None;
+    for resolved in resolver.lookup(addr).await? {
+        match tcp::connect(resolved, options).await {
+            Ok(stream) => return Ok(stream),
+            Err(e) => last_error = Some(e),
+        }
+    }
+    Err(Error::Backend(match last_error {
+        Some(e) => format!("connect to {addr} failed: {e}"),
+        None => format!("{addr} resolved to no addresses"),
+    }))
 }
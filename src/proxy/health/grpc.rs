@@ -0,0 +1,157 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use reqwest::Client;
+
+use cert_keeper::dns::Resolver;
+use cert_keeper::error::{Error, Result};
+
+/// `grpc.health.v1.HealthCheckResponse.ServingStatus.SERVING`.
+pub const GRPC_SERVING: u64 = 1;
+/// `grpc.health.v1.HealthCheckResponse.ServingStatus.NOT_SERVING`.
+#[cfg(feature = "admin")]
+pub const GRPC_NOT_SERVING: u64 = 2;
+
+/// A client for [`check`]; gRPC is HTTP/2, here without TLS.
+pub fn client(resolver: Arc<Resolver>, timeout: Duration) -> Result<Client> {
+    Ok(Client::builder()
+        .dns_resolver(resolver)
+        .http2_prior_knowledge()
+        .timeout(timeout)
+        .build()?)
+}
+
+/// Call `grpc.health.v1.Health/Check` and fail unless the backend reports
+/// `SERVING`.
+pub async fn check(client: &Client, backend_addr: &str, service: &str) -> Result<()> {
+    let url = format!("http://{backend_addr}/grpc.health.v1.Health/Check");
+
+    let response = client
+        .post(&url)
+        .header("content-type", "application/grpc")
+        .header("te", "trailers")
+        .body(grpc_frame(&encode_request(service)))
+        .send()
+        .await?;
+
+    if !response.status().is_success() {
+        return Err(Error::Backend(format!(
+            "health check returned {}",
+            response.status()
+        )));
+    }
+
+    let body = response.bytes().await?;
+    // A trailers-only response (e.g. NOT_FOUND for an unknown service) has
+    // no message at all.
+    let message = body
+        .get(5..)
+        .ok_or_else(|| Error::Backend("empty gRPC health response".into()))?;
+
+    match decode_status(message) {
+        GRPC_SERVING => Ok(()),
+        status => Err(Error::Backend(format!(
+            "backend reports serving status {status}, not SERVING"
+        ))),
+    }
+}
+
+/// Encode `HealthCheckRequest { service }` (field 1, length-delimited).
+fn encode_request(service: &str) -> Vec<u8> {
+    let mut buf = Vec::new();
+    if !service.is_empty() {
+        buf.push(0x0a);
+        encode_varint(service.len() as u64, &mut buf);
+        buf.extend_from_slice(service.as_bytes());
+    }
+    buf
+}
+
+/// Encode `HealthCheckResponse { status }` (field 1, varint).
+#[cfg(feature = "admin")]
+pub fn encode_response(status: u64) -> Vec<u8> {
+    let mut buf = vec![0x08];
+    encode_varint(status, &mut buf);
+    buf
+}
+
+/// Extract `HealthCheckRequest.service` (field 1, length-delimited); a
+/// missing field is the empty service, meaning the server as a whole.
+/// `None` if the message does not decode.
+#[cfg(feature = "admin")]
+pub fn decode_request(msg: &[u8]) -> Option<String> {
+    match find_field(msg, 1) {
+        Some(Field::Bytes(service)) => String::from_utf8(service.to_vec()).ok(),
+        Some(Field::Varint(_)) => None,
+        None => Some(String::new()),
+    }
+}
+
+/// Extract `HealthCheckResponse.status` (field 1, varint); a missing field
+/// decodes to `UNKNOWN` (0).
+fn decode_status(msg: &[u8]) -> u64 {
+    match find_field(msg, 1) {
+        Some(Field::Varint(status)) => status,
+        _ => 0,
+    }
+}
+
+/// A protobuf field value of the wire types the health messages use.
+#[cfg_attr(not(feature = "admin"), allow(dead_code))]
+enum Field<'a> {
+    Varint(u64),
+    Bytes(&'a [u8]),
+}
+
+/// The first occurrence of field number `wanted`; unknown fields are
+/// skipped, and decoding stops at anything malformed.
+fn find_field(mut msg: &[u8], wanted: u64) -> Option<Field<'_>> {
+    while let Some((key, rest)) = decode_varint(msg) {
+        let (field, wire_type) = (key >> 3, key & 0x7);
+        let (value, rest) = match wire_type {
+            0 => {
+                let (value, rest) = decode_varint(rest)?;
+                (Field::Varint(value), rest)
+            }
+            2 => {
+                let (len, rest) = decode_varint(rest)?;
+                let len = usize::try_from(len).ok()?;
+                (Field::Bytes(rest.get(..len)?), rest.get(len..)?)
+            }
+            _ => return None,
+        };
+        if field == wanted {
+            return Some(value);
+        }
+        msg = rest;
+    }
+    None
+}
+
+/// Prefix a message with the gRPC length-prefixed framing (uncompressed).
+pub fn grpc_frame(message: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(5 + message.len());
+    frame.push(0);
+    frame.extend_from_slice(&(message.len() as u32).to_be_bytes());
+    frame.extend_from_slice(message);
+    frame
+}
+
+fn encode_varint(mut value: u64, buf: &mut Vec<u8>) {
+    while value >= 0x80 {
+        buf.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+fn decode_varint(buf: &[u8]) -> Option<(u64, &[u8])> {
+    let mut value = 0u64;
+    for (i, byte) in buf.iter().enumerate().take(10) {
+        value |= u64::from(byte & 0x7f) << (7 * i);
+        if byte & 0x80 == 0 {
+            return Some((value, &buf[i + 1..]));
+        }
+    }
+    None
+}
@@ -0,0 +1,161 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use reqwest::redirect::Policy;
+use reqwest::Client;
+use tokio::net::TcpStream;
+use tokio::sync::watch;
+use tokio::time::timeout;
+use tracing::{debug, info, warn};
+
+use cert_keeper::config::BackendHealthCheck;
+use cert_keeper::dns::Resolver;
+use cert_keeper::error::{Error, Result};
+use cert_keeper::metrics;
+
+#[cfg(feature = "grpc-health")]
+pub mod grpc;
+
+/// How one backend address is checked.
+enum Probe {
+    /// A TCP connection is accepted.
+    Tcp,
+    /// `GET path` answers with a `2xx`.
+    Http { client: Client, path: String },
+    /// `grpc.health.v1.Health/Check` reports `SERVING`.
+    #[cfg(feature = "grpc-health")]
+    Grpc { client: Client, service: String },
+}
+
+impl Probe {
+    /// `None` for [`BackendHealthCheck::None`], and for gRPC in a build
+    /// without it, which config validation already rejects.
+    fn new(
+        check: BackendHealthCheck,
+        resolver: Arc<Resolver>,
+        timeout: Duration,
+    ) -> Result<Option<Self>> {
+        Ok(match check {
+            BackendHealthCheck::None => None,
+            BackendHealthCheck::Tcp => Some(Self::Tcp),
+            // Redirects are answers too, and following one could leave
+            // the backend.
+            BackendHealthCheck::Http { path } => Some(Self::Http {
+                client: Client::builder()
+                    .dns_resolver(resolver)
+                    .redirect(Policy::none())
+                    .no_proxy()
+                    .timeout(timeout)
+                    .build()?,
+                path,
+            }),
+            #[cfg(feature = "grpc-health")]
+            BackendHealthCheck::Grpc { service } => Some(Self::Grpc {
+                client: grpc::client(resolver, timeout)?,
+                service,
+            }),
+            #[cfg(not(feature = "grpc-health"))]
+            BackendHealthCheck::Grpc { .. } => None,
+        })
+    }
+
+    /// Check the backend at `addr`, failing with why it is not healthy.
+    async fn check(&self, resolver: &Resolver, addr: &str, limit: Duration) -> Result<()> {
+        match self {
+            Self::Tcp => {
+                let connect = async {
+                    let mut last_error = None;
+                    for resolved in resolver.lookup(addr).await? {
+                        match TcpStream::connect(resolved).await {
+                            Ok(_) => return Ok(()),
+                            Err(e) => last_error = Some(e),
+                        }
+                    }
+                    Err(Error::Backend(match last_error {
+                        Some(e) => format!("connect to {addr} failed: {e}"),
+                        None => format!("{addr} resolved to no addresses"),
+                    }))
+                };
+                timeout(limit, connect).await.unwrap_or_else(|_| {
+                    Err(Error::Backend(format!(
+                        "connect to {addr} timed out after {limit:?}"
+                    )))
+                })
+            }
+            Self::Http { client, path } => {
+                let status = client
+                    .get(format!("http://{addr}{path}"))
+                    .send()
+                    .await?
+                    .status();
+                if !status.is_success() {
+                    return Err(Error::Backend(format!("GET {path} returned {status}")));
+                }
+                Ok(())
+            }
+            #[cfg(feature = "grpc-health")]
+            Self::Grpc { client, service } => grpc::check(client, addr, service).await,
+        }
+    }
+}
+
+/// Periodically probe the backend and publish whether it is ready for traffic.
+///
+/// Each of `backend_addrs` is checked every `interval`, and the backend is
+/// ready while any of them passes. The proxy reads the published value
+/// after each handshake, so clients are told the backend is down, rather
+/// than left waiting on a connect, while the application itself is not
+/// serving. The result for each address is also the `backend_up` gauge.
+pub async fn run(
+    check: BackendHealthCheck,
+    backend_addrs: Vec<String>,
+    resolver: Arc<Resolver>,
+    interval: Duration,
+    timeout: Duration,
+    tx: watch::Sender<bool>,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    let probe = match Probe::new(check, resolver.clone(), timeout) {
+        Ok(Some(probe)) => probe,
+        Ok(None) => {
+            let _ = tx.send(true);
+            return;
+        }
+        Err(e) => {
+            warn!(error = %e, "failed to build backend health client, backend gating disabled");
+            let _ = tx.send(true);
+            return;
+        }
+    };
+
+    loop {
+        let mut healthy = false;
+        for addr in &backend_addrs {
+            let up = match probe.check(&resolver, addr, timeout).await {
+                Ok(()) => true,
+                Err(e) => {
+                    debug!(backend = %addr, error = %e, code = e.code(), "backend health check failed");
+                    false
+                }
+            };
+            metrics::set("backend_up", addr, if up { 1.0 } else { 0.0 });
+            healthy |= up;
+        }
+
+        let was_healthy = *tx.borrow();
+        if healthy != was_healthy {
+            let backend = backend_addrs.join(",");
+            if healthy {
+                info!(backend, "backend is healthy, accepting connections");
+            } else {
+                warn!(backend, "backend is not healthy, rejecting connections");
+            }
+        }
+        tx.send_replace(healthy);
+
+        tokio::select! {
+            _ = tokio::time::sleep(interval) => {}
+            _ = shutdown.changed() => return,
+        }
+    }
+}
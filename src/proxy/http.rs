@@ -0,0 +1,500 @@
+use std::convert::Infallible;
+use std::fmt::Write as _;
+use std::io;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use bytes::Bytes;
+use http_body_util::combinators::UnsyncBoxBody;
+use http_body_util::{BodyExt, Either, Full};
+use hyper::body::Incoming;
+use hyper::client::conn::http1 as client;
+use hyper::header::{
+    HeaderMap, HeaderName, HeaderValue, CACHE_CONTROL, CONNECTION, CONTENT_TYPE, FORWARDED,
+    UPGRADE,
+};
+use hyper::server::conn::http1 as server;
+use hyper::service::service_fn;
+use hyper::upgrade::OnUpgrade;
+use hyper::{Request, Response, StatusCode};
+use hyper_util::rt::TokioIo;
+use ring::rand::{SecureRandom, SystemRandom};
+use tokio::io::{copy_bidirectional, AsyncRead, AsyncWrite};
+use tokio::task::JoinHandle;
+use tracing::{debug, warn};
+
+use cert_keeper::error::{Error, Result};
+
+use crate::proxy::client_cert::ClientCert;
+use crate::proxy::forwarder::{connect, ActiveGuard, Backend, Close};
+use crate::proxy::listener::{Io, Peer};
+
+const X_FORWARDED_FOR: &str = "x-forwarded-for";
+const X_FORWARDED_PROTO: &str = "x-forwarded-proto";
+const X_REQUEST_ID: &str = "x-request-id";
+const X_FORWARDED_CLIENT_CERT: &str = "x-forwarded-client-cert";
+
+/// Headers about one connection rather than the message, which a proxy
+/// must not pass on (RFC 9110 section 7.6.1).
+const HOP_BY_HOP: &[&str] = &[
+    "connection",
+    "keep-alive",
+    "proxy-connection",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+];
+
+type Body = Either<Incoming, Full<Bytes>>;
+
+pub type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// A request body on its way to the backend, from whichever kind of client
+/// connection it came in on.
+pub type ProxyBody = UnsyncBoxBody<Bytes, BoxError>;
+
+/// How the requests on one client connection are forwarded.
+pub struct Options<'a> {
+    pub peer: Peer,
+    /// `HTTP_TRUST_FORWARDED`.
+    pub trust_forwarded: bool,
+    /// The client's certificate, if it sent one.
+    pub client_cert: Option<&'a ClientCert>,
+    /// PROXY protocol header sent ahead of each backend connection.
+    pub header: Option<&'a [u8]>,
+}
+
+/// The backend connections of one client connection.
+///
+/// A connection that has finished its last response is reused; one the
+/// backend has closed is dropped, and a new one is made when none is free.
+pub struct Pool<'a> {
+    backend: &'a Backend,
+    header: Option<&'a [u8]>,
+    idle: Mutex<Vec<Upstream<'a>>>,
+    /// The target of the latest connection, for the access log.
+    target: Mutex<Option<String>>,
+}
+
+/// One backend connection.
+struct Upstream<'a> {
+    sender: client::SendRequest<ProxyBody>,
+    /// Drives the connection; aborted with it so a client that goes away
+    /// mid-response does not leave it behind.
+    task: JoinHandle<()>,
+    _active: ActiveGuard<'a>,
+}
+
+impl Drop for Upstream<'_> {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+impl<'a> Pool<'a> {
+    pub fn new(backend: &'a Backend, header: Option<&'a [u8]>) -> Self {
+        Self {
+            backend,
+            header,
+            idle: Mutex::new(Vec::new()),
+            target: Mutex::new(None),
+        }
+    }
+
+    /// Add a connection made before the first request.
+    pub async fn add(&self, connected: (Box<dyn Io>, ActiveGuard<'a>)) -> Result<()> {
+        let upstream = self.handshake(connected).await?;
+        lock(&self.idle).push(upstream);
+        Ok(())
+    }
+
+    /// The target the latest connection went to.
+    #[cfg_attr(not(feature = "quic"), allow(dead_code))]
+    pub fn target(&self) -> Option<String> {
+        lock(&self.target).clone()
+    }
+
+    /// Send `request` over a free connection, or a new one if none is.
+    pub async fn send(&self, mut request: Request<ProxyBody>) -> Result<Response<Incoming>> {
+        loop {
+            let (mut upstream, fresh) = match self.take().await {
+                Some(upstream) => (upstream, false),
+                None => {
+                    let connected = connect(self.backend, self.header).await?;
+                    (self.handshake(connected).await?, true)
+                }
+            };
+            match upstream.sender.try_send_request(request).await {
+                Ok(response) => {
+                    lock(&self.idle).push(upstream);
+                    return Ok(response);
+                }
+                Err(mut e) => match e.take_message() {
+                    // Never written: the backend closed an idle connection
+                    // just as it was reused.
+                    Some(unsent) if !fresh => request = unsent,
+                    _ => {
+                        return Err(Error::Backend(format!(
+                            "request to the backend failed: {}",
+                            e.error()
+                        )))
+                    }
+                },
+            }
+        }
+    }
+
+    /// A connection that can take a request now, dropping the closed ones.
+    async fn take(&self) -> Option<Upstream<'a>> {
+        for _ in 0..2 {
+            {
+                let mut idle = lock(&self.idle);
+                idle.retain(|upstream| !upstream.sender.is_closed());
+                if let Some(free) = idle.iter().position(|upstream| upstream.sender.is_ready()) {
+                    return Some(idle.swap_remove(free));
+                }
+                if idle.is_empty() {
+                    return None;
+                }
+            }
+            // A connection whose response just ended is only ready once its
+            // task has run.
+            tokio::task::yield_now().await;
+        }
+        None
+    }
+
+    /// Start an HTTP/1.1 client connection over a connected stream.
+    async fn handshake(
+        &self,
+        (stream, active): (Box<dyn Io>, ActiveGuard<'a>),
+    ) -> Result<Upstream<'a>> {
+        let (sender, connection) = client::handshake(TokioIo::new(stream))
+            .await
+            .map_err(|e| Error::Backend(format!("HTTP handshake with the backend failed: {e}")))?;
+        let task = tokio::spawn(async move {
+            if let Err(e) = connection.with_upgrades().await {
+                debug!(error = %e, "backend connection ended");
+            }
+        });
+        *lock(&self.target) = Some(active.addr().to_string());
+        Ok(Upstream {
+            sender,
+            task,
+            _active: active,
+        })
+    }
+}
+
+/// Sets the forwarding headers on the requests of one client.
+pub struct Forwarding {
+    /// `None` for a client on a Unix socket.
+    client: Option<IpAddr>,
+    trust: bool,
+    /// With `FORWARD_CLIENT_CERT`, the `X-Forwarded-Client-Cert` element
+    /// of the client's certificate, `None` if it sent none.
+    client_cert: Option<Option<String>>,
+    random: SystemRandom,
+}
+
+impl Forwarding {
+    pub fn new(peer: Peer, trust: bool) -> Self {
+        let client = match peer {
+            Peer::Tcp { addr, .. } => Some(addr.ip().to_canonical()),
+            Peer::Unix => None,
+        };
+        Self {
+            client,
+            trust,
+            client_cert: None,
+            random: SystemRandom::new(),
+        }
+    }
+
+    /// Also set `X-Forwarded-Client-Cert`, to `client_cert`'s identity.
+    pub fn with_client_cert(mut self, client_cert: Option<&ClientCert>) -> Self {
+        self.client_cert = Some(client_cert.map(ClientCert::xfcc));
+        self
+    }
+
+    /// Replace or extend the forwarding headers, and return the request ID.
+    pub fn apply(&self, headers: &mut HeaderMap) -> String {
+        let client = self.client.map(|ip| ip.to_string());
+        let prior = self
+            .trust
+            .then(|| {
+                headers
+                    .get_all(X_FORWARDED_FOR)
+                    .iter()
+                    .filter_map(|v| v.to_str().ok())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            })
+            .filter(|prior| !prior.is_empty());
+        let forwarded_for = match (prior, client) {
+            (Some(prior), Some(client)) => Some(format!("{prior}, {client}")),
+            (prior, client) => prior.or(client),
+        };
+        headers.remove(X_FORWARDED_FOR);
+        if let Some(value) = forwarded_for.and_then(|v| HeaderValue::try_from(v).ok()) {
+            headers.insert(X_FORWARDED_FOR, value);
+        }
+        if !self.trust {
+            // Could otherwise be taken for what this proxy observed.
+            headers.remove(FORWARDED);
+        }
+        headers.insert(X_FORWARDED_PROTO, HeaderValue::from_static("https"));
+        if let Some(ref client_cert) = self.client_cert {
+            let prior = self
+                .trust
+                .then(|| {
+                    headers
+                        .get_all(X_FORWARDED_CLIENT_CERT)
+                        .iter()
+                        .filter_map(|v| v.to_str().ok())
+                        .collect::<Vec<_>>()
+                        .join(",")
+                })
+                .filter(|prior| !prior.is_empty());
+            let value = match (prior, client_cert) {
+                (Some(prior), Some(cert)) => Some(format!("{prior},{cert}")),
+                (prior, cert) => prior.or_else(|| cert.clone()),
+            };
+            headers.remove(X_FORWARDED_CLIENT_CERT);
+            if let Some(value) = value.and_then(|v| HeaderValue::try_from(v).ok()) {
+                headers.insert(X_FORWARDED_CLIENT_CERT, value);
+            }
+        }
+
+        let prior = headers
+            .get(X_REQUEST_ID)
+            .filter(|_| self.trust)
+            .and_then(|v| v.to_str().ok())
+            .filter(|v| !v.is_empty())
+            .map(String::from);
+        match prior {
+            Some(request_id) => request_id,
+            None => {
+                let request_id = self.request_id();
+                headers.insert(
+                    X_REQUEST_ID,
+                    HeaderValue::try_from(&request_id).expect("hex digits are a valid header"),
+                );
+                request_id
+            }
+        }
+    }
+
+    /// A random version 4 UUID.
+    fn request_id(&self) -> String {
+        let mut bytes = [0u8; 16];
+        // Only fails if the OS has no randomness to give, which is fatal
+        // for TLS long before this.
+        self.random
+            .fill(&mut bytes)
+            .expect("system random number generator failed");
+        bytes[6] = (bytes[6] & 0x0f) | 0x40;
+        bytes[8] = (bytes[8] & 0x3f) | 0x80;
+        let mut id = String::with_capacity(36);
+        for (i, b) in bytes.iter().enumerate() {
+            if matches!(i, 4 | 6 | 8 | 10) {
+                id.push('-');
+            }
+            let _ = write!(id, "{b:02x}");
+        }
+        id
+    }
+}
+
+/// The HTTP/1.1 side of one client connection.
+struct Proxy<'a> {
+    pool: Pool<'a>,
+    forwarding: Forwarding,
+    peer: Peer,
+    /// Both sides of a switched protocol, joined once the server has handed
+    /// the client connection over.
+    tunnel: Mutex<Option<(OnUpgrade, OnUpgrade)>>,
+}
+
+/// Serve HTTP/1.1 on `client`, sending each request on to the backend with
+/// `X-Forwarded-For`, `X-Forwarded-Proto`, and `X-Request-Id` set.
+///
+/// Requests go over `first` until the backend closes it, then over a new
+/// connection from [`connect`]. A request that switches protocols, such as
+/// a WebSocket handshake, turns the rest of the connection into a tunnel.
+pub async fn relay<'a, C>(
+    client: C,
+    first: (Box<dyn Io>, ActiveGuard<'a>),
+    backend: &'a Backend,
+    options: Options<'a>,
+) -> Result<Close>
+where
+    C: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let mut forwarding = Forwarding::new(options.peer, options.trust_forwarded);
+    if backend.forwards_client_cert() {
+        forwarding = forwarding.with_client_cert(options.client_cert);
+    }
+    let proxy = Proxy {
+        pool: Pool::new(backend, options.header),
+        forwarding,
+        peer: options.peer,
+        tunnel: Mutex::new(None),
+    };
+    proxy.pool.add(first).await?;
+
+    let service = service_fn(|request| async { Ok::<_, Infallible>(proxy.handle(request).await) });
+    server::Builder::new()
+        .serve_connection(TokioIo::new(client), service)
+        .with_upgrades()
+        .await
+        .map_err(io_error)?;
+
+    let tunnel = lock(&proxy.tunnel).take();
+    if let Some((client, server)) = tunnel {
+        let (client, server) = tokio::try_join!(client, server).map_err(io_error)?;
+        copy_bidirectional(&mut TokioIo::new(client), &mut TokioIo::new(server)).await?;
+    }
+    Ok(Close::Eof)
+}
+
+impl Proxy<'_> {
+    async fn handle(&self, mut request: Request<Incoming>) -> Response<Body> {
+        let upgrade = is_upgrade(request.headers()).then(|| hyper::upgrade::on(&mut request));
+        strip_hop_by_hop(request.headers_mut(), upgrade.is_some());
+        let request_id = self.forwarding.apply(request.headers_mut());
+        let method = request.method().clone();
+        let path = request.uri().path().to_string();
+
+        let request = request.map(|body| body.map_err(BoxError::from).boxed_unsync());
+        let mut response = match self.pool.send(request).await {
+            Ok(response) => response,
+            Err(e) => {
+                warn!(peer = %self.peer, request_id, method = %method, path, error = %e, code = e.code(), "backend request failed");
+                return bad_gateway().map(Either::Right);
+            }
+        };
+        let switched = response.status() == StatusCode::SWITCHING_PROTOCOLS;
+        match upgrade {
+            Some(client) if switched => {
+                let server = hyper::upgrade::on(&mut response);
+                *lock(&self.tunnel) = Some((client, server));
+            }
+            _ => strip_hop_by_hop(response.headers_mut(), false),
+        }
+        debug!(peer = %self.peer, request_id, method = %method, path, status = response.status().as_u16(), "request forwarded");
+        response.map(Either::Left)
+    }
+}
+
+/// The answer to a request the backend could not be reached for.
+pub fn bad_gateway() -> Response<Full<Bytes>> {
+    let mut response = Response::new(Full::new(Bytes::from_static(b"bad gateway\n")));
+    *response.status_mut() = StatusCode::BAD_GATEWAY;
+    response
+}
+
+/// The answer to a request while the backend is failing its health check.
+pub fn service_unavailable() -> Response<Full<Bytes>> {
+    let mut response = Response::new(Full::new(Bytes::from_static(b"backend unavailable\n")));
+    *response.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
+    response
+}
+
+/// Answer the first request on `client` with [`service_unavailable`], then
+/// close the connection.
+pub async fn unavailable<C>(client: C) -> Result<()>
+where
+    C: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let service = service_fn(|_| async { Ok::<_, Infallible>(service_unavailable()) });
+    server::Builder::new()
+        .keep_alive(false)
+        .serve_connection(TokioIo::new(client), service)
+        .await
+        .map_err(io_error)?;
+    Ok(())
+}
+
+/// The answer to a request while no certificate has been issued yet:
+/// `page` as HTML, never cached, since the real site is about to replace
+/// it.
+fn holding_page(page: &str) -> Response<Full<Bytes>> {
+    let mut response = Response::new(Full::new(Bytes::copy_from_slice(page.as_bytes())));
+    *response.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
+    let headers = response.headers_mut();
+    headers.insert(CONTENT_TYPE, HeaderValue::from_static("text/html; charset=utf-8"));
+    headers.insert(CACHE_CONTROL, HeaderValue::from_static("no-store"));
+    response
+}
+
+/// Answer the first request on `client` with [`holding_page`], then close
+/// the connection.
+pub async fn holding<C>(client: C, page: Arc<str>) -> Result<()>
+where
+    C: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let service = service_fn(move |_| {
+        let response = holding_page(&page);
+        async move { Ok::<_, Infallible>(response) }
+    });
+    server::Builder::new()
+        .keep_alive(false)
+        .serve_connection(TokioIo::new(client), service)
+        .await
+        .map_err(io_error)?;
+    Ok(())
+}
+
+/// Whether the request asks to switch protocols, as a WebSocket does.
+fn is_upgrade(headers: &HeaderMap) -> bool {
+    headers.contains_key(UPGRADE)
+        && headers
+            .get_all(CONNECTION)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .any(|token| token.trim().eq_ignore_ascii_case("upgrade"))
+}
+
+/// Remove the hop-by-hop headers, and any the `Connection` header names.
+/// `keep_upgrade` keeps what the backend needs to switch protocols.
+pub fn strip_hop_by_hop(headers: &mut HeaderMap, keep_upgrade: bool) {
+    let listed: Vec<HeaderName> = headers
+        .get_all(CONNECTION)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .filter_map(|token| HeaderName::try_from(token.trim()).ok())
+        .collect();
+    for name in listed {
+        if !(keep_upgrade && name == UPGRADE) {
+            headers.remove(name);
+        }
+    }
+    for name in HOP_BY_HOP {
+        if !(keep_upgrade && *name == "upgrade") {
+            headers.remove(*name);
+        }
+    }
+    if keep_upgrade {
+        headers.insert(CONNECTION, HeaderValue::from_static("upgrade"));
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// The I/O error behind a hyper error, so the access log can tell a reset
+/// from a client that stopped mid-request.
+fn io_error(e: hyper::Error) -> Error {
+    let kind = std::error::Error::source(&e)
+        .and_then(|source| source.downcast_ref::<io::Error>())
+        .map(io::Error::kind);
+    match kind {
+        Some(kind) => Error::Io(io::Error::new(kind, e)),
+        None => Error::Io(io::Error::other(e)),
+    }
+}
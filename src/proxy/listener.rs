@@ -0,0 +1,218 @@
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+use std::os::fd::{AsFd, OwnedFd};
+use std::os::unix::fs::FileTypeExt;
+
+use socket2::{Socket, Type};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpListener, UnixListener};
+use tracing::warn;
+
+use cert_keeper::config::{ListenAddr, TcpOptions};
+use cert_keeper::error::{Error, Result};
+
+use crate::proxy::tcp;
+use crate::systemd;
+
+/// A byte stream accepted from any listener kind.
+pub trait Io: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Io for T {}
+
+/// The proxy's listening socket: TCP, or a Unix domain socket for serving
+/// TLS to co-located components without exposing a TCP port.
+pub enum Listener {
+    /// With the options accepted connections get.
+    Tcp(TcpListener, TcpOptions),
+    Unix(UnixListener),
+}
+
+/// The remote end of an accepted connection.
+#[derive(Debug, Clone, Copy)]
+pub enum Peer {
+    Tcp {
+        addr: SocketAddr,
+        /// The address the connection was accepted on.
+        local: SocketAddr,
+    },
+    /// Unix peers are almost always unnamed, so there is nothing to record.
+    Unix,
+}
+
+impl Listener {
+    /// Bind `addr` for `count` acceptor tasks, returning a listener for
+    /// each of them on each address, address by address. TCP addresses are
+    /// bound `count` times with `SO_REUSEPORT`, so the kernel spreads
+    /// connections across the sockets; other kinds can only be bound once,
+    /// so their acceptors get copies of one socket.
+    pub async fn bind(addr: &ListenAddr, options: &TcpOptions, count: usize) -> Result<Vec<Self>> {
+        let bind_err = |e: io::Error| Error::Bind(format!("{addr}: {e}"));
+
+        let socket = match addr {
+            ListenAddr::Tcp(addrs) => return bind_tcp(addrs, only_v6(addrs), options, count),
+            ListenAddr::Unix(path) => {
+                // A socket file left behind by a previous run would make
+                // bind fail with EADDRINUSE.
+                if std::fs::symlink_metadata(path).is_ok_and(|m| m.file_type().is_socket()) {
+                    std::fs::remove_file(path).map_err(bind_err)?;
+                }
+                Self::Unix(UnixListener::bind(path).map_err(bind_err)?)
+            }
+            ListenAddr::UnixAbstract(name) => Self::Unix(bind_abstract(name).map_err(bind_err)?),
+            ListenAddr::Systemd(name) => systemd::listener(name)
+                .and_then(|fd| Self::from_passed(fd, options))
+                .map_err(bind_err)?,
+        };
+        let mut listeners = (1..count)
+            .map(|_| socket.try_clone())
+            .collect::<io::Result<Vec<_>>>()
+            .map_err(bind_err)?;
+        listeners.insert(0, socket);
+        Ok(listeners)
+    }
+
+    /// Bind what `addr` adds to `bound`, which stays listened on, returning
+    /// the addresses bound and their listeners as [`Listener::bind`] does.
+    /// Only TCP addresses can be kept; any other change binds all of
+    /// `addr`.
+    pub async fn bind_added(
+        addr: &ListenAddr,
+        bound: &ListenAddr,
+        options: &TcpOptions,
+        count: usize,
+    ) -> Result<(ListenAddr, Vec<Self>)> {
+        let (ListenAddr::Tcp(addrs), ListenAddr::Tcp(bound)) = (addr, bound) else {
+            return Ok((addr.clone(), Self::bind(addr, options, count).await?));
+        };
+        let added: Vec<_> = addrs.iter().filter(|a| !bound.contains(a)).copied().collect();
+        let listeners = bind_tcp(&added, only_v6(addrs), options, count)?;
+        Ok((ListenAddr::Tcp(added), listeners))
+    }
+
+    /// Another listener on the same socket.
+    fn try_clone(&self) -> io::Result<Self> {
+        match self {
+            Self::Tcp(listener, options) => {
+                let listener = std::net::TcpListener::from(listener.as_fd().try_clone_to_owned()?);
+                Ok(Self::Tcp(TcpListener::from_std(listener)?, options.clone()))
+            }
+            Self::Unix(listener) => {
+                let listener =
+                    std::os::unix::net::UnixListener::from(listener.as_fd().try_clone_to_owned()?);
+                Ok(Self::Unix(UnixListener::from_std(listener)?))
+            }
+        }
+    }
+
+    /// Listen on a socket systemd passed, which is already bound.
+    fn from_passed(fd: OwnedFd, options: &TcpOptions) -> io::Result<Self> {
+        let socket = Socket::from(fd);
+        if socket.r#type()? != Type::STREAM {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "not a stream socket; set ListenStream= in the socket unit",
+            ));
+        }
+        socket.set_nonblocking(true)?;
+        if socket.local_addr()?.is_unix() {
+            let listener = std::os::unix::net::UnixListener::from(OwnedFd::from(socket));
+            Ok(Self::Unix(UnixListener::from_std(listener)?))
+        } else {
+            let listener = TcpListener::from_std(socket.into())?;
+            Ok(Self::Tcp(listener, options.clone()))
+        }
+    }
+
+    pub async fn accept(&self) -> io::Result<(Box<dyn Io>, Peer)> {
+        match self {
+            Self::Tcp(listener, options) => {
+                let (stream, addr) = listener.accept().await?;
+                let local = stream.local_addr()?;
+                // IPv4 clients of a dual-stack socket as IPv4, not mapped
+                // into IPv6.
+                let addr = SocketAddr::new(addr.ip().to_canonical(), addr.port());
+                let local = SocketAddr::new(local.ip().to_canonical(), local.port());
+                if let Err(e) = tcp::configure(&stream, options) {
+                    warn!(peer = %addr, error = %e, "failed to set TCP socket options");
+                }
+                Ok((Box::new(stream), Peer::Tcp { addr, local }))
+            }
+            Self::Unix(listener) => {
+                let (stream, _) = listener.accept().await?;
+                Ok((Box::new(stream), Peer::Unix))
+            }
+        }
+    }
+
+    /// Human-readable local address for logging.
+    pub fn local_addr(&self) -> String {
+        match self {
+            Self::Tcp(listener, _) => listener
+                .local_addr()
+                .map(|a| a.to_string())
+                .unwrap_or_else(|e| e.to_string()),
+            Self::Unix(listener) => match listener.local_addr() {
+                Ok(addr) => format!("{addr:?}"),
+                Err(e) => e.to_string(),
+            },
+        }
+    }
+}
+
+impl fmt::Display for Peer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Tcp { addr, .. } => addr.fmt(f),
+            Self::Unix => f.write_str("unix"),
+        }
+    }
+}
+
+/// Whether the IPv6 addresses of `addrs` only take IPv6 connections: they
+/// do when listed next to an IPv4 address, so that both can be bound;
+/// otherwise they take IPv4 ones too, whatever the system default.
+fn only_v6(addrs: &[SocketAddr]) -> bool {
+    addrs.iter().any(SocketAddr::is_ipv4)
+}
+
+/// `count` listeners on each of `addrs`, IPv6 ones `only_v6` or not.
+fn bind_tcp(
+    addrs: &[SocketAddr],
+    only_v6: bool,
+    options: &TcpOptions,
+    count: usize,
+) -> Result<Vec<Listener>> {
+    let reuse_port = count > 1;
+    let mut listeners = Vec::new();
+    for &addr in addrs {
+        let bind_err = |e: io::Error| Error::Bind(format!("{addr}: {e}"));
+        let first = tcp::listen(addr, options, only_v6, reuse_port).map_err(bind_err)?;
+        // The port the first one got, if `addr` asks for any.
+        let local = first.local_addr().map_err(bind_err)?;
+        listeners.push(Listener::Tcp(first, options.clone()));
+        for _ in 1..count {
+            let listener = tcp::listen(local, options, only_v6, true).map_err(bind_err)?;
+            listeners.push(Listener::Tcp(listener, options.clone()));
+        }
+    }
+    Ok(listeners)
+}
+
+#[cfg(target_os = "linux")]
+fn bind_abstract(name: &str) -> io::Result<UnixListener> {
+    use std::os::linux::net::SocketAddrExt;
+
+    let addr = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+    let listener = std::os::unix::net::UnixListener::bind_addr(&addr)?;
+    listener.set_nonblocking(true)?;
+    UnixListener::from_std(listener)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn bind_abstract(_name: &str) -> io::Result<UnixListener> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "abstract Unix sockets are only available on Linux",
+    ))
+}
@@ -1,2 +1,19 @@
+pub mod backend_tls;
+pub mod client_cert;
+#[cfg(feature = "dtls")]
+pub mod dtls;
 pub mod forwarder;
+pub mod health;
+#[cfg(feature = "http-proxy")]
+pub mod http;
+pub mod listener;
+pub mod passthrough;
+pub mod proxy_protocol;
+#[cfg(feature = "quic")]
+pub mod quic;
+#[cfg(feature = "http-redirect")]
+pub mod redirect;
+pub mod shadow;
+pub mod tcp;
+pub mod throttle;
 pub mod tls_acceptor;
@@ -0,0 +1,118 @@
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use rustls::server::Acceptor;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, ReadBuf};
+
+use crate::proxy::listener::Io;
+
+/// A ClientHello may span several records, but one this large is not a
+/// client worth waiting on.
+const MAX_CLIENT_HELLO: usize = 64 * 1024;
+
+/// Read the ClientHello off `stream` without answering it, for
+/// `PROXY_MODE=passthrough`. Returns the bytes read, which the backend
+/// must get first, and the SNI the client asked for, if any.
+///
+/// A stream that is not TLS fails with the `rustls` error inside, like a
+/// failed handshake does.
+pub async fn read_client_hello(stream: &mut Box<dyn Io>) -> io::Result<(Vec<u8>, Option<String>)> {
+    let mut acceptor = Acceptor::default();
+    let mut read = Vec::new();
+    let mut chunk = [0u8; 4096];
+    loop {
+        let n = stream.read(&mut chunk).await?;
+        if n == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        read.extend_from_slice(&chunk[..n]);
+        if read.len() > MAX_CLIENT_HELLO {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "ClientHello too large",
+            ));
+        }
+
+        let mut rest = &chunk[..n];
+        while !rest.is_empty() {
+            acceptor.read_tls(&mut rest)?;
+        }
+        match acceptor.accept() {
+            Ok(Some(accepted)) => {
+                let sni = accepted.client_hello().server_name().map(str::to_string);
+                return Ok((read, sni));
+            }
+            Ok(None) => {}
+            Err((e, _)) => return Err(io::Error::new(io::ErrorKind::InvalidData, e)),
+        }
+    }
+}
+
+/// A stream whose reads start with bytes already taken off it.
+pub struct Rewound<S> {
+    read: Vec<u8>,
+    pos: usize,
+    inner: S,
+}
+
+impl<S> Rewound<S> {
+    pub fn new(read: Vec<u8>, inner: S) -> Self {
+        Self {
+            read,
+            pos: 0,
+            inner,
+        }
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for Rewound<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if this.pos < this.read.len() {
+            let n = buf.remaining().min(this.read.len() - this.pos);
+            buf.put_slice(&this.read[this.pos..this.pos + n]);
+            this.pos += n;
+            if this.pos == this.read.len() {
+                this.read = Vec::new();
+                this.pos = 0;
+            }
+            return Poll::Ready(Ok(()));
+        }
+        Pin::new(&mut this.inner).poll_read(cx, buf)
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for Rewound<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().inner).poll_write_vectored(cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.inner.is_write_vectored()
+    }
+}
@@ -0,0 +1,137 @@
+use std::net::SocketAddr;
+
+use tracing::warn;
+
+use cert_keeper::config::ProxyProtocol;
+
+use crate::proxy::client_cert::ClientCert;
+use crate::proxy::listener::Peer;
+
+/// Fixed prefix of every v2 header.
+const V2_SIGNATURE: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+/// Version 2; PROXY (relayed connection) or LOCAL (no address to pass on).
+const V2_CMD_PROXY: u8 = 0x21;
+const V2_CMD_LOCAL: u8 = 0x20;
+/// Address family and transport.
+const V2_TCP4: u8 = 0x11;
+const V2_TCP6: u8 = 0x21;
+const V2_UNSPEC: u8 = 0x00;
+/// TLV types: TLS details, with the common name of the client certificate
+/// among them, and the first of the range left for applications.
+const PP2_TYPE_SSL: u8 = 0x20;
+const PP2_SUBTYPE_SSL_CN: u8 = 0x22;
+const PP2_TYPE_CLIENT_CERT: u8 = 0xE0;
+/// `PP2_TYPE_SSL` flags: the client connected over TLS and presented a
+/// certificate on this connection.
+const PP2_CLIENT_SSL: u8 = 0x01;
+const PP2_CLIENT_CERT_CONN: u8 = 0x02;
+
+/// The PROXY protocol header announcing `peer` to the backend, or `None`
+/// when `BACKEND_PROXY_PROTOCOL` is off. A v2 header also carries
+/// `client_cert`, if given.
+pub fn header(
+    version: ProxyProtocol,
+    peer: Peer,
+    client_cert: Option<&ClientCert>,
+) -> Option<Vec<u8>> {
+    let addrs = match peer {
+        // A dual-stack listener reports IPv4 clients as mapped IPv6.
+        Peer::Tcp { addr, local } => Some((canonical(addr), canonical(local)))
+            .filter(|(src, dst)| src.is_ipv4() == dst.is_ipv4()),
+        Peer::Unix => None,
+    };
+
+    match version {
+        ProxyProtocol::None => None,
+        ProxyProtocol::V1 => Some(v1(addrs)),
+        ProxyProtocol::V2 => Some(v2(addrs, client_cert)),
+    }
+}
+
+fn canonical(addr: SocketAddr) -> SocketAddr {
+    SocketAddr::new(addr.ip().to_canonical(), addr.port())
+}
+
+fn v1(addrs: Option<(SocketAddr, SocketAddr)>) -> Vec<u8> {
+    let line = match addrs {
+        Some((src, dst)) => format!(
+            "PROXY {} {} {} {} {}\r\n",
+            if src.is_ipv4() { "TCP4" } else { "TCP6" },
+            src.ip(),
+            dst.ip(),
+            src.port(),
+            dst.port()
+        ),
+        None => "PROXY UNKNOWN\r\n".to_string(),
+    };
+    line.into_bytes()
+}
+
+fn v2(addrs: Option<(SocketAddr, SocketAddr)>, client_cert: Option<&ClientCert>) -> Vec<u8> {
+    let mut out = V2_SIGNATURE.to_vec();
+    let mut body = Vec::with_capacity(36);
+    let family = match addrs {
+        Some((SocketAddr::V4(src), SocketAddr::V4(dst))) => {
+            body.extend_from_slice(&src.ip().octets());
+            body.extend_from_slice(&dst.ip().octets());
+            body.extend_from_slice(&src.port().to_be_bytes());
+            body.extend_from_slice(&dst.port().to_be_bytes());
+            V2_TCP4
+        }
+        Some((SocketAddr::V6(src), SocketAddr::V6(dst))) => {
+            body.extend_from_slice(&src.ip().octets());
+            body.extend_from_slice(&dst.ip().octets());
+            body.extend_from_slice(&src.port().to_be_bytes());
+            body.extend_from_slice(&dst.port().to_be_bytes());
+            V2_TCP6
+        }
+        _ => V2_UNSPEC,
+    };
+    if let Some(cert) = client_cert {
+        // The header's length is 16 bits. A certificate whose identity
+        // would not fit is left out, rather than the header corrupted.
+        let tlvs = client_cert_tlvs(cert)
+            .filter(|tlvs| u16::try_from(body.len() + tlvs.len()).is_ok());
+        match tlvs {
+            Some(tlvs) => body.extend_from_slice(&tlvs),
+            None => warn!("client certificate too large for the PROXY header, sending it without"),
+        }
+    }
+
+    out.push(if family == V2_UNSPEC {
+        V2_CMD_LOCAL
+    } else {
+        V2_CMD_PROXY
+    });
+    out.push(family);
+    let len = u16::try_from(body.len()).expect("addresses and checked TLVs fit");
+    out.extend_from_slice(&len.to_be_bytes());
+    out.extend_from_slice(&body);
+    out
+}
+
+/// A `PP2_TYPE_SSL` TLV with the certificate's common name, as HAProxy
+/// sends it, and a `PP2_TYPE_CLIENT_CERT` one with its
+/// `X-Forwarded-Client-Cert` element, which has the subject, SANs, and
+/// hash. `None` if a value is too long for its TLV.
+fn client_cert_tlvs(cert: &ClientCert) -> Option<Vec<u8>> {
+    // Flags, then a verification result of 0, for success.
+    let mut ssl = vec![PP2_CLIENT_SSL | PP2_CLIENT_CERT_CONN, 0, 0, 0, 0];
+    if let Some(ref cn) = cert.common_name {
+        tlv(&mut ssl, PP2_SUBTYPE_SSL_CN, cn.as_bytes())?;
+    }
+    let mut out = Vec::new();
+    tlv(&mut out, PP2_TYPE_SSL, &ssl)?;
+    tlv(&mut out, PP2_TYPE_CLIENT_CERT, cert.xfcc().as_bytes())?;
+    Some(out)
+}
+
+/// Append a TLV, or `None` if `value` is longer than its length field
+/// allows.
+fn tlv(out: &mut Vec<u8>, kind: u8, value: &[u8]) -> Option<()> {
+    let len = u16::try_from(value.len()).ok()?;
+    out.push(kind);
+    out.extend_from_slice(&len.to_be_bytes());
+    out.extend_from_slice(value);
+    Some(())
+}
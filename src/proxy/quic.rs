@@ -0,0 +1,618 @@
+use std::net::{SocketAddr, UdpSocket};
+use std::pin::{pin, Pin};
+use std::sync::Arc;
+use std::task::{ready, Context, Poll};
+use std::time::{Duration, Instant};
+
+use bytes::{Buf, Bytes};
+use futures_util::stream::{FuturesUnordered, StreamExt};
+use h3::server::{RequestResolver, RequestStream};
+use http_body_util::{BodyExt, Empty};
+use hyper::body::{Body, Frame};
+use hyper::header::{HeaderValue, CONTENT_LENGTH, COOKIE, HOST};
+use hyper::http::request::Parts;
+use hyper::{Method, Request, Response, StatusCode, Uri, Version};
+use quinn::crypto::rustls::{HandshakeData, QuicServerConfig};
+use quinn::{
+    Connecting, ConnectionError, Endpoint, EndpointConfig, IdleTimeout, TokioRuntime,
+    TransportConfig, VarInt,
+};
+use rustls::pki_types::CertificateDer;
+use rustls::ServerConfig;
+use tokio::sync::{watch, Semaphore};
+use tokio::task::JoinSet;
+use tokio::time::timeout;
+use tracing::field::Empty as EmptyField;
+use tracing::{debug, error, info, info_span, warn, Instrument, Span};
+
+use cert_keeper::config::Config;
+use cert_keeper::error::{Error, Result};
+use cert_keeper::metrics;
+
+use crate::proxy::client_cert::ClientCert;
+use crate::proxy::forwarder::Backend;
+use crate::proxy::http::{bad_gateway, strip_hop_by_hop, BoxError, Forwarding, Pool, ProxyBody};
+use crate::proxy::listener::Peer;
+use crate::proxy::tls_acceptor;
+
+/// The only ALPN protocol offered over QUIC.
+const ALPN_H3: &[u8] = b"h3";
+
+type Resolver = RequestResolver<h3_quinn::Connection, Bytes>;
+type SendStream = RequestStream<h3_quinn::SendStream<Bytes>, Bytes>;
+type RecvStream = RequestStream<h3_quinn::RecvStream, Bytes>;
+
+/// How [`run`] handles connections.
+pub struct Options {
+    /// Connection limit, timeouts, access log, and address filtering, the
+    /// same as on the TLS listener.
+    pub listener: tls_acceptor::Options,
+    /// Close connections with no traffic for this long. `None` leaves it
+    /// to the client.
+    pub idle_timeout: Option<Duration>,
+    /// `HTTP_TRUST_FORWARDED`.
+    pub trust_forwarded: bool,
+}
+
+impl Options {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            listener: tls_acceptor::Options::from_config(config),
+            idle_timeout: config.idle_timeout,
+            trust_forwarded: config.http_trust_forwarded,
+        }
+    }
+
+    /// Take the settings of a reloaded config that apply to a running
+    /// endpoint.
+    pub fn reload(&mut self, config: &Config) {
+        self.listener.reload(config);
+        self.idle_timeout = config.idle_timeout;
+    }
+}
+
+/// Bind the QUIC endpoint. It answers no handshakes until [`run`] has a
+/// certificate for it.
+pub fn bind(addr: SocketAddr) -> Result<Endpoint> {
+    let bind_err = |e: std::io::Error| Error::Bind(format!("{addr} (udp): {e}"));
+    let socket = UdpSocket::bind(addr).map_err(bind_err)?;
+    Endpoint::new(
+        EndpointConfig::default(),
+        None,
+        socket,
+        Arc::new(TokioRuntime),
+    )
+    .map_err(bind_err)
+}
+
+/// Serve HTTP/3 on `endpoint`, translating each request to HTTP/1.1 for
+/// the backend.
+///
+/// The endpoint presents the same certificates as the TLS listener and
+/// picks up renewals from the same watch channel. Clients are turned away
+/// by address, while `backend_ready` is false, and beyond the connection
+/// limit, as on the TLS listener. Reloaded `settings` apply as they do
+/// there, and to the idle timeout of new connections.
+///
+/// On shutdown each connection is sent a GOAWAY, and its open requests get
+/// up to the drain timeout to finish.
+pub async fn run(
+    endpoint: Endpoint,
+    backend: watch::Receiver<Arc<Backend>>,
+    mut config_rx: watch::Receiver<Option<Arc<ServerConfig>>>,
+    backend_ready: watch::Receiver<bool>,
+    mut options: Options,
+    mut settings: watch::Receiver<Arc<Config>>,
+    mut shutdown: watch::Receiver<bool>,
+) -> Result<()> {
+    let local = endpoint.local_addr()?;
+    let mut listening = false;
+    // Pick up a certificate that is already there.
+    config_rx.mark_changed();
+
+    let mut connections = JoinSet::new();
+    let limit = Arc::new(Semaphore::new(options.listener.max_connections));
+    loop {
+        tokio::select! {
+            Ok(()) = config_rx.changed() => {
+                let Some(tls) = config_rx.borrow_and_update().clone() else {
+                    continue;
+                };
+                match server_config(&tls, options.idle_timeout) {
+                    Ok(config) => endpoint.set_server_config(Some(config)),
+                    Err(e) => {
+                        error!(error = %e, code = e.code(), "failed to load certificate for QUIC");
+                        continue;
+                    }
+                }
+                if !listening {
+                    listening = true;
+                    info!(addr = %local, "HTTP/3 listening");
+                }
+            }
+            Some(incoming) = endpoint.accept() => {
+                // Announced like a TCP client: the forwarding headers and
+                // the PROXY protocol only carry the addresses, and the
+                // backend connection is TCP either way.
+                let peer_addr = Peer::Tcp {
+                    addr: incoming.remote_address(),
+                    local,
+                };
+                debug!(peer = %peer_addr, "accepted QUIC connection");
+
+                if let Some(setting) = options.listener.refused_by(&peer_addr) {
+                    incoming.refuse();
+                    let count = metrics::inc("connections_rejected", setting);
+                    info!(peer = %peer_addr, count, "connection refused by {}", setting.to_uppercase());
+                    continue;
+                }
+
+                if !*backend_ready.borrow() {
+                    incoming.refuse();
+                    debug!(peer = %peer_addr, "backend not ready, refusing connection");
+                    continue;
+                }
+
+                let Ok(permit) = limit.clone().try_acquire_owned() else {
+                    incoming.refuse();
+                    let count = metrics::inc("connections_rejected", "max_connections");
+                    debug!(peer = %peer_addr, count, "connection limit reached, refusing connection");
+                    continue;
+                };
+
+                let connecting = match incoming.accept() {
+                    Ok(connecting) => connecting,
+                    Err(e) => {
+                        debug!(peer = %peer_addr, error = %e, "failed to accept QUIC connection");
+                        continue;
+                    }
+                };
+
+                let backend = backend.borrow().clone();
+                let handshake_timeout = options.listener.handshake_timeout;
+                let access_log = options.listener.access_log;
+                let trust_forwarded = options.trust_forwarded;
+                let shutdown = shutdown.clone();
+                // Each connection is its own trace.
+                let span = info_span!(
+                    parent: None,
+                    "connection",
+                    peer = %peer_addr,
+                    sni = EmptyField,
+                    close_reason = EmptyField
+                );
+                connections.spawn(
+                    async move {
+                        let _permit = permit;
+                        let connection = Connection {
+                            peer: peer_addr,
+                            backend: &backend,
+                            trust_forwarded,
+                            access_log,
+                        };
+                        connection.handle(connecting, handshake_timeout, shutdown).await;
+                    }
+                    .instrument(span),
+                );
+            }
+            Ok(()) = settings.changed() => {
+                options.reload(&settings.borrow_and_update());
+                // The idle timeout is part of the server config.
+                config_rx.mark_changed();
+            }
+            // Reap finished connections so the set only holds open ones.
+            Some(_) = connections.join_next(), if !connections.is_empty() => {}
+            _ = shutdown.changed() => break,
+        }
+    }
+
+    // Refuse new connections while the open ones drain.
+    endpoint.set_server_config(None);
+    info!(
+        connections = connections.len(),
+        drain_timeout_secs = options.listener.drain_timeout.as_secs(),
+        "HTTP/3 listener shutting down, draining connections"
+    );
+    let drained = timeout(options.listener.drain_timeout, async {
+        while connections.join_next().await.is_some() {}
+    })
+    .await;
+    if drained.is_err() {
+        warn!(
+            connections = connections.len(),
+            "drain timeout reached, closing remaining QUIC connections"
+        );
+        connections.shutdown().await;
+    }
+    endpoint.close(VarInt::from_u32(0), b"shutting down");
+    endpoint.wait_idle().await;
+    Ok(())
+}
+
+/// The QUIC server config for the listener's TLS config `tls`.
+fn server_config(
+    tls: &ServerConfig,
+    idle_timeout: Option<Duration>,
+) -> Result<quinn::ServerConfig> {
+    let mut tls = tls.clone();
+    tls.alpn_protocols = vec![ALPN_H3.to_vec()];
+    let crypto = QuicServerConfig::try_from(tls).map_err(|e| Error::Tls(e.to_string()))?;
+
+    let mut transport = TransportConfig::default();
+    // Beyond what QUIC can express, which is far beyond any useful timeout.
+    transport.max_idle_timeout(idle_timeout.and_then(|d| IdleTimeout::try_from(d).ok()));
+    let mut config = quinn::ServerConfig::with_crypto(Arc::new(crypto));
+    config.transport_config(Arc::new(transport));
+    Ok(config)
+}
+
+/// One client connection.
+struct Connection<'a> {
+    peer: Peer,
+    backend: &'a Backend,
+    trust_forwarded: bool,
+    access_log: bool,
+}
+
+impl Connection<'_> {
+    /// Complete the handshake and serve requests until the client closes
+    /// the connection or shutdown.
+    async fn handle(
+        &self,
+        connecting: Connecting,
+        handshake_timeout: Duration,
+        shutdown: watch::Receiver<bool>,
+    ) {
+        let peer_addr = self.peer;
+        let start = Instant::now();
+        let handshake = timeout(handshake_timeout, connecting)
+            .instrument(info_span!("tls_handshake"))
+            .await
+            .unwrap_or(Err(ConnectionError::TimedOut));
+        let result = if handshake.is_ok() {
+            "success"
+        } else {
+            "failure"
+        };
+        metrics::observe(
+            "tls_handshake_duration_seconds",
+            result,
+            metrics::DURATION_BUCKETS,
+            start.elapsed().as_secs_f64(),
+        );
+        let connection = match handshake {
+            Ok(connection) => connection,
+            Err(e) => {
+                let reason = handshake_failure_reason(&e);
+                let count = metrics::inc("tls_handshake_failures", reason);
+                info!(peer = %peer_addr, reason, count, error = %e, "QUIC handshake failed");
+                tls_acceptor::observe_connection("handshake_failure", start);
+                return;
+            }
+        };
+
+        let sni = connection
+            .handshake_data()
+            .and_then(|data| data.downcast::<HandshakeData>().ok())
+            .and_then(|data| data.server_name)
+            .unwrap_or_else(|| "-".into());
+        if sni != "-" {
+            Span::current().record("sni", sni.as_str());
+        }
+        debug!(peer = %peer_addr, sni, "QUIC handshake completed");
+
+        let client_cert = self
+            .backend
+            .forwards_client_cert()
+            .then(|| connection.peer_identity())
+            .flatten()
+            .and_then(|identity| identity.downcast::<Vec<CertificateDer<'static>>>().ok())
+            .and_then(|chain| ClientCert::from_chain(Some(&chain)));
+        let header = self.backend.proxy_header(self.peer, client_cert.as_ref());
+        let pool = Pool::new(self.backend, header.as_deref());
+        let mut forwarding = Forwarding::new(self.peer, self.trust_forwarded);
+        if self.backend.forwards_client_cert() {
+            forwarding = forwarding.with_client_cert(client_cert.as_ref());
+        }
+        self.serve(connection.clone(), &pool, &forwarding, shutdown)
+            .await;
+
+        let close_reason = close_reason(connection.close_reason());
+        Span::current().record("close_reason", close_reason);
+        // Each request has its own backend connection, so a refused one
+        // does not end the connection.
+        tls_acceptor::observe_connection("success", start);
+
+        let stats = connection.stats();
+        if self.access_log {
+            info!(
+                target: "access",
+                peer = %peer_addr,
+                sni,
+                alpn = "h3",
+                version = "TLSv1_3",
+                backend = pool.target().as_deref().unwrap_or("-"),
+                bytes_in = stats.udp_rx.bytes,
+                bytes_out = stats.udp_tx.bytes,
+                duration_ms = start.elapsed().as_millis() as u64,
+                close_reason,
+                "access"
+            );
+        } else {
+            debug!(
+                peer = %peer_addr,
+                client_to_server = stats.udp_rx.bytes,
+                server_to_client = stats.udp_tx.bytes,
+                close_reason,
+                "connection closed"
+            );
+        }
+    }
+
+    /// Take requests off the connection and relay them side by side.
+    async fn serve(
+        &self,
+        connection: quinn::Connection,
+        pool: &Pool<'_>,
+        forwarding: &Forwarding,
+        mut shutdown: watch::Receiver<bool>,
+    ) {
+        let mut h3 = match h3::server::builder()
+            .build(h3_quinn::Connection::new(connection))
+            .await
+        {
+            Ok(h3) => h3,
+            Err(e) => {
+                debug!(peer = %self.peer, error = %e, "HTTP/3 connection setup failed");
+                return;
+            }
+        };
+
+        let mut requests = FuturesUnordered::new();
+        let mut accepting = true;
+        loop {
+            tokio::select! {
+                result = h3.accept(), if accepting => match result {
+                    Ok(Some(resolver)) => requests.push(self.request(resolver, pool, forwarding)),
+                    // The client sent a GOAWAY and its requests are done.
+                    Ok(None) => accepting = false,
+                    Err(e) => {
+                        if !e.is_h3_no_error() {
+                            debug!(peer = %self.peer, error = %e, "HTTP/3 connection ended");
+                        }
+                        accepting = false;
+                    }
+                },
+                Some(()) = requests.next(), if !requests.is_empty() => {}
+                // Let the client finish what it has started and send
+                // anything new elsewhere.
+                _ = shutdown.changed(), if accepting => {
+                    let _ = h3.shutdown(0).await;
+                    accepting = false;
+                }
+                else => break,
+            }
+        }
+    }
+
+    /// Relay one request to the backend and its response back.
+    async fn request(&self, resolver: Resolver, pool: &Pool<'_>, forwarding: &Forwarding) {
+        let (request, stream) = match resolver.resolve_request().await {
+            Ok(resolved) => resolved,
+            Err(e) => {
+                debug!(peer = %self.peer, error = %e, "failed to read HTTP/3 request");
+                return;
+            }
+        };
+        let (mut send, recv) = stream.split();
+        let (mut parts, ()) = request.into_parts();
+        let method = parts.method.clone();
+        let path = parts.uri.path().to_string();
+
+        // There is no HTTP/1.1 equivalent to tunnel it over.
+        if method == Method::CONNECT {
+            let mut response = Response::new(Empty::<Bytes>::new());
+            *response.status_mut() = StatusCode::NOT_IMPLEMENTED;
+            let _ = respond(&mut send, response).await;
+            return;
+        }
+
+        let request_id = forwarding.apply(&mut parts.headers);
+        let request = match to_http1(parts, recv).await {
+            Ok(request) => request,
+            Err(e) => {
+                debug!(peer = %self.peer, request_id, error = %e, "failed to read HTTP/3 request body");
+                return;
+            }
+        };
+        let response = match pool.send(request).await {
+            Ok(response) => response,
+            Err(e) => {
+                warn!(peer = %self.peer, request_id, method = %method, path, error = %e, code = e.code(), "backend request failed");
+                let _ = respond(&mut send, bad_gateway()).await;
+                return;
+            }
+        };
+        let status = response.status().as_u16();
+        match respond(&mut send, response).await {
+            Ok(()) => {
+                debug!(peer = %self.peer, request_id, method = %method, path, status, "request forwarded")
+            }
+            Err(e) => {
+                debug!(peer = %self.peer, request_id, method = %method, path, status, error = %e, "failed to send HTTP/3 response")
+            }
+        }
+    }
+}
+
+/// Turn the head of an HTTP/3 request into an HTTP/1.1 one for the
+/// backend, with `recv` as its body.
+async fn to_http1(mut parts: Parts, mut recv: RecvStream) -> Result<Request<ProxyBody>> {
+    // HTTP/1.1 carries the authority in `Host` and only the path in the
+    // request line.
+    if let Some(authority) = parts.uri.authority() {
+        if !parts.headers.contains_key(HOST) {
+            let host = HeaderValue::from_str(authority.as_str())
+                .map_err(|e| Error::Backend(format!("invalid authority: {e}")))?;
+            parts.headers.insert(HOST, host);
+        }
+    }
+    parts.uri = parts
+        .uri
+        .path_and_query()
+        .cloned()
+        .map_or_else(|| Uri::from_static("/"), Uri::from);
+    parts.version = Version::HTTP_11;
+
+    // HTTP/3 may split cookies over several fields; HTTP/1.1 expects one
+    // (RFC 9114 section 4.2.1).
+    let cookies = parts
+        .headers
+        .get_all(COOKIE)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .collect::<Vec<_>>();
+    if cookies.len() > 1 {
+        if let Ok(joined) = HeaderValue::try_from(cookies.join("; ")) {
+            parts.headers.insert(COOKIE, joined);
+        }
+    }
+    strip_hop_by_hop(&mut parts.headers, false);
+
+    // Without a length the body would go out chunked, which is wrong for a
+    // request that has none, so look for one first.
+    let body = if parts.headers.contains_key(CONTENT_LENGTH) {
+        RecvBody::new(recv, None).boxed_unsync()
+    } else {
+        match recv.recv_data().await.map_err(stream_error)? {
+            Some(mut first) => {
+                RecvBody::new(recv, Some(first.copy_to_bytes(first.remaining()))).boxed_unsync()
+            }
+            None => Empty::new().map_err(|never| match never {}).boxed_unsync(),
+        }
+    };
+    Ok(Request::from_parts(parts, body))
+}
+
+/// Send `response` back over HTTP/3, body and trailers included.
+async fn respond<B>(
+    send: &mut SendStream,
+    response: Response<B>,
+) -> std::result::Result<(), BoxError>
+where
+    B: Body<Data = Bytes>,
+    B::Error: Into<BoxError>,
+{
+    let (mut parts, body) = response.into_parts();
+    strip_hop_by_hop(&mut parts.headers, false);
+    send.send_response(Response::from_parts(parts, ())).await?;
+
+    let mut body = pin!(body);
+    while let Some(frame) = body.frame().await {
+        match frame.map_err(Into::into)?.into_data() {
+            Ok(data) => send.send_data(data).await?,
+            Err(frame) => {
+                if let Ok(trailers) = frame.into_trailers() {
+                    send.send_trailers(trailers).await?;
+                    break;
+                }
+            }
+        }
+    }
+    send.finish().await?;
+    Ok(())
+}
+
+/// A request body read off an HTTP/3 stream.
+struct RecvBody {
+    stream: RecvStream,
+    /// Data read ahead to tell whether there is a body at all.
+    first: Option<Bytes>,
+    state: RecvState,
+}
+
+enum RecvState {
+    Data,
+    Trailers,
+    Done,
+}
+
+impl RecvBody {
+    fn new(stream: RecvStream, first: Option<Bytes>) -> Self {
+        Self {
+            stream,
+            first,
+            state: RecvState::Data,
+        }
+    }
+}
+
+impl Body for RecvBody {
+    type Data = Bytes;
+    type Error = BoxError;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<std::result::Result<Frame<Bytes>, BoxError>>> {
+        let this = self.get_mut();
+        if let Some(first) = this.first.take() {
+            return Poll::Ready(Some(Ok(Frame::data(first))));
+        }
+        loop {
+            match this.state {
+                RecvState::Data => match ready!(this.stream.poll_recv_data(cx)) {
+                    Ok(Some(mut data)) => {
+                        let data = data.copy_to_bytes(data.remaining());
+                        return Poll::Ready(Some(Ok(Frame::data(data))));
+                    }
+                    Ok(None) => this.state = RecvState::Trailers,
+                    Err(e) => return Poll::Ready(Some(Err(e.into()))),
+                },
+                RecvState::Trailers => {
+                    let trailers = ready!(this.stream.poll_recv_trailers(cx));
+                    this.state = RecvState::Done;
+                    return Poll::Ready(match trailers {
+                        Ok(Some(trailers)) => Some(Ok(Frame::trailers(trailers))),
+                        Ok(None) => None,
+                        Err(e) => Some(Err(e.into())),
+                    });
+                }
+                RecvState::Done => return Poll::Ready(None),
+            }
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        matches!(self.state, RecvState::Done)
+    }
+}
+
+/// Classify a failed QUIC handshake into the reasons the TLS listener
+/// counts under.
+fn handshake_failure_reason(err: &ConnectionError) -> &'static str {
+    match err {
+        ConnectionError::VersionMismatch => "protocol_version",
+        ConnectionError::TimedOut => "timeout",
+        ConnectionError::Reset => "reset",
+        // TLS alerts arrive as the client closing the connection.
+        ConnectionError::ConnectionClosed(_) => "alert_received",
+        _ => "other",
+    }
+}
+
+/// The access log's `close_reason` for a connection that ended with `err`.
+fn close_reason(err: Option<ConnectionError>) -> &'static str {
+    match err {
+        None | Some(ConnectionError::ApplicationClosed(_) | ConnectionError::LocallyClosed) => {
+            "eof"
+        }
+        Some(ConnectionError::TimedOut) => "idle_timeout",
+        Some(ConnectionError::Reset) => "reset",
+        Some(_) => "error",
+    }
+}
+
+fn stream_error(e: h3::error::StreamError) -> Error {
+    Error::Io(std::io::Error::other(e))
+}
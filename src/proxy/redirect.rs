@@ -0,0 +1,118 @@
+use std::convert::Infallible;
+use std::sync::Arc;
+
+use bytes::Bytes;
+use http_body_util::Full;
+use hyper::body::Incoming;
+use hyper::header::{HeaderValue, CONTENT_TYPE, HOST, LOCATION};
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper::{Request, Response, StatusCode};
+use hyper_util::rt::TokioIo;
+use tokio::net::TcpListener;
+use tokio::sync::watch;
+use tracing::{debug, error, info};
+
+use cert_keeper::config::Config;
+use cert_keeper::error::Result;
+
+/// Answers every plain-HTTP request with a `301` to the same host and
+/// path over HTTPS, for services whose clients still try port 80 first.
+pub struct Redirect {
+    /// Left out of the `Location` when it is 443.
+    port: u16,
+    /// Used for requests without a usable `Host` header, as HTTP/1.0 ones
+    /// may be.
+    default_host: String,
+}
+
+impl Redirect {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            port: config.http_redirect_port,
+            default_host: config.cert_common_name.clone(),
+        }
+    }
+
+    fn respond(&self, req: &Request<Incoming>) -> Response<Full<Bytes>> {
+        let host = req
+            .headers()
+            .get(HOST)
+            .and_then(|value| value.to_str().ok())
+            .and_then(host_only)
+            .unwrap_or(&self.default_host);
+        let path = req.uri().path_and_query().map_or("/", |p| p.as_str());
+        let location = match self.port {
+            443 => format!("https://{host}{path}"),
+            port => format!("https://{host}:{port}{path}"),
+        };
+        debug!(method = %req.method(), location, "redirecting to HTTPS");
+
+        let Ok(value) = HeaderValue::try_from(&location) else {
+            let mut response = Response::new(Full::new(Bytes::new()));
+            *response.status_mut() = StatusCode::BAD_REQUEST;
+            return response;
+        };
+        let mut response = Response::new(Full::new(Bytes::from(format!("Moved to {location}\n"))));
+        *response.status_mut() = StatusCode::MOVED_PERMANENTLY;
+        response.headers_mut().insert(LOCATION, value);
+        response
+            .headers_mut()
+            .insert(CONTENT_TYPE, HeaderValue::from_static("text/plain"));
+        response
+    }
+}
+
+/// The host part of a `Host` header, without its port, if it looks like a
+/// name or an address.
+fn host_only(value: &str) -> Option<&str> {
+    let host = match value.strip_prefix('[') {
+        // `[::1]:8080`
+        Some(rest) => &value[..rest.find(']')? + 2],
+        None => value.split(':').next()?,
+    };
+    let valid = |b: u8| b.is_ascii_alphanumeric() || b"-.:[]".contains(&b);
+    (!host.is_empty() && host.bytes().all(valid)).then_some(host)
+}
+
+/// Serve redirects until shutdown.
+pub async fn run(
+    redirect: Arc<Redirect>,
+    listener: TcpListener,
+    mut shutdown: watch::Receiver<bool>,
+) -> Result<()> {
+    info!(addr = %listener.local_addr()?, "redirecting plain HTTP to HTTPS");
+
+    loop {
+        tokio::select! {
+            result = listener.accept() => {
+                let (tcp_stream, peer_addr) = match result {
+                    Ok(conn) => conn,
+                    Err(e) => {
+                        error!(error = %e, "failed to accept redirect connection");
+                        continue;
+                    }
+                };
+
+                let redirect = redirect.clone();
+                tokio::spawn(async move {
+                    let service = service_fn(move |req| {
+                        let response = redirect.respond(&req);
+                        async move { Ok::<_, Infallible>(response) }
+                    });
+
+                    if let Err(e) = http1::Builder::new()
+                        .serve_connection(TokioIo::new(tcp_stream), service)
+                        .await
+                    {
+                        debug!(peer = %peer_addr, error = %e, "redirect connection ended");
+                    }
+                });
+            }
+            _ = shutdown.changed() => {
+                info!("redirect listener shutting down");
+                return Ok(());
+            }
+        }
+    }
+}
@@ -0,0 +1,88 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::io::{self, AsyncWriteExt};
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tokio::time::timeout;
+use tracing::debug;
+
+use cert_keeper::metrics;
+
+use crate::proxy::forwarder::{self, Backend};
+
+/// Reads from the client that can wait for a slow shadow backend before
+/// the connection stops being mirrored.
+const SHADOW_QUEUE: usize = 64;
+
+/// How long the shadow backend gets to answer once the client is done,
+/// before its connection is closed.
+const SHADOW_LINGER: Duration = Duration::from_secs(5);
+
+/// Copies of what a client sends, on their way to the shadow backend.
+pub struct Mirror {
+    tx: Option<mpsc::Sender<Vec<u8>>>,
+}
+
+impl Mirror {
+    /// Connect to `shadow` in a task of its own, sending `header` first,
+    /// and pass on everything given to [`Mirror::send`] from then on.
+    pub fn spawn(shadow: Arc<Backend>, header: Option<Vec<u8>>) -> Self {
+        let (tx, rx) = mpsc::channel(SHADOW_QUEUE);
+        tokio::spawn(run(shadow, header, rx));
+        Self { tx: Some(tx) }
+    }
+
+    /// Queue a copy of `data`. A shadow backend that has fallen behind is
+    /// left behind: the connection is no longer mirrored, rather than the
+    /// client kept waiting on it.
+    pub fn send(&mut self, data: &[u8]) {
+        let Some(tx) = &self.tx else {
+            return;
+        };
+        if let Err(e) = tx.try_send(data.to_vec()) {
+            if let TrySendError::Full(_) = e {
+                let count = metrics::inc("shadow_connections", "lagged");
+                debug!(count, "shadow backend fell behind, no longer mirroring the connection");
+            }
+            self.tx = None;
+        }
+    }
+}
+
+/// Send the mirrored bytes to the shadow backend until the client is done,
+/// throwing away whatever it answers.
+async fn run(shadow: Arc<Backend>, header: Option<Vec<u8>>, mut rx: mpsc::Receiver<Vec<u8>>) {
+    let (stream, active) = match forwarder::connect(&shadow, header.as_deref()).await {
+        Ok(connected) => connected,
+        Err(e) => {
+            let count = metrics::inc("shadow_connections", "failed");
+            debug!(error = %e, count, "shadow backend connect failed");
+            return;
+        }
+    };
+    metrics::inc("shadow_connections", "mirrored");
+    let (mut reader, mut writer) = io::split(stream);
+    let mut sink = io::sink();
+    // Read while sending too, so a shadow backend that answers does not
+    // stall on a full send buffer.
+    let discard = io::copy(&mut reader, &mut sink);
+    tokio::pin!(discard);
+    let send = async {
+        while let Some(chunk) = rx.recv().await {
+            writer.write_all(&chunk).await?;
+        }
+        writer.shutdown().await
+    };
+
+    let sent = tokio::select! {
+        sent = send => sent,
+        // The shadow backend closed the connection; nothing more to do.
+        _ = &mut discard => return,
+    };
+    match sent {
+        Ok(()) => {
+            let _ = timeout(SHADOW_LINGER, discard).await;
+        }
+        Err(e) => debug!(backend = %active.addr(), error = %e, "sending to shadow backend failed"),
+    }
+}
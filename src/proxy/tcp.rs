@@ -0,0 +1,69 @@
+use std::io;
+use std::net::SocketAddr;
+
+use socket2::{SockRef, TcpKeepalive};
+use tokio::net::{TcpListener, TcpSocket, TcpStream};
+
+use cert_keeper::config::TcpOptions;
+
+/// Listen on `addr` with the buffer sizes set, so accepted connections
+/// start with them. An IPv6 `addr` takes IPv4 connections too unless
+/// `only_v6`. With `reuse_port`, further sockets can listen on it too, and
+/// the kernel spreads connections across them.
+pub fn listen(
+    addr: SocketAddr,
+    options: &TcpOptions,
+    only_v6: bool,
+    reuse_port: bool,
+) -> io::Result<TcpListener> {
+    let socket = new_socket(addr, options)?;
+    socket.set_reuseaddr(true)?;
+    if addr.is_ipv6() {
+        SockRef::from(&socket).set_only_v6(only_v6)?;
+    }
+    if reuse_port {
+        socket.set_reuseport(true)?;
+    }
+    socket.bind(addr)?;
+    socket.listen(1024)
+}
+
+/// Connect to `addr`. The buffer sizes are set before the handshake, where
+/// the window scale that lets a large receive buffer be used is agreed.
+pub async fn connect(addr: SocketAddr, options: &TcpOptions) -> io::Result<TcpStream> {
+    let stream = new_socket(addr, options)?.connect(addr).await?;
+    configure(&stream, options)?;
+    Ok(stream)
+}
+
+/// Set `TCP_NODELAY` and keepalive on a connected socket.
+pub fn configure(stream: &TcpStream, options: &TcpOptions) -> io::Result<()> {
+    if options.nodelay {
+        stream.set_nodelay(true)?;
+    }
+    let Some(time) = options.keepalive_time else {
+        return Ok(());
+    };
+    let mut keepalive = TcpKeepalive::new().with_time(time);
+    if let Some(interval) = options.keepalive_interval {
+        keepalive = keepalive.with_interval(interval);
+    }
+    if let Some(retries) = options.keepalive_retries {
+        keepalive = keepalive.with_retries(retries);
+    }
+    SockRef::from(stream).set_tcp_keepalive(&keepalive)
+}
+
+fn new_socket(addr: SocketAddr, options: &TcpOptions) -> io::Result<TcpSocket> {
+    let socket = match addr {
+        SocketAddr::V4(_) => TcpSocket::new_v4()?,
+        SocketAddr::V6(_) => TcpSocket::new_v6()?,
+    };
+    if let Some(size) = options.send_buffer {
+        socket.set_send_buffer_size(size)?;
+    }
+    if let Some(size) = options.recv_buffer {
+        socket.set_recv_buffer_size(size)?;
+    }
+    Ok(socket)
+}
@@ -0,0 +1,129 @@
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use std::time::Duration;
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::time::{sleep, Instant, Sleep};
+
+use cert_keeper::config::Bandwidth;
+
+/// A leaky bucket: bytes drain through it at `rate` per second, and up to a
+/// second's worth can build up while the connection is quiet.
+struct Bucket {
+    rate: f64,
+    /// Bytes that may pass right now.
+    allowance: f64,
+    last: Instant,
+    /// Bytes worth waiting for once the bucket is empty, so a slow rate
+    /// does not turn into a wakeup per byte.
+    chunk: f64,
+    delay: Option<Pin<Box<Sleep>>>,
+}
+
+impl Bucket {
+    fn new(rate: u64) -> Self {
+        let rate = rate as f64;
+        Self {
+            rate,
+            allowance: rate,
+            last: Instant::now(),
+            // 10ms worth.
+            chunk: (rate / 100.0).max(1.0),
+            delay: None,
+        }
+    }
+
+    /// Ready with how many bytes may pass, once there are some.
+    fn poll_allowance(&mut self, cx: &mut Context<'_>) -> Poll<usize> {
+        loop {
+            if let Some(delay) = &mut self.delay {
+                ready!(delay.as_mut().poll(cx));
+                self.delay = None;
+            }
+            let now = Instant::now();
+            let refill = now.duration_since(self.last).as_secs_f64() * self.rate;
+            self.allowance = (self.allowance + refill).min(self.rate);
+            self.last = now;
+            if self.allowance >= 1.0 {
+                return Poll::Ready(self.allowance as usize);
+            }
+            let wait = (self.chunk - self.allowance) / self.rate;
+            self.delay = Some(Box::pin(sleep(Duration::from_secs_f64(wait))));
+        }
+    }
+
+    fn spend(&mut self, bytes: usize) {
+        self.allowance -= bytes as f64;
+    }
+}
+
+/// A stream whose reads and writes are each held to a rate, if it has one.
+///
+/// Reads are the client's upload and writes its download, so wrapping the
+/// client side limits both directions of a proxied connection.
+pub struct Throttled<S> {
+    inner: S,
+    read: Option<Bucket>,
+    write: Option<Bucket>,
+}
+
+impl<S> Throttled<S> {
+    pub fn new(inner: S, limit: Bandwidth) -> Self {
+        Self {
+            inner,
+            read: limit.upload.map(Bucket::new),
+            write: limit.download.map(Bucket::new),
+        }
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for Throttled<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let Some(bucket) = &mut this.read else {
+            return Pin::new(&mut this.inner).poll_read(cx, buf);
+        };
+        let allowance = ready!(bucket.poll_allowance(cx));
+        // Read no more than the allowance, so the bucket never runs into
+        // debt that would stall the connection.
+        let limit = buf.remaining().min(allowance);
+        let mut limited = ReadBuf::new(buf.initialize_unfilled_to(limit));
+        ready!(Pin::new(&mut this.inner).poll_read(cx, &mut limited))?;
+        let read = limited.filled().len();
+        buf.advance(read);
+        bucket.spend(read);
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for Throttled<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let Some(bucket) = &mut this.write else {
+            return Pin::new(&mut this.inner).poll_write(cx, buf);
+        };
+        let allowance = ready!(bucket.poll_allowance(cx));
+        let limit = buf.len().min(allowance);
+        let written = ready!(Pin::new(&mut this.inner).poll_write(cx, &buf[..limit]))?;
+        bucket.spend(written);
+        Poll::Ready(Ok(written))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
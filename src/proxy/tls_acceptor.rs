@@ -1,27 +1,201 @@
+use std::io;
 use std::net::SocketAddr;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use rustls::ServerConfig;
+use rustls::server::Acceptor;
+use rustls::{PeerIncompatible, ServerConfig};
+use tokio::io::AsyncWriteExt;
 use tokio::net::TcpListener;
-use tokio::sync::watch;
-use tokio_rustls::TlsAcceptor;
-use tracing::{debug, error, info, warn};
+use tokio::sync::{watch, Semaphore};
+use tokio::task::JoinSet;
+use tokio::time::timeout;
+use tokio_rustls::server::TlsStream;
+use tokio_rustls::{LazyConfigAcceptor, TlsAcceptor};
+use tracing::field::Empty;
+use tracing::{debug, error, info, info_span, warn, Instrument, Span};
 
-use crate::error::{Error, Result};
-use crate::proxy::forwarder;
+use cert_keeper::cert::keygen;
+use cert_keeper::cert::manager::{build_server_config, certified_key};
+use cert_keeper::cert::resolver::AlpnChallenges;
+use cert_keeper::config::{Cidr, Config, ListenAddr, ProxyMode};
+use cert_keeper::error::{Error, Result};
+use cert_keeper::metrics;
+
+use crate::proxy::client_cert::ClientCert;
+use crate::proxy::forwarder::{self, Backend, Forwarded};
+use crate::proxy::listener::{Io, Listener, Peer};
+use crate::proxy::passthrough::{self, Rewound};
+
+/// Bind the listener of the ACME server, admin API, or HTTP redirect; the
+/// proxy's own goes through [`Listener::bind`].
+///
+/// Kept separate from the servers so that a bind failure is reported before
+/// their task is spawned.
+#[cfg_attr(
+    not(any(feature = "acme", feature = "admin", feature = "http-redirect")),
+    allow(dead_code)
+)]
+pub async fn bind(listen_addr: SocketAddr) -> Result<TcpListener> {
+    TcpListener::bind(listen_addr)
+        .await
+        .map_err(|e| Error::Bind(format!("{listen_addr}: {e}")))
+}
+
+/// The page answered with until the first certificate is issued, unless
+/// `HOLDING_PAGE_FILE` replaces it.
+const DEFAULT_HOLDING_PAGE: &str = "<!DOCTYPE html>
+<html>
+<head><title>503 Service Unavailable</title></head>
+<body>
+<h1>Service Unavailable</h1>
+<p>This server is starting and waiting for its certificate. Please try again shortly.</p>
+</body>
+</html>
+";
+
+/// How long the holding page's certificate is valid; clients that accept
+/// a self-signed certificate rarely check that either.
+const HOLDING_VALIDITY: Duration = Duration::from_secs(7 * 24 * 3600);
+
+/// What `HOLDING_PAGE` answers with while no certificate has been issued.
+#[derive(Clone)]
+pub struct Holding {
+    /// A server config presenting a self-signed certificate for the
+    /// primary names.
+    tls: Arc<ServerConfig>,
+    #[cfg_attr(not(feature = "http-proxy"), allow(dead_code))]
+    page: Arc<str>,
+}
+
+impl Holding {
+    /// `None` unless `HOLDING_PAGE` is set.
+    pub fn from_config(config: &Config) -> Result<Option<Self>> {
+        if !config.holding_page {
+            return Ok(None);
+        }
+        let page = match config.holding_page_file {
+            Some(ref path) => std::fs::read_to_string(path).map_err(|e| {
+                Error::Config(format!("failed to read HOLDING_PAGE_FILE {path}: {e}"))
+            })?,
+            None => DEFAULT_HOLDING_PAGE.to_string(),
+        };
+        let (chain, key) = keygen::self_signed(&config.primary_cert(), HOLDING_VALIDITY)?;
+        let key = Arc::new(certified_key(&chain, &key)?);
+        let tls = build_server_config(config, &[key], None)?;
+        Ok(Some(Self {
+            tls: Arc::new(tls),
+            page: page.into(),
+        }))
+    }
+}
+
+/// How [`run`] handles connections.
+#[derive(Clone)]
+pub struct Options {
+    /// Where the listeners given to [`run`] are bound; a reloaded
+    /// `LISTEN_ADDR` that differs moves them.
+    pub listen_addr: ListenAddr,
+    pub max_connections: usize,
+    /// Time a client gets to complete the TLS handshake.
+    pub handshake_timeout: Duration,
+    /// How long shutdown waits for open connections to finish.
+    pub drain_timeout: Duration,
+    /// Log every proxied connection under the `access` target.
+    pub access_log: bool,
+    /// Answer ACME `tls-alpn-01` challenges from here. The proxy then
+    /// listens before the first certificate exists.
+    pub alpn_challenges: Option<AlpnChallenges>,
+    /// Answer with a holding page until the first certificate exists. The
+    /// proxy then listens before it does.
+    pub holding: Option<Holding>,
+    pub allow_cidrs: Vec<Cidr>,
+    pub deny_cidrs: Vec<Cidr>,
+    /// Route connections by SNI without terminating TLS.
+    pub passthrough: bool,
+}
+
+impl Options {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            listen_addr: config.listen_addr.clone(),
+            max_connections: config.max_connections,
+            handshake_timeout: config.tls_handshake_timeout,
+            drain_timeout: config.drain_timeout,
+            access_log: config.access_log,
+            alpn_challenges: None,
+            holding: None,
+            allow_cidrs: config.allow_cidrs.clone(),
+            deny_cidrs: config.deny_cidrs.clone(),
+            passthrough: config.proxy_mode == ProxyMode::Passthrough,
+        }
+    }
+
+    /// Take the settings of a reloaded config that apply to a running
+    /// listener; the connection limit stays.
+    pub fn reload(&mut self, config: &Config) {
+        self.handshake_timeout = config.tls_handshake_timeout;
+        self.drain_timeout = config.drain_timeout;
+        self.access_log = config.access_log;
+        self.allow_cidrs = config.allow_cidrs.clone();
+        self.deny_cidrs = config.deny_cidrs.clone();
+    }
+
+    /// Which setting turns `peer` away, if any, in lower case:
+    /// `DENY_CIDRS` when it matches, else `ALLOW_CIDRS` when it does not.
+    pub fn refused_by(&self, peer: &Peer) -> Option<&'static str> {
+        let Peer::Tcp { addr, .. } = peer else {
+            return None;
+        };
+        let ip = addr.ip();
+        if self.deny_cidrs.iter().any(|cidr| cidr.contains(ip)) {
+            Some("deny_cidrs")
+        } else if !self.allow_cidrs.is_empty()
+            && !self.allow_cidrs.iter().any(|cidr| cidr.contains(ip))
+        {
+            Some("allow_cidrs")
+        } else {
+            None
+        }
+    }
+}
 
 /// Run the TLS proxy listener.
 ///
-/// Accepts TLS connections, terminates TLS, and forwards plaintext to the
-/// backend address. Uses a watch channel to hot-reload certificates.
+/// Accepts TLS connections, terminates TLS, and forwards the stream to the
+/// backend, or with `PROXY_MODE=passthrough` forwards the TLS stream itself
+/// to the backend routed to by its SNI. Uses a watch channel to hot-reload
+/// certificates, and turns connections away once handshaken while
+/// `backend_ready` is false, and outright when the connection limit is
+/// reached. New connections go to the latest `backend` and follow the
+/// listener settings of the latest `settings`. Each of `listeners` is
+/// accepted from by a task of its own, all of them counted against one
+/// connection limit.
+///
+/// When `settings` move `LISTEN_ADDR`, the new address is bound and
+/// accepted on before the old listeners stop accepting and drain their
+/// connections; if it cannot be bound, the proxy stays where it is. TCP
+/// addresses the old and new lists share keep their listeners.
+///
+/// On shutdown it stops accepting and gives open connections up to the
+/// drain timeout to finish before cutting them.
 pub async fn run(
-    listen_addr: SocketAddr,
-    backend_addr: SocketAddr,
+    listeners: Vec<Listener>,
+    backend: watch::Receiver<Arc<Backend>>,
     mut config_rx: watch::Receiver<Option<Arc<ServerConfig>>>,
+    backend_ready: watch::Receiver<bool>,
+    mut options: Options,
+    mut settings: watch::Receiver<Arc<Config>>,
     mut shutdown: watch::Receiver<bool>,
 ) -> Result<()> {
-    // Wait for the first certificate to be available.
-    while config_rx.borrow().is_none() {
+    // Wait for the first certificate to be available, unless it can only
+    // be issued once the proxy answers its challenges, is not served here,
+    // or a holding page answers until then.
+    while options.alpn_challenges.is_none()
+        && options.holding.is_none()
+        && !options.passthrough
+        && config_rx.borrow().is_none()
+    {
         tokio::select! {
             result = config_rx.changed() => {
                 if result.is_err() {
@@ -34,49 +208,630 @@ pub async fn run(
         }
     }
 
-    let listener = TcpListener::bind(listen_addr).await?;
-    info!(addr = %listen_addr, "TLS proxy listening");
+    let shared = Shared {
+        backend,
+        config_rx,
+        backend_ready,
+        limit: Arc::new(Semaphore::new(options.max_connections)),
+    };
+    let mut acceptors = JoinSet::new();
+    let mut serving = start(
+        listeners,
+        &options.listen_addr,
+        &shared,
+        &options,
+        &settings,
+        &shutdown,
+        &mut acceptors,
+    );
+    loop {
+        tokio::select! {
+            Ok(()) = settings.changed() => {
+                let config = settings.borrow_and_update().clone();
+                if config.listen_addr == options.listen_addr {
+                    continue;
+                }
+                // The old address stays served until the new one is, and
+                // addresses in both stay served by the sockets they have.
+                let bound = Listener::bind_added(
+                    &config.listen_addr,
+                    &options.listen_addr,
+                    &config.tcp,
+                    config.acceptor_threads,
+                )
+                .await;
+                match bound {
+                    Ok((added, listeners)) => {
+                        info!(
+                            from = %options.listen_addr,
+                            to = %config.listen_addr,
+                            "listen address reloaded, handing over to the new listener"
+                        );
+                        options.reload(&config);
+                        options.listen_addr = config.listen_addr.clone();
+                        let new = start(
+                            listeners,
+                            &added,
+                            &shared,
+                            &options,
+                            &settings,
+                            &shutdown,
+                            &mut acceptors,
+                        );
+                        let kept: &[SocketAddr] = match &config.listen_addr {
+                            ListenAddr::Tcp(addrs) => addrs,
+                            _ => &[],
+                        };
+                        serving.retain(|(addr, retire)| {
+                            let kept = addr.is_some_and(|addr| kept.contains(&addr));
+                            if !kept {
+                                let _ = retire.send(true);
+                            }
+                            kept
+                        });
+                        serving.extend(new);
+                    }
+                    Err(e) => {
+                        error!(
+                            error = %e,
+                            code = e.code(),
+                            addr = %options.listen_addr,
+                            "failed to bind the reloaded listen address, staying on the current one"
+                        );
+                    }
+                }
+            }
+            // Reap the acceptors of retired listeners once drained.
+            Some(_) = acceptors.join_next() => {}
+            _ = shutdown.changed() => break,
+        }
+    }
+    while acceptors.join_next().await.is_some() {}
+    Ok(())
+}
+
+/// Spawn an acceptor into `acceptors` for each of `listeners`, bound for
+/// `addr` by [`Listener::bind`]. Returns, for each TCP address of `addr`,
+/// or for `addr` as a whole if it is not TCP, the sender that retires its
+/// acceptors once it is no longer listened on.
+fn start(
+    listeners: Vec<Listener>,
+    addr: &ListenAddr,
+    shared: &Shared,
+    options: &Options,
+    settings: &watch::Receiver<Arc<Config>>,
+    shutdown: &watch::Receiver<bool>,
+    acceptors: &mut JoinSet<()>,
+) -> Vec<(Option<SocketAddr>, watch::Sender<bool>)> {
+    if listeners.is_empty() {
+        return Vec::new();
+    }
+    let mut addrs: Vec<_> = listeners.iter().map(Listener::local_addr).collect();
+    addrs.dedup();
+    info!(
+        addr = %addrs.join(","),
+        acceptors = listeners.len(),
+        "TLS proxy listening"
+    );
 
+    let keys: Vec<_> = match addr {
+        ListenAddr::Tcp(addrs) => addrs.iter().copied().map(Some).collect(),
+        _ => vec![None],
+    };
+    // Listeners come address by address, as many for each.
+    let per_addr = listeners.len() / keys.len();
+    let several = listeners.len() > 1;
+    let mut serving = Vec::new();
+    let mut listeners = listeners.into_iter().enumerate();
+    for key in keys {
+        let (retire, retired) = watch::channel(false);
+        for (index, listener) in listeners.by_ref().take(per_addr) {
+            let span = if several {
+                info_span!("acceptor", index)
+            } else {
+                Span::current()
+            };
+            acceptors.spawn(
+                accept(
+                    listener,
+                    shared.clone(),
+                    options.clone(),
+                    settings.clone(),
+                    retired.clone(),
+                    shutdown.clone(),
+                )
+                .instrument(span),
+            );
+        }
+        serving.push((key, retire));
+    }
+    serving
+}
+
+/// What the acceptors of one listener have in common.
+#[derive(Clone)]
+struct Shared {
+    backend: watch::Receiver<Arc<Backend>>,
+    config_rx: watch::Receiver<Option<Arc<ServerConfig>>>,
+    backend_ready: watch::Receiver<bool>,
+    /// The connection limit, across all of them.
+    limit: Arc<Semaphore>,
+}
+
+/// Accept connections on `listener` and handle each on a task of its own,
+/// until shutdown or until `retired` because the listener was replaced,
+/// then drain them.
+async fn accept(
+    listener: Listener,
+    shared: Shared,
+    mut options: Options,
+    mut settings: watch::Receiver<Arc<Config>>,
+    mut retired: watch::Receiver<bool>,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    let Shared {
+        backend,
+        config_rx,
+        backend_ready,
+        limit,
+    } = shared;
+    let mut connections = JoinSet::new();
     loop {
         tokio::select! {
             result = listener.accept() => {
-                let (tcp_stream, peer_addr) = match result {
+                let (stream, peer_addr) = match result {
                     Ok(conn) => conn,
                     Err(e) => {
-                        error!(error = %e, "failed to accept TCP connection");
+                        error!(error = %e, "failed to accept connection");
                         continue;
                     }
                 };
 
-                debug!(peer = %peer_addr, "accepted TCP connection");
+                debug!(peer = %peer_addr, "accepted connection");
 
-                // Build a fresh TLS acceptor from the latest server config.
-                let acceptor = match config_rx.borrow().clone() {
-                    Some(config) => TlsAcceptor::from(config),
-                    None => {
-                        warn!("no TLS config available, dropping connection");
-                        continue;
-                    }
+                if let Some(setting) = options.refused_by(&peer_addr) {
+                    let count = metrics::inc("connections_rejected", setting);
+                    info!(peer = %peer_addr, count, "connection refused by {}", setting.to_uppercase());
+                    continue;
+                }
+
+                // Without a handshake of its own, passthrough can only
+                // turn clients away before theirs.
+                if options.passthrough && !*backend_ready.borrow() {
+                    let count = metrics::inc("connections_rejected", "backend_down");
+                    debug!(peer = %peer_addr, count, "backend not ready, closing connection");
+                    continue;
+                }
+
+                // Refusing outright keeps a flood from piling up tasks and
+                // buffers; clients see a reset and can retry elsewhere.
+                let Ok(permit) = limit.clone().try_acquire_owned() else {
+                    let count = metrics::inc("connections_rejected", "max_connections");
+                    debug!(peer = %peer_addr, count, "connection limit reached, closing connection");
+                    continue;
                 };
 
-                let backend = backend_addr;
-                tokio::spawn(async move {
-                    match acceptor.accept(tcp_stream).await {
-                        Ok(tls_stream) => {
-                            if let Err(e) = forwarder::forward(tls_stream, backend).await {
-                                debug!(peer = %peer_addr, error = %e, "connection ended");
-                            }
+                let backend = backend.borrow().clone();
+                let handshake_timeout = options.handshake_timeout;
+                let access_log = options.access_log;
+                // Each connection is its own trace.
+                let span = info_span!(
+                    parent: None,
+                    "connection",
+                    peer = %peer_addr,
+                    sni = Empty,
+                    close_reason = Empty
+                );
+
+                if options.passthrough {
+                    connections.spawn(
+                        async move {
+                            let _permit = permit;
+                            handle_passthrough(stream, peer_addr, &backend, handshake_timeout, access_log).await;
                         }
-                        Err(e) => {
-                            debug!(peer = %peer_addr, error = %e, "TLS handshake failed");
+                        .instrument(span),
+                    );
+                    continue;
+                }
+
+                // Build a fresh TLS acceptor from the latest server config.
+                let acceptor = match options.alpn_challenges {
+                    Some(ref challenges) => Accept::Challenges {
+                        challenges: challenges.clone(),
+                        config_rx: config_rx.clone(),
+                        holding: options.holding.clone(),
+                    },
+                    None => match (config_rx.borrow().clone(), &options.holding) {
+                        (Some(config), _) => Accept::Tls(TlsAcceptor::from(config)),
+                        (None, Some(holding)) => Accept::Holding(holding.clone()),
+                        (None, None) => {
+                            warn!("no TLS config available, dropping connection");
+                            continue;
                         }
+                    },
+                };
+
+                let backend_ready = backend_ready.clone();
+                connections.spawn(
+                    async move {
+                        let _permit = permit;
+                        handle(acceptor, stream, peer_addr, &backend, &backend_ready, handshake_timeout, access_log).await;
                     }
-                });
+                    .instrument(span),
+                );
             }
-            _ = shutdown.changed() => {
-                info!("TLS proxy shutting down");
-                return Ok(());
+            Ok(()) = settings.changed() => {
+                options.reload(&settings.borrow_and_update());
+            }
+            // Reap finished connections so the set only holds open ones.
+            Some(_) = connections.join_next(), if !connections.is_empty() => {}
+            _ = retired.changed() => break,
+            _ = shutdown.changed() => break,
+        }
+    }
+
+    drop(listener);
+    let stopping = if *shutdown.borrow() {
+        "TLS proxy shutting down"
+    } else {
+        "stopped accepting on the old listen address"
+    };
+    info!(
+        connections = connections.len(),
+        drain_timeout_secs = options.drain_timeout.as_secs(),
+        "{stopping}, draining connections"
+    );
+    let drained = timeout(options.drain_timeout, async {
+        while connections.join_next().await.is_some() {}
+    })
+    .await;
+    if drained.is_err() {
+        warn!(
+            connections = connections.len(),
+            "drain timeout reached, closing remaining connections"
+        );
+        connections.shutdown().await;
+    }
+}
+
+/// How a connection's handshake is set up.
+enum Accept {
+    /// With the latest server config.
+    Tls(TlsAcceptor),
+    /// With a challenge certificate if the ClientHello asks for one, else
+    /// with the server config current at that point, or the holding page
+    /// if there is none yet.
+    Challenges {
+        challenges: AlpnChallenges,
+        config_rx: watch::Receiver<Option<Arc<ServerConfig>>>,
+        holding: Option<Holding>,
+    },
+    /// With the holding certificate, to answer with the holding page.
+    Holding(Holding),
+}
+
+impl Accept {
+    /// Complete the handshake, or `None` if the connection was a challenge
+    /// or cannot be served yet.
+    async fn accept(
+        self,
+        stream: Box<dyn Io>,
+        peer_addr: &Peer,
+    ) -> io::Result<Option<TlsStream<Box<dyn Io>>>> {
+        let (challenges, config_rx, holding) = match self {
+            Self::Tls(acceptor) => return acceptor.accept(stream).await.map(Some),
+            Self::Holding(holding) => {
+                let tls_stream = TlsAcceptor::from(holding.tls.clone()).accept(stream).await?;
+                hold(tls_stream, holding, peer_addr).await;
+                return Ok(None);
+            }
+            Self::Challenges {
+                challenges,
+                config_rx,
+                holding,
+            } => (challenges, config_rx, holding),
+        };
+
+        let start = LazyConfigAcceptor::new(Acceptor::default(), stream).await?;
+        if let Some(config) = challenges.server_config(&start.client_hello()) {
+            let domain = start
+                .client_hello()
+                .server_name()
+                .unwrap_or("-")
+                .to_string();
+            let mut tls_stream = start.into_stream(config).await?;
+            let _ = tls_stream.shutdown().await;
+            info!(peer = %peer_addr, domain, "answered ACME tls-alpn-01 challenge");
+            return Ok(None);
+        }
+
+        let config = config_rx.borrow().clone();
+        match (config, holding) {
+            (Some(config), _) => start.into_stream(config).await.map(Some),
+            (None, Some(holding)) => {
+                let tls_stream = start.into_stream(holding.tls.clone()).await?;
+                hold(tls_stream, holding, peer_addr).await;
+                Ok(None)
+            }
+            (None, None) => {
+                debug!(peer = %peer_addr, "no certificate issued yet, closing connection");
+                Ok(None)
             }
         }
     }
 }
+
+/// Answer a connection handshaken with the holding certificate with the
+/// holding page, so a client can tell a proxy still waiting for its
+/// certificate from one that is not there.
+#[cfg_attr(not(feature = "http-proxy"), allow(unused_variables))]
+async fn hold(tls_stream: TlsStream<Box<dyn Io>>, holding: Holding, peer_addr: &Peer) {
+    let count = metrics::inc("connections_rejected", "cert_pending");
+    debug!(peer = %peer_addr, count, "no certificate issued yet, answering with the holding page");
+    // Config validation rejects HOLDING_PAGE without the feature.
+    #[cfg(feature = "http-proxy")]
+    let _ = crate::proxy::http::holding(tls_stream, holding.page).await;
+}
+
+/// Terminate TLS on one connection and forward it to the backend, or while
+/// `backend_ready` is false, tell the client it is down.
+async fn handle(
+    acceptor: Accept,
+    stream: Box<dyn Io>,
+    peer_addr: Peer,
+    backend: &Backend,
+    backend_ready: &watch::Receiver<bool>,
+    handshake_timeout: Duration,
+    access_log: bool,
+) {
+    let start = Instant::now();
+    let handshake = timeout(handshake_timeout, acceptor.accept(stream, &peer_addr))
+        .instrument(info_span!("tls_handshake"))
+        .await
+        .unwrap_or_else(|_| Err(io::ErrorKind::TimedOut.into()));
+    if !matches!(handshake, Ok(None)) {
+        let result = if handshake.is_ok() {
+            "success"
+        } else {
+            "failure"
+        };
+        metrics::observe(
+            "tls_handshake_duration_seconds",
+            result,
+            metrics::DURATION_BUCKETS,
+            start.elapsed().as_secs_f64(),
+        );
+    }
+    let tls_stream = match handshake {
+        Ok(Some(tls_stream)) => tls_stream,
+        Ok(None) => return,
+        Err(e) => {
+            let reason = handshake_failure_reason(&e);
+            let count = metrics::inc("tls_handshake_failures", reason);
+            info!(peer = %peer_addr, reason, count, error = %e, "TLS handshake failed");
+            observe_connection("handshake_failure", start);
+            return;
+        }
+    };
+
+    let (_, conn) = tls_stream.get_ref();
+    if let Some(sni) = conn.server_name() {
+        Span::current().record("sni", sni);
+    }
+    let sni = conn.server_name().unwrap_or("-").to_string();
+    let version = conn
+        .protocol_version()
+        .map(|v| format!("{v:?}"))
+        .unwrap_or_else(|| "-".into());
+    let alpn = conn
+        .alpn_protocol()
+        .map(|p| String::from_utf8_lossy(p).into_owned())
+        .unwrap_or_else(|| "-".into());
+    debug!(
+        peer = %peer_addr,
+        sni,
+        version,
+        cipher_suite = ?conn.negotiated_cipher_suite().map(|s| s.suite()),
+        alpn,
+        "TLS handshake completed"
+    );
+
+    if !*backend_ready.borrow() {
+        let count = metrics::inc("connections_rejected", "backend_down");
+        info!(peer = %peer_addr, sni, count, "backend is down, turning connection away");
+        reject(tls_stream, backend, handshake_timeout).await;
+        observe_connection("backend_down", start);
+        return;
+    }
+
+    let client_cert = backend
+        .forwards_client_cert()
+        .then(|| ClientCert::from_chain(conn.peer_certificates()))
+        .flatten();
+    let server_name = conn.server_name().map(str::to_string);
+    let forwarded = forwarder::forward(
+        tls_stream,
+        backend,
+        peer_addr,
+        client_cert.as_ref(),
+        server_name.as_deref(),
+    )
+    .await;
+    finish(
+        &peer_addr, &sni, &alpn, &version, &forwarded, start, access_log,
+    );
+}
+
+/// Answer a handshaken connection while the backend is down: a `503` to
+/// the first request in HTTP mode, otherwise a TLS `close_notify`, so the
+/// client learns right away rather than waiting on a connect that would
+/// fail. Either is cut short after `limit`.
+#[cfg_attr(not(feature = "http-proxy"), allow(unused_variables))]
+async fn reject(mut tls_stream: TlsStream<Box<dyn Io>>, backend: &Backend, limit: Duration) {
+    #[cfg(feature = "http-proxy")]
+    if backend.mode() == ProxyMode::Http {
+        let _ = timeout(limit, crate::proxy::http::unavailable(tls_stream)).await;
+        return;
+    }
+    let _ = timeout(limit, tls_stream.shutdown()).await;
+}
+
+/// Forward one connection to the backend its SNI routes to, TLS and all.
+async fn handle_passthrough(
+    mut stream: Box<dyn Io>,
+    peer_addr: Peer,
+    backend: &Backend,
+    handshake_timeout: Duration,
+    access_log: bool,
+) {
+    let start = Instant::now();
+    let hello = timeout(
+        handshake_timeout,
+        passthrough::read_client_hello(&mut stream),
+    )
+    .instrument(info_span!("client_hello"))
+    .await
+    .unwrap_or_else(|_| Err(io::ErrorKind::TimedOut.into()));
+    let (read, sni) = match hello {
+        Ok(hello) => hello,
+        Err(e) => {
+            let reason = handshake_failure_reason(&e);
+            let count = metrics::inc("tls_handshake_failures", reason);
+            info!(peer = %peer_addr, reason, count, error = %e, "failed to read ClientHello");
+            observe_connection("handshake_failure", start);
+            return;
+        }
+    };
+    if let Some(ref sni) = sni {
+        Span::current().record("sni", sni.as_str());
+    }
+    let routed = backend.route(sni.as_deref());
+    debug!(
+        peer = %peer_addr,
+        sni = sni.as_deref().unwrap_or("-"),
+        "ClientHello read, passing the connection through"
+    );
+
+    let forwarded = forwarder::forward(
+        Rewound::new(read, stream),
+        routed,
+        peer_addr,
+        None,
+        sni.as_deref(),
+    )
+    .await;
+    let sni = sni.as_deref().unwrap_or("-");
+    finish(&peer_addr, sni, "-", "-", &forwarded, start, access_log);
+}
+
+/// Record why a forwarded connection ended, and log it.
+fn finish(
+    peer_addr: &Peer,
+    sni: &str,
+    alpn: &str,
+    version: &str,
+    forwarded: &Forwarded,
+    start: Instant,
+    access_log: bool,
+) {
+    let close_reason = match forwarded.result {
+        Ok(close) => close.as_str(),
+        Err(ref e @ (Error::Backend(_) | Error::Dns(_))) => {
+            warn!(peer = %peer_addr, error = %e, code = e.code(), "backend unavailable, closing connection");
+            "backend_unavailable"
+        }
+        Err(ref e) => {
+            debug!(peer = %peer_addr, error = %e, "connection ended");
+            match e {
+                // Plenty of clients skip close_notify.
+                Error::Io(e) if e.kind() == io::ErrorKind::UnexpectedEof => "eof",
+                Error::Io(e) if e.kind() == io::ErrorKind::ConnectionReset => "reset",
+                _ => "error",
+            }
+        }
+    };
+
+    Span::current().record("close_reason", close_reason);
+    observe_connection(
+        if close_reason == "backend_unavailable" {
+            "backend_refused"
+        } else {
+            "success"
+        },
+        start,
+    );
+
+    if access_log {
+        info!(
+            target: "access",
+            peer = %peer_addr,
+            sni,
+            alpn,
+            version,
+            backend = forwarded.backend.as_deref().unwrap_or("-"),
+            bytes_in = forwarded.client_to_server,
+            bytes_out = forwarded.server_to_client,
+            duration_ms = start.elapsed().as_millis() as u64,
+            close_reason,
+            "access"
+        );
+    } else {
+        debug!(
+            peer = %peer_addr,
+            client_to_server = forwarded.client_to_server,
+            server_to_client = forwarded.server_to_client,
+            close_reason,
+            "connection closed"
+        );
+    }
+}
+
+/// Record how long a connection lasted, by how it went: `success` once
+/// forwarded, else `handshake_failure`, `backend_refused`, or `backend_down`.
+pub fn observe_connection(result: &'static str, start: Instant) {
+    metrics::observe(
+        "connection_duration_seconds",
+        result,
+        metrics::LIFETIME_BUCKETS,
+        start.elapsed().as_secs_f64(),
+    );
+}
+
+/// Classify a failed handshake into a short, bounded reason used as the
+/// counter label.
+fn handshake_failure_reason(err: &io::Error) -> &'static str {
+    let Some(tls) = err
+        .get_ref()
+        .and_then(|e| e.downcast_ref::<rustls::Error>())
+    else {
+        return match err.kind() {
+            io::ErrorKind::UnexpectedEof => "eof",
+            io::ErrorKind::ConnectionReset => "reset",
+            io::ErrorKind::TimedOut => "timeout",
+            _ => "io",
+        };
+    };
+
+    match tls {
+        rustls::Error::PeerIncompatible(
+            PeerIncompatible::SupportedVersionsExtensionRequired
+            | PeerIncompatible::SignatureAlgorithmsExtensionRequired
+            | PeerIncompatible::Tls12NotOffered
+            | PeerIncompatible::Tls12NotOfferedOrEnabled,
+        ) => "protocol_version",
+        rustls::Error::PeerIncompatible(
+            PeerIncompatible::NoCipherSuitesInCommon
+            | PeerIncompatible::NoKxGroupsInCommon
+            | PeerIncompatible::NoSignatureSchemesInCommon,
+        ) => "no_shared_parameters",
+        rustls::Error::PeerIncompatible(_) => "incompatible",
+        rustls::Error::AlertReceived(_) => "alert_received",
+        rustls::Error::InvalidMessage(_)
+        | rustls::Error::InappropriateMessage { .. }
+        | rustls::Error::InappropriateHandshakeMessage { .. } => "invalid_message",
+        rustls::Error::NoCertificatesPresented | rustls::Error::InvalidCertificate(_) => {
+            "client_certificate"
+        }
+        _ => "other",
+    }
+}
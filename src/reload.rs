@@ -0,0 +1,81 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::sync::watch;
+use tokio::time::Instant;
+use tracing::{debug, error, info, warn};
+
+use cert_keeper::config::Config;
+
+use crate::logging::LogControl;
+use crate::systemd;
+
+/// Check the config file every `interval` and publish the settings of a
+/// changed one that apply without a restart (see [`Config::reloaded`]) to
+/// `settings`.
+///
+/// The contents are compared rather than the modification time, since a
+/// mounted ConfigMap is updated by swapping a symlink. A file that fails to
+/// load is reported and the running settings are kept.
+pub async fn run(
+    path: PathBuf,
+    interval: Duration,
+    settings: watch::Sender<Arc<Config>>,
+    log_control: Arc<LogControl>,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    info!(path = %path.display(), interval_secs = interval.as_secs(), "watching config file for changes");
+    let mut contents = tokio::fs::read(&path).await.ok();
+    let mut check = tokio::time::interval_at(Instant::now() + interval, interval);
+
+    loop {
+        tokio::select! {
+            _ = check.tick() => {}
+            _ = shutdown.changed() => return,
+        }
+
+        let current = match tokio::fs::read(&path).await {
+            Ok(current) => current,
+            Err(e) => {
+                debug!(path = %path.display(), error = %e, "failed to read config file");
+                continue;
+            }
+        };
+        if contents.as_ref() == Some(&current) {
+            continue;
+        }
+        contents = Some(current);
+
+        let new = match Config::load(Some(&path)) {
+            Ok(config) => config,
+            Err(e) => {
+                error!(error = %e, code = e.code(), "changed config file is invalid, keeping the running settings");
+                continue;
+            }
+        };
+        let old = settings.borrow().clone();
+        let applied = old.reloaded(&new);
+        if applied != new {
+            warn!("config file changes settings that only apply after a restart");
+        }
+        if applied == *old {
+            continue;
+        }
+        systemd::notify_reloading();
+
+        if applied.log_format != old.log_format {
+            if let Err(e) = log_control.set_format(&applied.log_format) {
+                warn!(error = %e, code = e.code(), "failed to apply LOG_FORMAT");
+            }
+        }
+        if applied.log_level != old.log_level {
+            if let Err(e) = log_control.set_level(&applied.log_level) {
+                warn!(error = %e, code = e.code(), "failed to apply LOG_LEVEL");
+            }
+        }
+        settings.send_replace(Arc::new(applied));
+        info!(path = %path.display(), "configuration reloaded");
+        systemd::notify("READY=1");
+    }
+}
@@ -0,0 +1,117 @@
+use std::future::Future;
+use std::time::Duration;
+
+use tokio::sync::watch;
+use tokio::task::JoinSet;
+use tokio::time::Instant;
+use tracing::{warn, Instrument, Span};
+
+use cert_keeper::config::Config;
+use cert_keeper::error::{Error, Result};
+use cert_keeper::metrics;
+
+/// Restarts come at most this far apart, and a task that runs this long
+/// before failing again starts counting its restarts from zero.
+const MAX_RESTART_BACKOFF: Duration = Duration::from_secs(300);
+
+/// The long-running tasks of the sidecar. A task that fails, such as a
+/// listener whose socket stops working, is restarted up to
+/// `TASK_RESTART_LIMIT` times in a row with backoff; after that
+/// [`failed`](Self::failed) reports it, so the process shuts down instead
+/// of running on without it.
+///
+/// Panics need no supervising; the panic hook exits the process.
+pub struct Supervisor {
+    tasks: JoinSet<(&'static str, Result<()>)>,
+    restart_limit: u32,
+    restart_backoff: Duration,
+    shutdown: watch::Receiver<bool>,
+}
+
+impl Supervisor {
+    pub fn new(config: &Config, shutdown: watch::Receiver<bool>) -> Self {
+        Self {
+            tasks: JoinSet::new(),
+            restart_limit: config.task_restart_limit,
+            restart_backoff: config.task_restart_backoff,
+            shutdown,
+        }
+    }
+
+    /// The shutdown signal the tasks stop on.
+    pub fn shutdown(&self) -> watch::Receiver<bool> {
+        self.shutdown.clone()
+    }
+
+    /// Run `task` under `span`, starting it again with `start` each time
+    /// it fails. `start` gets the shutdown signal; the task it returns
+    /// should end with `Ok` once it is sent.
+    pub fn spawn<F, T>(&mut self, task: &'static str, span: Span, mut start: F)
+    where
+        F: FnMut(watch::Receiver<bool>) -> T + Send + 'static,
+        T: Future<Output = Result<()>> + Send + 'static,
+    {
+        let mut shutdown = self.shutdown.clone();
+        let (limit, backoff) = (self.restart_limit, self.restart_backoff);
+        let supervised = async move {
+            let mut restarts = 0;
+            loop {
+                let started = Instant::now();
+                let e = match start(shutdown.clone()).await {
+                    Ok(()) => return Ok(()),
+                    Err(_) if *shutdown.borrow() => return Ok(()),
+                    Err(e) => e,
+                };
+                if started.elapsed() >= MAX_RESTART_BACKOFF {
+                    restarts = 0;
+                }
+                if restarts >= limit {
+                    return Err(e);
+                }
+                restarts += 1;
+                let delay = backoff
+                    .saturating_mul(1 << (restarts - 1).min(16))
+                    .min(MAX_RESTART_BACKOFF);
+                warn!(
+                    error = %e,
+                    code = e.code(),
+                    restart = restarts,
+                    restart_in_ms = delay.as_millis() as u64,
+                    "{task} failed, restarting"
+                );
+                metrics::inc("task_restarts", task);
+                tokio::select! {
+                    _ = tokio::time::sleep(delay) => {}
+                    _ = shutdown.wait_for(|&stop| stop) => return Ok(()),
+                }
+            }
+        };
+        self.tasks
+            .spawn(async move { (task, supervised.await) }.instrument(span));
+    }
+
+    /// Run `task`, which cannot be restarted, under `span`.
+    pub fn spawn_once<T>(&mut self, task: &'static str, span: Span, run: T)
+    where
+        T: Future<Output = Result<()>> + Send + 'static,
+    {
+        self.tasks
+            .spawn(async move { (task, run.await) }.instrument(span));
+    }
+
+    /// Wait for a task to fail for good, returning its name and error.
+    /// Never returns if none does.
+    pub async fn failed(&mut self) -> (&'static str, Error) {
+        while let Some(joined) = self.tasks.join_next().await {
+            if let Ok((task, Err(e))) = joined {
+                return (task, e);
+            }
+        }
+        std::future::pending().await
+    }
+
+    /// Wait for every task to finish once shutdown has been sent.
+    pub async fn join(mut self) {
+        while self.tasks.join_next().await.is_some() {}
+    }
+}
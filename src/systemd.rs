@@ -0,0 +1,142 @@
+use std::ffi::OsStr;
+use std::io;
+use std::os::fd::{BorrowedFd, OwnedFd, RawFd};
+use std::os::unix::net::UnixDatagram;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use tokio::sync::watch;
+use tracing::{debug, warn};
+
+/// The first descriptor of socket activation, `SD_LISTEN_FDS_START`.
+const LISTEN_FDS_START: RawFd = 3;
+
+/// A socket systemd passed, under the `FileDescriptorName=` of its unit.
+struct Passed {
+    fd: RawFd,
+    name: String,
+}
+
+/// The sockets systemd passed to this process through `LISTEN_FDS`, read
+/// once, since a restarted listener takes its socket again.
+fn passed() -> &'static [Passed] {
+    static PASSED: OnceLock<Vec<Passed>> = OnceLock::new();
+    PASSED.get_or_init(|| {
+        // Meant for another process if the PID differs, such as the one
+        // that started cert-keeper through a shell.
+        let pid = std::env::var("LISTEN_PID").ok().and_then(|pid| pid.parse().ok());
+        if pid != Some(std::process::id()) {
+            return Vec::new();
+        }
+        let count: RawFd = std::env::var("LISTEN_FDS")
+            .ok()
+            .and_then(|count| count.parse().ok())
+            .unwrap_or(0);
+        let names = std::env::var("LISTEN_FDNAMES").unwrap_or_default();
+        let mut names = names.split(':');
+        (LISTEN_FDS_START..LISTEN_FDS_START + count)
+            .map(|fd| {
+                // Kept from the renewal hook and other children.
+                // SAFETY: F_SETFD only changes the flags of `fd`.
+                unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) };
+                let name = names.next().unwrap_or_default().to_string();
+                debug!(fd, name, "socket passed by systemd");
+                Passed { fd, name }
+            })
+            .collect()
+    })
+}
+
+/// A duplicate of the socket systemd passed under `name`, or of the first
+/// one if `name` is empty. The passed socket itself stays open.
+pub fn listener(name: &str) -> io::Result<OwnedFd> {
+    let passed = passed();
+    let socket = match name {
+        "" => passed.first(),
+        name => passed.iter().find(|socket| socket.name == name),
+    };
+    let Some(socket) = socket else {
+        let message = if passed.is_empty() {
+            "systemd passed no sockets (LISTEN_FDS)".to_string()
+        } else {
+            format!("systemd passed no socket named '{name}'")
+        };
+        return Err(io::Error::new(io::ErrorKind::NotFound, message));
+    };
+    // SAFETY: systemd passed the descriptor to this process, and nothing
+    // closes it.
+    unsafe { BorrowedFd::borrow_raw(socket.fd) }.try_clone_to_owned()
+}
+
+/// Tell systemd about a change of state, such as `READY=1`, if it started
+/// cert-keeper with `Type=notify`. Nothing depends on it arriving, so a
+/// failure is only logged.
+pub fn notify(state: &str) {
+    let Some(path) = std::env::var_os("NOTIFY_SOCKET") else {
+        return;
+    };
+    if let Err(e) = send(&path, state) {
+        warn!(error = %e, state, "failed to notify systemd");
+    }
+}
+
+/// Tell systemd a reload has started, with the timestamp
+/// `Type=notify-reload` needs. `READY=1` ends it.
+pub fn notify_reloading() {
+    let mut now = libc::timespec { tv_sec: 0, tv_nsec: 0 };
+    // SAFETY: clock_gettime only writes to `now`.
+    unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut now) };
+    let usec = now.tv_sec as u64 * 1_000_000 + now.tv_nsec as u64 / 1_000;
+    notify(&format!("RELOADING=1\nMONOTONIC_USEC={usec}"));
+}
+
+fn send(path: &OsStr, state: &str) -> io::Result<()> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let socket = UnixDatagram::unbound()?;
+    match path.as_bytes().strip_prefix(b"@") {
+        Some(name) => send_abstract(&socket, name, state),
+        None => socket.send_to(state.as_bytes(), path).map(drop),
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn send_abstract(socket: &UnixDatagram, name: &[u8], state: &str) -> io::Result<()> {
+    use std::os::linux::net::SocketAddrExt;
+
+    let addr = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+    socket.send_to_addr(state.as_bytes(), &addr).map(drop)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn send_abstract(_socket: &UnixDatagram, _name: &[u8], _state: &str) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "abstract Unix sockets are only available on Linux",
+    ))
+}
+
+/// How often systemd expects `WATCHDOG=1` from this process, with
+/// `WatchdogSec=` set on the unit.
+pub fn watchdog_interval() -> Option<Duration> {
+    if let Ok(pid) = std::env::var("WATCHDOG_PID") {
+        if pid.parse().ok() != Some(std::process::id()) {
+            return None;
+        }
+    }
+    let usec: u64 = std::env::var("WATCHDOG_USEC").ok()?.parse().ok()?;
+    (usec > 0).then(|| Duration::from_micros(usec))
+}
+
+/// Send `WATCHDOG=1` twice every `interval` until shutdown. It comes from
+/// the runtime the proxy runs on, so systemd restarts a process whose
+/// runtime has stalled.
+pub async fn watchdog(interval: Duration, mut shutdown: watch::Receiver<bool>) {
+    let mut keepalive = tokio::time::interval(interval / 2);
+    loop {
+        tokio::select! {
+            _ = keepalive.tick() => notify("WATCHDOG=1"),
+            _ = shutdown.wait_for(|&stop| stop) => return,
+        }
+    }
+}
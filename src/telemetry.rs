@@ -0,0 +1,99 @@
+#[cfg(feature = "otel")]
+use opentelemetry::trace::TracerProvider as _;
+#[cfg(feature = "otel")]
+use opentelemetry_otlp::SpanExporter;
+#[cfg(feature = "otel")]
+use opentelemetry_sdk::trace::SdkTracerProvider;
+#[cfg(feature = "otel")]
+use opentelemetry_sdk::Resource;
+use tracing::Subscriber;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Layer;
+
+use cert_keeper::config::Config;
+#[cfg(feature = "otel")]
+use cert_keeper::error::Error;
+use cert_keeper::error::Result;
+
+/// Exports tracing spans to an OTLP collector over HTTP/protobuf.
+///
+/// The exporter takes its endpoint, headers, timeout, and compression from
+/// the standard `OTEL_EXPORTER_OTLP_*` variables, and the resource takes
+/// `OTEL_SERVICE_NAME` and `OTEL_RESOURCE_ATTRIBUTES`.
+#[cfg(feature = "otel")]
+pub struct Telemetry {
+    provider: SdkTracerProvider,
+}
+
+/// Build the exporter, or `None` when no collector is configured.
+#[cfg(feature = "otel")]
+pub fn init(config: &Config) -> Result<Option<Telemetry>> {
+    if config.otel_endpoint.is_none() {
+        return Ok(None);
+    }
+
+    let exporter = SpanExporter::builder()
+        .with_http()
+        .build()
+        .map_err(|e| Error::Config(format!("failed to build OTLP exporter: {e}")))?;
+    let provider = SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(resource())
+        .build();
+    Ok(Some(Telemetry { provider }))
+}
+
+#[cfg(feature = "otel")]
+impl Telemetry {
+    pub fn layer<S>(&self) -> impl Layer<S>
+    where
+        S: Subscriber + for<'a> LookupSpan<'a>,
+    {
+        tracing_opentelemetry::layer().with_tracer(self.provider.tracer(env!("CARGO_PKG_NAME")))
+    }
+
+    /// Export the spans still queued. Blocks for up to the exporter timeout.
+    pub fn shutdown(self) {
+        if let Err(e) = self.provider.shutdown() {
+            tracing::warn!(error = %e, "failed to flush spans to the OTLP collector");
+        }
+    }
+}
+
+/// The detected resource, named `cert-keeper` unless the environment names
+/// the service.
+#[cfg(feature = "otel")]
+fn resource() -> Resource {
+    let detected = Resource::builder().build();
+    let named = detected
+        .get(&opentelemetry::Key::from_static_str("service.name"))
+        .is_some_and(|name| name.as_str() != "unknown_service");
+    if named {
+        detected
+    } else {
+        Resource::builder()
+            .with_service_name(env!("CARGO_PKG_NAME"))
+            .build()
+    }
+}
+
+/// Span export is compiled out.
+#[cfg(not(feature = "otel"))]
+pub struct Telemetry;
+
+#[cfg(not(feature = "otel"))]
+pub fn init(_config: &Config) -> Result<Option<Telemetry>> {
+    Ok(None)
+}
+
+#[cfg(not(feature = "otel"))]
+impl Telemetry {
+    pub fn layer<S>(&self) -> impl Layer<S>
+    where
+        S: Subscriber + for<'a> LookupSpan<'a>,
+    {
+        tracing_subscriber::layer::Identity::new()
+    }
+
+    pub fn shutdown(self) {}
+}
@@ -0,0 +1,266 @@
+use std::env;
+use std::time::Duration;
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use reqwest::RequestBuilder;
+use ring::{digest, hmac};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use time::OffsetDateTime;
+use tracing::debug;
+
+use crate::error::{Error, Result};
+
+/// Host of the ECS credentials endpoint that
+/// `AWS_CONTAINER_CREDENTIALS_RELATIVE_URI` is relative to.
+const ECS_CREDENTIALS_HOST: &str = "http://169.254.170.2";
+const IMDS_ENDPOINT: &str = "http://169.254.169.254";
+/// The session token is only used for the two requests that follow it.
+const IMDS_TOKEN_TTL_SECS: &str = "60";
+const METADATA_TIMEOUT: Duration = Duration::from_secs(2);
+
+const STS_BODY: &str = "Action=GetCallerIdentity&Version=2011-06-15";
+const STS_CONTENT_TYPE: &str = "application/x-www-form-urlencoded; charset=utf-8";
+
+#[derive(Deserialize)]
+struct Credentials {
+    #[serde(rename = "AccessKeyId")]
+    access_key_id: String,
+    #[serde(rename = "SecretAccessKey")]
+    secret_access_key: String,
+    #[serde(rename = "Token", default)]
+    session_token: Option<String>,
+}
+
+/// Login payload for the AWS auth method (IAM type).
+///
+/// Signs an `sts:GetCallerIdentity` request with the task or instance role
+/// credentials; Vault replays it against STS to learn our identity. The
+/// request itself is never sent from here.
+pub async fn login_payload(
+    role: &str,
+    region: Option<&str>,
+    iam_server_id: Option<&str>,
+) -> Result<Value> {
+    let credentials = load_credentials().await?;
+
+    let (region, host) = match region {
+        Some(region) => (region, format!("sts.{region}.amazonaws.com")),
+        None => ("us-east-1", "sts.amazonaws.com".to_string()),
+    };
+    let headers = sign(
+        &credentials,
+        region,
+        &host,
+        iam_server_id,
+        OffsetDateTime::now_utc(),
+    );
+
+    // Vault expects the headers as a JSON object of string lists, like Go's
+    // http.Header.
+    let headers: Map<String, Value> = headers
+        .into_iter()
+        .map(|(name, value)| (name.to_string(), Value::from(vec![value])))
+        .collect();
+
+    Ok(serde_json::json!({
+        "role": role,
+        "iam_http_request_method": "POST",
+        "iam_request_url": STANDARD.encode(format!("https://{host}/")),
+        "iam_request_body": STANDARD.encode(STS_BODY),
+        "iam_request_headers": STANDARD.encode(serde_json::to_vec(&headers)?),
+    }))
+}
+
+/// Resolve credentials the way the AWS SDKs do, minus profiles: static
+/// environment variables, then the ECS container endpoint, then EC2
+/// instance metadata (IMDSv2).
+async fn load_credentials() -> Result<Credentials> {
+    if let (Ok(access_key_id), Ok(secret_access_key)) = (
+        env::var("AWS_ACCESS_KEY_ID"),
+        env::var("AWS_SECRET_ACCESS_KEY"),
+    ) {
+        debug!("using aws credentials from the environment");
+        return Ok(Credentials {
+            access_key_id,
+            secret_access_key,
+            session_token: env::var("AWS_SESSION_TOKEN").ok(),
+        });
+    }
+
+    // Link-local endpoints must never go through a proxy.
+    let http = reqwest::Client::builder()
+        .timeout(METADATA_TIMEOUT)
+        .no_proxy()
+        .build()?;
+
+    if let Some(credentials) = container_credentials(&http).await? {
+        return Ok(credentials);
+    }
+    instance_credentials(&http).await
+}
+
+/// Task role credentials from the ECS (or EKS Pod Identity) agent, if the
+/// environment points at one.
+async fn container_credentials(http: &reqwest::Client) -> Result<Option<Credentials>> {
+    let url = if let Ok(relative) = env::var("AWS_CONTAINER_CREDENTIALS_RELATIVE_URI") {
+        format!("{ECS_CREDENTIALS_HOST}{relative}")
+    } else if let Ok(full) = env::var("AWS_CONTAINER_CREDENTIALS_FULL_URI") {
+        full
+    } else {
+        return Ok(None);
+    };
+
+    let token = match env::var("AWS_CONTAINER_AUTHORIZATION_TOKEN_FILE") {
+        Ok(path) => Some(
+            tokio::fs::read_to_string(&path)
+                .await
+                .map_err(|e| {
+                    Error::VaultAuth(format!(
+                        "failed to read container authorization token from {path}: {e}"
+                    ))
+                })?
+                .trim()
+                .to_string(),
+        ),
+        Err(_) => env::var("AWS_CONTAINER_AUTHORIZATION_TOKEN").ok(),
+    };
+
+    let mut request = http.get(&url);
+    if let Some(token) = token {
+        request = request.header("Authorization", token);
+    }
+
+    debug!(url = %url, "fetching aws container credentials");
+    let body = fetch(request, "container credentials endpoint").await?;
+    Ok(Some(serde_json::from_str(&body)?))
+}
+
+/// Instance role credentials from the EC2 instance metadata service.
+async fn instance_credentials(http: &reqwest::Client) -> Result<Credentials> {
+    let token = fetch(
+        http.put(format!("{IMDS_ENDPOINT}/latest/api/token"))
+            .header("X-aws-ec2-metadata-token-ttl-seconds", IMDS_TOKEN_TTL_SECS),
+        "instance metadata service",
+    )
+    .await?;
+
+    let base = format!("{IMDS_ENDPOINT}/latest/meta-data/iam/security-credentials/");
+    let roles = fetch(
+        http.get(&base).header("X-aws-ec2-metadata-token", &token),
+        "instance metadata service",
+    )
+    .await?;
+    let role = roles
+        .lines()
+        .next()
+        .filter(|role| !role.is_empty())
+        .ok_or_else(|| Error::VaultAuth("instance has no IAM role attached".into()))?;
+
+    debug!(role, "fetching aws instance role credentials");
+    let body = fetch(
+        http.get(format!("{base}{role}"))
+            .header("X-aws-ec2-metadata-token", &token),
+        "instance metadata service",
+    )
+    .await?;
+    Ok(serde_json::from_str(&body)?)
+}
+
+async fn fetch(request: RequestBuilder, what: &str) -> Result<String> {
+    let response = request
+        .send()
+        .await
+        .map_err(|e| Error::VaultAuth(format!("no aws credentials: {what} unreachable: {e}")))?;
+    if !response.status().is_success() {
+        return Err(Error::VaultAuth(format!(
+            "{what} returned {}",
+            response.status()
+        )));
+    }
+    Ok(response.text().await?)
+}
+
+/// Headers of a SigV4-signed `sts:GetCallerIdentity` POST, including
+/// `Authorization`.
+fn sign(
+    credentials: &Credentials,
+    region: &str,
+    host: &str,
+    iam_server_id: Option<&str>,
+    now: OffsetDateTime,
+) -> Vec<(&'static str, String)> {
+    let amz_date = format!(
+        "{:04}{:02}{:02}T{:02}{:02}{:02}Z",
+        now.year(),
+        u8::from(now.month()),
+        now.day(),
+        now.hour(),
+        now.minute(),
+        now.second()
+    );
+    let date = &amz_date[..8];
+
+    // Kept in the order their lowercase names sort, as SigV4 requires.
+    let mut headers = vec![
+        ("Content-Type", STS_CONTENT_TYPE.to_string()),
+        ("Host", host.to_string()),
+        ("X-Amz-Date", amz_date.clone()),
+    ];
+    if let Some(ref token) = credentials.session_token {
+        headers.push(("X-Amz-Security-Token", token.clone()));
+    }
+    if let Some(id) = iam_server_id {
+        headers.push(("X-Vault-AWS-IAM-Server-ID", id.to_string()));
+    }
+
+    let canonical_headers: String = headers
+        .iter()
+        .map(|(name, value)| format!("{}:{}\n", name.to_lowercase(), value.trim()))
+        .collect();
+    let signed_headers = headers
+        .iter()
+        .map(|(name, _)| name.to_lowercase())
+        .collect::<Vec<_>>()
+        .join(";");
+    let canonical_request = format!(
+        "POST\n/\n\n{canonical_headers}\n{signed_headers}\n{}",
+        sha256_hex(STS_BODY.as_bytes())
+    );
+
+    let scope = format!("{date}/{region}/sts/aws4_request");
+    let string_to_sign = format!(
+        "AWS4-HMAC-SHA256\n{amz_date}\n{scope}\n{}",
+        sha256_hex(canonical_request.as_bytes())
+    );
+
+    let signing_key = [date, region, "sts", "aws4_request"].iter().fold(
+        format!("AWS4{}", credentials.secret_access_key).into_bytes(),
+        |key, part| hmac_sha256(&key, part.as_bytes()),
+    );
+    let signature = hex(&hmac_sha256(&signing_key, string_to_sign.as_bytes()));
+
+    headers.push((
+        "Authorization",
+        format!(
+            "AWS4-HMAC-SHA256 Credential={}/{scope}, SignedHeaders={signed_headers}, Signature={signature}",
+            credentials.access_key_id
+        ),
+    ));
+    headers
+}
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> Vec<u8> {
+    hmac::sign(&hmac::Key::new(hmac::HMAC_SHA256, key), data)
+        .as_ref()
+        .to_vec()
+}
+
+fn sha256_hex(data: &[u8]) -> String {
+    hex(digest::digest(&digest::SHA256, data).as_ref())
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
@@ -0,0 +1,136 @@
+use std::env;
+use std::time::Duration;
+
+use reqwest::RequestBuilder;
+use serde::Deserialize;
+use serde_json::Value;
+use time::OffsetDateTime;
+use tracing::debug;
+
+use crate::config::GcpRoleType;
+use crate::error::{Error, Result};
+
+/// Used unless `GCE_METADATA_HOST` points elsewhere, as the Google SDKs
+/// allow.
+const METADATA_HOST: &str = "metadata.google.internal";
+const METADATA_TIMEOUT: Duration = Duration::from_secs(2);
+const IAM_CREDENTIALS_ENDPOINT: &str = "https://iamcredentials.googleapis.com/v1";
+const IAM_TIMEOUT: Duration = Duration::from_secs(10);
+/// Lifetime of the signed JWT; Vault rejects `iam` logins with one longer
+/// than its `max_jwt_exp`, 15 minutes by default.
+const JWT_LIFETIME: Duration = Duration::from_secs(600);
+
+#[derive(Deserialize)]
+struct AccessToken {
+    access_token: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SignedJwt {
+    signed_jwt: String,
+}
+
+/// Login payload for the GCP auth method.
+///
+/// For a `gce` role the metadata server issues an identity token with the
+/// instance's details. For an `iam` role, which is also what Workload
+/// Identity on GKE needs, the service account signs a JWT through the IAM
+/// Credentials API, using the access token the metadata server hands out
+/// for it.
+pub async fn login_payload(
+    role: &str,
+    role_type: GcpRoleType,
+    service_account: Option<&str>,
+) -> Result<Value> {
+    // The metadata server is link-local and must never go through a proxy.
+    let metadata = reqwest::Client::builder()
+        .timeout(METADATA_TIMEOUT)
+        .no_proxy()
+        .build()?;
+
+    let jwt = match role_type {
+        GcpRoleType::Gce => {
+            let audience = format!("http://vault/{role}");
+            debug!(audience, "fetching gce identity token");
+            fetch(
+                metadata_get(&metadata, "instance/service-accounts/default/identity")
+                    .query(&[("audience", audience.as_str()), ("format", "full")]),
+                "metadata server",
+            )
+            .await?
+        }
+        GcpRoleType::Iam => sign_jwt(&metadata, role, service_account).await?,
+    };
+
+    Ok(serde_json::json!({
+        "role": role,
+        "jwt": jwt.trim(),
+    }))
+}
+
+/// A JWT for `vault/<role>` signed as `service_account`, or as the one the
+/// metadata server runs as.
+async fn sign_jwt(
+    metadata: &reqwest::Client,
+    role: &str,
+    service_account: Option<&str>,
+) -> Result<String> {
+    let token: AccessToken = serde_json::from_str(
+        &fetch(
+            metadata_get(metadata, "instance/service-accounts/default/token"),
+            "metadata server",
+        )
+        .await?,
+    )?;
+    let email = match service_account {
+        Some(email) => email.to_string(),
+        None => fetch(
+            metadata_get(metadata, "instance/service-accounts/default/email"),
+            "metadata server",
+        )
+        .await?
+        .trim()
+        .to_string(),
+    };
+
+    let exp = OffsetDateTime::now_utc().unix_timestamp() + JWT_LIFETIME.as_secs() as i64;
+    let claims = serde_json::json!({
+        "aud": format!("vault/{role}"),
+        "sub": email,
+        "exp": exp,
+    });
+    debug!(service_account = email, "signing gcp iam login jwt");
+    let iam = reqwest::Client::builder().timeout(IAM_TIMEOUT).build()?;
+    let body = fetch(
+        iam.post(format!(
+            "{IAM_CREDENTIALS_ENDPOINT}/projects/-/serviceAccounts/{email}:signJwt"
+        ))
+        .bearer_auth(token.access_token)
+        .json(&serde_json::json!({ "payload": claims.to_string() })),
+        "IAM Credentials API",
+    )
+    .await?;
+    let signed: SignedJwt = serde_json::from_str(&body)?;
+    Ok(signed.signed_jwt)
+}
+
+fn metadata_get(metadata: &reqwest::Client, path: &str) -> RequestBuilder {
+    let host = env::var("GCE_METADATA_HOST").unwrap_or_else(|_| METADATA_HOST.into());
+    metadata
+        .get(format!("http://{host}/computeMetadata/v1/{path}"))
+        .header("Metadata-Flavor", "Google")
+}
+
+async fn fetch(request: RequestBuilder, what: &str) -> Result<String> {
+    let response = request
+        .send()
+        .await
+        .map_err(|e| Error::VaultAuth(format!("no gcp identity: {what} unreachable: {e}")))?;
+    if !response.status().is_success() {
+        let status = response.status();
+        let body = response.text().await.unwrap_or_default();
+        return Err(Error::VaultAuth(format!("{what} returned {status}: {body}")));
+    }
+    Ok(response.text().await?)
+}
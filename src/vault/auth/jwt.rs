@@ -0,0 +1,18 @@
+use serde_json::Value;
+
+use crate::error::{Error, Result};
+
+/// Login payload for the JWT/OIDC auth method, and for the Kubernetes
+/// method which takes the same shape.
+///
+/// The file is read on every login so rotated tokens are picked up.
+pub async fn login_payload(role: &str, token_file: &str) -> Result<Value> {
+    let jwt = tokio::fs::read_to_string(token_file)
+        .await
+        .map_err(|e| Error::VaultAuth(format!("failed to read JWT from {token_file}: {e}")))?;
+
+    Ok(serde_json::json!({
+        "role": role,
+        "jwt": jwt.trim(),
+    }))
+}
@@ -0,0 +1,19 @@
+use serde_json::Value;
+
+use crate::error::Result;
+use crate::vault::auth::jwt;
+
+/// The pod's default service account token, used when
+/// `VAULT_AUTH_TOKEN_PATH` is not set.
+pub const SA_TOKEN_PATH: &str = "/var/run/secrets/kubernetes.io/serviceaccount/token";
+
+/// The pod's namespace, mounted with the service account token.
+pub const SA_NAMESPACE_PATH: &str = "/var/run/secrets/kubernetes.io/serviceaccount/namespace";
+
+/// Login payload for the Kubernetes auth method.
+///
+/// Sends the service account JWT in `token_file`, read on every login
+/// since the kubelet rotates projected tokens well before they expire.
+pub async fn login_payload(role: &str, token_file: &str) -> Result<Value> {
+    jwt::login_payload(role, token_file).await
+}
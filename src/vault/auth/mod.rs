@@ -0,0 +1,249 @@
+mod aws;
+mod gcp;
+mod jwt;
+mod kubernetes;
+mod token;
+
+use std::time::Duration;
+
+use serde::Deserialize;
+use tracing::{debug, info, instrument, warn};
+
+use crate::config::{AuthMethod, Config};
+use crate::error::{Error, Result};
+use crate::vault::client::{Scope, TokenLease, VaultClient};
+use crate::vault::token_cache::TokenCache;
+
+pub use kubernetes::{SA_NAMESPACE_PATH, SA_TOKEN_PATH};
+
+/// A cached token with less time left than this is not worth resuming.
+const MIN_RESUME_TTL_SECS: u64 = 300;
+
+#[derive(Debug, Deserialize)]
+struct AuthResponse {
+    auth: AuthData,
+}
+
+#[derive(Debug, Deserialize)]
+struct AuthData {
+    client_token: String,
+    /// Identifies the token in Vault's audit log without revealing it.
+    #[serde(default)]
+    accessor: String,
+    lease_duration: u64,
+    #[serde(default)]
+    renewable: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct LookupResponse {
+    data: LookupData,
+}
+
+#[derive(Debug, Deserialize)]
+struct LookupData {
+    ttl: u64,
+    #[serde(default)]
+    renewable: bool,
+}
+
+/// Authenticate to Vault with the configured auth method and store the
+/// resulting token on the client.
+///
+/// With a static token there is nothing to log in to; the token is just
+/// (re-)loaded from its source.
+#[instrument(name = "vault_login", skip_all, fields(method = %config.vault_auth_method))]
+pub async fn login(client: &VaultClient, config: &Config) -> Result<()> {
+    let role = &config.vault_auth_role;
+    let payload = match config.vault_auth_method {
+        AuthMethod::Token { ref token_file } => {
+            client
+                .set_token(token::load(token_file.as_deref()).await?, None)
+                .await;
+            debug!("loaded static vault token");
+            return Ok(());
+        }
+        AuthMethod::Kubernetes { ref token_file } => {
+            kubernetes::login_payload(role, token_file).await?
+        }
+        AuthMethod::Jwt { ref token_file } => jwt::login_payload(role, token_file).await?,
+        AuthMethod::Aws {
+            ref region,
+            ref iam_server_id,
+        } => aws::login_payload(role, region.as_deref(), iam_server_id.as_deref()).await?,
+        AuthMethod::Gcp {
+            role_type,
+            ref service_account,
+        } => gcp::login_payload(role, role_type, service_account.as_deref()).await?,
+    };
+
+    let url = format!(
+        "{}/v1/auth/{}/login",
+        client.addr, config.vault_auth_mount
+    );
+
+    debug!(
+        url = %url,
+        method = %config.vault_auth_method,
+        role = %config.vault_auth_role,
+        "authenticating to vault"
+    );
+
+    let mut request = client.http.post(&url).json(&payload);
+
+    request = client.namespaced(request, Scope::Auth);
+
+    let response = client.send(request).await?;
+
+    if !response.status().is_success() {
+        let status = response.status();
+        let body = response.text().await.unwrap_or_default();
+        return Err(Error::VaultAuth(format!(
+            "login returned {status}: {body}"
+        )));
+    }
+
+    let auth_resp: AuthResponse = response.json().await?;
+    let lease_duration = auth_resp.auth.lease_duration;
+    let accessor = auth_resp.auth.accessor.clone();
+    store_token(client, config, auth_resp.auth).await;
+    info!(lease_duration, accessor, "vault authentication successful");
+
+    Ok(())
+}
+
+/// Keep the client's token valid for at least `needed`.
+///
+/// A renewable token is extended with `renew-self`; a full login only
+/// happens when the token is not renewable, renewal fails, or the token's
+/// max TTL would cut the renewed lease short of `needed`.
+pub async fn refresh(client: &VaultClient, config: &Config, needed: Duration) -> Result<()> {
+    // An expired token cannot be renewed.
+    let renewable = client
+        .token_lease()
+        .await
+        .is_some_and(|lease| lease.renewable && !lease.remaining().is_zero());
+    if renewable {
+        match renew_self(client).await {
+            Ok(auth) if auth.lease_duration >= needed.as_secs() => {
+                debug!(lease_duration = auth.lease_duration, "vault token renewed");
+                store_token(client, config, auth).await;
+                return Ok(());
+            }
+            Ok(auth) => {
+                debug!(
+                    lease_duration = auth.lease_duration,
+                    needed_secs = needed.as_secs(),
+                    "vault token is near its max TTL, logging in again"
+                );
+            }
+            Err(e) => {
+                warn!(error = %e, code = e.code(), "vault token renewal failed, logging in again");
+            }
+        }
+    }
+
+    login(client, config).await
+}
+
+/// Install a token Vault just issued or renewed, and update the cache.
+async fn store_token(client: &VaultClient, config: &Config, auth: AuthData) {
+    if let Some(cache) = TokenCache::from_config(config) {
+        if let Err(e) = cache
+            .store(&auth.client_token, &auth.accessor, auth.lease_duration)
+            .await
+        {
+            warn!(error = %e, code = e.code(), "failed to write vault token cache");
+        }
+    }
+
+    let lease = TokenLease::new(auth.renewable, auth.lease_duration);
+    client.set_token(auth.client_token, Some(lease)).await;
+}
+
+#[instrument(name = "vault_token_renew", skip_all)]
+async fn renew_self(client: &VaultClient) -> Result<AuthData> {
+    let url = format!("{}/v1/auth/token/renew-self", client.addr);
+    let mut request = client
+        .http
+        .post(&url)
+        .header("X-Vault-Token", client.token().await)
+        .json(&serde_json::json!({}));
+
+    request = client.namespaced(request, Scope::Auth);
+
+    let response = client.send(request).await?;
+    if !response.status().is_success() {
+        return Err(Error::VaultAuth(format!(
+            "token renewal returned {}",
+            response.status()
+        )));
+    }
+
+    let auth_resp: AuthResponse = response.json().await?;
+    Ok(auth_resp.auth)
+}
+
+/// Reuse the token cached by a previous run if Vault still accepts it.
+///
+/// Returns `false` when there is no usable cached token and the caller
+/// should log in.
+pub async fn resume_cached_token(client: &VaultClient, config: &Config) -> bool {
+    let Some(cache) = TokenCache::from_config(config) else {
+        return false;
+    };
+
+    let cached = match cache.load().await {
+        Ok(Some(cached)) => cached,
+        Ok(None) => return false,
+        Err(e) => {
+            warn!(error = %e, code = e.code(), "ignoring unusable vault token cache");
+            return false;
+        }
+    };
+
+    client.set_token(cached.token, None).await;
+    match lookup_self(client).await {
+        Ok(data) if data.ttl >= MIN_RESUME_TTL_SECS => {
+            info!(
+                ttl = data.ttl,
+                accessor = cached.accessor,
+                "resumed cached vault token"
+            );
+            client
+                .set_token_lease(TokenLease::new(data.renewable, data.ttl))
+                .await;
+            true
+        }
+        Ok(LookupData { ttl, .. }) => {
+            debug!(ttl, "cached vault token is close to expiry, logging in");
+            false
+        }
+        Err(e) => {
+            debug!(error = %e, "cached vault token was rejected, logging in");
+            false
+        }
+    }
+}
+
+/// Remaining TTL and renewability of the client's current token.
+async fn lookup_self(client: &VaultClient) -> Result<LookupData> {
+    let url = format!("{}/v1/auth/token/lookup-self", client.addr);
+    let mut request = client
+        .http
+        .get(&url)
+        .header("X-Vault-Token", client.token().await);
+
+    request = client.namespaced(request, Scope::Auth);
+
+    let response = client.send(request).await?;
+    if !response.status().is_success() {
+        return Err(Error::VaultAuth(format!(
+            "token lookup returned {}",
+            response.status()
+        )));
+    }
+
+    let lookup: LookupResponse = response.json().await?;
+    Ok(lookup.data)
+}
@@ -0,0 +1,29 @@
+use std::env;
+
+use crate::error::{Error, Result};
+
+/// Current value of a static token: the contents of `token_file`, or
+/// `VAULT_TOKEN` when no file is configured.
+pub async fn load(token_file: Option<&str>) -> Result<String> {
+    let (token, source) = match token_file {
+        Some(path) => (
+            tokio::fs::read_to_string(path).await.map_err(|e| {
+                Error::VaultAuth(format!("failed to read vault token from {path}: {e}"))
+            })?,
+            path,
+        ),
+        None => (
+            env::var("VAULT_TOKEN")
+                .map_err(|_| Error::VaultAuth("VAULT_TOKEN is not set".into()))?,
+            "VAULT_TOKEN",
+        ),
+    };
+
+    let token = token.trim();
+    if token.is_empty() {
+        return Err(Error::VaultAuth(format!(
+            "vault token from {source} is empty"
+        )));
+    }
+    Ok(token.to_string())
+}
@@ -1,31 +1,76 @@
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use reqwest::Client;
+use reqwest::{Client, Request, RequestBuilder, Response};
 use tokio::sync::RwLock;
+use tracing::warn;
 
 use crate::config::Config;
+use crate::dns::Resolver;
 use crate::error::{Error, Result};
+use crate::metrics;
+use crate::vault::retry::{self, Circuit, CircuitStatus, RetryPolicy, MAX_RETRY_DELAY};
+use crate::vault::tls;
 
 /// Shared Vault HTTP client with managed token state.
 pub struct VaultClient {
     pub http: Client,
     pub addr: String,
-    pub namespace: Option<String>,
+    namespace: Option<String>,
+    auth_namespace: Option<String>,
+    pki_namespace: Option<String>,
+    /// `X-Vault-Wrap-TTL` for PKI requests, if responses are wrapped.
+    pub wrap_ttl: Option<Duration>,
     token: Arc<RwLock<String>>,
+    lease: RwLock<Option<TokenLease>>,
+    retry: RetryPolicy,
+    circuit: Circuit,
+    operation_timeout: Duration,
 }
 
-impl VaultClient {
-    pub fn new(config: &Config) -> Result<Self> {
-        let mut builder = Client::builder();
-
-        if let Some(ref ca_path) = config.vault_cacert {
-            let ca_pem = std::fs::read(ca_path)
-                .map_err(|e| Error::Config(format!("failed to read VAULT_CACERT '{ca_path}': {e}")))?;
-            let cert = reqwest::Certificate::from_pem(&ca_pem)
-                .map_err(|e| Error::Config(format!("invalid VAULT_CACERT: {e}")))?;
-            builder = builder.add_root_certificate(cert);
+/// What a request to Vault is for, which decides the namespace it is made
+/// in.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Scope {
+    /// Logging in, and the token's own endpoints.
+    Auth,
+    /// The PKI mount, and unwrapping what it returned.
+    Pki,
+    /// KV secrets and Transit keys.
+    Secrets,
+}
+
+/// What Vault reported about the current token's lease.
+#[derive(Debug, Clone, Copy)]
+pub struct TokenLease {
+    pub renewable: bool,
+    pub expires_at: Instant,
+}
+
+impl TokenLease {
+    pub fn new(renewable: bool, ttl_secs: u64) -> Self {
+        Self {
+            renewable,
+            expires_at: Instant::now() + Duration::from_secs(ttl_secs),
         }
+    }
+
+    pub fn remaining(&self) -> Duration {
+        self.expires_at.saturating_duration_since(Instant::now())
+    }
+}
 
+impl VaultClient {
+    pub fn new(config: &Config, resolver: Arc<Resolver>) -> Result<Self> {
+        let mut builder = Client::builder()
+            .dns_resolver(resolver)
+            .use_preconfigured_tls(tls::client_config(config)?)
+            .connect_timeout(config.vault_connect_timeout)
+            .timeout(config.vault_request_timeout)
+            .pool_idle_timeout(config.vault_pool_idle_timeout);
+        if let Some(max_idle) = config.vault_pool_max_idle {
+            builder = builder.pool_max_idle_per_host(max_idle);
+        }
         let http = builder
             .build()
             .map_err(|e| Error::Config(format!("failed to build HTTP client: {e}")))?;
@@ -34,13 +79,134 @@ impl VaultClient {
             http,
             addr: config.vault_addr.trim_end_matches('/').to_string(),
             namespace: config.vault_namespace.clone(),
+            auth_namespace: config.vault_auth_namespace.clone(),
+            pki_namespace: config.vault_pki_namespace.clone(),
+            wrap_ttl: config.vault_wrap_ttl,
             token: Arc::new(RwLock::new(String::new())),
+            lease: RwLock::new(None),
+            retry: RetryPolicy::from_config(config),
+            circuit: Circuit::from_config(config),
+            operation_timeout: config.vault_operation_timeout,
         })
     }
 
-    pub async fn set_token(&self, token: String) {
+    /// The namespace requests for `scope` are made in, or `None` for the
+    /// root namespace.
+    pub fn namespace(&self, scope: Scope) -> Option<&str> {
+        match scope {
+            Scope::Auth => self.auth_namespace.as_deref(),
+            Scope::Pki => self.pki_namespace.as_deref(),
+            Scope::Secrets => self.namespace.as_deref(),
+        }
+    }
+
+    /// `request` with the `X-Vault-Namespace` header of `scope`, if any.
+    pub fn namespaced(&self, request: RequestBuilder, scope: Scope) -> RequestBuilder {
+        match self.namespace(scope) {
+            Some(ns) => request.header("X-Vault-Namespace", ns),
+            None => request,
+        }
+    }
+
+    /// Send a request to Vault, retrying connection errors, timeouts, 5xx,
+    /// and 429 responses with jittered exponential backoff, or after the
+    /// `Retry-After` delay Vault asks for.
+    ///
+    /// The last response is returned as is once the retries run out, so
+    /// callers still see Vault's error. While the circuit is open the
+    /// request is not sent at all. Attempts and retries together stop at
+    /// `VAULT_OPERATION_TIMEOUT`.
+    pub async fn send(&self, request: RequestBuilder) -> Result<Response> {
+        if !self.circuit.allow() {
+            metrics::inc("vault_circuit", "rejected");
+            return Err(Error::VaultUnavailable(format!(
+                "circuit open after {} consecutive failed requests",
+                self.circuit.failures()
+            )));
+        }
+
+        let request = request.build()?;
+        let path = request.url().path().to_string();
+        match tokio::time::timeout(self.operation_timeout, self.send_with_retries(request)).await {
+            Ok(outcome) => outcome,
+            Err(_) => {
+                self.circuit.record_failure();
+                Err(Error::Timeout(format!(
+                    "vault request to {path} did not complete within {:?}, retries included",
+                    self.operation_timeout
+                )))
+            }
+        }
+    }
+
+    async fn send_with_retries(&self, request: Request) -> Result<Response> {
+        let mut retry = 0;
+        loop {
+            // Only streamed bodies cannot be cloned, and none are sent.
+            let Some(attempt) = request.try_clone() else {
+                return Ok(self.http.execute(request).await?);
+            };
+            let started = Instant::now();
+            let outcome = self.http.execute(attempt).await;
+            metrics::observe(
+                "vault_request_duration_seconds",
+                if outcome.is_ok() { "response" } else { "error" },
+                metrics::DURATION_BUCKETS,
+                started.elapsed().as_secs_f64(),
+            );
+            let Some(reason) = retry::retry_reason(&outcome) else {
+                if outcome.is_ok() {
+                    self.circuit.record_success();
+                }
+                return Ok(outcome?);
+            };
+
+            let delay = retry::retry_after(&outcome).unwrap_or_else(|| self.retry.delay(retry));
+            if retry >= self.retry.retries || delay > MAX_RETRY_DELAY {
+                self.circuit.record_failure();
+                return Ok(outcome?);
+            }
+            let count = metrics::inc("vault_retries", reason);
+            match outcome {
+                Ok(ref response) => warn!(
+                    path = request.url().path(),
+                    status = %response.status(),
+                    retry_in_ms = delay.as_millis() as u64,
+                    count,
+                    "vault request failed, retrying"
+                ),
+                Err(ref e) => warn!(
+                    path = request.url().path(),
+                    error = %e,
+                    retry_in_ms = delay.as_millis() as u64,
+                    count,
+                    "vault request failed, retrying"
+                ),
+            }
+            tokio::time::sleep(delay).await;
+            retry += 1;
+        }
+    }
+
+    /// Whether requests are currently being sent to Vault.
+    pub fn circuit_status(&self) -> CircuitStatus {
+        self.circuit.status()
+    }
+
+    /// Replace the token. `lease` is `None` when the token's lease is
+    /// unknown, e.g. a static token.
+    pub async fn set_token(&self, token: String, lease: Option<TokenLease>) {
         let mut guard = self.token.write().await;
         *guard = token;
+        *self.lease.write().await = lease;
+    }
+
+    pub async fn set_token_lease(&self, lease: TokenLease) {
+        *self.lease.write().await = Some(lease);
+    }
+
+    pub async fn token_lease(&self) -> Option<TokenLease> {
+        *self.lease.read().await
     }
 
     pub async fn token(&self) -> String {
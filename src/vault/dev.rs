@@ -0,0 +1,412 @@
+use std::convert::Infallible;
+use std::sync::Arc;
+use std::time::Duration;
+
+use bytes::Bytes;
+use http_body_util::{BodyExt, Full, Limited};
+use hyper::body::Incoming;
+use hyper::header::{HeaderValue, CONTENT_TYPE};
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper::{Method, Request, Response, StatusCode};
+use hyper_util::rt::TokioIo;
+use rcgen::{
+    BasicConstraints, Certificate, CertificateParams, DistinguishedName, DnType,
+    ExtendedKeyUsagePurpose, IsCa, KeyPair, KeyUsagePurpose, PublicKeyData, SerialNumber,
+    SignatureAlgorithm, PKCS_ECDSA_P256_SHA256, PKCS_ECDSA_P384_SHA384, PKCS_ED25519,
+    PKCS_RSA_SHA256,
+};
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::Deserialize;
+use serde_json::json;
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+use tokio::net::TcpListener;
+use tokio::sync::watch;
+use tracing::{debug, error, info};
+use x509_parser::oid_registry::{
+    OID_EC_P256, OID_KEY_TYPE_EC_PUBLIC_KEY, OID_NIST_EC_P384, OID_PKCS1_RSAENCRYPTION,
+    OID_SIG_ED25519,
+};
+use x509_parser::prelude::{FromDer, X509CertificationRequest};
+
+use crate::cert::info::format_hex;
+use crate::cert::keygen;
+use crate::config::{parse_duration, CertNames};
+use crate::error::{Error, Result};
+
+const MAX_BODY_BYTES: usize = 64 * 1024;
+/// Lease of the tokens handed out by logins and renewals.
+const TOKEN_TTL: Duration = Duration::from_secs(3600);
+/// Lifetime of a certificate whose request names no `ttl`, as on a Vault
+/// role without one.
+const DEFAULT_CERT_TTL: Duration = Duration::from_secs(768 * 3600);
+const CA_VALIDITY: Duration = Duration::from_secs(10 * 365 * 24 * 3600);
+
+/// A stand-in for the parts of Vault's HTTP API that cert-keeper uses, for
+/// running it locally and in integration tests without a Vault server.
+///
+/// It answers `sys/health`, a login on any auth mount, as the Kubernetes
+/// and JWT methods post it, token lookups and renewals, and on any PKI
+/// mount `issue/<role>` and `sign/<role>` from a CA generated at startup,
+/// along with the CA chain, roles, capabilities, and revocations that
+/// `cert-keeper check` and the renewal loop ask about. Every login succeeds
+/// and every role exists; everything but a login needs the token given to
+/// [`DevVault::new`], which logins return. Nothing is kept across restarts.
+pub struct DevVault {
+    token: String,
+    ca_key: KeyPair,
+    ca_cert: Certificate,
+    rng: SystemRandom,
+}
+
+/// The body of `issue/<role>` and `sign/<role>`.
+#[derive(Deserialize)]
+struct IssueRequest {
+    common_name: String,
+    #[serde(default)]
+    alt_names: Option<String>,
+    #[serde(default)]
+    ip_sans: Option<String>,
+    #[serde(default)]
+    uri_sans: Option<String>,
+    #[serde(default)]
+    ttl: Option<String>,
+    #[serde(default)]
+    not_after: Option<String>,
+    #[serde(default)]
+    exclude_cn_from_sans: bool,
+    /// Only on `sign/<role>`.
+    #[serde(default)]
+    csr: Option<String>,
+}
+
+/// The public key of a CSR, for signing a certificate over it.
+struct CsrKey {
+    der: Vec<u8>,
+    algorithm: &'static SignatureAlgorithm,
+}
+
+impl PublicKeyData for CsrKey {
+    fn der_bytes(&self) -> &[u8] {
+        &self.der
+    }
+
+    fn algorithm(&self) -> &SignatureAlgorithm {
+        self.algorithm
+    }
+}
+
+impl DevVault {
+    /// Generate the CA, a P-256 root valid for ten years. Requests must
+    /// carry `token` in `X-Vault-Token`.
+    pub fn new(token: impl Into<String>) -> Result<Self> {
+        let failed = |e: rcgen::Error| Error::KeyGen(format!("failed to build the dev CA: {e}"));
+        let ca_key = KeyPair::generate_for(&PKCS_ECDSA_P256_SHA256).map_err(failed)?;
+        let mut params = CertificateParams::default();
+        let mut subject = DistinguishedName::new();
+        subject.push(DnType::CommonName, "cert-keeper dev-vault CA");
+        params.distinguished_name = subject;
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        params.key_usages = vec![
+            KeyUsagePurpose::KeyCertSign,
+            KeyUsagePurpose::CrlSign,
+            KeyUsagePurpose::DigitalSignature,
+        ];
+        let now = OffsetDateTime::now_utc();
+        params.not_before = now - Duration::from_secs(60);
+        params.not_after = now + CA_VALIDITY;
+        let ca_cert = params.self_signed(&ca_key).map_err(failed)?;
+
+        Ok(Self {
+            token: token.into(),
+            ca_key,
+            ca_cert,
+            rng: SystemRandom::new(),
+        })
+    }
+
+    /// PEM certificate of the CA, for clients to trust.
+    pub fn ca_pem(&self) -> String {
+        self.ca_cert.pem()
+    }
+
+    async fn handle(&self, req: Request<Incoming>) -> Response<Full<Bytes>> {
+        let method = req.method().clone();
+        let path = req.uri().path().trim_end_matches('/').to_string();
+        debug!(%method, path, "dev-vault request");
+        let Some(route) = path.strip_prefix("/v1/") else {
+            return not_found();
+        };
+        let segments: Vec<&str> = route.split('/').collect();
+
+        // Vault serves these without a token.
+        if segments == ["sys", "health"] {
+            return json_response(
+                StatusCode::OK,
+                &json!({ "initialized": true, "sealed": false, "standby": false }),
+            );
+        }
+        if method == Method::GET
+            && matches!(segments.as_slice(), [.., "ca_chain"] | [.., "ca", "pem"])
+        {
+            let mut response = Response::new(Full::new(Bytes::from(self.ca_pem())));
+            response.headers_mut().insert(
+                CONTENT_TYPE,
+                HeaderValue::from_static("application/pem-certificate-chain"),
+            );
+            return response;
+        }
+        let login = method == Method::POST
+            && matches!(segments.as_slice(), ["auth", .., "login"])
+            && segments.len() > 2;
+        let authorized = req
+            .headers()
+            .get("x-vault-token")
+            .is_some_and(|token| token.as_bytes() == self.token.as_bytes());
+        if !login && !authorized {
+            return error_response(StatusCode::FORBIDDEN, "permission denied");
+        }
+
+        let body = match Limited::new(req.into_body(), MAX_BODY_BYTES).collect().await {
+            Ok(body) => body.to_bytes(),
+            Err(e) => {
+                return error_response(
+                    StatusCode::BAD_REQUEST,
+                    &format!("failed to read request body: {e}"),
+                )
+            }
+        };
+        let post = method == Method::POST || method == Method::PUT;
+
+        match segments.as_slice() {
+            _ if login => {
+                let request: serde_json::Value = serde_json::from_slice(&body).unwrap_or_default();
+                if request["role"].as_str().is_none_or(str::is_empty) {
+                    return error_response(StatusCode::BAD_REQUEST, "missing role");
+                }
+                info!(mount = %segments[1..segments.len() - 1].join("/"), "dev-vault login");
+                self.auth()
+            }
+            ["auth", "token", "lookup-self"] if method == Method::GET => json_response(
+                StatusCode::OK,
+                &json!({ "data": { "ttl": TOKEN_TTL.as_secs(), "renewable": true } }),
+            ),
+            ["auth", "token", "renew-self"] if post => self.auth(),
+            ["sys", "capabilities-self"] if post => {
+                json_response(StatusCode::OK, &json!({ "capabilities": ["root"] }))
+            }
+            [.., "issue", _] if post => self.issue(&body, false),
+            [.., "sign", _] if post => self.issue(&body, true),
+            [.., "roles", _] if method == Method::GET => {
+                json_response(StatusCode::OK, &json!({ "data": {} }))
+            }
+            [.., "revoke"] if post => json_response(
+                StatusCode::OK,
+                &json!({ "data": { "revocation_time": OffsetDateTime::now_utc().unix_timestamp() } }),
+            ),
+            _ => not_found(),
+        }
+    }
+
+    fn auth(&self) -> Response<Full<Bytes>> {
+        json_response(
+            StatusCode::OK,
+            &json!({
+                "auth": {
+                    "client_token": self.token,
+                    "accessor": "dev-vault",
+                    "lease_duration": TOKEN_TTL.as_secs(),
+                    "renewable": true,
+                    "policies": ["root"],
+                }
+            }),
+        )
+    }
+
+    /// Issue a certificate for the names in `body`, over a new P-256 key,
+    /// or over the key of its `csr` when `sign`.
+    fn issue(&self, body: &[u8], sign: bool) -> Response<Full<Bytes>> {
+        let result = serde_json::from_slice(body)
+            .map_err(|e| Error::Config(format!("invalid request: {e}")))
+            .and_then(|req: IssueRequest| {
+                let names = CertNames {
+                    common_name: req.common_name.clone(),
+                    alt_names: req.alt_names.clone(),
+                    ip_sans: req.ip_sans.clone(),
+                    uri_sans: req.uri_sans.clone(),
+                };
+                let valid_for = validity(&req)?;
+                let mut params = keygen::leaf_params(&names, !req.exclude_cn_from_sans, valid_for)?;
+                let mut serial = [0u8; 16];
+                self.rng
+                    .fill(&mut serial)
+                    .map_err(|_| Error::KeyGen("failed to generate a serial number".into()))?;
+                // Kept positive in its DER encoding.
+                serial[0] &= 0x7f;
+                params.serial_number = Some(SerialNumber::from_slice(&serial));
+                params.use_authority_key_identifier_extension = true;
+                params.key_usages = vec![
+                    KeyUsagePurpose::DigitalSignature,
+                    KeyUsagePurpose::KeyEncipherment,
+                    KeyUsagePurpose::KeyAgreement,
+                ];
+                params.extended_key_usages = vec![
+                    ExtendedKeyUsagePurpose::ServerAuth,
+                    ExtendedKeyUsagePurpose::ClientAuth,
+                ];
+
+                let failed = |e: rcgen::Error| Error::KeyGen(format!("failed to issue: {e}"));
+                let (cert, private_key) = if sign {
+                    let csr = req
+                        .csr
+                        .as_deref()
+                        .ok_or_else(|| Error::Config("missing csr".into()))?;
+                    let key = csr_key(csr)?;
+                    (params.signed_by(&key, &self.ca_cert, &self.ca_key).map_err(failed)?, None)
+                } else {
+                    let key = KeyPair::generate_for(&PKCS_ECDSA_P256_SHA256).map_err(failed)?;
+                    let cert = params.signed_by(&key, &self.ca_cert, &self.ca_key).map_err(failed)?;
+                    (cert, Some(key.serialize_pem()))
+                };
+                let serial = format_hex(&serial, ':');
+                info!(common_name = %req.common_name, serial, "dev-vault issued a certificate");
+                Ok((cert, private_key, serial))
+            });
+
+        let (cert, private_key, serial) = match result {
+            Ok(issued) => issued,
+            Err(e) => return error_response(StatusCode::BAD_REQUEST, &e.to_string()),
+        };
+        let ca_pem = self.ca_pem();
+        let mut data = json!({
+            "certificate": cert.pem(),
+            "issuing_ca": ca_pem,
+            "ca_chain": [ca_pem],
+            "serial_number": serial,
+        });
+        if let Some(private_key) = private_key {
+            data["private_key"] = private_key.into();
+            data["private_key_type"] = "ec".into();
+        }
+        json_response(
+            StatusCode::OK,
+            &json!({ "lease_duration": 0, "renewable": false, "data": data }),
+        )
+    }
+}
+
+/// How long from now the requested certificate is valid: until its
+/// `not_after`, for its `ttl`, or for [`DEFAULT_CERT_TTL`].
+fn validity(req: &IssueRequest) -> Result<Duration> {
+    if let Some(ref not_after) = req.not_after {
+        let not_after = OffsetDateTime::parse(not_after, &Rfc3339)
+            .map_err(|e| Error::Config(format!("invalid not_after {not_after}: {e}")))?;
+        return Duration::try_from(not_after - OffsetDateTime::now_utc())
+            .map_err(|_| Error::Config("not_after is in the past".into()));
+    }
+    match req.ttl.as_deref() {
+        // Vault takes bare numbers as seconds.
+        Some(ttl) if ttl.bytes().all(|b| b.is_ascii_digit()) && !ttl.is_empty() => ttl
+            .parse()
+            .map(Duration::from_secs)
+            .map_err(|e| Error::Config(format!("invalid ttl {ttl}: {e}"))),
+        Some(ttl) => parse_duration(ttl),
+        None => Ok(DEFAULT_CERT_TTL),
+    }
+}
+
+/// The public key of a PEM CSR. Its signature is not checked.
+fn csr_key(pem: &str) -> Result<CsrKey> {
+    let invalid = |message: String| Error::Config(format!("invalid csr: {message}"));
+    let der = rustls_pemfile::csr(&mut pem.as_bytes())
+        .map_err(|e| invalid(e.to_string()))?
+        .ok_or_else(|| invalid("no CERTIFICATE REQUEST block".into()))?;
+    let (_, csr) = X509CertificationRequest::from_der(der.as_ref())
+        .map_err(|e| invalid(e.to_string()))?;
+    let spki = &csr.certification_request_info.subject_pki;
+    let curve = spki
+        .algorithm
+        .parameters
+        .as_ref()
+        .and_then(|p| p.as_oid().ok());
+    let key_type = &spki.algorithm.algorithm;
+    let algorithm = if *key_type == OID_KEY_TYPE_EC_PUBLIC_KEY {
+        match curve {
+            Some(curve) if curve == OID_EC_P256 => &PKCS_ECDSA_P256_SHA256,
+            Some(curve) if curve == OID_NIST_EC_P384 => &PKCS_ECDSA_P384_SHA384,
+            _ => return Err(invalid("unsupported elliptic curve".into())),
+        }
+    } else if *key_type == OID_SIG_ED25519 {
+        &PKCS_ED25519
+    } else if *key_type == OID_PKCS1_RSAENCRYPTION {
+        &PKCS_RSA_SHA256
+    } else {
+        return Err(invalid(format!("unsupported key type {key_type}")));
+    };
+    Ok(CsrKey {
+        der: spki.subject_public_key.data.to_vec(),
+        algorithm,
+    })
+}
+
+/// Serve the API over plain HTTP until shutdown.
+pub async fn run(
+    server: Arc<DevVault>,
+    listener: TcpListener,
+    mut shutdown: watch::Receiver<bool>,
+) -> Result<()> {
+    info!(addr = %listener.local_addr()?, "dev-vault listening");
+
+    loop {
+        tokio::select! {
+            result = listener.accept() => {
+                let (tcp_stream, peer_addr) = match result {
+                    Ok(conn) => conn,
+                    Err(e) => {
+                        error!(error = %e, "failed to accept dev-vault connection");
+                        continue;
+                    }
+                };
+
+                let server = server.clone();
+                tokio::spawn(async move {
+                    let service = service_fn(move |req| {
+                        let server = server.clone();
+                        async move { Ok::<_, Infallible>(server.handle(req).await) }
+                    });
+
+                    if let Err(e) = http1::Builder::new()
+                        .serve_connection(TokioIo::new(tcp_stream), service)
+                        .await
+                    {
+                        debug!(peer = %peer_addr, error = %e, "dev-vault connection ended");
+                    }
+                });
+            }
+            _ = shutdown.changed() => {
+                info!("dev-vault shutting down");
+                return Ok(());
+            }
+        }
+    }
+}
+
+fn json_response(status: StatusCode, body: &serde_json::Value) -> Response<Full<Bytes>> {
+    let mut response = Response::new(Full::new(Bytes::from(body.to_string())));
+    *response.status_mut() = status;
+    response
+        .headers_mut()
+        .insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    response
+}
+
+/// Vault's error body: `{"errors": [...]}`.
+fn error_response(status: StatusCode, message: &str) -> Response<Full<Bytes>> {
+    json_response(status, &json!({ "errors": [message] }))
+}
+
+/// What Vault answers for a path without a handler.
+fn not_found() -> Response<Full<Bytes>> {
+    json_response(StatusCode::NOT_FOUND, &json!({ "errors": [] }))
+}
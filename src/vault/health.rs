@@ -0,0 +1,82 @@
+use std::time::Duration;
+
+use reqwest::StatusCode;
+use tokio::time::Instant;
+use tracing::{info, instrument, warn};
+
+use crate::vault::client::VaultClient;
+
+const INITIAL_POLL_INTERVAL: Duration = Duration::from_secs(1);
+const MAX_POLL_INTERVAL: Duration = Duration::from_secs(10);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Poll `sys/health` until Vault is initialized, unsealed, and able to
+/// serve requests, for up to `timeout`.
+///
+/// Gives up quietly when `timeout` passes: the caller carries on and fails
+/// with the real error if Vault is still down. A zero `timeout` skips the
+/// check.
+#[instrument(name = "vault_health", skip_all)]
+pub async fn wait_until_ready(client: &VaultClient, timeout: Duration) {
+    if timeout.is_zero() {
+        return;
+    }
+    let deadline = Instant::now() + timeout;
+    let mut interval = INITIAL_POLL_INTERVAL;
+    let mut waited = false;
+    loop {
+        let reason = match check(client).await {
+            Ok(()) => {
+                if waited {
+                    info!("vault is ready");
+                }
+                return;
+            }
+            Err(reason) => reason,
+        };
+
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            warn!(
+                reason = %reason,
+                timeout_secs = timeout.as_secs(),
+                "vault is still not ready after VAULT_STARTUP_TIMEOUT, starting anyway"
+            );
+            return;
+        }
+        let sleep_for = interval.min(remaining);
+        warn!(
+            reason = %reason,
+            retry_in_ms = sleep_for.as_millis() as u64,
+            "waiting for vault to become ready"
+        );
+        tokio::time::sleep(sleep_for).await;
+        interval = (interval * 2).min(MAX_POLL_INTERVAL);
+        waited = true;
+    }
+}
+
+/// Why Vault cannot serve requests yet, if it cannot.
+///
+/// Standbys count as ready, since they forward requests to the active
+/// node.
+async fn check(client: &VaultClient) -> Result<(), String> {
+    let url = format!(
+        "{}/v1/sys/health?standbyok=true&perfstandbyok=true",
+        client.addr
+    );
+    let response = client
+        .http
+        .get(&url)
+        .timeout(REQUEST_TIMEOUT)
+        .send()
+        .await
+        .map_err(|e| format!("unreachable: {e}"))?;
+    match response.status() {
+        StatusCode::OK => Ok(()),
+        StatusCode::NOT_IMPLEMENTED => Err("not initialized".into()),
+        StatusCode::SERVICE_UNAVAILABLE => Err("sealed".into()),
+        status if status.as_u16() == 472 => Err("disaster recovery secondary".into()),
+        status => Err(format!("health check returned {status}")),
+    }
+}
@@ -0,0 +1,175 @@
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use rustls::sign::CertifiedKey;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use tokio::sync::watch;
+use tokio::time::Instant;
+use tracing::{debug, error, info, instrument};
+
+use crate::cert::store::write_atomic;
+use crate::config::{Config, KvSecret};
+use crate::error::{Error, Result};
+use crate::vault::auth;
+use crate::vault::client::{Scope, VaultClient};
+
+/// How soon a failed refresh is tried again.
+const RETRY_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How long the Vault token must stay valid for a refresh.
+const TOKEN_VALIDITY: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Deserialize)]
+struct KvResponse {
+    data: KvData,
+    /// Zero for KV v2, which issues no leases, unless a plugin behind the
+    /// mount sets one.
+    #[serde(default)]
+    lease_duration: u64,
+}
+
+#[derive(Debug, Deserialize)]
+struct KvData {
+    /// Null when the latest version was deleted.
+    data: Option<Map<String, Value>>,
+}
+
+/// Read the latest version of the KV v2 secret at `path` under `mount`,
+/// with its lease if Vault gave one.
+pub async fn read(
+    client: &VaultClient,
+    mount: &str,
+    path: &str,
+) -> Result<(Map<String, Value>, Option<Duration>)> {
+    let url = format!("{}/v1/{}/data/{}", client.addr, mount, path);
+    let mut request = client
+        .http
+        .get(&url)
+        .header("X-Vault-Token", client.token().await);
+    request = client.namespaced(request, Scope::Secrets);
+
+    let response = client.send(request).await?;
+    if !response.status().is_success() {
+        let status = response.status();
+        let body = response.text().await.unwrap_or_default();
+        return Err(Error::VaultKv(format!(
+            "reading {mount}/{path} returned {status}: {body}"
+        )));
+    }
+
+    let response: KvResponse = response.json().await?;
+    let data = response.data.data.ok_or_else(|| {
+        Error::VaultKv(format!("the latest version of {mount}/{path} is deleted"))
+    })?;
+    let lease = Some(Duration::from_secs(response.lease_duration)).filter(|d| !d.is_zero());
+    Ok((data, lease))
+}
+
+/// The file contents for `secret`: the value of its key as is, or the
+/// whole secret as a JSON object.
+fn render(secret: &KvSecret, data: &Map<String, Value>) -> Result<String> {
+    let Some(ref field) = secret.field else {
+        return Ok(format!("{}\n", serde_json::to_string_pretty(data)?));
+    };
+    match data.get(field) {
+        Some(Value::String(value)) => Ok(value.clone()),
+        Some(value) => Ok(value.to_string()),
+        None => Err(Error::VaultKv(format!(
+            "secret {} has no key '{field}'",
+            secret.path
+        ))),
+    }
+}
+
+/// Read every secret in `KV_SECRETS` and write the files that changed to
+/// `SECRETS_DIR`. Returns the shortest lease among them, if any has one.
+#[instrument(name = "kv_refresh", skip_all, fields(dir = %config.secrets_dir))]
+pub async fn write_secrets(client: &VaultClient, config: &Config) -> Result<Option<Duration>> {
+    let expiring = client
+        .token_lease()
+        .await
+        .is_none_or(|lease| lease.remaining() < TOKEN_VALIDITY);
+    if expiring {
+        auth::refresh(client, config, TOKEN_VALIDITY).await?;
+    }
+
+    let dir = Path::new(&config.secrets_dir);
+    tokio::fs::create_dir_all(dir).await?;
+    let mut lease: Option<Duration> = None;
+    let mut written = 0;
+    for secret in &config.kv_secrets {
+        let (data, secret_lease) = read(client, &config.vault_kv_mount, &secret.path).await?;
+        lease = lease.into_iter().chain(secret_lease).min();
+
+        let contents = render(secret, &data)?;
+        let path = dir.join(&secret.file);
+        // Left alone when unchanged, so apps watching the file only see
+        // real rotations.
+        if tokio::fs::read_to_string(&path).await.ok().as_ref() == Some(&contents) {
+            continue;
+        }
+        write_atomic(
+            &path,
+            contents,
+            config.cert_key_file_mode,
+            config.cert_file_uid,
+            config.cert_file_gid,
+        )
+        .await
+        .map_err(|e| Error::VaultKv(format!("failed to write {}: {e}", path.display())))?;
+        written += 1;
+    }
+
+    if written > 0 {
+        info!(written, "KV secrets written");
+    } else {
+        debug!("KV secrets unchanged");
+    }
+    Ok(lease)
+}
+
+/// Keep the files of `KV_SECRETS` up to date until shutdown.
+///
+/// They are written right away, then re-read whenever a new certificate is
+/// issued, as seen on `issued`, and when the shortest lease Vault gave
+/// them runs out. A failed refresh is retried after [`RETRY_INTERVAL`].
+pub async fn run(
+    client: Arc<VaultClient>,
+    config: Config,
+    mut issued: watch::Receiver<Option<Arc<CertifiedKey>>>,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    let mut current = issued.borrow_and_update().clone();
+    loop {
+        let next = match write_secrets(&client, &config).await {
+            Ok(lease) => lease,
+            Err(e) => {
+                error!(error = %e, code = e.code(), retry_in_secs = RETRY_INTERVAL.as_secs(), "failed to refresh KV secrets");
+                Some(RETRY_INTERVAL)
+            }
+        };
+        let deadline = next.map(|wait| Instant::now() + wait);
+
+        loop {
+            tokio::select! {
+                _ = tokio::time::sleep_until(deadline.unwrap_or_else(Instant::now)), if deadline.is_some() => break,
+                Ok(()) = issued.changed() => {
+                    // The server config is also rebuilt without a renewal,
+                    // as when a CRL is refreshed.
+                    let key = issued.borrow_and_update().clone();
+                    let renewed = match (&key, &current) {
+                        (Some(key), Some(current)) => !Arc::ptr_eq(key, current),
+                        (key, _) => key.is_some(),
+                    };
+                    current = key;
+                    if renewed {
+                        break;
+                    }
+                }
+                _ = shutdown.changed() => return,
+            }
+        }
+    }
+}
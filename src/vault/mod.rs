@@ -1,3 +1,13 @@
 pub mod auth;
 pub mod client;
+#[cfg(feature = "dev-vault")]
+pub mod dev;
+pub mod health;
+pub mod kv;
 pub mod pki;
+pub mod retry;
+pub mod tls;
+pub mod token_cache;
+#[cfg(feature = "key-encryption")]
+pub mod transit;
+pub mod trust_bundle;
@@ -1,101 +1,550 @@
+use reqwest::StatusCode;
 use serde::Deserialize;
-use tracing::{debug, info};
+use time::format_description::well_known::Rfc3339;
+use tracing::{debug, error, info, instrument};
 
-use crate::config::Config;
+use crate::cert::info::{parse_pem_chain, CertInfo};
+use crate::cert::issuer::CertBundle;
+use crate::cert::keygen;
+use crate::cert::secret::KeyPem;
+use crate::config::{CertNames, Config, KeySource};
 use crate::error::{Error, Result};
-use crate::vault::client::VaultClient;
+use crate::vault::client::{Scope, VaultClient};
 
 #[derive(Debug, Deserialize)]
 struct PkiResponse {
+    #[serde(default)]
+    request_id: Option<String>,
     data: PkiData,
     lease_duration: u64,
 }
 
+/// A response-wrapped reply: only the single-use token that unwraps it.
+#[derive(Debug, Deserialize)]
+struct WrappedResponse {
+    wrap_info: WrapInfo,
+}
+
+#[derive(Debug, Deserialize)]
+struct WrapInfo {
+    token: String,
+    accessor: String,
+    /// The path whose response was wrapped.
+    creation_path: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct CapabilitiesResponse {
+    /// The token's capabilities on the requested path.
+    capabilities: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct IssuersResponse {
+    data: IssuersData,
+}
+
+#[derive(Debug, Deserialize)]
+struct IssuersData {
+    #[serde(default)]
+    keys: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct IssuerResponse {
+    data: IssuerData,
+}
+
+#[derive(Debug, Deserialize)]
+struct IssuerData {
+    certificate: String,
+    #[serde(default)]
+    ca_chain: Vec<String>,
+}
+
 #[derive(Debug, Deserialize)]
 struct PkiData {
     certificate: String,
     issuing_ca: String,
-    private_key: String,
+    /// The issuing CA and every CA above it, when Vault knows them. Older
+    /// Vault versions and some mounts leave it out.
+    #[serde(default)]
+    ca_chain: Vec<String>,
+    /// Absent on `sign` responses, where the caller holds the key.
+    #[serde(default)]
+    private_key: KeyPem,
 }
 
-/// A certificate bundle returned from Vault PKI.
-pub struct CertBundle {
-    /// PEM-encoded certificate (leaf + issuing CA).
-    pub certificate: String,
-    /// PEM-encoded private key.
-    pub private_key: String,
-    /// PEM-encoded issuing CA certificate.
-    pub ca_certificate: String,
-    /// Lease duration in seconds (used for renewal scheduling).
-    pub lease_duration_secs: u64,
-}
+/// Issue a new certificate for `names` from Vault's PKI secrets engine.
+///
+/// With `CERT_KEY_SOURCE=local` the key is generated here and only a CSR is
+/// sent to `pki/sign`; otherwise Vault generates the key via `pki/issue`.
+#[instrument(name = "pki_issue", skip_all, fields(common_name = %names.common_name))]
+pub async fn issue_certificate(
+    client: &VaultClient,
+    config: &Config,
+    names: &CertNames,
+) -> Result<CertBundle> {
+    let local_key = match config.cert_key_source {
+        KeySource::Vault => None,
+        KeySource::Local => Some(keygen::generate(config, names)?),
+    };
 
-/// Issue a new certificate from Vault's PKI secrets engine.
-pub async fn issue_certificate(client: &VaultClient, config: &Config) -> Result<CertBundle> {
-    let url = format!(
-        "{}/v1/{}/issue/{}",
-        client.addr, config.vault_pki_mount, config.vault_pki_role
-    );
+    let path = issue_path(config);
+    let url = format!("{}/v1/{}", client.addr, path);
 
     debug!(
         url = %url,
-        common_name = %config.cert_common_name,
+        common_name = %names.common_name,
         ttl = %config.cert_ttl,
         "requesting certificate from vault PKI"
     );
 
     let mut body = serde_json::json!({
-        "common_name": config.cert_common_name,
-        "ttl": config.cert_ttl,
+        "common_name": names.common_name,
     });
 
-    if let Some(ref alt_names) = config.cert_alt_names {
+    // Vault ignores the TTL when given a fixed expiry.
+    match config.cert_not_after {
+        Some(not_after) => {
+            let not_after = not_after
+                .format(&Rfc3339)
+                .map_err(|e| Error::VaultPki(format!("invalid not_after: {e}")))?;
+            body["not_after"] = not_after.into();
+        }
+        None => body["ttl"] = config.cert_ttl.clone().into(),
+    }
+
+    if config.cert_exclude_cn_from_sans {
+        body["exclude_cn_from_sans"] = true.into();
+    }
+
+    if let Some(ref other_sans) = config.cert_other_sans {
+        body["other_sans"] = serde_json::Value::String(other_sans.clone());
+    }
+
+    if let Some(ref serial_number) = config.cert_serial_number {
+        body["serial_number"] = serial_number.clone().into();
+    }
+
+    if let Some(ref alt_names) = names.alt_names {
         body["alt_names"] = serde_json::Value::String(alt_names.clone());
     }
 
-    if let Some(ref ip_sans) = config.cert_ip_sans {
+    if let Some(ref ip_sans) = names.ip_sans {
         body["ip_sans"] = serde_json::Value::String(ip_sans.clone());
     }
 
-    let token = client.token().await;
+    if let Some(ref uri_sans) = names.uri_sans {
+        body["uri_sans"] = serde_json::Value::String(uri_sans.clone());
+    }
+
+    match local_key {
+        Some(ref key) => body["csr"] = serde_json::Value::String(key.csr_pem.clone()),
+        // Only meaningful when Vault generates the key; on `sign` the CSR
+        // determines it.
+        None => {
+            if let Some(key_type) = config.cert_key_type {
+                body["key_type"] = key_type.name().into();
+            }
+            if let Some(bits) = config.cert_key_bits {
+                body["key_bits"] = bits.into();
+            }
+            if let Some(format) = config.cert_private_key_format {
+                body["private_key_format"] = format.name().into();
+            }
+        }
+    }
+
+    let pki_resp = send_pki_request(client, &path, &body).await?;
+
+    info!(
+        lease_duration = pki_resp.lease_duration,
+        common_name = %names.common_name,
+        "certificate issued successfully"
+    );
+
+    let mut bundle = into_bundle(pki_resp);
+    check_uri_sans(&bundle, names)?;
+    if let Some(key) = local_key {
+        bundle.private_key = key.private_key_pem;
+    }
+    Ok(bundle)
+}
+
+/// Fail if the issued certificate lacks a requested URI SAN. A role whose
+/// `allowed_uri_sans` does not match, or a `sign` that takes its SANs from
+/// somewhere else, can otherwise hand back a certificate without the
+/// identity workloads are authorized by.
+fn check_uri_sans(bundle: &CertBundle, names: &CertNames) -> Result<()> {
+    let Some(ref uri_sans) = names.uri_sans else {
+        return Ok(());
+    };
+    let leaf = CertInfo::from_der(&parse_pem_chain(&bundle.certificate)?[0])?;
+    let missing = uri_sans
+        .split(',')
+        .map(str::trim)
+        .filter(|uri| !uri.is_empty() && !leaf.uris.iter().any(|u| u == uri))
+        .collect::<Vec<_>>();
+    if missing.is_empty() {
+        return Ok(());
+    }
+    Err(Error::VaultPki(format!(
+        "issued certificate for {} is missing URI SAN(s) {}; check the role's allowed_uri_sans",
+        names.common_name,
+        missing.join(", ")
+    )))
+}
+
+/// The path [`issue_certificate`] posts to: `<mount>/issue/<role>`, or
+/// `<mount>/sign/<role>` when the key is generated locally. With
+/// `VAULT_PKI_ISSUER` set, `<mount>/issuer/<issuer>/issue/<role>` pins the
+/// signing issuer.
+pub fn issue_path(config: &Config) -> String {
+    let endpoint = match config.cert_key_source {
+        KeySource::Vault => "issue",
+        KeySource::Local => "sign",
+    };
+    match config.vault_pki_issuer {
+        Some(ref issuer) => format!(
+            "{}/issuer/{}/{}/{}",
+            config.vault_pki_mount, issuer, endpoint, config.vault_pki_role
+        ),
+        None => format!(
+            "{}/{}/{}",
+            config.vault_pki_mount, endpoint, config.vault_pki_role
+        ),
+    }
+}
+
+/// Whether the client's token may post to [`issue_path`], according to
+/// `sys/capabilities-self`. Nothing is issued.
+pub async fn can_issue(client: &VaultClient, config: &Config) -> Result<bool> {
+    let path = issue_path(config);
+    let url = format!("{}/v1/sys/capabilities-self", client.addr);
     let mut request = client
         .http
         .post(&url)
-        .header("X-Vault-Token", &token)
-        .json(&body);
-
-    if let Some(ref ns) = client.namespace {
-        request = request.header("X-Vault-Namespace", ns);
-    }
+        .header("X-Vault-Token", client.token().await)
+        .json(&serde_json::json!({ "paths": [path] }));
 
-    let response = request.send().await?;
+    request = client.namespaced(request, Scope::Pki);
 
+    let response = client.send(request).await?;
     if !response.status().is_success() {
         let status = response.status();
         let body = response.text().await.unwrap_or_default();
         return Err(Error::VaultPki(format!(
-            "PKI issue returned {status}: {body}"
+            "capabilities lookup returned {status}: {body}"
         )));
     }
 
-    let pki_resp: PkiResponse = response.json().await?;
+    let capabilities: CapabilitiesResponse = response.json().await?;
+    Ok(capabilities
+        .capabilities
+        .iter()
+        .any(|c| matches!(c.as_str(), "create" | "update" | "root")))
+}
 
-    // Build full chain: leaf cert + issuing CA
-    let full_chain = format!(
-        "{}\n{}",
-        pki_resp.data.certificate.trim(),
-        pki_resp.data.issuing_ca.trim()
+/// Whether `VAULT_PKI_ROLE` exists on the mount, read from
+/// `<mount>/roles/<role>`, or `None` if the token may not read roles.
+/// Issuing needs no such permission, so least-privilege policies leave it
+/// out.
+pub async fn role_exists(client: &VaultClient, config: &Config) -> Result<Option<bool>> {
+    let url = format!(
+        "{}/v1/{}/roles/{}",
+        client.addr, config.vault_pki_mount, config.vault_pki_role
     );
+    let mut request = client
+        .http
+        .get(&url)
+        .header("X-Vault-Token", client.token().await);
+
+    request = client.namespaced(request, Scope::Pki);
+
+    let response = client.send(request).await?;
+    match response.status() {
+        status if status.is_success() => Ok(Some(true)),
+        StatusCode::NOT_FOUND => Ok(Some(false)),
+        StatusCode::FORBIDDEN => Ok(None),
+        status => {
+            let body = response.text().await.unwrap_or_default();
+            Err(Error::VaultPki(format!(
+                "reading role {} returned {status}: {body}",
+                config.vault_pki_role
+            )))
+        }
+    }
+}
+
+/// Sign an externally generated CSR with Vault's PKI secrets engine.
+///
+/// The returned bundle has an empty `private_key`; the key never leaves
+/// whoever generated the CSR. `common_name` and `alt_names` are only used
+/// when the role does not take them from the CSR (`use_csr_common_name`,
+/// `use_csr_sans`).
+#[cfg(feature = "acme")]
+#[instrument(name = "pki_sign", skip_all, fields(common_name = %common_name))]
+pub async fn sign_csr(
+    client: &VaultClient,
+    mount: &str,
+    role: &str,
+    csr_pem: &str,
+    common_name: &str,
+    alt_names: &[String],
+    ttl: &str,
+) -> Result<CertBundle> {
+    let path = format!("{mount}/sign/{role}");
+    let url = format!("{}/v1/{}", client.addr, path);
+
+    debug!(url = %url, common_name = %common_name, ttl = %ttl, "submitting CSR to vault PKI");
+
+    let mut body = serde_json::json!({
+        "csr": csr_pem,
+        "common_name": common_name,
+        "ttl": ttl,
+    });
+    if !alt_names.is_empty() {
+        body["alt_names"] = serde_json::Value::String(alt_names.join(","));
+    }
+
+    let pki_resp = send_pki_request(client, &path, &body).await?;
 
     info!(
         lease_duration = pki_resp.lease_duration,
-        "certificate issued successfully"
+        common_name = %common_name,
+        "CSR signed successfully"
     );
 
-    Ok(CertBundle {
+    Ok(into_bundle(pki_resp))
+}
+
+/// PEM CA chain of the PKI mount, falling back to the mount's CA
+/// certificate when the chain is empty (a mount holding only a root).
+pub async fn fetch_ca_chain(client: &VaultClient, config: &Config) -> Result<String> {
+    fetch_mount_ca_chain(client, &config.vault_pki_mount).await
+}
+
+/// [`fetch_ca_chain`] of `mount`.
+pub async fn fetch_mount_ca_chain(client: &VaultClient, mount: &str) -> Result<String> {
+    for endpoint in ["ca_chain", "ca/pem"] {
+        let url = format!("{}/v1/{}/{}", client.addr, mount, endpoint);
+        let mut request = client.http.get(&url);
+        request = client.namespaced(request, Scope::Pki);
+
+        let response = client.send(request).await?;
+        if !response.status().is_success() {
+            return Err(Error::VaultPki(format!(
+                "fetching {endpoint} returned {}",
+                response.status()
+            )));
+        }
+
+        let pem = response.text().await?;
+        if !pem.trim().is_empty() {
+            return Ok(pem);
+        }
+    }
+
+    Err(Error::VaultPki(format!(
+        "PKI mount '{mount}' has no CA certificate"
+    )))
+}
+
+/// IDs of every issuer in `mount`, or `None` from a Vault older than 1.11,
+/// whose mounts hold a single CA.
+pub async fn list_issuers(client: &VaultClient, mount: &str) -> Result<Option<Vec<String>>> {
+    let url = format!("{}/v1/{}/issuers?list=true", client.addr, mount);
+    let request = client.namespaced(client.http.get(&url), Scope::Pki);
+
+    let response = client.send(request).await?;
+    match response.status() {
+        // Also what a mount without issuers answers.
+        StatusCode::NOT_FOUND => return Ok(None),
+        status if !status.is_success() => {
+            return Err(Error::VaultPki(format!(
+                "listing {mount}/issuers returned {status}"
+            )))
+        }
+        _ => {}
+    }
+    let issuers: IssuersResponse = response.json().await?;
+    Ok(Some(issuers.data.keys))
+}
+
+/// PEM certificate of `issuer` in `mount`, followed by the CAs above it.
+pub async fn fetch_issuer(client: &VaultClient, mount: &str, issuer: &str) -> Result<String> {
+    let url = format!("{}/v1/{}/issuer/{}/json", client.addr, mount, issuer);
+    let request = client.namespaced(client.http.get(&url), Scope::Pki);
+
+    let response = client.send(request).await?;
+    if !response.status().is_success() {
+        return Err(Error::VaultPki(format!(
+            "fetching {mount}/issuer/{issuer} returned {}",
+            response.status()
+        )));
+    }
+    let issuer: IssuerResponse = response.json().await?;
+    let mut pem = issuer.data.certificate;
+    for ca in issuer.data.ca_chain {
+        pem.push('\n');
+        pem.push_str(&ca);
+    }
+    Ok(pem)
+}
+
+/// PEM CRL of the PKI mount's default issuer.
+pub async fn fetch_crl(client: &VaultClient, config: &Config) -> Result<String> {
+    let url = format!("{}/v1/{}/crl/pem", client.addr, config.vault_pki_mount);
+    let mut request = client.http.get(&url);
+    request = client.namespaced(request, Scope::Pki);
+
+    let response = client.send(request).await?;
+    if !response.status().is_success() {
+        return Err(Error::VaultPki(format!(
+            "fetching crl/pem returned {}",
+            response.status()
+        )));
+    }
+    Ok(response.text().await?)
+}
+
+/// Revoke the certificate with `serial` (colon-separated hex, as Vault
+/// reports it) through `<mount>/revoke`, adding it to the mount's CRL.
+#[instrument(name = "pki_revoke", skip_all, fields(serial = %serial))]
+pub async fn revoke_certificate(client: &VaultClient, config: &Config, serial: &str) -> Result<()> {
+    let url = format!("{}/v1/{}/revoke", client.addr, config.vault_pki_mount);
+    let mut request = client
+        .http
+        .post(&url)
+        .header("X-Vault-Token", client.token().await)
+        .json(&serde_json::json!({ "serial_number": serial }));
+    request = client.namespaced(request, Scope::Pki);
+
+    let response = client.send(request).await?;
+    if !response.status().is_success() {
+        let status = response.status();
+        let body = response.text().await.unwrap_or_default();
+        return Err(Error::VaultPki(format!(
+            "revoking {serial} returned {status}: {body}"
+        )));
+    }
+    Ok(())
+}
+
+/// Post `body` to the PKI endpoint `path`, response-wrapped when
+/// `VAULT_WRAP_TTL` is set.
+async fn send_pki_request(
+    client: &VaultClient,
+    path: &str,
+    body: &serde_json::Value,
+) -> Result<PkiResponse> {
+    let url = format!("{}/v1/{}", client.addr, path);
+    let token = client.token().await;
+    let mut request = client
+        .http
+        .post(&url)
+        .header("X-Vault-Token", &token)
+        .json(body);
+
+    request = client.namespaced(request, Scope::Pki);
+    if let Some(ttl) = client.wrap_ttl {
+        request = request.header("X-Vault-Wrap-TTL", format!("{}s", ttl.as_secs()));
+    }
+
+    let response = client.send(request).await?;
+
+    if !response.status().is_success() {
+        let status = response.status();
+        let body = response.text().await.unwrap_or_default();
+        return Err(Error::VaultPki(format!(
+            "PKI request returned {status}: {body}"
+        )));
+    }
+
+    if client.wrap_ttl.is_none() {
+        return Ok(response.json().await?);
+    }
+    let wrapped: WrappedResponse = response
+        .json()
+        .await
+        .map_err(|e| Error::VaultUnwrap(format!("expected a wrapped response from {path}: {e}")))?;
+    unwrap(client, path, wrapped.wrap_info).await
+}
+
+/// Exchange a wrapping token for the response it wraps.
+///
+/// The token is single-use, so Vault refusing it means someone else
+/// unwrapped it first: the certificate and key may be in their hands. That
+/// is never retried or papered over, only reported.
+async fn unwrap(client: &VaultClient, path: &str, wrap_info: WrapInfo) -> Result<PkiResponse> {
+    // A token minted for another path could have been swapped in. Within a
+    // namespace, Vault may report the path with the namespace in front.
+    let created = wrap_info.creation_path.trim_matches('/');
+    let created = match client.namespace(Scope::Pki) {
+        Some(ns) => created
+            .strip_prefix(ns.trim_matches('/'))
+            .and_then(|rest| rest.strip_prefix('/'))
+            .unwrap_or(created),
+        None => created,
+    };
+    if created != path.trim_matches('/') {
+        error!(
+            accessor = %wrap_info.accessor,
+            creation_path = %wrap_info.creation_path,
+            expected = %path,
+            "wrapping token was not created by the PKI request; possible interception"
+        );
+        return Err(Error::VaultUnwrap(format!(
+            "wrapping token was created for '{}', not '{path}'",
+            wrap_info.creation_path
+        )));
+    }
+
+    let url = format!("{}/v1/sys/wrapping/unwrap", client.addr);
+    let mut request = client
+        .http
+        .post(&url)
+        .header("X-Vault-Token", &wrap_info.token);
+    request = client.namespaced(request, Scope::Pki);
+
+    let response = client.send(request).await?;
+    if !response.status().is_success() {
+        let status = response.status();
+        let body = response.text().await.unwrap_or_default();
+        error!(
+            accessor = %wrap_info.accessor,
+            status = %status,
+            "vault refused the wrapping token; it may have been unwrapped by someone else"
+        );
+        return Err(Error::VaultUnwrap(format!(
+            "unwrapping returned {status}: {body}"
+        )));
+    }
+
+    debug!(accessor = %wrap_info.accessor, "unwrapped PKI response");
+    Ok(response.json().await?)
+}
+
+fn into_bundle(pki_resp: PkiResponse) -> CertBundle {
+    // Build full chain: leaf cert + every CA up to the root, so clients
+    // without the intermediates installed can still build a path. Without
+    // a `ca_chain`, the issuing CA is all there is.
+    let mut full_chain = pki_resp.data.certificate.trim().to_string();
+    if pki_resp.data.ca_chain.is_empty() {
+        full_chain.push('\n');
+        full_chain.push_str(pki_resp.data.issuing_ca.trim());
+    }
+    for ca in &pki_resp.data.ca_chain {
+        full_chain.push('\n');
+        full_chain.push_str(ca.trim());
+    }
+
+    CertBundle {
         certificate: full_chain,
         private_key: pki_resp.data.private_key,
         ca_certificate: pki_resp.data.issuing_ca,
         lease_duration_secs: pki_resp.lease_duration,
-    })
+        request_id: pki_resp.request_id,
+    }
 }
@@ -0,0 +1,182 @@
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+use reqwest::header::RETRY_AFTER;
+use reqwest::{Response, StatusCode};
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::Serialize;
+use tracing::{info, warn};
+
+use crate::config::Config;
+use crate::metrics;
+
+/// Longest delay between two attempts. A `Retry-After` asking for more
+/// ends the retries instead, since the caller has its own schedule.
+pub const MAX_RETRY_DELAY: Duration = Duration::from_secs(30);
+
+/// How often, and how patiently, a failed Vault request is retried.
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    /// Further attempts after the first.
+    pub retries: u32,
+    /// Delay before the first retry, doubled for each one after it.
+    pub backoff: Duration,
+}
+
+impl RetryPolicy {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            retries: config.vault_retries,
+            backoff: config.vault_retry_backoff,
+        }
+    }
+
+    /// Delay before retry number `retry` (from 0), between half and all of
+    /// the exponential backoff so that replicas do not retry in lockstep.
+    pub fn delay(&self, retry: u32) -> Duration {
+        let full = self
+            .backoff
+            .saturating_mul(2u32.saturating_pow(retry))
+            .min(MAX_RETRY_DELAY);
+        let mut buf = [0u8; 4];
+        // Without randomness, retrying on the full backoff is still correct.
+        if SystemRandom::new().fill(&mut buf).is_err() {
+            return full;
+        }
+        let unit = u32::from_be_bytes(buf) as f64 / u32::MAX as f64;
+        full.mul_f64(0.5 + unit / 2.0)
+    }
+}
+
+/// Why an attempt is worth repeating, as counted in `vault_retries`.
+pub fn retry_reason(outcome: &reqwest::Result<Response>) -> Option<&'static str> {
+    match outcome {
+        Ok(response) if response.status() == StatusCode::TOO_MANY_REQUESTS => Some("rate_limited"),
+        Ok(response) if response.status().is_server_error() => Some("server_error"),
+        Ok(_) => None,
+        Err(e) if e.is_timeout() => Some("timeout"),
+        Err(e) if e.is_connect() || e.is_request() => Some("connect"),
+        Err(_) => None,
+    }
+}
+
+/// The delay a 429 or 503 response asks for in `Retry-After`, when given
+/// in seconds.
+pub fn retry_after(outcome: &reqwest::Result<Response>) -> Option<Duration> {
+    let response = outcome.as_ref().ok()?;
+    let value = response.headers().get(RETRY_AFTER)?.to_str().ok()?;
+    value.trim().parse().ok().map(Duration::from_secs)
+}
+
+/// Stops sending requests to a Vault that keeps failing, so that every
+/// caller does not wait out its own retries against an outage.
+///
+/// After `threshold` consecutive failed requests the circuit opens and
+/// requests fail immediately. Once `cooldown` has passed, one request is
+/// let through; its success closes the circuit and its failure reopens it.
+pub struct Circuit {
+    threshold: u32,
+    cooldown: Duration,
+    state: Mutex<CircuitState>,
+}
+
+#[derive(Default)]
+struct CircuitState {
+    failures: u32,
+    opened_at: Option<Instant>,
+    /// When the request testing a half-open circuit was let through. One
+    /// that never finished (its caller gave up) stops counting after
+    /// another cooldown.
+    probe_at: Option<Instant>,
+}
+
+/// What the admin API reports about the circuit.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CircuitStatus {
+    Closed,
+    Open,
+    HalfOpen,
+}
+
+impl Circuit {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            threshold: config.vault_circuit_threshold,
+            cooldown: config.vault_circuit_cooldown,
+            state: Mutex::new(CircuitState::default()),
+        }
+    }
+
+    /// Whether a request may be sent now.
+    pub fn allow(&self) -> bool {
+        let mut state = self.lock();
+        let Some(opened_at) = state.opened_at else {
+            return true;
+        };
+        if opened_at.elapsed() < self.cooldown {
+            return false;
+        }
+        match state.probe_at {
+            Some(probe_at) if probe_at.elapsed() < self.cooldown => false,
+            _ => {
+                state.probe_at = Some(Instant::now());
+                true
+            }
+        }
+    }
+
+    /// Vault answered, even if with an error of the caller's making.
+    pub fn record_success(&self) {
+        let mut state = self.lock();
+        if state.opened_at.is_some() {
+            let count = metrics::inc("vault_circuit", "closed");
+            info!(count, "vault is reachable again, closing the circuit");
+        }
+        *state = CircuitState::default();
+    }
+
+    /// A request failed for lack of a working Vault, after its retries.
+    pub fn record_failure(&self) {
+        if self.threshold == 0 {
+            return;
+        }
+        let mut state = self.lock();
+        state.failures = state.failures.saturating_add(1);
+        if state.probe_at.is_some() {
+            state.opened_at = Some(Instant::now());
+            state.probe_at = None;
+            warn!(
+                cooldown_secs = self.cooldown.as_secs(),
+                "vault is still failing, keeping the circuit open"
+            );
+        } else if state.opened_at.is_none() && state.failures >= self.threshold {
+            state.opened_at = Some(Instant::now());
+            let count = metrics::inc("vault_circuit", "opened");
+            warn!(
+                failures = state.failures,
+                cooldown_secs = self.cooldown.as_secs(),
+                count,
+                "vault keeps failing, opening the circuit"
+            );
+        }
+    }
+
+    pub fn status(&self) -> CircuitStatus {
+        let state = self.lock();
+        match state.opened_at {
+            None => CircuitStatus::Closed,
+            Some(opened_at) if opened_at.elapsed() < self.cooldown => CircuitStatus::Open,
+            Some(_) => CircuitStatus::HalfOpen,
+        }
+    }
+
+    /// Failed requests since Vault last answered.
+    pub fn failures(&self) -> u32 {
+        self.lock().failures
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, CircuitState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
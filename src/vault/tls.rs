@@ -0,0 +1,170 @@
+use std::sync::Arc;
+
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::client::WebPkiServerVerifier;
+use rustls::crypto::{verify_tls12_signature, verify_tls13_signature, CryptoProvider};
+use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use rustls::{ClientConfig, DigitallySignedStruct, RootCertStore, SignatureScheme};
+use tracing::warn;
+
+use crate::cert::info::parse_pem_chain;
+use crate::cert::manager::parse_private_key;
+use crate::config::Config;
+use crate::error::{Error, Result};
+
+/// TLS settings for talking to Vault: the public roots plus
+/// `VAULT_CACERT`, the `VAULT_CLIENT_CERT` identity for listeners that
+/// require one, and the `VAULT_TLS_SERVER_NAME` and `VAULT_SKIP_VERIFY`
+/// overrides.
+///
+/// The provider is set explicitly so an embedding program need not
+/// install one.
+pub fn client_config(config: &Config) -> Result<ClientConfig> {
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let builder = ClientConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()
+        .map_err(|e| Error::Tls(format!("failed to set up Vault TLS: {e}")))?;
+
+    let mut roots = RootCertStore::empty();
+    roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+    if let Some(ref path) = config.vault_cacert {
+        let certs = parse_pem_chain(&read("VAULT_CACERT", path)?)
+            .map_err(|e| Error::Config(format!("invalid VAULT_CACERT: {e}")))?;
+        roots.add_parsable_certificates(certs);
+    }
+    let webpki = WebPkiServerVerifier::builder_with_provider(Arc::new(roots), provider.clone())
+        .build()
+        .map_err(|e| Error::Tls(format!("failed to build Vault certificate verifier: {e}")))?;
+
+    let builder = if config.vault_skip_verify {
+        warn!("VAULT_SKIP_VERIFY is set, Vault's certificate is not checked");
+        builder
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(NoVerifier(provider)))
+    } else if let Some(ref name) = config.vault_tls_server_name {
+        let name = ServerName::try_from(name.clone())
+            .map_err(|e| Error::Config(format!("invalid VAULT_TLS_SERVER_NAME '{name}': {e}")))?;
+        builder
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(RenamedVerifier {
+                inner: webpki,
+                name,
+            }))
+    } else {
+        builder.with_webpki_verifier(webpki)
+    };
+
+    let mut tls = match (&config.vault_client_cert, &config.vault_client_key) {
+        (Some(cert), Some(key)) => {
+            let certs = parse_pem_chain(&read("VAULT_CLIENT_CERT", cert)?)?;
+            let key = parse_private_key(&read("VAULT_CLIENT_KEY", key)?)?;
+            builder
+                .with_client_auth_cert(certs, key)
+                .map_err(|e| Error::Tls(format!("invalid Vault client certificate: {e}")))?
+        }
+        _ => builder.with_no_client_auth(),
+    };
+    // Without ALPN the client would never speak HTTP/2.
+    tls.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    Ok(tls)
+}
+
+fn read(var: &str, path: &str) -> Result<String> {
+    std::fs::read_to_string(path)
+        .map_err(|e| Error::Config(format!("failed to read {var} '{path}': {e}")))
+}
+
+/// WebPKI verification against `VAULT_TLS_SERVER_NAME` rather than the
+/// host in `VAULT_ADDR`.
+#[derive(Debug)]
+struct RenamedVerifier {
+    inner: Arc<WebPkiServerVerifier>,
+    name: ServerName<'static>,
+}
+
+impl ServerCertVerifier for RenamedVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> std::result::Result<ServerCertVerified, rustls::Error> {
+        self.inner
+            .verify_server_cert(end_entity, intermediates, &self.name, ocsp_response, now)
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> std::result::Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> std::result::Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.inner.supported_verify_schemes()
+    }
+}
+
+/// Accepts any certificate for `VAULT_SKIP_VERIFY`. The handshake
+/// signatures are still checked, so the server holds the key of the
+/// certificate it sent.
+#[derive(Debug)]
+struct NoVerifier(Arc<CryptoProvider>);
+
+impl ServerCertVerifier for NoVerifier {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> std::result::Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> std::result::Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> std::result::Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
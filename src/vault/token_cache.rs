@@ -0,0 +1,159 @@
+use std::io;
+use std::path::{Path, PathBuf};
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN};
+use ring::hkdf::{Salt, HKDF_SHA256};
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+use tokio::fs;
+use tokio::io::AsyncWriteExt;
+
+use crate::config::Config;
+use crate::error::{Error, Result};
+
+const KEY_INFO: &[u8] = b"cert-keeper vault token cache v1";
+const SALT_LEN: usize = 16;
+
+/// Encrypted on-disk copy of the Vault token, so a restarted pod can resume
+/// with its previous token instead of logging in again.
+///
+/// The key is derived with HKDF from `VAULT_TOKEN_CACHE_KEY_FILE`, which
+/// defaults to the login JWT (see `AuthMethod::default_cache_key_file`). A
+/// rotated JWT simply makes the cache unreadable, which falls back to a
+/// normal login.
+pub struct TokenCache {
+    path: PathBuf,
+    key_file: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Envelope {
+    salt: String,
+    nonce: String,
+    ciphertext: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct CachedToken {
+    pub token: String,
+    /// Lets a resumed token be matched to its login in Vault's audit log.
+    /// Missing from caches written before it was recorded.
+    #[serde(default)]
+    pub accessor: String,
+    /// Unix timestamp after which the token is no longer worth trying.
+    expires_at: i64,
+}
+
+impl TokenCache {
+    pub fn from_config(config: &Config) -> Option<Self> {
+        let path = config.vault_token_cache.as_ref()?;
+        Some(Self {
+            path: PathBuf::from(path),
+            key_file: config.vault_token_cache_key_file.clone()?,
+        })
+    }
+
+    /// Return the cached token if the file exists, decrypts, and has not
+    /// expired.
+    pub async fn load(&self) -> Result<Option<CachedToken>> {
+        let raw = match fs::read(&self.path).await {
+            Ok(raw) => raw,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+
+        let envelope: Envelope = serde_json::from_slice(&raw)?;
+        let salt = decode(&envelope.salt)?;
+        let nonce = Nonce::try_assume_unique_for_key(&decode(&envelope.nonce)?)
+            .map_err(|_| Error::VaultAuth("token cache has an invalid nonce".into()))?;
+        let mut data = decode(&envelope.ciphertext)?;
+
+        let plaintext = self
+            .key(&salt)
+            .await?
+            .open_in_place(nonce, Aad::empty(), &mut data)
+            .map_err(|_| {
+                Error::VaultAuth("token cache could not be decrypted (key changed?)".into())
+            })?;
+        let cached: CachedToken = serde_json::from_slice(plaintext)?;
+
+        if cached.expires_at <= OffsetDateTime::now_utc().unix_timestamp() {
+            return Ok(None);
+        }
+        Ok(Some(cached))
+    }
+
+    /// Encrypt and write `token`, valid for `lease_secs`.
+    pub async fn store(&self, token: &str, accessor: &str, lease_secs: u64) -> Result<()> {
+        let rng = SystemRandom::new();
+        let mut salt = [0u8; SALT_LEN];
+        let mut nonce = [0u8; NONCE_LEN];
+        rng.fill(&mut salt)
+            .and_then(|_| rng.fill(&mut nonce))
+            .map_err(|_| Error::VaultAuth("failed to generate token cache nonce".into()))?;
+
+        let mut data = serde_json::to_vec(&CachedToken {
+            token: token.to_string(),
+            accessor: accessor.to_string(),
+            expires_at: OffsetDateTime::now_utc().unix_timestamp() + lease_secs as i64,
+        })?;
+        self.key(&salt)
+            .await?
+            .seal_in_place_append_tag(Nonce::assume_unique_for_key(nonce), Aad::empty(), &mut data)
+            .map_err(|_| Error::VaultAuth("failed to encrypt token cache".into()))?;
+
+        let envelope = serde_json::to_vec(&Envelope {
+            salt: STANDARD.encode(salt),
+            nonce: STANDARD.encode(nonce),
+            ciphertext: STANDARD.encode(&data),
+        })?;
+        write_private(&self.path, &envelope).await
+    }
+
+    async fn key(&self, salt: &[u8]) -> Result<LessSafeKey> {
+        let secret = fs::read(&self.key_file).await.map_err(|e| {
+            Error::VaultAuth(format!(
+                "failed to read token cache key from {}: {e}",
+                self.key_file
+            ))
+        })?;
+
+        // Hand-written key files usually end in a newline.
+        let prk = Salt::new(HKDF_SHA256, salt).extract(secret.trim_ascii());
+        let okm = prk
+            .expand(&[KEY_INFO], &AES_256_GCM)
+            .map_err(|_| Error::VaultAuth("failed to derive token cache key".into()))?;
+        Ok(LessSafeKey::new(UnboundKey::from(okm)))
+    }
+}
+
+/// Write `contents` readable only by the owner, via a temporary file and
+/// rename.
+async fn write_private(path: &Path, contents: &[u8]) -> Result<()> {
+    let tmp = path.with_extension("tmp");
+    // A leftover from an interrupted write would keep its old mode, which
+    // `mode` only applies to new files.
+    match fs::remove_file(&tmp).await {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+        _ => {}
+    }
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(&tmp)
+        .await?;
+    file.write_all(contents).await?;
+    file.sync_all().await?;
+    fs::rename(&tmp, path).await?;
+    Ok(())
+}
+
+fn decode(value: &str) -> Result<Vec<u8>> {
+    STANDARD
+        .decode(value)
+        .map_err(|e| Error::VaultAuth(format!("token cache is corrupt: {e}")))
+}
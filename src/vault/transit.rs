@@ -0,0 +1,76 @@
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::error::{Error, Result};
+use crate::vault::client::{Scope, VaultClient};
+
+#[derive(Debug, Deserialize)]
+struct TransitResponse<T> {
+    data: T,
+}
+
+#[derive(Debug, Deserialize)]
+struct DataKey {
+    plaintext: String,
+    ciphertext: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Plaintext {
+    plaintext: String,
+}
+
+/// A new 256-bit data key from the Transit key `key` under `mount`: the key
+/// itself, and the key wrapped by `key` as Vault's `vault:v1:...` string.
+pub async fn data_key(client: &VaultClient, mount: &str, key: &str) -> Result<(Vec<u8>, String)> {
+    let path = format!("{mount}/datakey/plaintext/{key}");
+    let data: DataKey = post(client, &path, &serde_json::json!({ "bits": 256 })).await?;
+    Ok((decode(&path, &data.plaintext)?, data.ciphertext))
+}
+
+/// Unwrap a data key that [`data_key`] returned wrapped.
+pub async fn decrypt(
+    client: &VaultClient,
+    mount: &str,
+    key: &str,
+    ciphertext: &str,
+) -> Result<Vec<u8>> {
+    let path = format!("{mount}/decrypt/{key}");
+    let data: Plaintext = post(
+        client,
+        &path,
+        &serde_json::json!({ "ciphertext": ciphertext }),
+    )
+    .await?;
+    decode(&path, &data.plaintext)
+}
+
+async fn post<T: DeserializeOwned>(client: &VaultClient, path: &str, body: &Value) -> Result<T> {
+    let url = format!("{}/v1/{}", client.addr, path);
+    let mut request = client
+        .http
+        .post(&url)
+        .header("X-Vault-Token", client.token().await)
+        .json(body);
+    request = client.namespaced(request, Scope::Secrets);
+
+    let response = client.send(request).await?;
+    if !response.status().is_success() {
+        let status = response.status();
+        let body = response.text().await.unwrap_or_default();
+        return Err(Error::KeyEncryption(format!(
+            "vault transit request to {path} returned {status}: {body}"
+        )));
+    }
+    let response: TransitResponse<T> = response.json().await?;
+    Ok(response.data)
+}
+
+fn decode(path: &str, plaintext: &str) -> Result<Vec<u8>> {
+    STANDARD.decode(plaintext).map_err(|e| {
+        Error::KeyEncryption(format!("vault transit {path} returned an invalid key: {e}"))
+    })
+}
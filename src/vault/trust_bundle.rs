@@ -0,0 +1,133 @@
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use rustls::sign::CertifiedKey;
+use time::OffsetDateTime;
+use tokio::sync::watch;
+use tokio::time::Instant;
+use tracing::{debug, error, info, instrument, warn};
+
+use crate::cert::info::{parse_pem_chain, pem_encode, CertInfo};
+use crate::cert::store::write_atomic;
+use crate::config::Config;
+use crate::error::{Error, Result};
+use crate::vault::client::VaultClient;
+use crate::vault::pki;
+
+/// How soon a failed refresh is tried again.
+const RETRY_INTERVAL: Duration = Duration::from_secs(30);
+
+/// The CA certificates of every issuer in `mount`, or its CA chain on a
+/// Vault without multiple issuers.
+async fn fetch_mount(client: &VaultClient, mount: &str) -> Result<String> {
+    let Some(issuers) = pki::list_issuers(client, mount).await? else {
+        return pki::fetch_mount_ca_chain(client, mount).await;
+    };
+    let mut pem = String::new();
+    for issuer in issuers {
+        pem.push_str(&pki::fetch_issuer(client, mount, &issuer).await?);
+        pem.push('\n');
+    }
+    Ok(pem)
+}
+
+/// Build the trust bundle from `TRUST_BUNDLE_MOUNTS` and write it to
+/// `TRUST_BUNDLE_FILE` if it changed. Certificates that appear under more
+/// than one issuer or mount are written once, and expired ones not at all.
+#[instrument(name = "trust_bundle", skip_all, fields(path = %path.display()))]
+pub async fn write_bundle(client: &VaultClient, config: &Config, path: &Path) -> Result<()> {
+    let now = OffsetDateTime::now_utc();
+    let mut certs = Vec::new();
+    let mut expired = 0;
+    for mount in &config.trust_bundle_mounts {
+        for cert in parse_pem_chain(&fetch_mount(client, mount).await?)? {
+            if certs.contains(&cert) {
+                continue;
+            }
+            match CertInfo::from_der(&cert) {
+                Ok(info) if info.not_after <= now => expired += 1,
+                Ok(_) => certs.push(cert),
+                Err(e) => warn!(mount, error = %e, "skipping unparsable CA certificate"),
+            }
+        }
+    }
+    if certs.is_empty() {
+        return Err(Error::VaultPki(format!(
+            "no unexpired CA certificates in {}",
+            config.trust_bundle_mounts.join(", ")
+        )));
+    }
+
+    let contents: String = certs.iter().map(|cert| pem_encode("CERTIFICATE", cert)).collect();
+    // Left alone when unchanged, so apps watching the file only reload on
+    // a real change of CAs.
+    if tokio::fs::read_to_string(path).await.ok().as_ref() == Some(&contents) {
+        debug!(certs = certs.len(), "trust bundle unchanged");
+        return Ok(());
+    }
+    if let Some(dir) = path.parent() {
+        tokio::fs::create_dir_all(dir).await?;
+    }
+    write_atomic(
+        path,
+        contents,
+        config.cert_file_mode,
+        config.cert_file_uid,
+        config.cert_file_gid,
+    )
+    .await
+    .map_err(|e| Error::VaultPki(format!("failed to write {}: {e}", path.display())))?;
+    info!(certs = certs.len(), expired, "trust bundle written");
+    Ok(())
+}
+
+/// Keep `TRUST_BUNDLE_FILE` up to date until shutdown.
+///
+/// It is written right away, then rebuilt every
+/// `TRUST_BUNDLE_REFRESH_INTERVAL` and whenever a new certificate is
+/// issued, as seen on `issued`, which during a CA rotation is often the
+/// first sign of a new issuer. A failed refresh keeps the file and is
+/// retried after [`RETRY_INTERVAL`].
+pub async fn run(
+    client: Arc<VaultClient>,
+    config: Config,
+    mut issued: watch::Receiver<Option<Arc<CertifiedKey>>>,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    let Some(path) = config.trust_bundle_file.clone() else {
+        return;
+    };
+    let path = Path::new(&path);
+    let mut current = issued.borrow_and_update().clone();
+    loop {
+        let next = match write_bundle(&client, &config, path).await {
+            Ok(()) => config.trust_bundle_refresh_interval,
+            Err(e) => {
+                error!(error = %e, code = e.code(), retry_in_secs = RETRY_INTERVAL.as_secs(), "failed to refresh trust bundle");
+                RETRY_INTERVAL
+            }
+        };
+        let deadline = Instant::now() + next;
+
+        loop {
+            tokio::select! {
+                _ = tokio::time::sleep_until(deadline) => break,
+                Ok(()) = issued.changed() => {
+                    // The server config is also rebuilt without a renewal,
+                    // as when a CRL is refreshed.
+                    let key = issued.borrow_and_update().clone();
+                    let renewed = match (&key, &current) {
+                        (Some(key), Some(current)) => !Arc::ptr_eq(key, current),
+                        (key, _) => key.is_some(),
+                    };
+                    current = key;
+                    if renewed {
+                        break;
+                    }
+                }
+                _ = shutdown.changed() => return,
+            }
+        }
+    }
+}